@@ -24,7 +24,7 @@ fn main() {
 						loop {
 							let n = stream.read(&mut buffer).unwrap();
 							info!("< {:?}", from_utf8(&buffer[0..n]).unwrap());
-							stream.write("pong".as_bytes()).unwrap();
+							stream.write_all("pong".as_bytes()).unwrap();
 						}
 					});
 				}
@@ -38,7 +38,7 @@ fn main() {
 	// connect through i2p to our local destination
 	let mut client = I2pStream::connect(our_dest).unwrap();
 	let msg = "ping";
-	client.write(msg.as_bytes()).unwrap();
+	client.write_all(msg.as_bytes()).unwrap();
 	let mut buffer = [0; 100];
 	let n = client.read(&mut buffer).unwrap();
 	info!("> {:?}", from_utf8(&buffer[0..n]).unwrap());
@@ -0,0 +1,803 @@
+//! Local address books, in the `hosts.txt` format routers share.
+//!
+//! Each line maps a hostname to its base64 destination as `name=dest`,
+//! optionally followed by `#!` and signed properties, which are ignored.
+//! Lines starting with `#` are comments. An `AddressBook` is a `Resolver`,
+//! and can be chained before the bridge:
+//!
+//! ```no_run
+//! use i2p::addressbook::AddressBook;
+//! use i2p::naming::{ResolverChain, SamResolver};
+//! use i2p::SamConnection;
+//!
+//! let book = AddressBook::load("hosts.txt").unwrap();
+//! let sam = SamConnection::connect("127.0.0.1:7656").unwrap();
+//! let resolver = ResolverChain::new()
+//!     .then(book)
+//!     .then(SamResolver::new(sam.duplicate().unwrap()));
+//! let sam = sam.with_resolver(resolver);
+//! ```
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use log::debug;
+use rand::{self, Rng};
+
+use crate::destination::Destination;
+use crate::error::{Error, ErrorKind, LookupFailure};
+use crate::naming::{find_hostname, reverse_target, NamingCache, Resolver};
+use crate::net::{I2pAddr, I2pStream};
+use crate::sam::{SamConnection, Session};
+
+/// Longest HTTP response read from a feed or jump service
+const MAX_FEED_LEN: u64 = 16 * 1024 * 1024;
+/// How long a feed or jump server may stay silent during a fetch
+const FETCH_TIMEOUT: Duration = Duration::from_secs(120);
+/// Default delay before retrying a failed refresh, doubled on each new
+/// failure
+const DEFAULT_RETRY_DELAY: Duration = Duration::from_secs(30);
+/// Default share of the delays randomly added or removed
+const DEFAULT_JITTER: f64 = 0.1;
+
+/// Hostnames mapped to base64 destinations. Clones share the same entries,
+/// so names added to a book plugged into a `SamConnection` get resolved
+/// right away.
+///
+/// # Examples
+///
+/// ```
+/// use i2p::addressbook::AddressBook;
+///
+/// let book = AddressBook::parse("# comment\nexample.i2p=not a destination\n");
+/// assert!(book.is_empty());
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct AddressBook {
+	entries: Arc<Mutex<BTreeMap<String, String>>>,
+}
+
+impl AddressBook {
+	pub fn new() -> AddressBook {
+		AddressBook::default()
+	}
+
+	/// Reads a `hosts.txt` file, skipping invalid entries.
+	pub fn load<P: AsRef<Path>>(path: P) -> Result<AddressBook, Error> {
+		AddressBook::from_reader(BufReader::new(fs::File::open(path)?))
+	}
+
+	/// Reads `hosts.txt` lines, skipping invalid entries.
+	pub fn from_reader<R: BufRead>(reader: R) -> Result<AddressBook, Error> {
+		let book = AddressBook::new();
+		for line in reader.lines() {
+			book.add_line(&line?);
+		}
+		Ok(book)
+	}
+
+	/// Parses the content of a `hosts.txt` file, skipping invalid entries.
+	pub fn parse(content: &str) -> AddressBook {
+		let book = AddressBook::new();
+		for line in content.lines() {
+			book.add_line(line);
+		}
+		book
+	}
+
+	fn add_line(&self, line: &str) {
+		let line = line.trim();
+		if line.is_empty() || line.starts_with('#') {
+			return;
+		}
+		let entry = line.split('#').next().unwrap_or_default();
+		let parsed = entry
+			.split_once('=')
+			.ok_or_else(|| ErrorKind::BadAddressEncoding(line.to_string()).into())
+			.and_then(|(name, dest)| self.insert(name.trim(), dest.trim()));
+		if let Err(e) = parsed {
+			debug!("Skipping address book entry {}: {}", line, e);
+		}
+	}
+
+	/// Adds or replaces the destination of a hostname, both being validated.
+	pub fn insert(&self, name: &str, dest: &str) -> Result<(), Error> {
+		let name = checked_hostname(name)?;
+		Destination::from_base64(dest)?;
+		self.lock()?.insert(name, dest.to_string());
+		Ok(())
+	}
+
+	/// The destination of a hostname, if known.
+	pub fn get(&self, name: &str) -> Option<String> {
+		self.lock().ok()?.get(&name.to_ascii_lowercase()).cloned()
+	}
+
+	/// The hostname of a base64 destination or b32 address, if the book
+	/// knows one.
+	pub fn lookup_reverse(&self, dest: &str) -> Option<String> {
+		self.reverse(&reverse_target(dest)?)
+	}
+
+	/// Forgets a hostname, returning its destination.
+	pub fn remove(&self, name: &str) -> Option<String> {
+		self.lock().ok()?.remove(&name.to_ascii_lowercase())
+	}
+
+	pub fn len(&self) -> usize {
+		self.lock().map(|e| e.len()).unwrap_or(0)
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+
+	/// The hostnames and destinations of the book, sorted by hostname.
+	pub fn entries(&self) -> Vec<(String, String)> {
+		self.lock()
+			.map(|e| e.iter().map(|(n, d)| (n.clone(), d.clone())).collect())
+			.unwrap_or_default()
+	}
+
+	/// Writes the book in the `hosts.txt` format.
+	pub fn write_to<W: Write>(&self, mut writer: W) -> Result<(), Error> {
+		for (name, dest) in self.entries() {
+			writeln!(writer, "{}={}", name, dest)?;
+		}
+		writer.flush()?;
+		Ok(())
+	}
+
+	/// Writes the book to a `hosts.txt` file, replacing its content.
+	pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+		let mut content = vec![];
+		self.write_to(&mut content)?;
+		fs::write(path, content)?;
+		Ok(())
+	}
+
+	fn lock(&self) -> Result<std::sync::MutexGuard<'_, BTreeMap<String, String>>, Error> {
+		self.entries
+			.lock()
+			.map_err(|_| ErrorKind::Io("Address book lock poisoned".to_string()).into())
+	}
+}
+
+impl Resolver for AddressBook {
+	fn resolve(&self, name: &str) -> Result<String, Error> {
+		self.get(name)
+			.ok_or_else(|| ErrorKind::SAMKeyNotFound(name.to_string()).into())
+	}
+
+	fn reverse(&self, addr: &I2pAddr) -> Option<String> {
+		find_hostname(self.lock().ok()?.iter(), addr)
+	}
+}
+
+/// Keeps an address book up to date from `hosts.txt` feeds, fetched over
+/// HTTP through streams of the provided session.
+///
+/// Names already in the book are never replaced by a feed, so a feed can't
+/// take over a known name. Feeds are fetched conditionally, and unchanged
+/// ones aren't downloaded again.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use i2p::addressbook::{AddressBook, Subscriber};
+/// use i2p::Session;
+///
+/// let book = AddressBook::load("hosts.txt").unwrap();
+/// let session = Session::transient("127.0.0.1:7656").unwrap();
+/// let subscriber = Subscriber::new(&book, session)
+///     .subscribe("http://i2p-projekt.i2p/hosts.txt")
+///     .unwrap();
+/// let _updates = subscriber.run(Duration::from_secs(12 * 3600));
+/// ```
+pub struct Subscriber {
+	book: AddressBook,
+	session: Session,
+	feeds: Vec<Feed>,
+}
+
+struct Feed {
+	url: String,
+	etag: Option<String>,
+	last_modified: Option<String>,
+}
+
+impl Subscriber {
+	/// Creates a subscriber updating `book`, without any feed yet.
+	pub fn new(book: &AddressBook, session: Session) -> Subscriber {
+		Subscriber {
+			book: book.clone(),
+			session,
+			feeds: vec![],
+		}
+	}
+
+	/// Adds a feed, as an `http://` URL on an I2P host.
+	pub fn subscribe(mut self, url: &str) -> Result<Subscriber, Error> {
+		parse_url(url)?;
+		self.feeds.push(Feed {
+			url: url.to_string(),
+			etag: None,
+			last_modified: None,
+		});
+		Ok(self)
+	}
+
+	/// Fetches all the feeds once, merging their new names into the book.
+	/// Returns how many names were added, or the last error if no feed could
+	/// be fetched.
+	pub fn update(&mut self) -> Result<usize, Error> {
+		let mut added = 0;
+		let mut last_err = None;
+		let mut fetched = false;
+		for i in 0..self.feeds.len() {
+			match self.fetch(i) {
+				Ok(content) => {
+					fetched = true;
+					if let Some(content) = content {
+						added += self.merge(&content);
+					}
+				}
+				Err(e) => {
+					debug!("Couldn't fetch {}: {}", self.feeds[i].url, e);
+					last_err = Some(e);
+				}
+			}
+		}
+		match last_err {
+			Some(e) if !fetched => Err(e),
+			_ => Ok(added),
+		}
+	}
+
+	/// Updates the book every `interval` from a background thread, starting
+	/// right away. Updates stop when the returned handle is dropped.
+	pub fn run(mut self, interval: Duration) -> SubscriberHandle {
+		let (stop, stopped) = mpsc::channel::<()>();
+		thread::spawn(move || loop {
+			if let Ok(added) = self.update() {
+				debug!("Added {} names to the address book", added);
+			}
+			if let Err(RecvTimeoutError::Disconnected) | Ok(()) = stopped.recv_timeout(interval) {
+				return;
+			}
+		});
+		SubscriberHandle { _stop: stop }
+	}
+
+	fn merge(&self, content: &str) -> usize {
+		let mut added = 0;
+		for (name, dest) in AddressBook::parse(content).entries() {
+			if self.book.get(&name).is_none() && self.book.insert(&name, &dest).is_ok() {
+				added += 1;
+			}
+		}
+		added
+	}
+
+	/// The content of a feed, `None` if it didn't change since last fetched
+	fn fetch(&mut self, feed: usize) -> Result<Option<String>, Error> {
+		let feed = &mut self.feeds[feed];
+		let mut headers = vec![];
+		if let Some(ref etag) = feed.etag {
+			headers.push(("If-None-Match", etag.as_str()));
+		}
+		if let Some(ref last_modified) = feed.last_modified {
+			headers.push(("If-Modified-Since", last_modified.as_str()));
+		}
+		let response = http_get(&self.session, &feed.url, &headers)?;
+		match response.status.as_str() {
+			"200" => {}
+			"304" => return Ok(None),
+			status => return Err(ErrorKind::Io(format!("{} answered {}", feed.url, status)).into()),
+		}
+		if let Some(etag) = response.header("ETag") {
+			feed.etag = Some(etag.to_string());
+		}
+		if let Some(last_modified) = response.header("Last-Modified") {
+			feed.last_modified = Some(last_modified.to_string());
+		}
+		Ok(Some(String::from_utf8_lossy(&response.body).into_owned()))
+	}
+}
+
+/// Handle to a running `Subscriber`, see `Subscriber::run`. Updates stop when
+/// this is dropped.
+pub struct SubscriberHandle {
+	_stop: mpsc::Sender<()>,
+}
+
+/// Refreshes address book subscriptions and naming caches from a
+/// background thread, until shut down.
+///
+/// Each round updates the subscriber, then looks the names of each cache up
+/// again, through the bridge, so names in use don't expire. Names the
+/// bridge doesn't know anymore are removed from their cache. A round
+/// failing, as no feed could be fetched or a bridge couldn't be reached, is
+/// retried after the retry delay, doubled on each consecutive failure up to
+/// the interval. All delays are randomly shortened or lengthened by the
+/// jitter, so applications started together don't refresh in step.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use i2p::addressbook::{AddressBook, Refresher, Subscriber};
+/// use i2p::naming::NamingCache;
+/// use i2p::{SamConnection, Session};
+///
+/// let book = AddressBook::load("hosts.txt").unwrap();
+/// let session = Session::transient("127.0.0.1:7656").unwrap();
+/// let subscriber = Subscriber::new(&book, session)
+///     .subscribe("http://i2p-projekt.i2p/hosts.txt")
+///     .unwrap();
+/// let cache = NamingCache::new(Duration::from_secs(3600), 256);
+/// let sam = SamConnection::connect("127.0.0.1:7656").unwrap();
+/// let refresh = Refresher::new(Duration::from_secs(12 * 3600))
+///     .subscriber(subscriber)
+///     .naming_cache(cache.clone(), sam.duplicate().unwrap())
+///     .spawn();
+/// // ...
+/// refresh.shutdown();
+/// ```
+pub struct Refresher {
+	interval: Duration,
+	retry_delay: Duration,
+	jitter: f64,
+	subscriber: Option<Subscriber>,
+	caches: Vec<(NamingCache, SamConnection)>,
+}
+
+impl Refresher {
+	/// Refreshes every `interval`, failed rounds being retried after 30
+	/// seconds at first, with delays varying by 10%.
+	pub fn new(interval: Duration) -> Refresher {
+		Refresher {
+			interval,
+			retry_delay: DEFAULT_RETRY_DELAY,
+			jitter: DEFAULT_JITTER,
+			subscriber: None,
+			caches: vec![],
+		}
+	}
+
+	/// The delay before retrying after a first failure.
+	pub fn retry_delay(mut self, delay: Duration) -> Self {
+		self.retry_delay = delay;
+		self
+	}
+
+	/// The share of each delay randomly added or removed, from 0 to 1.
+	pub fn jitter(mut self, jitter: f64) -> Self {
+		self.jitter = jitter.clamp(0.0, 1.0);
+		self
+	}
+
+	/// Updates the subscriptions of `subscriber` on each round.
+	pub fn subscriber(mut self, subscriber: Subscriber) -> Self {
+		self.subscriber = Some(subscriber);
+		self
+	}
+
+	/// Looks the names of `cache` up again on each round, through `sam`.
+	pub fn naming_cache(mut self, cache: NamingCache, sam: SamConnection) -> Self {
+		self.caches.push((cache, sam));
+		self
+	}
+
+	/// Starts refreshing from a background thread, the first round right
+	/// away.
+	pub fn spawn(mut self) -> RefreshHandle {
+		let (stop, stopped) = mpsc::channel::<()>();
+		let thread = thread::spawn(move || {
+			let mut failures = 0;
+			loop {
+				match self.refresh() {
+					Ok(()) => failures = 0,
+					Err(e) => {
+						debug!("Couldn't refresh the address book: {}", e);
+						failures += 1;
+					}
+				}
+				if let Err(RecvTimeoutError::Disconnected) | Ok(()) = stopped.recv_timeout(self.delay(failures)) {
+					return;
+				}
+			}
+		});
+		RefreshHandle {
+			stop: Some(stop),
+			thread: Some(thread),
+		}
+	}
+
+	/// Runs a round, failing with the first error fatal to a part of it.
+	fn refresh(&mut self) -> Result<(), Error> {
+		let mut res = Ok(());
+		if let Some(ref mut subscriber) = self.subscriber {
+			match subscriber.update() {
+				Ok(added) => debug!("Added {} names to the address book", added),
+				Err(e) => res = Err(e),
+			}
+		}
+		for (cache, sam) in &mut self.caches {
+			if let Err(e) = refresh_cache(cache, sam) {
+				res = res.and(Err(e));
+			}
+		}
+		res
+	}
+
+	/// The delay before the next round, after `failures` consecutive failed
+	/// ones.
+	fn delay(&self, failures: u32) -> Duration {
+		let base = match failures {
+			0 => self.interval,
+			n => self
+				.retry_delay
+				.checked_mul(1 << (n - 1).min(16))
+				.map_or(self.interval, |delay| delay.min(self.interval)),
+		};
+		if self.jitter == 0.0 {
+			return base;
+		}
+		base.mul_f64(1.0 + rand::thread_rng().gen_range(-self.jitter, self.jitter))
+	}
+}
+
+fn refresh_cache(cache: &NamingCache, sam: &mut SamConnection) -> Result<(), Error> {
+	for name in cache.names() {
+		match sam.lookup(&name) {
+			Ok(reply) => cache.insert(&name, &reply.value),
+			Err(e) => match e.kind().lookup_failure() {
+				Some(LookupFailure::NotFound) | Some(LookupFailure::InvalidName) => cache.remove(&name),
+				_ => return Err(e),
+			},
+		}
+	}
+	Ok(())
+}
+
+/// Handle to a running `Refresher`, see `Refresher::spawn`. Refreshing stops
+/// when this is dropped, or waiting for the current round with `shutdown`.
+pub struct RefreshHandle {
+	stop: Option<mpsc::Sender<()>>,
+	thread: Option<JoinHandle<()>>,
+}
+
+impl RefreshHandle {
+	/// Stops refreshing, once the current round is over if one is running.
+	pub fn shutdown(mut self) {
+		self.stop.take();
+		if let Some(thread) = self.thread.take() {
+			let _ = thread.join();
+		}
+	}
+
+	/// Same as `shutdown`, without blocking the calling task.
+	#[cfg(feature = "async")]
+	pub async fn shutdown_async(self) {
+		crate::asynch::blocking(move || self.shutdown()).await
+	}
+}
+
+/// Resolves the hostnames the router doesn't know through jump services,
+/// queried over HTTP through streams of the provided session. A jump
+/// service redirects to the host with the destination in an
+/// `i2paddresshelper` parameter.
+///
+/// Meant to come last in a `ResolverChain`, after the bridge. Found names
+/// can be registered in an address book, so they're only looked up once.
+///
+/// # Examples
+///
+/// ```no_run
+/// use i2p::addressbook::{AddressBook, JumpResolver};
+/// use i2p::naming::{Resolver, ResolverChain, SamResolver};
+/// use i2p::{SamConnection, Session};
+///
+/// let book = AddressBook::new();
+/// let session = Session::transient("127.0.0.1:7656").unwrap();
+/// let resolver = ResolverChain::new()
+///     .then(book.clone())
+///     .then(SamResolver::new(SamConnection::connect("127.0.0.1:7656").unwrap()))
+///     .then(
+///         JumpResolver::new(session)
+///             .service("http://stats.i2p/cgi-bin/jump.cgi?a=")
+///             .register_in(&book),
+///     );
+/// let dest = resolver.resolve("example.i2p").unwrap();
+/// ```
+pub struct JumpResolver {
+	session: Mutex<Session>,
+	services: Vec<String>,
+	book: Option<AddressBook>,
+}
+
+impl JumpResolver {
+	/// Creates a resolver without any jump service yet.
+	pub fn new(session: Session) -> JumpResolver {
+		JumpResolver {
+			session: Mutex::new(session),
+			services: vec![],
+			book: None,
+		}
+	}
+
+	/// Adds a jump service, as the `http://` URL the hostname gets appended
+	/// to. Services are queried in the order they were added.
+	pub fn service(mut self, url: &str) -> JumpResolver {
+		self.services.push(url.to_string());
+		self
+	}
+
+	/// Adds the names found to `book`.
+	pub fn register_in(mut self, book: &AddressBook) -> JumpResolver {
+		self.book = Some(book.clone());
+		self
+	}
+
+	fn jump(&self, service: &str, name: &str) -> Result<Option<String>, Error> {
+		let session = self
+			.session
+			.lock()
+			.map_err(|_| ErrorKind::Io("Jump resolver lock poisoned".to_string()))?;
+		let response = http_get(&session, &format!("{}{}", service, name), &[])?;
+		let dest = response
+			.header("Location")
+			.and_then(address_helper)
+			.or_else(|| address_helper(&String::from_utf8_lossy(&response.body)));
+		Ok(dest.filter(|dest| Destination::from_base64(dest).is_ok()))
+	}
+}
+
+impl Resolver for JumpResolver {
+	fn resolve(&self, name: &str) -> Result<String, Error> {
+		let name = checked_hostname(name)
+			.map_err(|_| Error::from(ErrorKind::SAMKeyNotFound(name.to_string())))?;
+		for service in &self.services {
+			match self.jump(service, &name) {
+				Ok(Some(dest)) => {
+					if let Some(ref book) = self.book {
+						book.insert(&name, &dest)?;
+					}
+					return Ok(dest);
+				}
+				Ok(None) => debug!("{} doesn't know {}", service, name),
+				Err(e) => debug!("Couldn't query {} for {}: {}", service, name, e),
+			}
+		}
+		Err(ErrorKind::SAMKeyNotFound(name).into())
+	}
+}
+
+/// The destination in the `i2paddresshelper` parameter of a URL or page
+fn address_helper(text: &str) -> Option<String> {
+	const PARAM: &str = "i2paddresshelper=";
+	let start = text.find(PARAM)? + PARAM.len();
+	let value: String = text[start..]
+		.chars()
+		.take_while(|c| c.is_ascii_alphanumeric() || "-~=%".contains(*c))
+		.collect();
+	Some(value.replace("%3D", "=").replace("%3d", "="))
+}
+
+struct HttpResponse {
+	status: String,
+	headers: Vec<(String, String)>,
+	body: Vec<u8>,
+}
+
+impl HttpResponse {
+	fn header(&self, name: &str) -> Option<&str> {
+		self.headers
+			.iter()
+			.find(|(n, _)| n.eq_ignore_ascii_case(name))
+			.map(|(_, v)| v.as_str())
+	}
+}
+
+/// Fetches an `http://` URL through a stream of `session`.
+fn http_get(session: &Session, url: &str, headers: &[(&str, &str)]) -> Result<HttpResponse, Error> {
+	let (host, port, path) = parse_url(url)?;
+	let mut stream = I2pStream::connect_with_session(session, (host, port))?;
+	stream.set_read_timeout(Some(FETCH_TIMEOUT))?;
+
+	let mut request = format!("GET {} HTTP/1.0\r\nHost: {}\r\nConnection: close\r\n", path, host);
+	for (name, value) in headers {
+		request.push_str(&format!("{}: {}\r\n", name, value));
+	}
+	request.push_str("\r\n");
+	stream.write_all(request.as_bytes())?;
+
+	let mut response = vec![];
+	(&stream).take(MAX_FEED_LEN).read_to_end(&mut response)?;
+	let invalid = || ErrorKind::Io(format!("Invalid HTTP response from {}", url));
+	let head_len = response
+		.windows(4)
+		.position(|w| w == b"\r\n\r\n")
+		.ok_or_else(invalid)?;
+	let head = String::from_utf8_lossy(&response[..head_len]).into_owned();
+	let mut lines = head.lines();
+	let status = lines
+		.next()
+		.and_then(|l| l.split(' ').nth(1))
+		.ok_or_else(invalid)?
+		.to_string();
+	let headers = lines
+		.filter_map(|line| line.split_once(':'))
+		.map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+		.collect();
+	response.drain(..head_len + 4);
+	Ok(HttpResponse {
+		status,
+		headers,
+		body: response,
+	})
+}
+
+/// Host, port and path of an `http://` URL
+fn parse_url(url: &str) -> Result<(&str, u16, &str), Error> {
+	let invalid = || ErrorKind::BadAddressEncoding(url.to_string());
+	let rest = url.strip_prefix("http://").ok_or_else(invalid)?;
+	let (authority, path) = match rest.find('/') {
+		Some(i) => rest.split_at(i),
+		None => (rest, "/"),
+	};
+	let (host, port) = match authority.rsplit_once(':') {
+		Some((host, port)) => (host, port.parse().map_err(|_| invalid())?),
+		None => (authority, 80),
+	};
+	if host.is_empty() {
+		return Err(invalid().into());
+	}
+	Ok((host, port, path))
+}
+
+/// The lowercase form of a .i2p hostname, b32 addresses being rejected as
+/// they don't need an address book.
+fn checked_hostname(name: &str) -> Result<String, Error> {
+	let name = name.to_ascii_lowercase();
+	let addr: I2pAddr = name.parse()?;
+	if !name.ends_with(".i2p") || addr.to_hash().is_some() || addr.is_b33() {
+		return Err(ErrorKind::BadAddressEncoding(name).into());
+	}
+	Ok(name)
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::addressbook::{address_helper, parse_url, AddressBook, JumpResolver, Refresher, Subscriber};
+	use crate::naming::Resolver;
+	use crate::net::BASE64_I2P;
+	use crate::sam::tests::{fake_bridge, fake_http_bridge};
+	use crate::sam::{Session, SessionStyle};
+
+	fn dest(fill: u8) -> String {
+		let mut dest = vec![fill; 384];
+		dest.extend_from_slice(&[5, 0, 4, 0, 7, 0, 4]);
+		BASE64_I2P.encode(&dest)
+	}
+
+	#[test]
+	fn hosts_txt() {
+		let content = format!(
+			"# my hosts\n\nExample.i2p={}\nsigned.i2p={}#!date=1#sig=x\nbad.i2p=dest\nnoequals.i2p\n{}.b32.i2p={}\n",
+			dest(1),
+			dest(2),
+			"a".repeat(52),
+			dest(3)
+		);
+		let book = AddressBook::parse(&content);
+		assert_eq!(book.len(), 2);
+		assert_eq!(book.get("example.i2p"), Some(dest(1)));
+		assert_eq!(book.resolve("SIGNED.i2p").unwrap(), dest(2));
+		assert!(book.resolve("bad.i2p").is_err());
+
+		let shared = book.clone();
+		shared.insert("new.i2p", &dest(4)).unwrap();
+		assert!(shared.insert("new.i2p", "dest").is_err());
+		assert_eq!(book.get("new.i2p"), Some(dest(4)));
+		assert_eq!(book.lookup_reverse(&dest(2)), Some("signed.i2p".to_string()));
+		let b32 = crate::net::I2pAddr::from_b64(&dest(1)).unwrap().string();
+		assert_eq!(book.lookup_reverse(&b32), Some("example.i2p".to_string()));
+		assert_eq!(book.remove("signed.i2p"), Some(dest(2)));
+		assert_eq!(book.lookup_reverse(&dest(2)), None);
+
+		let mut written = vec![];
+		book.write_to(&mut written).unwrap();
+		let written = String::from_utf8(written).unwrap();
+		assert_eq!(written, format!("example.i2p={}\nnew.i2p={}\n", dest(1), dest(4)));
+		assert_eq!(AddressBook::from_reader(written.as_bytes()).unwrap().entries(), book.entries());
+	}
+
+	#[test]
+	fn urls() {
+		assert_eq!(parse_url("http://example.i2p/hosts.txt").unwrap(), ("example.i2p", 80, "/hosts.txt"));
+		assert_eq!(parse_url("http://example.i2p:8080").unwrap(), ("example.i2p", 8080, "/"));
+		assert!(parse_url("https://example.i2p/").is_err());
+		assert!(parse_url("http://:80/").is_err());
+	}
+
+	#[test]
+	fn subscription() {
+		let response = format!(
+			"HTTP/1.0 200 OK\r\nETag: \"v1\"\r\n\r\nknown.i2p={}\nnew.i2p={}\n",
+			dest(2),
+			dest(3)
+		);
+		let bridge = fake_http_bridge(Box::leak(response.into_bytes().into_boxed_slice()));
+		let session = Session::create(bridge, "TRANSIENT", "subscriber", SessionStyle::Stream).unwrap();
+
+		let book = AddressBook::new();
+		book.insert("known.i2p", &dest(1)).unwrap();
+		let mut subscriber = Subscriber::new(&book, session)
+			.subscribe(&format!("http://{}/hosts.txt", "A".repeat(516)))
+			.unwrap();
+		assert_eq!(subscriber.update().unwrap(), 1);
+		assert_eq!(book.get("known.i2p"), Some(dest(1)));
+		assert_eq!(book.get("new.i2p"), Some(dest(3)));
+		assert_eq!(subscriber.feeds[0].etag.as_deref(), Some("\"v1\""));
+	}
+
+	#[test]
+	fn refresher() {
+		use std::thread;
+		use std::time::{Duration, Instant};
+		use crate::naming::NamingCache;
+		use crate::sam::SamConnection;
+
+		let refresher = Refresher::new(Duration::from_secs(100)).retry_delay(Duration::from_secs(10));
+		assert_eq!(refresher.jitter(0.0).delay(0), Duration::from_secs(100));
+		let refresher = Refresher::new(Duration::from_secs(100)).retry_delay(Duration::from_secs(10));
+		for (failures, base) in [(1, 10.0), (2, 20.0), (4, 80.0), (5, 100.0), (60, 100.0)] {
+			let delay = refresher.delay(failures).as_secs_f64();
+			assert!(delay >= base * 0.9 && delay <= base * 1.1, "{} after {} failures", delay, failures);
+		}
+
+		let cache = NamingCache::new(Duration::from_secs(60), 8);
+		cache.insert("cached.i2p", "old");
+		let sam = SamConnection::connect(fake_bridge(b"")).unwrap();
+		let refresh = Refresher::new(Duration::from_secs(3600))
+			.naming_cache(cache.clone(), sam)
+			.spawn();
+		let start = Instant::now();
+		while cache.get("cached.i2p").as_deref() != Some("dest") {
+			assert!(start.elapsed() < Duration::from_secs(5), "cache not refreshed");
+			thread::sleep(Duration::from_millis(10));
+		}
+		// doesn't wait for the next round
+		refresh.shutdown();
+		assert!(start.elapsed() < Duration::from_secs(5));
+	}
+
+	#[test]
+	fn jump_service() {
+		assert_eq!(address_helper("http://a.i2p/?i2paddresshelper=ab~-%3D&x=1").as_deref(), Some("ab~-="));
+		assert_eq!(address_helper("nothing here"), None);
+
+		let response = format!(
+			"HTTP/1.0 301 Moved Permanently\r\nLocation: http://long.i2p/?i2paddresshelper={}\r\n\r\n",
+			dest(5)
+		);
+		let bridge = fake_http_bridge(Box::leak(response.into_bytes().into_boxed_slice()));
+		let session = Session::create(bridge, "TRANSIENT", "jumper", SessionStyle::Stream).unwrap();
+		let book = AddressBook::new();
+		let jump = JumpResolver::new(session)
+			.service("http://jump.i2p/jump?a=")
+			.register_in(&book);
+		assert_eq!(jump.resolve("long.i2p").unwrap(), dest(5));
+		assert_eq!(book.get("long.i2p"), Some(dest(5)));
+		assert!(jump.resolve(&format!("{}.b32.i2p", "a".repeat(52))).is_err());
+	}
+}
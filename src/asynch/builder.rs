@@ -0,0 +1,258 @@
+use std::fmt;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::asynch::{blocking, Session};
+use crate::error::{Error, ErrorKind};
+use crate::sam::{self, SessionStyle};
+use crate::sam_options::SessionOptions;
+use crate::transport::SamStream;
+
+/// Default time given to the TCP connection to the bridge
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Default time given to the bridge to answer HELLO
+const DEFAULT_HELLO_TIMEOUT: Duration = Duration::from_secs(10);
+/// Default time given to the router to build the tunnels of a new session
+const DEFAULT_CREATE_TIMEOUT: Duration = Duration::from_secs(180);
+
+/// The phases of creating a session, see `SessionBuilder::on_progress`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SessionProgress {
+	/// Connecting to the bridge
+	Connecting,
+	/// Connected, waiting for the reply to HELLO
+	Handshaking,
+	/// The bridge answered, waiting for the router to build the tunnels of
+	/// the session
+	Creating,
+	/// The session is ready
+	Created,
+}
+
+/// Creates an asynchronous session, each phase having its own timeout: a
+/// bridge that can't be reached or doesn't answer fails in seconds, while
+/// the router is given minutes to build the tunnels of the session.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use i2p::asynch::{SessionBuilder, SessionProgress};
+///
+/// async fn start() {
+///     let session = SessionBuilder::new()
+///         .connect_timeout(Some(Duration::from_secs(2)))
+///         .create_timeout(Some(Duration::from_secs(60)))
+///         .on_progress(|progress| {
+///             if progress == SessionProgress::Creating {
+///                 println!("building tunnels...");
+///             }
+///         })
+///         .build("127.0.0.1:7656")
+///         .await;
+/// }
+/// ```
+#[derive(Clone)]
+pub struct SessionBuilder {
+	destination: String,
+	nickname: Option<String>,
+	style: SessionStyle,
+	options: SessionOptions,
+	connect_timeout: Option<Duration>,
+	hello_timeout: Option<Duration>,
+	create_timeout: Option<Duration>,
+	on_progress: Option<Arc<dyn Fn(SessionProgress) + Send + Sync>>,
+}
+
+impl Default for SessionBuilder {
+	fn default() -> SessionBuilder {
+		SessionBuilder {
+			destination: "TRANSIENT".to_string(),
+			nickname: None,
+			style: SessionStyle::Stream,
+			options: SessionOptions::default(),
+			connect_timeout: Some(DEFAULT_CONNECT_TIMEOUT),
+			hello_timeout: Some(DEFAULT_HELLO_TIMEOUT),
+			create_timeout: Some(DEFAULT_CREATE_TIMEOUT),
+			on_progress: None,
+		}
+	}
+}
+
+impl fmt::Debug for SessionBuilder {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("SessionBuilder")
+			.field("nickname", &self.nickname)
+			.field("style", &self.style)
+			.field("connect_timeout", &self.connect_timeout)
+			.field("hello_timeout", &self.hello_timeout)
+			.field("create_timeout", &self.create_timeout)
+			.finish()
+	}
+}
+
+impl SessionBuilder {
+	/// A transient stream session with an auto-generated nickname, given 10
+	/// seconds to connect, 10 seconds to get the reply to HELLO and 3 minutes
+	/// to build its tunnels.
+	pub fn new() -> SessionBuilder {
+		SessionBuilder::default()
+	}
+
+	/// The private keys of the destination, `TRANSIENT` for a new one.
+	pub fn destination(mut self, destination: &str) -> Self {
+		self.destination = destination.to_string();
+		self
+	}
+
+	/// The nickname of the session, auto-generated otherwise.
+	pub fn nickname(mut self, nickname: &str) -> Self {
+		self.nickname = Some(nickname.to_string());
+		self
+	}
+
+	/// The style of the session, streams by default.
+	pub fn style(mut self, style: SessionStyle) -> Self {
+		self.style = style;
+		self
+	}
+
+	/// The I2CP and streaming options passed to the router.
+	pub fn options(mut self, options: SessionOptions) -> Self {
+		self.options = options;
+		self
+	}
+
+	/// How long connecting to the bridge may take, `None` waiting forever.
+	pub fn connect_timeout(mut self, timeout: Option<Duration>) -> Self {
+		self.connect_timeout = timeout;
+		self
+	}
+
+	/// How long the bridge may take to answer HELLO, `None` waiting forever.
+	pub fn hello_timeout(mut self, timeout: Option<Duration>) -> Self {
+		self.hello_timeout = timeout;
+		self
+	}
+
+	/// How long creating the session may take, tunnels included, `None`
+	/// waiting forever. A timeout fails with an error of kind
+	/// `ErrorKind::SAMTimeout`, whose `connect_failure` is
+	/// `TunnelsNotReady`.
+	pub fn create_timeout(mut self, timeout: Option<Duration>) -> Self {
+		self.create_timeout = timeout;
+		self
+	}
+
+	/// Calls `f` as each phase of the creation starts, from the blocking
+	/// task creating the session.
+	pub fn on_progress<F>(mut self, f: F) -> Self
+	where
+		F: Fn(SessionProgress) + Send + Sync + 'static,
+	{
+		self.on_progress = Some(Arc::new(f));
+		self
+	}
+
+	/// Creates the session on the bridge at `sam_addr`.
+	pub async fn build<A: ToSocketAddrs>(self, sam_addr: A) -> Result<Session, Error> {
+		let addrs: Vec<SocketAddr> = sam_addr.to_socket_addrs()?.collect();
+		let session = blocking(move || self.create(&addrs)).await?;
+		Ok(Session::from(session))
+	}
+
+	fn create(&self, addrs: &[SocketAddr]) -> Result<sam::Session, Error> {
+		self.progress(SessionProgress::Connecting);
+		let stream = match self.connect_timeout {
+			Some(timeout) => SamStream::connect_tcp_timeout(addrs, timeout),
+			None => SamStream::connect_tcp(addrs),
+		}
+		.map_err(|e| match e.kind() {
+			io::ErrorKind::TimedOut => {
+				ErrorKind::SAMTimeout("Couldn't connect to the SAM bridge in time".to_string()).into()
+			}
+			_ => Error::from(e),
+		})?;
+
+		self.progress(SessionProgress::Handshaking);
+		let mut sam = sam::SamConnection::hello_on(stream, self.hello_timeout)
+			.map_err(|e| timed_out(e, "The SAM bridge didn't answer HELLO in time"))?;
+
+		self.progress(SessionProgress::Creating);
+		sam.set_command_timeout(self.create_timeout);
+		let nickname = self.nickname.clone().unwrap_or_else(sam::nickname);
+		let mut session = sam::Session::with_connection(sam, &self.destination, &nickname, self.style, &self.options)
+			.map_err(|e| timed_out(e, "The tunnels of the session weren't built in time"))?;
+		session.set_command_timeout(None);
+
+		self.progress(SessionProgress::Created);
+		Ok(session)
+	}
+
+	fn progress(&self, progress: SessionProgress) {
+		if let Some(ref on_progress) = self.on_progress {
+			on_progress(progress);
+		}
+	}
+}
+
+/// Tells which phase timed out.
+fn timed_out(e: Error, msg: &str) -> Error {
+	match e.kind() {
+		ErrorKind::SAMTimeout(_) => ErrorKind::SAMTimeout(msg.to_string()).into(),
+		_ => e,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::{Arc, Mutex};
+	use std::time::Duration;
+
+	use crate::asynch::tests::block_on;
+	use crate::asynch::{SessionBuilder, SessionProgress};
+	use crate::error::{ConnectFailure, ErrorKind};
+	use crate::sam::tests::{fake_bridge, reply, scripted_bridge, Step};
+
+	#[test]
+	fn phases() {
+		let seen = Arc::new(Mutex::new(vec![]));
+		let progress = seen.clone();
+		let session = block_on(
+			SessionBuilder::new()
+				.nickname("built")
+				.on_progress(move |p| progress.lock().unwrap().push(p))
+				.build(fake_bridge(b"")),
+		)
+		.unwrap();
+		assert_eq!((session.id(), session.my_destination()), ("built".to_string(), "dest".to_string()));
+		assert_eq!(
+			*seen.lock().unwrap(),
+			[
+				SessionProgress::Connecting,
+				SessionProgress::Handshaking,
+				SessionProgress::Creating,
+				SessionProgress::Created,
+			]
+		);
+	}
+
+	#[test]
+	fn create_timeout() {
+		// answers HELLO, but never SESSION CREATE
+		let (addr, _played) =
+			scripted_bridge(vec![vec![reply("HELLO REPLY RESULT=OK VERSION=3.2\n"), Step::Read(1)]]);
+
+		let err = block_on(
+			SessionBuilder::new()
+				.create_timeout(Some(Duration::from_millis(100)))
+				.build(addr),
+		)
+		.err()
+		.expect("timed out");
+		assert!(matches!(err.kind(), ErrorKind::SAMTimeout(_)));
+		assert_eq!(err.kind().connect_failure(), Some(ConnectFailure::TunnelsNotReady));
+	}
+}
@@ -0,0 +1,77 @@
+use crate::asynch::{first_addr, I2pStream, Session};
+use crate::error::Error;
+use crate::net::ToI2pSocketAddrs;
+
+/// Dials I2P socket addresses through a session, resolving their names
+/// with `Session::lookup`, so a client reaching many peers at once sends its
+/// lookups in batches.
+///
+/// No `tower::Service` is implemented by the crate, which doesn't depend on
+/// tower. A `tower::Service<I2pSocketAddr>` is written by the application
+/// over `dial`, so that timeouts, retries or load shedding compose with I2P
+/// dialing like they do with TCP:
+///
+/// ```ignore
+/// impl tower::Service<I2pSocketAddr> for Dialer {
+///     type Response = i2p::asynch::I2pStream;
+///     type Error = i2p::Error;
+///     type Future = Pin<Box<dyn Future<Output = Result<Self::Response, i2p::Error>> + Send>>;
+///
+///     fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), i2p::Error>> {
+///         Poll::Ready(Ok(()))
+///     }
+///
+///     fn call(&mut self, addr: I2pSocketAddr) -> Self::Future {
+///         let connector = self.0.clone();
+///         Box::pin(async move { connector.dial(addr).await })
+///     }
+/// }
+///
+/// let dialer = tower::ServiceBuilder::new()
+///     .timeout(Duration::from_secs(60))
+///     .service(Dialer(connector));
+/// ```
+#[derive(Clone)]
+pub struct Connector {
+	session: Session,
+}
+
+impl Connector {
+	/// A connector opening its streams on `session`.
+	pub fn new(session: Session) -> Connector {
+		Connector { session }
+	}
+
+	/// The session the streams are opened on.
+	pub fn session(&self) -> &Session {
+		&self.session
+	}
+
+	/// Opens a stream to an I2P socket address, its name being resolved with
+	/// `Session::lookup`.
+	pub async fn dial<A: ToI2pSocketAddrs>(&self, addr: A) -> Result<I2pStream, Error> {
+		let addr = first_addr(addr)?;
+		let dest = self.session.lookup(&addr.dest().to_string()).await?;
+		I2pStream::connect_with_session(&self.session, (dest, addr.port())).await
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::asynch::tests::block_on;
+	use crate::asynch::{Connector, Session};
+	use crate::sam::tests::fake_bridge;
+
+	#[test]
+	fn dial() {
+		let addr = fake_bridge(b"HTTP/1.0 200 OK\r\n");
+		block_on(async {
+			let connector = Connector::new(Session::transient(addr).await.unwrap());
+			let mut stream = connector.dial("example.i2p:8080").await.unwrap();
+			assert_eq!(stream.peer_addr().unwrap().port(), 8080);
+			let mut status = [0; 17];
+			stream.read_exact(&mut status).await.unwrap();
+			assert_eq!(&status, b"HTTP/1.0 200 OK\r\n");
+		});
+	}
+}
@@ -0,0 +1,262 @@
+use std::future;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex};
+use std::task::{Context, Poll, Waker};
+
+use crate::asynch::{blocking, first_addr, lock, spawn, Session};
+use crate::error::Error;
+use crate::net::{self, I2pSocketAddr, ToI2pSocketAddrs};
+
+/// Largest datagram payload I2P carries.
+const MAX_DATAGRAM_SIZE: usize = 65536;
+
+/// A received datagram and its sender
+pub type Datagram = (Vec<u8>, I2pSocketAddr);
+
+/// Asynchronous counterpart of `net::I2pDatagramSocket`.
+///
+/// Once a receive is polled, a blocking task receives a datagram ahead of
+/// the application, so dropping a receive future loses no datagram. The
+/// session of the socket is closed once it's dropped.
+pub struct I2pDatagramSocket {
+	inner: Arc<net::I2pDatagramSocket>,
+	shared: Arc<Shared>,
+}
+
+struct Shared {
+	state: Mutex<State>,
+	consumed: Condvar,
+}
+
+#[derive(Default)]
+struct State {
+	datagram: Option<Result<Datagram, Error>>,
+	receiving: bool,
+	waker: Option<Waker>,
+	closed: bool,
+}
+
+impl I2pDatagramSocket {
+	/// Creates a datagram socket on a new transient destination of the
+	/// default SAM bridge, see `net::I2pDatagramSocket::bind`.
+	pub async fn bind<A: ToI2pSocketAddrs>(addr: A) -> Result<I2pDatagramSocket, Error> {
+		let addr = first_addr(addr)?;
+		let socket = blocking(move || net::I2pDatagramSocket::bind(addr)).await?;
+		Ok(I2pDatagramSocket::from_std(socket))
+	}
+
+	/// Same as `bind`, through the provided bridge.
+	pub async fn bind_via<A: ToSocketAddrs, B: ToI2pSocketAddrs>(
+		sam_addr: A,
+		addr: B,
+	) -> Result<I2pDatagramSocket, Error> {
+		let sam_addrs: Vec<SocketAddr> = sam_addr.to_socket_addrs()?.collect();
+		let addr = first_addr(addr)?;
+		let socket = blocking(move || net::I2pDatagramSocket::bind_via(&sam_addrs[..], addr)).await?;
+		Ok(I2pDatagramSocket::from_std(socket))
+	}
+
+	/// Creates a datagram socket on an existing session, which must have been
+	/// created with a datagram style.
+	pub async fn bind_with_session(session: &Session) -> Result<I2pDatagramSocket, Error> {
+		let socket = session.with(net::I2pDatagramSocket::bind_with_session).await?;
+		Ok(I2pDatagramSocket::from_std(socket))
+	}
+
+	/// Wraps a blocking datagram socket, which shouldn't be received from
+	/// through its clones anymore.
+	pub fn from_std(socket: net::I2pDatagramSocket) -> I2pDatagramSocket {
+		I2pDatagramSocket {
+			inner: Arc::new(socket),
+			shared: Arc::new(Shared {
+				state: Mutex::new(State::default()),
+				consumed: Condvar::new(),
+			}),
+		}
+	}
+
+	/// The b32 address of the destination of the socket, along with the port
+	/// it was bound to.
+	pub fn local_addr(&self) -> Result<I2pSocketAddr, Error> {
+		self.inner.local_addr()
+	}
+
+	/// Sends a datagram to the given address, resolving its name if needed.
+	pub async fn send_to<A: ToI2pSocketAddrs>(&self, buf: &[u8], addr: A) -> Result<usize, Error> {
+		let (inner, buf, addr) = (self.inner.clone(), buf.to_vec(), first_addr(addr)?);
+		blocking(move || inner.send_to(&buf, addr)).await
+	}
+
+	/// Only receives datagrams from the given address from now on, which
+	/// `send` sends to, see `net::I2pDatagramSocket::connect`.
+	pub async fn connect<A: ToI2pSocketAddrs>(&self, addr: A) -> Result<(), Error> {
+		let (inner, addr) = (self.inner.clone(), first_addr(addr)?);
+		blocking(move || inner.connect(addr)).await
+	}
+
+	/// Sends a datagram to the connected peer.
+	pub async fn send(&self, buf: &[u8]) -> Result<usize, Error> {
+		let (inner, buf) = (self.inner.clone(), buf.to_vec());
+		blocking(move || inner.send(&buf)).await
+	}
+
+	/// Receives a datagram into `buf`, returning its size and sender. The
+	/// excess bytes of datagrams too large for `buf` are discarded.
+	pub async fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, I2pSocketAddr), Error> {
+		future::poll_fn(|cx| self.poll_recv_from(cx, buf)).await
+	}
+
+	/// Attempts to receive a datagram into `buf`, registering the task to be
+	/// woken once one arrived otherwise.
+	pub fn poll_recv_from(&self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<Result<(usize, I2pSocketAddr), Error>> {
+		self.poll_datagram(cx).map_ok(|(datagram, addr)| {
+			let n = buf.len().min(datagram.len());
+			buf[..n].copy_from_slice(&datagram[..n]);
+			(n, addr)
+		})
+	}
+
+	/// A stream of the received datagrams, along with their senders, which
+	/// only ends with the socket.
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// use i2p::asynch::I2pDatagramSocket;
+	///
+	/// async fn echo(socket: I2pDatagramSocket) {
+	///     let mut datagrams = socket.datagrams();
+	///     while let Some(Ok((datagram, from))) = datagrams.next().await {
+	///         let _ = socket.send_to(&datagram, from).await;
+	///     }
+	/// }
+	/// ```
+	pub fn datagrams(&self) -> Datagrams<'_> {
+		Datagrams { socket: self }
+	}
+
+	fn poll_datagram(&self, cx: &mut Context<'_>) -> Poll<Result<Datagram, Error>> {
+		let mut state = lock(&self.shared.state);
+		match state.datagram.take() {
+			Some(res) => {
+				self.shared.consumed.notify_all();
+				Poll::Ready(res)
+			}
+			None => {
+				state.waker = Some(cx.waker().clone());
+				if !state.receiving {
+					state.receiving = true;
+					let (inner, shared) = (self.inner.clone(), self.shared.clone());
+					spawn(move || receive_ahead(&inner, &shared));
+				}
+				Poll::Pending
+			}
+		}
+	}
+}
+
+impl Drop for I2pDatagramSocket {
+	fn drop(&mut self) {
+		lock(&self.shared.state).closed = true;
+		self.shared.consumed.notify_all();
+		// ends a blocked receive
+		let _ = self.inner.shutdown();
+	}
+}
+
+/// Receives a datagram ahead of the application until a receive fails or
+/// the socket is dropped.
+fn receive_ahead(socket: &net::I2pDatagramSocket, shared: &Shared) {
+	loop {
+		let mut buf = vec![0; MAX_DATAGRAM_SIZE];
+		let res = socket.recv_from(&mut buf).map(|(n, addr)| {
+			buf.truncate(n);
+			(buf, addr)
+		});
+		let failed = res.is_err();
+		let mut state = lock(&shared.state);
+		if state.closed {
+			return;
+		}
+		state.datagram = Some(res);
+		if let Some(waker) = state.waker.take() {
+			waker.wake();
+		}
+		if failed {
+			// the next receive tries again
+			state.receiving = false;
+			return;
+		}
+		while state.datagram.is_some() && !state.closed {
+			state = shared.consumed.wait(state).unwrap_or_else(|e| e.into_inner());
+		}
+		if state.closed {
+			return;
+		}
+	}
+}
+
+/// The datagrams received by an `I2pDatagramSocket`, see
+/// `I2pDatagramSocket::datagrams`.
+///
+/// `poll_next` has the signature of `futures::Stream::poll_next`, so the
+/// trait can be implemented over it by a newtype.
+pub struct Datagrams<'a> {
+	socket: &'a I2pDatagramSocket,
+}
+
+impl<'a> Datagrams<'a> {
+	/// Waits for the next datagram.
+	pub async fn next(&mut self) -> Option<Result<Datagram, Error>> {
+		future::poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await
+	}
+
+	/// Attempts to get the next datagram, registering the task to be woken
+	/// once one arrived otherwise.
+	pub fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Datagram, Error>>> {
+		self.socket.poll_datagram(cx).map(Some)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::Read;
+
+	use crate::asynch::tests::block_on;
+	use crate::asynch::I2pDatagramSocket;
+	use crate::sam::tests::{scripted_bridge, session_script, Step};
+
+	#[test]
+	fn datagrams() {
+		let peer = "A".repeat(516);
+		let mut script = session_script("3.2");
+		for payload in &["ping", "pong"] {
+			let received = format!(
+				"DATAGRAM RECEIVED DESTINATION={} SIZE=4 FROM_PORT=9 TO_PORT=0\n{}",
+				peer, payload
+			);
+			script.push(Step::Write(received.into_bytes()));
+		}
+		script.push(Step::Read(1));
+		let (addr, played) = scripted_bridge(vec![script]);
+
+		block_on(async {
+			let socket = I2pDatagramSocket::bind_via(addr, "example.i2p:7").await.unwrap();
+			let mut buf = [0; 8];
+			let (n, from) = socket.recv_from(&mut buf).await.unwrap();
+			assert_eq!((&buf[..n], from.port()), (&b"ping"[..], 9));
+			let (datagram, from) = socket.datagrams().next().await.unwrap().unwrap();
+			assert_eq!(datagram, b"pong");
+			assert_eq!(socket.send_to(b"back", from).await.unwrap(), 4);
+		});
+
+		let mut control = played.recv().unwrap();
+		assert_eq!(control.lines[3], format!("DATAGRAM SEND DESTINATION={} SIZE=4 TO_PORT=9\n", peer));
+		let mut payload = [0; 4];
+		control.reader.read_exact(&mut payload).unwrap();
+		assert_eq!(&payload, b"back");
+		// the socket closes the session once dropped
+		assert_eq!(control.reader.read(&mut [0; 1]).unwrap(), 0);
+	}
+}
@@ -0,0 +1,446 @@
+//! Asynchronous SAM connections, sessions, streams, listeners and datagram
+//! sockets, behind the `async` feature.
+//!
+//! The types mirror their blocking counterparts, with futures in place of
+//! the calls waiting on the bridge or the network. The SAM protocol itself
+//! is left to the blocking layer, and the async types don't depend on any
+//! runtime: blocking work is handed over to a `Spawn`, a capped pool of
+//! helper threads by default, which wakes the polling task once done. They can be awaited from
+//! tokio, async-std, smol or any other executor, without wrapping every call
+//! in `spawn_blocking`.
+//!
+//! This isn't an integration with tokio or any other runtime: nothing here
+//! depends on one, and the I/O isn't driven by a reactor, each pending call
+//! occupying a thread of the `Spawn` until it completes. Traits of the async
+//! ecosystem, such as tokio's `AsyncRead` and `AsyncWrite` or
+//! `futures::Stream`, aren't implemented either; the methods with their
+//! names and signatures are inherent ones, left for adapters to forward to.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use i2p::asynch::{I2pStream, Session};
+//! use i2p::Error;
+//!
+//! async fn fetch() -> Result<Vec<u8>, Error> {
+//!     let session = Session::transient("127.0.0.1:7656").await?;
+//!     let mut stream = I2pStream::connect_with_session(&session, "example.i2p:80").await?;
+//!     stream.write_all(b"GET / HTTP/1.0\r\n\r\n").await?;
+//!     let mut page = vec![];
+//!     stream.read_to_end(&mut page).await?;
+//!     Ok(page)
+//! }
+//! ```
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, RwLock};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+use std::time::Duration;
+
+use lazy_static::lazy_static;
+
+use crate::error::{Error, ErrorKind};
+use crate::net::{I2pSocketAddr, ToI2pSocketAddrs};
+
+pub use self::builder::{SessionBuilder, SessionProgress};
+pub use self::connector::Connector;
+pub use self::datagram::{Datagram, Datagrams, I2pDatagramSocket};
+pub use self::pool::{PooledStream, StreamPool};
+pub use self::sam::{Lookup, SamConnection, Session};
+pub use self::streaming::{I2pListener, I2pStream, Incoming};
+
+mod builder;
+mod connector;
+mod datagram;
+mod pool;
+mod sam;
+mod streaming;
+
+lazy_static! {
+	static ref SPAWNER: RwLock<Arc<dyn Spawn>> = RwLock::new(Arc::new(ThreadPool::default()));
+}
+
+/// The number of threads of the default `ThreadPool`.
+pub const DEFAULT_MAX_THREADS: usize = 512;
+
+/// How long the threads of a `ThreadPool` wait for a new task before
+/// exiting.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Runs the blocking work of the async types, so it can be moved to the
+/// blocking thread pool of a runtime. Some tasks, like the reads and writes
+/// of streams, last as long as what they belong to.
+///
+/// The sockets being blocking ones, every call pending on the bridge or the
+/// network holds a thread until it completes: each connect, lookup or
+/// accept waiting for its reply, and for as long as they're open, two per
+/// stream and one per datagram socket. A spawner running a limited number
+/// of tasks at once, like the default `ThreadPool` or the blocking pool of a
+/// runtime, must allow for more than the streams, sockets and pending calls
+/// the application has at any time, or calls wait for streams to close.
+///
+/// Only where the blocking work runs is pluggable: the sockets stay the
+/// blocking ones of the crate, rather than those of the runtime driving
+/// `protocol::SamProtocol`. No adapter ships with the crate, which depends
+/// on no runtime.
+///
+/// # Examples
+///
+/// Adapters are one-liners for the application to write, for example for
+/// tokio:
+///
+/// ```ignore
+/// struct Tokio(tokio::runtime::Handle);
+///
+/// impl i2p::asynch::Spawn for Tokio {
+///     fn spawn_blocking(&self, task: Box<dyn FnOnce() + Send>) {
+///         self.0.spawn_blocking(task);
+///     }
+/// }
+///
+/// i2p::asynch::set_spawner(Tokio(tokio::runtime::Handle::current()));
+/// ```
+///
+/// or for async-std and smol, with `async_std::task::spawn_blocking(task)`
+/// and `blocking::unblock(task).detach()`.
+pub trait Spawn: Send + Sync {
+	/// Runs `task` somewhere it may block, without waiting for it.
+	fn spawn_blocking(&self, task: Box<dyn FnOnce() + Send>);
+}
+
+/// Runs each blocking task on a new thread, without any limit.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ThreadSpawn;
+
+impl Spawn for ThreadSpawn {
+	fn spawn_blocking(&self, task: Box<dyn FnOnce() + Send>) {
+		thread::spawn(task);
+	}
+}
+
+type Task = Box<dyn FnOnce() + Send>;
+
+/// Runs blocking tasks on at most `max_threads` threads, which are reused
+/// and exit once idle for a while, the default `Spawn` with
+/// `DEFAULT_MAX_THREADS`. Tasks spawned while all the threads are busy are
+/// queued until one is done, see `Spawn` for how many threads the async
+/// types need.
+///
+/// # Examples
+///
+/// ```
+/// use i2p::asynch::{set_spawner, ThreadPool};
+///
+/// // an application with up to a thousand streams open at once
+/// set_spawner(ThreadPool::new(2048));
+/// ```
+#[derive(Clone)]
+pub struct ThreadPool {
+	shared: Arc<PoolShared>,
+}
+
+struct PoolShared {
+	max_threads: usize,
+	queue: Mutex<PoolQueue>,
+	available: Condvar,
+}
+
+struct PoolQueue {
+	tasks: VecDeque<Task>,
+	threads: usize,
+	idle: usize,
+}
+
+impl ThreadPool {
+	/// A pool of at most `max_threads` threads, at least one.
+	pub fn new(max_threads: usize) -> ThreadPool {
+		ThreadPool {
+			shared: Arc::new(PoolShared {
+				max_threads: max_threads.max(1),
+				queue: Mutex::new(PoolQueue {
+					tasks: VecDeque::new(),
+					threads: 0,
+					idle: 0,
+				}),
+				available: Condvar::new(),
+			}),
+		}
+	}
+
+	/// The number of threads the pool runs tasks on at most.
+	pub fn max_threads(&self) -> usize {
+		self.shared.max_threads
+	}
+
+	/// The number of threads the pool currently has, running tasks or idle.
+	pub fn threads(&self) -> usize {
+		lock(&self.shared.queue).threads
+	}
+}
+
+impl Default for ThreadPool {
+	fn default() -> ThreadPool {
+		ThreadPool::new(DEFAULT_MAX_THREADS)
+	}
+}
+
+impl fmt::Debug for ThreadPool {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("ThreadPool")
+			.field("max_threads", &self.shared.max_threads)
+			.field("threads", &self.threads())
+			.finish()
+	}
+}
+
+impl Spawn for ThreadPool {
+	fn spawn_blocking(&self, task: Box<dyn FnOnce() + Send>) {
+		let mut queue = lock(&self.shared.queue);
+		queue.tasks.push_back(task);
+		if queue.tasks.len() > queue.idle && queue.threads < self.shared.max_threads {
+			queue.threads += 1;
+			let shared = self.shared.clone();
+			thread::spawn(move || pool_worker(&shared));
+		} else {
+			self.shared.available.notify_one();
+		}
+	}
+}
+
+/// Runs the tasks of a pool until none comes for `IDLE_TIMEOUT`.
+fn pool_worker(shared: &PoolShared) {
+	loop {
+		let task = {
+			let mut queue = lock(&shared.queue);
+			loop {
+				if let Some(task) = queue.tasks.pop_front() {
+					break task;
+				}
+				queue.idle += 1;
+				let (guard, waited) = shared
+					.available
+					.wait_timeout(queue, IDLE_TIMEOUT)
+					.unwrap_or_else(|e| e.into_inner());
+				queue = guard;
+				queue.idle -= 1;
+				if waited.timed_out() && queue.tasks.is_empty() {
+					queue.threads -= 1;
+					return;
+				}
+			}
+		};
+		// the tasks of the async types catch their own panics, others
+		// mustn't take the thread down with them
+		let _ = panic::catch_unwind(AssertUnwindSafe(task));
+	}
+}
+
+/// Sets where the blocking work of the async types runs from now on.
+pub fn set_spawner<S: Spawn + 'static>(spawner: S) {
+	*SPAWNER.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(spawner);
+}
+
+/// Hands a long-running task over to the spawner.
+pub(crate) fn spawn<F: FnOnce() + Send + 'static>(task: F) {
+	let spawner = SPAWNER.read().unwrap_or_else(|e| e.into_inner()).clone();
+	spawner.spawn_blocking(Box::new(task));
+}
+
+/// Runs `f` through the spawner, the returned future resolving to its
+/// result. Panics of `f`, and tasks dropped by the spawner without running
+/// them, are resumed as panics by the future.
+pub(crate) fn blocking<T, F>(f: F) -> Blocking<T>
+where
+	F: FnOnce() -> T + Send + 'static,
+	T: Send + 'static,
+{
+	let (done, future) = pending();
+	spawn(move || {
+		let value = panic::catch_unwind(AssertUnwindSafe(f));
+		done.complete(value);
+	});
+	future
+}
+
+/// A future completed through the returned `Done`, by a blocking task.
+pub(crate) fn pending<T>() -> (Done<T>, Blocking<T>) {
+	let slot = Arc::new(Mutex::new(Slot { value: None, waker: None }));
+	(Done(slot.clone()), Blocking { slot })
+}
+
+/// Future of a call handed over to the spawner, see `blocking`.
+pub(crate) struct Blocking<T> {
+	slot: Arc<Mutex<Slot<T>>>,
+}
+
+struct Slot<T> {
+	value: Option<thread::Result<T>>,
+	waker: Option<Waker>,
+}
+
+/// Completes a `Blocking`, even when the task is dropped without running.
+pub(crate) struct Done<T>(Arc<Mutex<Slot<T>>>);
+
+impl<T> Done<T> {
+	pub(crate) fn complete(&self, value: thread::Result<T>) {
+		let mut slot = lock(&self.0);
+		slot.value = Some(value);
+		if let Some(waker) = slot.waker.take() {
+			waker.wake();
+		}
+	}
+}
+
+impl<T> Drop for Done<T> {
+	fn drop(&mut self) {
+		if lock(&self.0).value.is_none() {
+			self.complete(Err(Box::new("blocking task dropped before completing")));
+		}
+	}
+}
+
+impl<T> Blocking<T> {
+	/// The result of the call if it's already there, for futures giving it
+	/// back somewhere once dropped.
+	pub(crate) fn take(&self) -> Option<T> {
+		lock(&self.slot).value.take().and_then(Result::ok)
+	}
+}
+
+impl<T> Future for Blocking<T> {
+	type Output = T;
+
+	fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+		let mut slot = lock(&self.slot);
+		match slot.value.take() {
+			Some(Ok(value)) => Poll::Ready(value),
+			Some(Err(panic)) => panic::resume_unwind(panic),
+			None => {
+				slot.waker = Some(cx.waker().clone());
+				Poll::Pending
+			}
+		}
+	}
+}
+
+/// The address to connect or send to, resolved before moving it to a
+/// blocking task.
+pub(crate) fn first_addr<A: ToI2pSocketAddrs>(addr: A) -> Result<I2pSocketAddr, Error> {
+	addr.to_socket_addrs()?
+		.next()
+		.ok_or_else(|| ErrorKind::UnresolvableAddress.into())
+}
+
+/// Locks a mutex shared with helper threads, which don't leave the
+/// protected state inconsistent when panicking.
+pub(crate) fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+	mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+	use std::future::Future;
+	use std::sync::{Arc, Mutex};
+	use std::task::{Context, Poll, Wake, Waker};
+	use std::thread;
+
+	use crate::asynch::{blocking, Blocking, Done, Slot};
+
+	/// Polls `future` to completion on the current thread, parking it while
+	/// the future is pending.
+	pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+		struct Unpark(thread::Thread);
+		impl Wake for Unpark {
+			fn wake(self: Arc<Self>) {
+				self.0.unpark()
+			}
+		}
+
+		let waker = Waker::from(Arc::new(Unpark(thread::current())));
+		let mut cx = Context::from_waker(&waker);
+		let mut future = Box::pin(future);
+		loop {
+			if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+				return output;
+			}
+			thread::park();
+		}
+	}
+
+	#[test]
+	fn blocking_calls() {
+		assert_eq!(block_on(blocking(|| 6 * 7)), 42);
+		let panicked = thread::spawn(|| block_on(blocking(|| panic!("in the helper thread")))).join();
+		assert!(panicked.is_err());
+	}
+
+	#[test]
+	fn spawners() {
+		use std::sync::atomic::{AtomicUsize, Ordering};
+
+		use crate::asynch::{set_spawner, Spawn, ThreadPool, ThreadSpawn};
+
+		static SPAWNED: AtomicUsize = AtomicUsize::new(0);
+		struct Counting;
+		impl Spawn for Counting {
+			fn spawn_blocking(&self, task: Box<dyn FnOnce() + Send>) {
+				SPAWNED.fetch_add(1, Ordering::SeqCst);
+				ThreadSpawn.spawn_blocking(task);
+			}
+		}
+
+		// other tests may run their tasks through it meanwhile
+		set_spawner(Counting);
+		assert_eq!(block_on(blocking(|| 1)), 1);
+		set_spawner(ThreadPool::default());
+		assert!(SPAWNED.load(Ordering::SeqCst) >= 1);
+
+		// a task dropped without running
+		let slot = Arc::new(Mutex::new(Slot::<u8> { value: None, waker: None }));
+		drop(Done(slot.clone()));
+		let dropped = thread::spawn(move || block_on(Blocking { slot })).join();
+		assert!(dropped.is_err());
+	}
+	#[test]
+	fn thread_pool() {
+		use std::sync::mpsc;
+		use std::time::Duration;
+
+		use crate::asynch::{Spawn, ThreadPool};
+
+		let pool = ThreadPool::new(2);
+		let (started, starts) = mpsc::channel();
+		let mut releases = vec![];
+		for i in 0..3 {
+			let (release, released) = mpsc::channel::<()>();
+			releases.push(release);
+			let started = started.clone();
+			pool.spawn_blocking(Box::new(move || {
+				started.send(i).unwrap();
+				let _ = released.recv();
+			}));
+		}
+		let mut running = vec![starts.recv().unwrap(), starts.recv().unwrap()];
+		running.sort();
+		assert_eq!(running, [0, 1]);
+		// the third one waits for a thread
+		assert!(starts.recv_timeout(Duration::from_millis(100)).is_err());
+		assert_eq!(pool.threads(), 2);
+		releases.remove(0);
+		assert_eq!(starts.recv().unwrap(), 2);
+		assert_eq!(pool.threads(), 2);
+
+		// panics don't cost a thread
+		pool.spawn_blocking(Box::new(|| panic!("in a pool thread")));
+		drop(releases);
+		let (done, finished) = mpsc::channel();
+		pool.spawn_blocking(Box::new(move || done.send(()).unwrap()));
+		finished.recv().unwrap();
+		assert_eq!(pool.threads(), 2);
+	}
+}
@@ -0,0 +1,321 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::asynch::{first_addr, lock, Connector, I2pStream};
+use crate::error::Error;
+use crate::net::{I2pSocketAddr, ToI2pSocketAddrs};
+
+/// Default number of idle streams kept per destination
+const DEFAULT_MAX_IDLE: usize = 4;
+/// Default time a stream may stay idle, below the inactivity timeout of the
+/// streaming library of the router
+const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+/// Default time a stream may be reused for
+const DEFAULT_MAX_LIFETIME: Duration = Duration::from_secs(600);
+
+type HealthCheck = dyn Fn(&I2pStream) -> bool + Send + Sync;
+
+/// Keeps the streams to each destination open between requests, as opening
+/// one builds a connection through the tunnels of both sides, which takes
+/// seconds rather than the milliseconds of a TCP handshake.
+///
+/// A stream checked out with `get` only goes back to the pool once given
+/// back with `PooledStream::release`, after its response was read in full;
+/// dropped streams are closed. Streams are discarded rather than reused
+/// once idle for longer than the idle timeout, older than the maximum
+/// lifetime, closed by the peer, holding unread data, or failing the health
+/// check set with `health_check`.
+///
+/// # Examples
+///
+/// ```no_run
+/// use i2p::asynch::{Connector, Session, StreamPool};
+/// use i2p::Error;
+///
+/// async fn ask(pool: &StreamPool) -> Result<Vec<u8>, Error> {
+///     let mut stream = pool.get("example.i2p:7000").await?;
+///     stream.write_all(b"PING\n").await?;
+///     let mut reply = [0; 5];
+///     stream.read_exact(&mut reply).await?;
+///     stream.release();
+///     Ok(reply.to_vec())
+/// }
+///
+/// async fn start() -> Result<StreamPool, Error> {
+///     let session = Session::transient("127.0.0.1:7656").await?;
+///     Ok(StreamPool::new(Connector::new(session)).max_idle(8))
+/// }
+/// ```
+#[derive(Clone)]
+pub struct StreamPool {
+	connector: Connector,
+	max_idle: usize,
+	idle_timeout: Option<Duration>,
+	max_lifetime: Option<Duration>,
+	health_check: Option<Arc<HealthCheck>>,
+	idle: Arc<Mutex<HashMap<I2pSocketAddr, Vec<Idle>>>>,
+}
+
+struct Idle {
+	stream: I2pStream,
+	created: Instant,
+	since: Instant,
+}
+
+impl fmt::Debug for StreamPool {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("StreamPool")
+			.field("max_idle", &self.max_idle)
+			.field("idle_timeout", &self.idle_timeout)
+			.field("max_lifetime", &self.max_lifetime)
+			.finish()
+	}
+}
+
+impl StreamPool {
+	/// A pool opening its streams with `connector`, keeping up to 4 idle
+	/// streams per destination for a minute each, and reusing a stream for
+	/// 10 minutes at most.
+	pub fn new(connector: Connector) -> StreamPool {
+		StreamPool {
+			connector,
+			max_idle: DEFAULT_MAX_IDLE,
+			idle_timeout: Some(DEFAULT_IDLE_TIMEOUT),
+			max_lifetime: Some(DEFAULT_MAX_LIFETIME),
+			health_check: None,
+			idle: Arc::new(Mutex::new(HashMap::new())),
+		}
+	}
+
+	/// The number of idle streams kept per destination, 0 disabling reuse.
+	pub fn max_idle(mut self, max_idle: usize) -> Self {
+		self.max_idle = max_idle;
+		self
+	}
+
+	/// How long a stream may stay idle before being closed, `None` keeping
+	/// it until the peer closes it.
+	pub fn idle_timeout(mut self, timeout: Option<Duration>) -> Self {
+		self.idle_timeout = timeout;
+		self
+	}
+
+	/// How long after being opened a stream may still be reused, `None`
+	/// reusing it for as long as it stays healthy.
+	pub fn max_lifetime(mut self, lifetime: Option<Duration>) -> Self {
+		self.max_lifetime = lifetime;
+		self
+	}
+
+	/// Checks idle streams before `get` hands them out, those failing it
+	/// being closed, in addition to the built-in checks.
+	pub fn health_check<F>(mut self, check: F) -> Self
+	where
+		F: Fn(&I2pStream) -> bool + Send + Sync + 'static,
+	{
+		self.health_check = Some(Arc::new(check));
+		self
+	}
+
+	/// The connector new streams are opened with.
+	pub fn connector(&self) -> &Connector {
+		&self.connector
+	}
+
+	/// An idle stream to `addr`, or a new one when there's none left
+	/// healthy.
+	pub async fn get<A: ToI2pSocketAddrs>(&self, addr: A) -> Result<PooledStream, Error> {
+		let addr = first_addr(addr)?;
+		if let Some(idle) = self.take_idle(&addr) {
+			return Ok(PooledStream {
+				stream: idle.stream,
+				addr,
+				created: idle.created,
+				reused: true,
+				pool: self.clone(),
+			});
+		}
+		let stream = self.connector.dial(addr.clone()).await?;
+		Ok(PooledStream {
+			stream,
+			addr,
+			created: Instant::now(),
+			reused: false,
+			pool: self.clone(),
+		})
+	}
+
+	/// The number of idle streams kept, all destinations together.
+	pub fn idle_count(&self) -> usize {
+		lock(&self.idle).values().map(Vec::len).sum()
+	}
+
+	/// Closes all the idle streams.
+	pub fn clear(&self) {
+		lock(&self.idle).clear();
+	}
+
+	/// The most recently used healthy stream, closing the others found on
+	/// the way.
+	fn take_idle(&self, addr: &I2pSocketAddr) -> Option<Idle> {
+		let mut idle = lock(&self.idle);
+		let streams = idle.get_mut(addr)?;
+		let mut found = None;
+		while let Some(candidate) = streams.pop() {
+			if self.is_reusable(&candidate.stream, candidate.created, Some(candidate.since)) {
+				found = Some(candidate);
+				break;
+			}
+		}
+		if streams.is_empty() {
+			idle.remove(addr);
+		}
+		found
+	}
+
+	fn is_reusable(&self, stream: &I2pStream, created: Instant, idle_since: Option<Instant>) -> bool {
+		!expired(created, self.max_lifetime)
+			&& idle_since.is_none_or(|since| !expired(since, self.idle_timeout))
+			&& stream.is_idle()
+			&& self.health_check.as_ref().is_none_or(|check| check(stream))
+	}
+
+	fn put_back(&self, addr: I2pSocketAddr, stream: I2pStream, created: Instant) {
+		if !self.is_reusable(&stream, created, None) {
+			return;
+		}
+		let mut idle = lock(&self.idle);
+		let streams = idle.entry(addr).or_default();
+		let idle_timeout = self.idle_timeout;
+		streams.retain(|idle| !expired(idle.since, idle_timeout));
+		if streams.len() >= self.max_idle {
+			return;
+		}
+		streams.push(Idle {
+			stream,
+			created,
+			since: Instant::now(),
+		});
+	}
+}
+
+/// Whether more than `limit` passed since `since`, never without limit.
+fn expired(since: Instant, limit: Option<Duration>) -> bool {
+	limit.is_some_and(|limit| since.elapsed() >= limit)
+}
+
+/// A stream checked out of a `StreamPool`, used through `Deref` like an
+/// `I2pStream`.
+pub struct PooledStream {
+	stream: I2pStream,
+	addr: I2pSocketAddr,
+	created: Instant,
+	reused: bool,
+	pool: StreamPool,
+}
+
+impl PooledStream {
+	/// Whether the stream was idle in the pool rather than just opened. The
+	/// peer may have closed a reused stream in the meantime, so a request
+	/// failing on one can be retried on a new stream.
+	pub fn is_reused(&self) -> bool {
+		self.reused
+	}
+
+	/// Gives the stream back to the pool, once the exchange is over and
+	/// nothing is left to read.
+	pub fn release(self) {
+		self.pool.put_back(self.addr, self.stream, self.created);
+	}
+
+	/// Takes the stream out of the pool for good.
+	pub fn detach(self) -> I2pStream {
+		self.stream
+	}
+}
+
+impl Deref for PooledStream {
+	type Target = I2pStream;
+
+	fn deref(&self) -> &I2pStream {
+		&self.stream
+	}
+}
+
+impl DerefMut for PooledStream {
+	fn deref_mut(&mut self) -> &mut I2pStream {
+		&mut self.stream
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::atomic::{AtomicBool, Ordering};
+	use std::sync::Arc;
+	use std::time::Duration;
+
+	use crate::asynch::tests::block_on;
+	use crate::asynch::{Connector, Session, StreamPool};
+	use crate::sam::tests::fake_bridge;
+
+	fn pool_on(data: &'static [u8]) -> StreamPool {
+		let session = block_on(Session::transient(fake_bridge(data))).unwrap();
+		StreamPool::new(Connector::new(session))
+	}
+
+	#[test]
+	fn reuse() {
+		let pool = pool_on(b"");
+		block_on(async {
+			let mut stream = pool.get("example.i2p:80").await.unwrap();
+			assert!(!stream.is_reused());
+			stream.write_all(b"ping").await.unwrap();
+			let mut echoed = [0; 4];
+			stream.read_exact(&mut echoed).await.unwrap();
+			stream.release();
+			assert_eq!(pool.idle_count(), 1);
+
+			let stream = pool.get("example.i2p:80").await.unwrap();
+			assert!(stream.is_reused());
+			assert_eq!(pool.idle_count(), 0);
+			// another port is another key
+			let other = pool.get("example.i2p:81").await.unwrap();
+			assert!(!other.is_reused());
+			drop(other);
+			stream.release();
+			assert_eq!(pool.idle_count(), 1);
+		});
+	}
+
+	#[test]
+	fn discarded() {
+		// the bridge sends data nobody asked for
+		let pool = pool_on(b"unexpected");
+		block_on(async {
+			let stream = pool.get("example.i2p:80").await.unwrap();
+			std::thread::sleep(Duration::from_millis(100));
+			stream.release();
+			assert_eq!(pool.idle_count(), 0);
+		});
+
+		let pool = pool_on(b"").max_lifetime(Some(Duration::from_millis(0)));
+		block_on(async {
+			pool.get("example.i2p:80").await.unwrap().release();
+			assert_eq!(pool.idle_count(), 0);
+		});
+
+		let healthy = Arc::new(AtomicBool::new(true));
+		let check = healthy.clone();
+		let pool = pool_on(b"").health_check(move |_| check.load(Ordering::SeqCst));
+		block_on(async {
+			pool.get("example.i2p:80").await.unwrap().release();
+			assert_eq!(pool.idle_count(), 1);
+			healthy.store(false, Ordering::SeqCst);
+			assert!(!pool.get("example.i2p:80").await.unwrap().is_reused());
+			assert_eq!(pool.idle_count(), 0);
+		});
+	}
+}
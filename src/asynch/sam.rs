@@ -0,0 +1,351 @@
+use std::collections::VecDeque;
+use std::future::Future;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::task::{Context, Poll};
+
+use crate::asynch::{blocking, lock, pending, spawn, Blocking, Done};
+use crate::error::Error;
+use crate::net::I2pAddr;
+use crate::sam;
+use crate::sam::SessionStyle;
+use crate::sam_options::SessionOptions;
+
+/// Most lookups sent to a control socket before waiting for their replies
+const MAX_PIPELINED_LOOKUPS: usize = 32;
+
+/// Asynchronous counterpart of `sam::SamConnection`. Commands are sent one
+/// at a time, concurrent calls waiting for their turn, except for lookups
+/// which are sent together, see `lookup`.
+#[derive(Clone)]
+pub struct SamConnection {
+	inner: Arc<Mutex<sam::SamConnection>>,
+	lookups: Arc<Lookups>,
+}
+
+impl SamConnection {
+	/// Connects to a SAM bridge and negotiates the protocol version, see
+	/// `sam::SamConnection::connect`.
+	pub async fn connect<A: ToSocketAddrs>(addr: A) -> Result<SamConnection, Error> {
+		let addrs = socket_addrs(addr)?;
+		let sam = blocking(move || sam::SamConnection::connect(&addrs[..])).await?;
+		Ok(SamConnection::from(sam))
+	}
+
+	/// Resolves a name through the bridge, or the naming cache of the
+	/// connection.
+	///
+	/// The lookup is queued right away, not once the future is polled. The
+	/// queued lookups, from clones of the connection too, are sent back to
+	/// back like with `lookup_many`, at most 32 at a time: the next ones wait
+	/// for their replies, so resolving many names neither waits for each
+	/// reply in turn nor floods the bridge.
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// use i2p::asynch::SamConnection;
+	///
+	/// async fn resolve(sam: &SamConnection, names: &[&str]) {
+	///     let lookups: Vec<_> = names.iter().map(|name| sam.lookup(name)).collect();
+	///     for lookup in lookups {
+	///         println!("{:?}", lookup.await);
+	///     }
+	/// }
+	/// ```
+	pub fn lookup(&self, name: &str) -> Lookup {
+		self.lookups.lookup(name)
+	}
+
+	/// Resolves a name through the bridge, or the naming cache of the
+	/// connection, see `lookup`.
+	pub async fn naming_lookup(&self, name: &str) -> Result<String, Error> {
+		self.lookup(name).await
+	}
+
+	/// Resolves several names at once, see `sam::SamConnection::lookup_many`.
+	pub async fn lookup_many(&self, names: &[&str]) -> Result<Vec<Result<String, Error>>, Error> {
+		let inner = self.inner.clone();
+		let names: Vec<String> = names.iter().map(|name| name.to_string()).collect();
+		blocking(move || {
+			let names: Vec<&str> = names.iter().map(String::as_str).collect();
+			lock(&inner).lookup_many(&names)
+		})
+		.await
+	}
+
+	/// Generates a new destination, returning its public and private keys.
+	pub async fn generate_destination(&self) -> Result<(String, String), Error> {
+		let inner = self.inner.clone();
+		blocking(move || lock(&inner).generate_destination()).await
+	}
+
+	/// Sends a PING and waits for its PONG.
+	pub async fn ping(&self, data: &str) -> Result<(), Error> {
+		let (inner, data) = (self.inner.clone(), data.to_string());
+		blocking(move || lock(&inner).ping(&data)).await
+	}
+
+	/// The SAM protocol version agreed upon with the bridge during HELLO.
+	pub fn version(&self) -> String {
+		lock(&self.inner).version().to_string()
+	}
+}
+
+impl From<sam::SamConnection> for SamConnection {
+	fn from(sam: sam::SamConnection) -> SamConnection {
+		let inner = Arc::new(Mutex::new(sam));
+		let sam = inner.clone();
+		SamConnection {
+			inner,
+			lookups: Lookups::new(move |names| lock(&sam).lookup_many(names)),
+		}
+	}
+}
+
+/// Asynchronous counterpart of `sam::Session`, cheaply cloned. Streams can
+/// be opened and accepted concurrently, while lookups on the control socket
+/// are sent in batches, see `SamConnection::lookup`.
+#[derive(Clone)]
+pub struct Session {
+	inner: Arc<RwLock<sam::Session>>,
+	lookups: Arc<Lookups>,
+}
+
+impl Session {
+	/// Creates a new session, see `sam::Session::create_with_options`.
+	pub async fn create<A: ToSocketAddrs>(
+		sam_addr: A,
+		destination: &str,
+		nickname: &str,
+		style: SessionStyle,
+		options: &SessionOptions,
+	) -> Result<Session, Error> {
+		let addrs = socket_addrs(sam_addr)?;
+		let (destination, nickname, options) = (destination.to_string(), nickname.to_string(), options.clone());
+		let session = blocking(move || {
+			sam::Session::create_with_options(&addrs[..], &destination, &nickname, style, &options)
+		})
+		.await?;
+		Ok(Session::from(session))
+	}
+
+	/// Creates a new stream session for a destination, with an auto-generated
+	/// nickname.
+	pub async fn from_destination<A: ToSocketAddrs>(sam_addr: A, destination: &str) -> Result<Session, Error> {
+		let addrs = socket_addrs(sam_addr)?;
+		let destination = destination.to_string();
+		let session = blocking(move || sam::Session::from_destination(&addrs[..], &destination)).await?;
+		Ok(Session::from(session))
+	}
+
+	/// Creates a new transient stream session, with an auto-generated
+	/// nickname.
+	pub async fn transient<A: ToSocketAddrs>(sam_addr: A) -> Result<Session, Error> {
+		let addrs = socket_addrs(sam_addr)?;
+		let session = blocking(move || sam::Session::transient(&addrs[..])).await?;
+		Ok(Session::from(session))
+	}
+
+	/// Resolves a name on the control socket of the session, sent along with
+	/// the other lookups of the session, see `SamConnection::lookup`.
+	pub fn lookup(&self, name: &str) -> Lookup {
+		self.lookups.lookup(name)
+	}
+
+	/// Resolves a name on the control socket of the session, see `lookup`.
+	pub async fn naming_lookup(&self, name: &str) -> Result<String, Error> {
+		self.lookup(name).await
+	}
+
+	/// Resolves several names at once on the control socket of the session.
+	pub async fn lookup_many(&self, names: &[&str]) -> Result<Vec<Result<String, Error>>, Error> {
+		let inner = self.inner.clone();
+		let names: Vec<String> = names.iter().map(|name| name.to_string()).collect();
+		blocking(move || {
+			let names: Vec<&str> = names.iter().map(String::as_str).collect();
+			write(&inner).lookup_many(&names)
+		})
+		.await
+	}
+
+	/// The nickname of the session.
+	pub fn id(&self) -> String {
+		self.blocking().id().to_string()
+	}
+
+	/// The full base64 destination of the session.
+	pub fn my_destination(&self) -> String {
+		self.blocking().my_destination().to_string()
+	}
+
+	/// The b32 address of the destination of the session.
+	pub fn my_address(&self) -> Result<I2pAddr, Error> {
+		self.blocking().my_address()
+	}
+
+	/// The underlying blocking session, for the calls without an async
+	/// counterpart. Holding it delays lookups on the session.
+	pub fn blocking(&self) -> RwLockReadGuard<'_, sam::Session> {
+		read(&self.inner)
+	}
+
+	/// Runs `f` with the blocking session on a helper thread, sharing it
+	/// with the other streams of the session.
+	pub(crate) async fn with<T, F>(&self, f: F) -> T
+	where
+		F: FnOnce(&sam::Session) -> T + Send + 'static,
+		T: Send + 'static,
+	{
+		let inner = self.inner.clone();
+		blocking(move || f(&read(&inner))).await
+	}
+}
+
+impl From<sam::Session> for Session {
+	fn from(session: sam::Session) -> Session {
+		let inner = Arc::new(RwLock::new(session));
+		let session = inner.clone();
+		Session {
+			inner,
+			lookups: Lookups::new(move |names| write(&session).lookup_many(names)),
+		}
+	}
+}
+
+/// A lookup queued on a control socket, see `SamConnection::lookup`.
+pub struct Lookup {
+	result: Blocking<Result<String, Error>>,
+}
+
+impl Future for Lookup {
+	type Output = Result<String, Error>;
+
+	fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<String, Error>> {
+		Pin::new(&mut self.result).poll(cx)
+	}
+}
+
+type LookupMany = dyn Fn(&[&str]) -> Result<Vec<Result<String, Error>>, Error> + Send + Sync;
+
+/// The lookups waiting for a control socket, sent in batches by a single
+/// blocking task at a time.
+struct Lookups {
+	queue: Mutex<LookupQueue>,
+	lookup_many: Box<LookupMany>,
+}
+
+#[derive(Default)]
+struct LookupQueue {
+	waiting: VecDeque<(String, Done<Result<String, Error>>)>,
+	sending: bool,
+}
+
+impl Lookups {
+	fn new<F>(lookup_many: F) -> Arc<Lookups>
+	where
+		F: Fn(&[&str]) -> Result<Vec<Result<String, Error>>, Error> + Send + Sync + 'static,
+	{
+		Arc::new(Lookups {
+			queue: Mutex::new(LookupQueue::default()),
+			lookup_many: Box::new(lookup_many),
+		})
+	}
+
+	fn lookup(self: &Arc<Self>, name: &str) -> Lookup {
+		let (done, result) = pending();
+		let mut queue = lock(&self.queue);
+		queue.waiting.push_back((name.to_string(), done));
+		if !queue.sending {
+			queue.sending = true;
+			let lookups = self.clone();
+			spawn(move || lookups.send());
+		}
+		Lookup { result }
+	}
+
+	/// Sends the waiting lookups until none is left.
+	fn send(&self) {
+		loop {
+			let batch: Vec<_> = {
+				let mut queue = lock(&self.queue);
+				if queue.waiting.is_empty() {
+					queue.sending = false;
+					return;
+				}
+				let n = queue.waiting.len().min(MAX_PIPELINED_LOOKUPS);
+				queue.waiting.drain(..n).collect()
+			};
+			let names: Vec<&str> = batch.iter().map(|(name, _)| name.as_str()).collect();
+			match panic::catch_unwind(AssertUnwindSafe(|| (self.lookup_many)(&names))) {
+				Ok(Ok(results)) => {
+					for ((_, done), res) in batch.iter().zip(results) {
+						done.complete(Ok(res));
+					}
+				}
+				Ok(Err(e)) => {
+					for (_, done) in &batch {
+						done.complete(Ok(Err(e.kind().into())));
+					}
+				}
+				Err(panic) => {
+					// the futures of the batch panic as their `Done` is
+					// dropped, the next lookup sends the ones left
+					lock(&self.queue).sending = false;
+					panic::resume_unwind(panic);
+				}
+			}
+		}
+	}
+}
+
+/// Resolves bridge addresses before moving them to a helper thread.
+fn socket_addrs<A: ToSocketAddrs>(addr: A) -> Result<Vec<SocketAddr>, Error> {
+	Ok(addr.to_socket_addrs()?.collect())
+}
+
+fn read(inner: &RwLock<sam::Session>) -> RwLockReadGuard<'_, sam::Session> {
+	inner.read().unwrap_or_else(|e| e.into_inner())
+}
+
+fn write(inner: &RwLock<sam::Session>) -> RwLockWriteGuard<'_, sam::Session> {
+	inner.write().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::asynch::tests::block_on;
+	use crate::asynch::{SamConnection, Session};
+	use crate::error::LookupFailure;
+	use crate::sam::tests::fake_bridge;
+
+	#[test]
+	fn lookups() {
+		let addr = fake_bridge(b"");
+		let sam = block_on(SamConnection::connect(addr)).unwrap();
+		assert_eq!(sam.version(), "3.2");
+		assert_eq!(block_on(sam.naming_lookup("ME")).unwrap(), "dest");
+
+		let session = block_on(Session::transient(addr)).unwrap();
+		assert_eq!(session.my_destination(), "dest");
+		let results = block_on(session.lookup_many(&["a.i2p", "b.i2p"])).unwrap();
+		assert_eq!(results.len(), 2);
+		assert_eq!(results[1].as_ref().unwrap(), "dest");
+	}
+
+	#[test]
+	fn queued_lookups() {
+		let sam = block_on(SamConnection::connect(fake_bridge(b""))).unwrap();
+		let lookups: Vec<_> = (0..100).map(|i| sam.lookup(&format!("peer{}.i2p", i))).collect();
+		let invalid = sam.clone().lookup("not a name");
+		for lookup in lookups {
+			assert_eq!(block_on(lookup).unwrap(), "dest");
+		}
+		assert_eq!(
+			block_on(invalid).unwrap_err().kind().lookup_failure(),
+			Some(LookupFailure::InvalidName)
+		);
+	}
+}
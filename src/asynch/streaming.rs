@@ -0,0 +1,611 @@
+use std::future::{self, Future};
+use std::io::{self, Read, Write};
+use std::net::Shutdown;
+use std::pin::Pin;
+use std::collections::VecDeque;
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+use std::task::{Context, Poll, Waker};
+
+use crate::asynch::{blocking, first_addr, lock, pending, spawn, Blocking, Session};
+use crate::error::Error;
+use crate::net::{self, I2pSocketAddr, ToI2pSocketAddrs};
+use crate::sam::AcceptAbort;
+
+/// Size of the chunks read ahead from the network
+const READ_CHUNK: usize = 16 * 1024;
+/// Largest chunk of data accepted by a single write
+const WRITE_CHUNK: usize = 64 * 1024;
+
+/// Asynchronous counterpart of `net::I2pStream`.
+///
+/// A blocking task reads a chunk ahead of the application, another writes
+/// the data handed over by `write` in the background, one chunk at a time.
+/// Written data is only known to be sent once `flush` completes. Dropping
+/// the stream closes it once the pending data is sent.
+///
+/// The stream implements neither the `AsyncRead` and `AsyncWrite` traits of
+/// `futures-io` nor those of tokio, the crate depending on neither. Its
+/// inherent `poll_*` methods have the signatures of the `futures-io` ones,
+/// and of tokio's once buffers are unwrapped, so the traits are left to a
+/// newtype of the application before the stream can be used with codecs,
+/// TLS wrappers or HTTP stacks:
+///
+/// ```ignore
+/// struct Compat(i2p::asynch::I2pStream);
+///
+/// impl futures_io::AsyncRead for Compat {
+///     fn poll_read(
+///         mut self: Pin<&mut Self>,
+///         cx: &mut Context<'_>,
+///         buf: &mut [u8],
+///     ) -> Poll<io::Result<usize>> {
+///         Pin::new(&mut self.0).poll_read(cx, buf)
+///     }
+/// }
+/// ```
+pub struct I2pStream {
+	inner: net::I2pStream,
+	shared: Arc<Shared>,
+	writer: mpsc::Sender<Vec<u8>>,
+}
+
+struct Shared {
+	state: Mutex<State>,
+	consumed: Condvar,
+}
+
+#[derive(Default)]
+struct State {
+	/// Chunk read ahead, empty at the end of the stream
+	read: Option<io::Result<Vec<u8>>>,
+	read_pos: usize,
+	read_done: bool,
+	read_waker: Option<Waker>,
+	writing: bool,
+	write_error: Option<io::Error>,
+	write_waker: Option<Waker>,
+	closed: bool,
+}
+
+impl I2pStream {
+	/// Opens a connection through the transient session shared by the
+	/// streams of the default SAM bridge, see `net::I2pStream::connect`.
+	pub async fn connect<A: ToI2pSocketAddrs>(addr: A) -> Result<I2pStream, Error> {
+		let addr = first_addr(addr)?;
+		let stream = blocking(move || net::I2pStream::connect(addr)).await?;
+		Ok(I2pStream::from_std(stream)?)
+	}
+
+	/// Opens a connection through an existing session.
+	pub async fn connect_with_session<A: ToI2pSocketAddrs>(session: &Session, addr: A) -> Result<I2pStream, Error> {
+		let addr = first_addr(addr)?;
+		let stream = session
+			.with(move |session| net::I2pStream::connect_with_session(session, addr))
+			.await?;
+		Ok(I2pStream::from_std(stream)?)
+	}
+
+	/// Wraps a blocking stream, which shouldn't be read or written through
+	/// its clones anymore.
+	pub fn from_std(stream: net::I2pStream) -> io::Result<I2pStream> {
+		let reader = stream.try_clone().map_err(to_io)?;
+		let writer = stream.try_clone().map_err(to_io)?;
+		let shared = Arc::new(Shared {
+			state: Mutex::new(State::default()),
+			consumed: Condvar::new(),
+		});
+		let (sender, receiver) = mpsc::channel();
+		let read_shared = shared.clone();
+		spawn(move || read_ahead(reader, &read_shared));
+		let write_shared = shared.clone();
+		spawn(move || write_behind(writer, &receiver, &write_shared));
+		Ok(I2pStream {
+			inner: stream,
+			shared,
+			writer: sender,
+		})
+	}
+
+	/// The address of the remote peer.
+	pub fn peer_addr(&self) -> Result<I2pSocketAddr, Error> {
+		self.inner.peer_addr()
+	}
+
+	/// The address of the local session.
+	pub fn local_addr(&self) -> Result<I2pSocketAddr, Error> {
+		self.inner.local_addr()
+	}
+
+	/// Whether the stream can carry another exchange: nothing was received
+	/// since the last read, the peer didn't close it and writes succeeded.
+	pub(crate) fn is_idle(&self) -> bool {
+		let state = lock(&self.shared.state);
+		state.read.is_none() && !state.read_done && !state.writing && state.write_error.is_none()
+	}
+
+	/// Reads some data into `buf`, returning how much was read, 0 at the end
+	/// of the stream.
+	pub async fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		future::poll_fn(|cx| Pin::new(&mut *self).poll_read(cx, buf)).await
+	}
+
+	/// Reads exactly enough data to fill `buf`.
+	pub async fn read_exact(&mut self, mut buf: &mut [u8]) -> io::Result<()> {
+		while !buf.is_empty() {
+			match self.read(buf).await? {
+				0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+				n => buf = &mut buf[n..],
+			}
+		}
+		Ok(())
+	}
+
+	/// Reads everything up to the end of the stream into `buf`, returning
+	/// how much was read.
+	pub async fn read_to_end(&mut self, buf: &mut Vec<u8>) -> io::Result<usize> {
+		let start = buf.len();
+		let mut chunk = vec![0; READ_CHUNK];
+		loop {
+			match self.read(&mut chunk).await? {
+				0 => return Ok(buf.len() - start),
+				n => buf.extend_from_slice(&chunk[..n]),
+			}
+		}
+	}
+
+	/// Hands some of `buf` over to be written, returning how much.
+	pub async fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		future::poll_fn(|cx| Pin::new(&mut *self).poll_write(cx, buf)).await
+	}
+
+	/// Hands all of `buf` over to be written.
+	pub async fn write_all(&mut self, mut buf: &[u8]) -> io::Result<()> {
+		while !buf.is_empty() {
+			let n = self.write(buf).await?;
+			buf = &buf[n..];
+		}
+		Ok(())
+	}
+
+	/// Waits for the written data to be sent.
+	pub async fn flush(&mut self) -> io::Result<()> {
+		future::poll_fn(|cx| Pin::new(&mut *self).poll_flush(cx)).await
+	}
+
+	/// Sends the written data, then shuts the read, write, or both halves
+	/// of the stream down, see `net::I2pStream::shutdown`.
+	pub async fn shutdown(&mut self, how: Shutdown) -> io::Result<()> {
+		self.flush().await?;
+		self.inner.shutdown(how).map_err(to_io)
+	}
+
+	/// Attempts to read some data into `buf`, registering the task to be
+	/// woken once data arrived if there's none yet.
+	pub fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+		let mut state = lock(&self.shared.state);
+		if buf.is_empty() || state.read_done {
+			return Poll::Ready(Ok(0));
+		}
+		match state.read.take() {
+			None => {
+				state.read_waker = Some(cx.waker().clone());
+				Poll::Pending
+			}
+			Some(Err(e)) => {
+				state.read_done = true;
+				Poll::Ready(Err(e))
+			}
+			Some(Ok(data)) => {
+				if data.is_empty() {
+					state.read_done = true;
+					return Poll::Ready(Ok(0));
+				}
+				let pos = state.read_pos;
+				let n = buf.len().min(data.len() - pos);
+				buf[..n].copy_from_slice(&data[pos..pos + n]);
+				if pos + n < data.len() {
+					state.read_pos = pos + n;
+					state.read = Some(Ok(data));
+				} else {
+					state.read_pos = 0;
+					self.shared.consumed.notify_all();
+				}
+				Poll::Ready(Ok(n))
+			}
+		}
+	}
+
+	/// Attempts to hand some of `buf` over to be written, registering the
+	/// task to be woken once the previous chunk is sent if it's still
+	/// being written.
+	pub fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+		let mut state = lock(&self.shared.state);
+		if let Some(e) = state.write_error.take() {
+			return Poll::Ready(Err(e));
+		}
+		if state.writing {
+			state.write_waker = Some(cx.waker().clone());
+			return Poll::Pending;
+		}
+		if buf.is_empty() {
+			return Poll::Ready(Ok(0));
+		}
+		let n = buf.len().min(WRITE_CHUNK);
+		if self.writer.send(buf[..n].to_vec()).is_err() {
+			return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+		}
+		state.writing = true;
+		Poll::Ready(Ok(n))
+	}
+
+	/// Attempts to send the written data, registering the task to be woken
+	/// once it's sent otherwise.
+	pub fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		let mut state = lock(&self.shared.state);
+		if let Some(e) = state.write_error.take() {
+			return Poll::Ready(Err(e));
+		}
+		if state.writing {
+			state.write_waker = Some(cx.waker().clone());
+			return Poll::Pending;
+		}
+		Poll::Ready(Ok(()))
+	}
+
+	/// Attempts to send the written data, then shuts the write half of the
+	/// stream down, like `AsyncWrite::poll_close` (`poll_shutdown` in
+	/// tokio).
+	pub fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		match self.as_mut().poll_flush(cx) {
+			Poll::Ready(Ok(())) => Poll::Ready(self.inner.shutdown(Shutdown::Write).map_err(to_io)),
+			other => other,
+		}
+	}
+}
+
+impl Drop for I2pStream {
+	fn drop(&mut self) {
+		lock(&self.shared.state).closed = true;
+		self.shared.consumed.notify_all();
+	}
+}
+
+/// Reads the stream a chunk ahead of the application, until its end or the
+/// async stream is dropped.
+fn read_ahead(mut stream: net::I2pStream, shared: &Shared) {
+	let mut buf = vec![0; READ_CHUNK];
+	loop {
+		let res = stream.read(&mut buf).map(|n| buf[..n].to_vec());
+		let done = !matches!(res, Ok(ref data) if !data.is_empty());
+		let mut state = lock(&shared.state);
+		state.read = Some(res);
+		if let Some(waker) = state.read_waker.take() {
+			waker.wake();
+		}
+		if done {
+			return;
+		}
+		while state.read.is_some() && !state.closed {
+			state = shared.consumed.wait(state).unwrap_or_else(|e| e.into_inner());
+		}
+		if state.closed {
+			return;
+		}
+	}
+}
+
+/// Writes the chunks handed over by the async stream, closing the stream
+/// once it's dropped.
+fn write_behind(mut stream: net::I2pStream, chunks: &mpsc::Receiver<Vec<u8>>, shared: &Shared) {
+	for chunk in chunks {
+		let res = stream.write_all(&chunk).and_then(|_| stream.flush());
+		let mut state = lock(&shared.state);
+		state.writing = false;
+		if let Err(e) = res {
+			state.write_error = Some(e);
+		}
+		if let Some(waker) = state.write_waker.take() {
+			waker.wake();
+		}
+	}
+	// also ends the read ahead
+	let _ = stream.shutdown(Shutdown::Both);
+}
+
+/// Asynchronous counterpart of `net::I2pListener`.
+#[derive(Clone)]
+pub struct I2pListener {
+	inner: Arc<net::I2pListener>,
+	// streams accepted as their accept was dropped, for the next accepts
+	backlog: Arc<Mutex<VecDeque<Accepted>>>,
+}
+
+type Accepted = (net::I2pStream, I2pSocketAddr);
+
+impl I2pListener {
+	/// Creates a listener on a new transient session of the default SAM
+	/// bridge.
+	pub async fn bind() -> Result<I2pListener, Error> {
+		let listener = blocking(net::I2pListener::bind).await?;
+		Ok(I2pListener::from_std(listener))
+	}
+
+	/// Creates a listener accepting the incoming streams of a session.
+	pub async fn bind_with_session(session: &Session) -> Result<I2pListener, Error> {
+		let listener = session.with(net::I2pListener::bind_with_session).await?;
+		Ok(I2pListener::from_std(listener))
+	}
+
+	/// Wraps a blocking listener.
+	pub fn from_std(listener: net::I2pListener) -> I2pListener {
+		I2pListener {
+			inner: Arc::new(listener),
+			backlog: Arc::new(Mutex::new(VecDeque::new())),
+		}
+	}
+
+	/// The address of the local session.
+	pub fn local_addr(&self) -> Result<I2pSocketAddr, Error> {
+		self.inner.local_addr()
+	}
+
+	/// Waits for a peer to connect to the session, returning the accepted
+	/// stream and the peer address.
+	///
+	/// # Cancel safety
+	///
+	/// Dropping the future, for example when another branch of a `select!`
+	/// completes first, shuts down the socket the accept is pending on, so
+	/// the bridge drops the request instead of handing it a peer nobody
+	/// reads from, and the rest of the session is left untouched. A peer
+	/// accepted just as the future got dropped isn't lost either: it's
+	/// returned by the next accept of the listener or its clones.
+	///
+	/// # Examples
+	///
+	/// ```ignore
+	/// loop {
+	///     tokio::select! {
+	///         accepted = listener.accept() => handle(accepted?),
+	///         _ = shutdown.recv() => break,
+	///     }
+	/// }
+	/// ```
+	pub async fn accept(&self) -> Result<(I2pStream, I2pSocketAddr), Error> {
+		accepted(self.accept_std().await)
+	}
+
+	/// The incoming connections, accepted one after the other, which never
+	/// end. `Incoming` isn't a `futures::Stream`, see its documentation. Servers stop accepting by dropping it, for example
+	/// once a shutdown signal wins a `select!`.
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// use i2p::asynch::I2pListener;
+	///
+	/// async fn serve(listener: I2pListener) {
+	///     let mut incoming = listener.incoming();
+	///     while let Some(accepted) = incoming.next().await {
+	///         match accepted {
+	///             Ok((stream, addr)) => println!("{} connected", addr),
+	///             Err(e) => eprintln!("accept failed: {}", e),
+	///         }
+	///     }
+	/// }
+	/// ```
+	pub fn incoming(&self) -> Incoming {
+		Incoming {
+			listener: self.clone(),
+			accepting: None,
+		}
+	}
+
+	fn accept_std(&self) -> Accept {
+		let (done, result) = pending();
+		let abort = Arc::new(AcceptAbort::default());
+		let dropped = Arc::new(Mutex::new(false));
+		let backlogged = lock(&self.backlog).pop_front();
+		match backlogged {
+			Some(accepted) => done.complete(Ok(Ok(accepted))),
+			None => {
+				let (inner, backlog) = (self.inner.clone(), self.backlog.clone());
+				let (abort, dropped) = (abort.clone(), dropped.clone());
+				spawn(move || {
+					let res = inner.accept_abortable(&abort);
+					let dropped = lock(&dropped);
+					match res {
+						Ok(accepted) if *dropped => lock(&backlog).push_back(accepted),
+						res => done.complete(Ok(res)),
+					}
+				});
+			}
+		}
+		Accept {
+			backlog: self.backlog.clone(),
+			abort,
+			dropped,
+			result,
+		}
+	}
+}
+
+/// An accept handed over to the spawner, aborted once dropped.
+struct Accept {
+	backlog: Arc<Mutex<VecDeque<Accepted>>>,
+	abort: Arc<AcceptAbort>,
+	// set once dropped, the accepting task then backlogging its stream
+	dropped: Arc<Mutex<bool>>,
+	result: Blocking<Result<Accepted, Error>>,
+}
+
+impl Future for Accept {
+	type Output = Result<Accepted, Error>;
+
+	fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<Accepted, Error>> {
+		Pin::new(&mut self.result).poll(cx)
+	}
+}
+
+impl Drop for Accept {
+	fn drop(&mut self) {
+		*lock(&self.dropped) = true;
+		self.abort.abort();
+		// accepted but not polled since
+		if let Some(Ok(accepted)) = self.result.take() {
+			lock(&self.backlog).push_back(accepted);
+		}
+	}
+}
+
+/// The incoming connections of an `I2pListener`, see `I2pListener::incoming`.
+///
+/// `Incoming` doesn't implement `futures::Stream`, the crate not depending
+/// on `futures`. Its inherent `poll_next` has the signature of
+/// `Stream::poll_next`, for a newtype of the application to implement the
+/// trait with.
+pub struct Incoming {
+	listener: I2pListener,
+	accepting: Option<Accept>,
+}
+
+impl Incoming {
+	/// Waits for the next incoming connection.
+	pub async fn next(&mut self) -> Option<Result<(I2pStream, I2pSocketAddr), Error>> {
+		future::poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await
+	}
+
+	/// Attempts to get the next incoming connection, registering the task to
+	/// be woken once a peer connected otherwise.
+	pub fn poll_next(
+		mut self: Pin<&mut Self>,
+		cx: &mut Context<'_>,
+	) -> Poll<Option<Result<(I2pStream, I2pSocketAddr), Error>>> {
+		if self.accepting.is_none() {
+			self.accepting = Some(self.listener.accept_std());
+		}
+		let accepting = self.accepting.as_mut().expect("accept started");
+		match Pin::new(accepting).poll(cx) {
+			Poll::Ready(res) => {
+				self.accepting = None;
+				Poll::Ready(Some(accepted(res)))
+			}
+			Poll::Pending => Poll::Pending,
+		}
+	}
+}
+
+fn accepted(res: Result<Accepted, Error>) -> Result<(I2pStream, I2pSocketAddr), Error> {
+	let (stream, addr) = res?;
+	Ok((I2pStream::from_std(stream)?, addr))
+}
+
+fn to_io(e: Error) -> io::Error {
+	io::Error::other(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+	use std::net::Shutdown;
+
+	use crate::asynch::tests::block_on;
+	use crate::asynch::{I2pListener, I2pStream, Session};
+	use crate::sam::tests::fake_bridge;
+
+	#[test]
+	fn echo() {
+		block_on(async {
+			let session = Session::transient(fake_bridge(b"hi ")).await.unwrap();
+			let mut stream = I2pStream::connect_with_session(&session, "peer.i2p:80").await.unwrap();
+			stream.write_all(b"ping").await.unwrap();
+			stream.shutdown(Shutdown::Write).await.unwrap();
+			let mut echoed = vec![];
+			stream.read_to_end(&mut echoed).await.unwrap();
+			assert_eq!(echoed, b"hi ping");
+			assert_eq!(stream.read(&mut [0; 8]).await.unwrap(), 0);
+		});
+	}
+
+	#[test]
+	fn poll_methods() {
+		use std::future;
+		use std::pin::Pin;
+
+		block_on(async {
+			let session = Session::transient(fake_bridge(b"")).await.unwrap();
+			let mut stream = I2pStream::connect_with_session(&session, "peer.i2p:80").await.unwrap();
+			let mut pinned = Pin::new(&mut stream);
+			let n = future::poll_fn(|cx| pinned.as_mut().poll_write(cx, b"ping")).await.unwrap();
+			assert_eq!(n, 4);
+			future::poll_fn(|cx| pinned.as_mut().poll_close(cx)).await.unwrap();
+			let mut echoed = vec![];
+			pinned.read_to_end(&mut echoed).await.unwrap();
+			assert_eq!(echoed, b"ping");
+		});
+	}
+
+	#[test]
+	fn accept() {
+		block_on(async {
+			let session = Session::transient(fake_bridge(b"AAAA FROM_PORT=7 TO_PORT=80\n")).await.unwrap();
+			let listener = I2pListener::bind_with_session(&session).await.unwrap();
+			let (mut stream, addr) = listener.accept().await.unwrap();
+			assert_eq!(addr.port(), 7);
+			let mut buf = [0; 4];
+			stream.write_all(b"pong").await.unwrap();
+			stream.read_exact(&mut buf).await.unwrap();
+			assert_eq!(&buf, b"pong");
+		});
+	}
+
+	#[test]
+	fn incoming() {
+		block_on(async {
+			let session = Session::transient(fake_bridge(b"AAAA FROM_PORT=7 TO_PORT=80\n")).await.unwrap();
+			let listener = I2pListener::bind_with_session(&session).await.unwrap();
+			let mut incoming = listener.incoming();
+			for _ in 0..2 {
+				let (_, addr) = incoming.next().await.unwrap().unwrap();
+				assert_eq!(addr.port(), 7);
+			}
+		});
+	}
+
+	#[test]
+	fn cancelled_accept() {
+		use std::future::Future;
+		use std::io::Read;
+		use std::task::{Context, Waker};
+		use std::time::Duration;
+
+		use crate::sam::tests::{scripted_bridge, Step};
+
+		// the first accept gets no peer, the next ones get one
+		let (addr, played) = scripted_bridge(vec![
+			vec![Step::Answer],
+			vec![Step::Answer],
+			vec![Step::Answer, Step::Write(b"AAAA FROM_PORT=7 TO_PORT=80\n".to_vec()), Step::Echo],
+		]);
+
+		let session = block_on(Session::transient(addr)).unwrap();
+		let listener = block_on(I2pListener::bind_with_session(&session)).unwrap();
+		let mut accept = Box::pin(listener.accept());
+		assert!(accept.as_mut().poll(&mut Context::from_waker(Waker::noop())).is_pending());
+		// the control connection never ends its script, the accept does once
+		// it got to the bridge
+		let mut accepting = played.recv_timeout(Duration::from_secs(10)).unwrap();
+		accepting.stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+		drop(accept);
+		// its socket gets closed
+		assert!(accepting.reader.read_to_end(&mut vec![]).is_ok());
+
+		block_on(async {
+			let (mut stream, addr) = listener.accept().await.unwrap();
+			assert_eq!(addr.port(), 7);
+			let mut buf = [0; 4];
+			stream.write_all(b"pong").await.unwrap();
+			stream.read_exact(&mut buf).await.unwrap();
+			assert_eq!(&buf, b"pong");
+		});
+	}
+}
@@ -0,0 +1,249 @@
+//! Framing of SAM command and reply lines, for clients driving the protocol
+//! over their own transport.
+//!
+//! `SamCodec` parses and quotes lines the same way as the connections of
+//! the crate, which frame HELLO and `SamConnection::send_command` with it
+//! through `protocol::SamProtocol`, their other commands still going through
+//! the parsers of each reply. It works on plain byte buffers.
+//!
+//! The `Decoder` and `Encoder` traits of `tokio_util::codec` aren't
+//! implemented, the crate not depending on tokio. Wrapping `SamCodec` in
+//! them is left to the application and only takes a few lines:
+//!
+//! ```ignore
+//! use bytes::{Buf, BytesMut};
+//! use i2p::codec::{SamCodec, SamLine};
+//!
+//! struct Sam(SamCodec);
+//!
+//! impl tokio_util::codec::Decoder for Sam {
+//!     type Item = SamLine;
+//!     type Error = i2p::Error;
+//!
+//!     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<SamLine>, i2p::Error> {
+//!         Ok(self.0.decode(src)?.map(|(line, len)| {
+//!             src.advance(len);
+//!             line
+//!         }))
+//!     }
+//! }
+//!
+//! impl tokio_util::codec::Encoder<SamLine> for Sam {
+//!     type Error = i2p::Error;
+//!
+//!     fn encode(&mut self, line: SamLine, dst: &mut BytesMut) -> Result<(), i2p::Error> {
+//!         let mut buf = vec![];
+//!         self.0.encode(&line, &mut buf)?;
+//!         dst.extend_from_slice(&buf);
+//!         Ok(())
+//!     }
+//! }
+//!
+//! let framed = tokio_util::codec::Framed::new(tcp_stream, Sam(SamCodec::new()));
+//! ```
+
+use std::collections::HashMap;
+
+use crate::error::{Error, ErrorKind};
+use crate::parsers::{is_key, quote, sam_reply, unescape};
+use crate::reply::SamReply;
+
+/// Longest line `SamCodec::new` accepts, well above the longest replies,
+/// which carry private keys.
+pub const DEFAULT_MAX_LINE_LENGTH: usize = 64 * 1024;
+
+/// A command or reply line: its leading words, such as `NAMING LOOKUP` or
+/// `STREAM STATUS`, and its fields in order, unescaped.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SamLine {
+	/// The leading words of the line
+	pub command: String,
+	/// The `KEY=VALUE` fields of the line, a key without value having an
+	/// empty one
+	pub fields: Vec<(String, String)>,
+}
+
+impl SamLine {
+	/// A line without fields.
+	pub fn new(command: &str) -> SamLine {
+		SamLine {
+			command: command.to_string(),
+			fields: vec![],
+		}
+	}
+
+	/// Adds a field, quoted when encoded if needed.
+	pub fn field(mut self, key: &str, value: &str) -> Self {
+		self.fields.push((key.to_string(), value.to_string()));
+		self
+	}
+
+	/// The value of the first field with the provided key.
+	pub fn get(&self, key: &str) -> Option<&str> {
+		self.fields.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+	}
+
+	/// The typed reply of a reply line. Unlike the replies read by a
+	/// `SamConnection`, failures reported in `RESULT` aren't turned into
+	/// errors.
+	pub fn into_reply(self) -> Result<SamReply, Error> {
+		let fields: HashMap<String, String> = self.fields.into_iter().collect();
+		SamReply::new(&self.command, fields)
+	}
+}
+
+/// Encodes and decodes SAM lines, see the module documentation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SamCodec {
+	max_length: usize,
+}
+
+impl Default for SamCodec {
+	fn default() -> SamCodec {
+		SamCodec::with_max_length(DEFAULT_MAX_LINE_LENGTH)
+	}
+}
+
+impl SamCodec {
+	/// A codec accepting lines of up to `DEFAULT_MAX_LINE_LENGTH` bytes.
+	pub fn new() -> SamCodec {
+		SamCodec::default()
+	}
+
+	/// A codec accepting lines of up to `max_length` bytes, line feed
+	/// included, protecting against peers never ending theirs.
+	pub fn with_max_length(max_length: usize) -> SamCodec {
+		SamCodec { max_length }
+	}
+
+	/// The longest line accepted.
+	pub fn max_length(&self) -> usize {
+		self.max_length
+	}
+
+	/// Appends `line` to `dst`, terminated by a line feed. Values are quoted
+	/// when they're empty or hold spaces, `=`, double quotes or backslashes.
+	/// Lines that couldn't be decoded back, such as values holding a line
+	/// feed, fail with `SAMInvalidMessage`.
+	pub fn encode(&self, line: &SamLine, dst: &mut Vec<u8>) -> Result<(), Error> {
+		let invalid = |msg: &str| -> Error { ErrorKind::SAMInvalidMessage(msg.to_string()).into() };
+		let command = line.command.trim();
+		if command.is_empty() || command.contains('\n') || command.split(' ').any(|word| word.contains('=')) {
+			return Err(invalid("Invalid SAM command"));
+		}
+		let mut encoded = command.to_string();
+		for (key, value) in &line.fields {
+			if !is_key(key) {
+				return Err(invalid("Invalid SAM field key"));
+			}
+			if value.contains('\n') {
+				return Err(invalid("Line feed in a SAM field value"));
+			}
+			encoded.push(' ');
+			encoded.push_str(key);
+			encoded.push('=');
+			encoded.push_str(&quote(value));
+		}
+		encoded.push('\n');
+		if encoded.len() > self.max_length {
+			return Err(invalid("SAM line too long"));
+		}
+		dst.extend_from_slice(encoded.as_bytes());
+		Ok(())
+	}
+
+	/// Decodes the first line of `src`, along with the number of bytes it
+	/// took, which the caller removes from its buffer. `None` means the line
+	/// isn't complete yet. Whatever follows the line, such as the payload of
+	/// a received datagram, is left alone.
+	pub fn decode(&self, src: &[u8]) -> Result<Option<(SamLine, usize)>, Error> {
+		let len = match src.iter().position(|&b| b == b'\n') {
+			Some(pos) => pos + 1,
+			None if src.len() >= self.max_length => {
+				return Err(ErrorKind::SAMInvalidMessage("SAM line too long".to_string()).into());
+			}
+			None => return Ok(None),
+		};
+		if len > self.max_length {
+			return Err(ErrorKind::SAMInvalidMessage("SAM line too long".to_string()).into());
+		}
+		let text = std::str::from_utf8(&src[..len]).map_err(|_| Error::from(ErrorKind::MessageParsing))?;
+		let (_, (command, fields)) = sam_reply(text)?;
+		let line = SamLine {
+			command: command.to_string(),
+			fields: fields
+				.into_iter()
+				.map(|(k, v)| (k.to_string(), unescape(v).into_owned()))
+				.collect(),
+		};
+		Ok(Some((line, len)))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::codec::{SamCodec, SamLine};
+	use crate::error::ErrorKind;
+	use crate::reply::TypedReply;
+
+	#[test]
+	fn round_trip() {
+		let codec = SamCodec::new();
+		let line = SamLine::new("SESSION CREATE")
+			.field("STYLE", "STREAM")
+			.field("ID", "a b")
+			.field("MESSAGE", "says \"hi\" \\o/")
+			.field("EMPTY", "");
+		let mut buf = vec![];
+		codec.encode(&line, &mut buf).unwrap();
+		assert_eq!(
+			buf,
+			&b"SESSION CREATE STYLE=STREAM ID=\"a b\" MESSAGE=\"says \\\"hi\\\" \\\\o/\" EMPTY=\"\"\n"[..]
+		);
+		buf.extend_from_slice(b"payload");
+		assert_eq!(codec.decode(&buf).unwrap(), Some((line, buf.len() - 7)));
+	}
+
+	#[test]
+	fn partial_lines() {
+		let codec = SamCodec::with_max_length(32);
+		assert_eq!(codec.decode(b"NAMING REPLY RESULT=OK").unwrap(), None);
+		let (line, len) = codec.decode(b"PING data\nPONG").unwrap().unwrap();
+		assert_eq!((line, len), (SamLine::new("PING data"), 10));
+		assert!(codec.decode(&[b'A'; 32]).is_err());
+		assert!(codec.decode(b"RESULT=OK\n").is_err());
+	}
+
+	#[test]
+	fn invalid_lines() {
+		let codec = SamCodec::new();
+		let mut buf = vec![];
+		for line in &[
+			SamLine::new(""),
+			SamLine::new("KEY=value"),
+			SamLine::new("HELLO VERSION").field("MIN MAX", "3.1"),
+			SamLine::new("HELLO VERSION").field("MIN", "3.1\n"),
+		] {
+			match codec.encode(line, &mut buf).unwrap_err().kind() {
+				ErrorKind::SAMInvalidMessage(_) => {}
+				kind => panic!("unexpected error: {:?}", kind),
+			}
+		}
+		assert!(buf.is_empty());
+		let long = SamLine::new("HELLO VERSION").field("MIN", &"3".repeat(64));
+		assert!(SamCodec::with_max_length(32).encode(&long, &mut buf).is_err());
+	}
+
+	#[test]
+	fn replies() {
+		let (line, _) = SamCodec::new()
+			.decode(b"NAMING REPLY RESULT=OK NAME=me VALUE=dest\n")
+			.unwrap()
+			.unwrap();
+		assert_eq!(line.get("VALUE"), Some("dest"));
+		match line.into_reply().unwrap().reply {
+			TypedReply::Naming(naming) => assert_eq!(naming.name, "me"),
+			reply => panic!("unexpected reply: {:?}", reply),
+		}
+	}
+}
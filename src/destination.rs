@@ -0,0 +1,737 @@
+//! Parsed I2P destinations.
+//!
+//! A destination is the public identity of an I2P endpoint: a 256 bytes area
+//! holding the encryption public key, a 128 bytes area holding the signing
+//! public key, and a certificate. Destinations with anything other than a
+//! DSA signing key and an ElGamal encryption key carry a key certificate
+//! naming both types. The encryption key is stored at the start of its area
+//! and the signing key at the end of its own, the remainder being padding.
+//! Signing keys longer than 128 bytes spill over into the key certificate.
+
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+
+use rand::RngCore;
+use ring::digest;
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use zeroize::Zeroize;
+
+use crate::error::{Error, ErrorKind};
+use crate::keyfile;
+use crate::net::{BytesVisitor, I2pAddr, BASE64_I2P};
+use crate::sam_options::SignatureType;
+
+const ENCRYPTION_KEY_AREA_LEN: usize = 256;
+const SIGNING_KEY_AREA_LEN: usize = 128;
+pub(crate) const PUBLIC_KEYS_LEN: usize = ENCRYPTION_KEY_AREA_LEN + SIGNING_KEY_AREA_LEN;
+
+/// Certificate type of destinations without a key certificate
+pub const NULL_CERT_TYPE: u8 = 0;
+/// Certificate type of key certificates
+pub const KEY_CERT_TYPE: u8 = 5;
+
+/// Encryption type code of ElGamal-2048 public keys
+pub const ELGAMAL_CRYPTO_TYPE: u16 = 0;
+/// Encryption type code of X25519 public keys
+pub const X25519_CRYPTO_TYPE: u16 = 4;
+
+/// A destination decoded into its keys and certificate. Serialized as its
+/// base64 form, or as raw bytes for binary formats.
+///
+/// # Examples
+///
+/// ```
+/// use i2p::destination::Destination;
+/// use i2p::sam_options::SignatureType;
+///
+/// let mut bytes = vec![1u8; 384];
+/// bytes.extend_from_slice(&[5, 0, 4, 0, 7, 0, 4]);
+/// let dest = Destination::from_bytes(&bytes).unwrap();
+/// assert_eq!(dest.signature_type(), SignatureType::EdDSA_SHA512_Ed25519);
+/// assert_eq!(dest.signing_public_key().len(), 32);
+/// assert_eq!(dest.to_bytes(), bytes);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Destination {
+	bytes: Vec<u8>,
+	sig_type: SignatureType,
+	crypto_type: u16,
+}
+
+impl Destination {
+	/// Decodes a binary destination, which has to be exactly as long as its
+	/// certificate declares.
+	pub fn from_bytes(bytes: &[u8]) -> Result<Destination, Error> {
+		let dest_len = declared_len(bytes)?;
+		if bytes.len() != dest_len {
+			return Err(ErrorKind::BadKeyMaterial(format!(
+				"destination is {} bytes long, expected {}",
+				bytes.len(),
+				dest_len
+			))
+			.into());
+		}
+		Destination::parse(bytes)
+	}
+
+	/// Decodes the destination at the start of `bytes`, such as private keys.
+	fn from_prefix(bytes: &[u8]) -> Result<Destination, Error> {
+		let dest_len = declared_len(bytes)?;
+		if bytes.len() < dest_len {
+			return Err(ErrorKind::BadKeyMaterial("truncated destination".to_string()).into());
+		}
+		Destination::parse(&bytes[..dest_len])
+	}
+
+	fn parse(bytes: &[u8]) -> Result<Destination, Error> {
+		let cert = &bytes[PUBLIC_KEYS_LEN + 3..];
+		let (sig_type, crypto_type) = if bytes[PUBLIC_KEYS_LEN] == KEY_CERT_TYPE {
+			if cert.len() < 4 {
+				return Err(ErrorKind::BadKeyMaterial("truncated key certificate".to_string()).into());
+			}
+			let sig_code = u16::from_be_bytes([cert[0], cert[1]]);
+			let sig_type = SignatureType::from_code(sig_code).ok_or_else(|| {
+				ErrorKind::BadKeyMaterial(format!("unknown signature type {}", sig_code))
+			})?;
+			(sig_type, u16::from_be_bytes([cert[2], cert[3]]))
+		} else {
+			(SignatureType::DSA_SHA1, ELGAMAL_CRYPTO_TYPE)
+		};
+
+		let excess = sig_type.public_key_len().saturating_sub(SIGNING_KEY_AREA_LEN);
+		if excess > 0 && cert.len() < 4 + excess {
+			return Err(ErrorKind::BadKeyMaterial(
+				"key certificate too short for the signing key".to_string(),
+			)
+			.into());
+		}
+
+		Ok(Destination {
+			bytes: bytes.to_vec(),
+			sig_type,
+			crypto_type,
+		})
+	}
+
+	/// Decodes a base64 destination, as returned by the SAM bridge.
+	pub fn from_base64(dest: &str) -> Result<Destination, Error> {
+		let bytes = BASE64_I2P
+			.decode(dest.as_bytes())
+			.map_err(|_| ErrorKind::BadAddressEncoding(dest.to_string()))?;
+		Destination::from_bytes(&bytes)
+	}
+
+	/// The binary encoding of this destination
+	pub fn to_bytes(&self) -> Vec<u8> {
+		self.bytes.clone()
+	}
+
+	/// The base64 encoding of this destination
+	pub fn to_base64(&self) -> String {
+		BASE64_I2P.encode(&self.bytes)
+	}
+
+	/// The base32 address of this destination, keeping the destination so
+	/// it can be connected to without a lookup
+	pub fn addr(&self) -> I2pAddr {
+		I2pAddr::from_b64(&self.to_base64()).unwrap_or_else(|_| I2pAddr::from_dest_bytes(&self.bytes))
+	}
+
+	/// Type of the signing key
+	pub fn signature_type(&self) -> SignatureType {
+		self.sig_type
+	}
+
+	/// Type code of the encryption key, see `ELGAMAL_CRYPTO_TYPE` and
+	/// `X25519_CRYPTO_TYPE`
+	pub fn crypto_type(&self) -> u16 {
+		self.crypto_type
+	}
+
+	/// The encryption public key. For unknown encryption types this is the
+	/// whole 256 bytes area, padding included.
+	pub fn encryption_public_key(&self) -> &[u8] {
+		let len = match self.crypto_type {
+			X25519_CRYPTO_TYPE => 32,
+			_ => ENCRYPTION_KEY_AREA_LEN,
+		};
+		&self.bytes[..len]
+	}
+
+	/// The signing public key, reassembled from the certificate for the key
+	/// types longer than 128 bytes.
+	pub fn signing_public_key(&self) -> Vec<u8> {
+		let len = self.sig_type.public_key_len();
+		if len <= SIGNING_KEY_AREA_LEN {
+			return self.bytes[PUBLIC_KEYS_LEN - len..PUBLIC_KEYS_LEN].to_vec();
+		}
+		let mut key = self.bytes[ENCRYPTION_KEY_AREA_LEN..PUBLIC_KEYS_LEN].to_vec();
+		key.extend_from_slice(&self.certificate()[4..4 + len - SIGNING_KEY_AREA_LEN]);
+		key
+	}
+
+	/// Type of the certificate, `KEY_CERT_TYPE` for key certificates
+	pub fn certificate_type(&self) -> u8 {
+		self.bytes[PUBLIC_KEYS_LEN]
+	}
+
+	/// Payload of the certificate, without its type and length
+	pub fn certificate(&self) -> &[u8] {
+		&self.bytes[PUBLIC_KEYS_LEN + 3..]
+	}
+}
+
+/// The length of a destination, as declared by its certificate
+fn declared_len(bytes: &[u8]) -> Result<usize, Error> {
+	if bytes.len() < PUBLIC_KEYS_LEN + 3 {
+		return Err(ErrorKind::BadKeyMaterial("destination too short".to_string()).into());
+	}
+	let cert_len = u16::from_be_bytes([bytes[PUBLIC_KEYS_LEN + 1], bytes[PUBLIC_KEYS_LEN + 2]]);
+	Ok(PUBLIC_KEYS_LEN + 3 + cert_len as usize)
+}
+
+impl fmt::Display for Destination {
+	fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+		fmt.write_str(&self.to_base64())
+	}
+}
+
+impl FromStr for Destination {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<Destination, Error> {
+		Destination::from_base64(s)
+	}
+}
+
+impl Serialize for Destination {
+	fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		if serializer.is_human_readable() {
+			serializer.serialize_str(&self.to_base64())
+		} else {
+			serializer.serialize_bytes(&self.bytes)
+		}
+	}
+}
+
+impl<'de> Deserialize<'de> for Destination {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Destination, D::Error> {
+		let dest = if deserializer.is_human_readable() {
+			Destination::from_base64(&String::deserialize(deserializer)?)
+		} else {
+			Destination::from_bytes(&deserializer.deserialize_bytes(BytesVisitor)?)
+		};
+		dest.map_err(|e| de::Error::custom(e.kind()))
+	}
+}
+
+/// The private keys of a destination, as the base64 blob handed to the SAM
+/// bridge: the destination, followed by its encryption and signing private
+/// keys. The blob is the long-term identity of a service, it's wiped from
+/// memory on drop and never shows up in `Debug` output.
+#[derive(Clone, PartialEq, Eq)]
+pub struct I2pPrivateKey {
+	key: String,
+}
+
+impl I2pPrivateKey {
+	/// Wraps a base64 private key without checking its content.
+	pub fn new(key: String) -> I2pPrivateKey {
+		I2pPrivateKey { key }
+	}
+
+	/// Decodes a base64 private key, checking that it's long enough to hold
+	/// the private keys of its destination.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use i2p::destination::I2pPrivateKey;
+	///
+	/// assert!(I2pPrivateKey::from_base64("priv").is_err());
+	/// ```
+	pub fn from_base64(key: &str) -> Result<I2pPrivateKey, Error> {
+		let mut bytes = BASE64_I2P
+			.decode(key.as_bytes())
+			.map_err(|_| ErrorKind::BadKeyMaterial("private key isn't valid base64".to_string()))?;
+		let checked = Destination::from_prefix(&bytes).and_then(|dest| {
+			let enc_len = match dest.crypto_type {
+				ELGAMAL_CRYPTO_TYPE => 256,
+				X25519_CRYPTO_TYPE => 32,
+				_ => 0,
+			};
+			let expected = dest.bytes.len() + enc_len + dest.sig_type.private_key_len();
+			if bytes.len() < expected {
+				return Err(ErrorKind::BadKeyMaterial(format!(
+					"private key is {} bytes long, expected at least {}",
+					bytes.len(),
+					expected
+				))
+				.into());
+			}
+			Ok(())
+		});
+		bytes.zeroize();
+		checked?;
+		Ok(I2pPrivateKey::new(key.to_string()))
+	}
+
+	/// Generates the keys of a new destination locally, without contacting
+	/// the router: an Ed25519 signing key and an X25519 encryption key, both
+	/// derived by `ring`.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use i2p::destination::I2pPrivateKey;
+	///
+	/// let key = I2pPrivateKey::generate();
+	/// let addr = key.destination().unwrap().addr();
+	/// assert!(addr.string().ends_with(".b32.i2p"));
+	/// ```
+	pub fn generate() -> I2pPrivateKey {
+		let mut rng = rand::thread_rng();
+		let mut enc_seed = [0u8; 32];
+		let mut sig_private = [0u8; 32];
+		let mut padding = [0u8; 32];
+		rng.fill_bytes(&mut enc_seed);
+		rng.fill_bytes(&mut sig_private);
+		rng.fill_bytes(&mut padding);
+
+		// the X25519 key is the Montgomery form of an Ed25519 key, whose
+		// scalar multiplication ring does: its private key is the clamped
+		// scalar of the seed, its public key the u coordinate of the point
+		let mut hash = [0u8; 64];
+		hash.copy_from_slice(digest::digest(&digest::SHA512, &enc_seed).as_ref());
+		let mut enc_private = [0u8; 32];
+		enc_private.copy_from_slice(&hash[..32]);
+		enc_private[0] &= 248;
+		enc_private[31] &= 127;
+		enc_private[31] |= 64;
+		hash.zeroize();
+		let enc_public = montgomery_u(&ed25519_public_key(&enc_seed));
+		enc_seed.zeroize();
+
+		let mut bytes = Vec::with_capacity(PUBLIC_KEYS_LEN + 7 + 64);
+		bytes.extend_from_slice(&enc_public);
+		// the padding doesn't have to be random throughout, repeating it
+		// keeps the destination compressible
+		bytes.extend(padding.iter().cycle().take(PUBLIC_KEYS_LEN - 64));
+		bytes.extend_from_slice(&ed25519_public_key(&sig_private));
+		bytes.extend_from_slice(&[KEY_CERT_TYPE, 0, 4]);
+		bytes.extend_from_slice(&SignatureType::EdDSA_SHA512_Ed25519.code().to_be_bytes());
+		bytes.extend_from_slice(&X25519_CRYPTO_TYPE.to_be_bytes());
+		bytes.extend_from_slice(&enc_private);
+		bytes.extend_from_slice(&sig_private);
+
+		let key = I2pPrivateKey::new(BASE64_I2P.encode(&bytes));
+		bytes.zeroize();
+		enc_private.zeroize();
+		sig_private.zeroize();
+		key
+	}
+
+	/// The public destination the keys belong to
+	pub fn destination(&self) -> Result<Destination, Error> {
+		let mut bytes = self.decode()?;
+		let dest = Destination::from_prefix(&bytes);
+		bytes.zeroize();
+		dest
+	}
+
+	/// The base64 private key, as passed in `DESTINATION=`
+	pub fn as_str(&self) -> &str {
+		&self.key
+	}
+
+	/// Reads a binary key file, as written by i2pd, i2ptunnel or `save`.
+	pub fn load<P: AsRef<Path>>(path: P) -> Result<I2pPrivateKey, Error> {
+		let mut bytes = fs::read(path)?;
+		let key = I2pPrivateKey::from_base64(&BASE64_I2P.encode(&bytes));
+		bytes.zeroize();
+		key
+	}
+
+	/// Writes the keys to a binary key file, in the format i2pd and
+	/// i2ptunnel read. The file is only readable by its owner on Unix.
+	pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+		let mut bytes = self.decode()?;
+		let written = write_private(path.as_ref(), &bytes);
+		bytes.zeroize();
+		written
+	}
+
+	/// Reads a key file written by `save_encrypted` with the same passphrase.
+	pub fn load_encrypted<P: AsRef<Path>>(path: P, passphrase: &str) -> Result<I2pPrivateKey, Error> {
+		let mut bytes = keyfile::open(&fs::read(path)?, passphrase)?;
+		let key = I2pPrivateKey::from_base64(&BASE64_I2P.encode(&bytes));
+		bytes.zeroize();
+		key
+	}
+
+	/// Writes the keys encrypted with a key derived from `passphrase`. The
+	/// container is specific to this crate, `save` writes the plain key file
+	/// routers read.
+	pub fn save_encrypted<P: AsRef<Path>>(&self, path: P, passphrase: &str) -> Result<(), Error> {
+		let mut bytes = self.decode()?;
+		let sealed = keyfile::seal(&bytes, passphrase, keyfile::DEFAULT_ITERATIONS);
+		bytes.zeroize();
+		write_private(path.as_ref(), &sealed)
+	}
+
+	fn decode(&self) -> Result<Vec<u8>, Error> {
+		BASE64_I2P
+			.decode(self.key.as_bytes())
+			.map_err(|_| ErrorKind::BadKeyMaterial("private key isn't valid base64".to_string()).into())
+	}
+}
+
+fn write_private(path: &Path, bytes: &[u8]) -> Result<(), Error> {
+	let mut options = OpenOptions::new();
+	options.write(true).create(true).truncate(true);
+	#[cfg(unix)]
+	{
+		use std::os::unix::fs::OpenOptionsExt;
+		options.mode(0o600);
+	}
+	options.open(path)?.write_all(bytes)?;
+	Ok(())
+}
+
+impl Drop for I2pPrivateKey {
+	fn drop(&mut self) {
+		self.key.zeroize();
+	}
+}
+
+impl fmt::Debug for I2pPrivateKey {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str("I2pPrivateKey(..)")
+	}
+}
+
+/// The Ed25519 public key of a 32 bytes private key seed
+fn ed25519_public_key(seed: &[u8; 32]) -> [u8; 32] {
+	let pair = Ed25519KeyPair::from_seed_unchecked(seed).expect("32 bytes seed");
+	let mut public = [0u8; 32];
+	public.copy_from_slice(pair.public_key().as_ref());
+	public
+}
+
+/// Elements of GF(2^255 - 19), in little endian 64 bits limbs.
+///
+/// The arithmetic below isn't constant time: its timing depends on the
+/// values it works on. That's acceptable as it only ever handles public key
+/// material: the Ed25519 public key being converted is as public as the
+/// X25519 key it's converted to and published in the destination, which
+/// gives it back as (u - 1) / (u + 1), so timing can't tell anything the
+/// destination doesn't. It must never be given private keys, seeds or any
+/// other secret, which ring handles instead.
+type Fe = [u64; 4];
+
+const P: Fe = [0xffff_ffff_ffff_ffed, u64::MAX, u64::MAX, 0x7fff_ffff_ffff_ffff];
+
+/// The u coordinate of the Montgomery form of an Ed25519 public key, its
+/// X25519 public key, as (1 + y) / (1 - y).
+fn montgomery_u(ed25519: &[u8; 32]) -> [u8; 32] {
+	let mut y = [0u64; 4];
+	for (i, limb) in y.iter_mut().enumerate() {
+		let mut word = [0u8; 8];
+		word.copy_from_slice(&ed25519[8 * i..8 * i + 8]);
+		*limb = u64::from_le_bytes(word);
+	}
+	// the top bit is the sign of x
+	y[3] &= 0x7fff_ffff_ffff_ffff;
+	let y = fe_reduce(y);
+	let one = [1, 0, 0, 0];
+	let u = fe_mul(fe_add(one, y), fe_invert(fe_sub(one, y)));
+	let mut bytes = [0u8; 32];
+	for (i, limb) in u.iter().enumerate() {
+		bytes[8 * i..8 * i + 8].copy_from_slice(&limb.to_le_bytes());
+	}
+	bytes
+}
+
+/// `a - b` over 256 bits, with the borrow
+fn sub_256(a: Fe, b: Fe) -> (Fe, bool) {
+	let mut out = [0u64; 4];
+	let mut borrow = false;
+	for i in 0..4 {
+		let (d, b1) = a[i].overflowing_sub(b[i]);
+		let (d, b2) = d.overflowing_sub(u64::from(borrow));
+		out[i] = d;
+		borrow = b1 || b2;
+	}
+	(out, borrow)
+}
+
+fn fe_reduce(mut a: Fe) -> Fe {
+	loop {
+		match sub_256(a, P) {
+			(_, true) => return a,
+			(d, false) => a = d,
+		}
+	}
+}
+
+fn fe_add(a: Fe, b: Fe) -> Fe {
+	// both are below 2^255, so the sum fits
+	let mut out = [0u64; 4];
+	let mut carry = 0u128;
+	for i in 0..4 {
+		let s = u128::from(a[i]) + u128::from(b[i]) + carry;
+		out[i] = s as u64;
+		carry = s >> 64;
+	}
+	fe_reduce(out)
+}
+
+fn fe_sub(a: Fe, b: Fe) -> Fe {
+	fe_add(a, sub_256(P, b).0)
+}
+
+fn fe_mul(a: Fe, b: Fe) -> Fe {
+	let mut wide = [0u64; 8];
+	for i in 0..4 {
+		let mut carry = 0u128;
+		for j in 0..4 {
+			let t = u128::from(a[i]) * u128::from(b[j]) + u128::from(wide[i + j]) + carry;
+			wide[i + j] = t as u64;
+			carry = t >> 64;
+		}
+		wide[i + 4] = carry as u64;
+	}
+	// 2^256 = 38 modulo p
+	let mut out = [0u64; 4];
+	let mut carry = 0u128;
+	for i in 0..4 {
+		let t = u128::from(wide[i]) + u128::from(wide[i + 4]) * 38 + carry;
+		out[i] = t as u64;
+		carry = t >> 64;
+	}
+	while carry != 0 {
+		let mut t = u128::from(out[0]) + carry * 38;
+		out[0] = t as u64;
+		carry = 0;
+		for limb in out.iter_mut().skip(1) {
+			t = u128::from(*limb) + (t >> 64);
+			*limb = t as u64;
+		}
+		carry += t >> 64;
+	}
+	fe_reduce(out)
+}
+
+/// `a^(p - 2)`, the inverse of `a`
+fn fe_invert(a: Fe) -> Fe {
+	let exponent = sub_256(P, [2, 0, 0, 0]).0;
+	let mut result = [1, 0, 0, 0];
+	for bit in (0..255).rev() {
+		result = fe_mul(result, result);
+		if (exponent[bit / 64] >> (bit % 64)) & 1 == 1 {
+			result = fe_mul(result, a);
+		}
+	}
+	result
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::destination::{
+		ed25519_public_key, montgomery_u, Destination, I2pPrivateKey, KEY_CERT_TYPE, NULL_CERT_TYPE,
+	};
+	use crate::net::{I2pAddr, BASE64_I2P};
+	use crate::sam_options::SignatureType;
+	use serde::de::value::{Error as ValueError, StrDeserializer};
+	use serde::de::{Deserializer, IntoDeserializer, Visitor};
+	use serde::Deserialize;
+
+	/// Hands out bytes the way binary formats do
+	struct Binary<'a>(&'a [u8]);
+
+	impl<'de> Deserializer<'de> for Binary<'de> {
+		type Error = ValueError;
+
+		fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ValueError> {
+			visitor.visit_borrowed_bytes(self.0)
+		}
+
+		fn is_human_readable(&self) -> bool {
+			false
+		}
+
+		serde::forward_to_deserialize_any! {
+			bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+			bytes byte_buf option unit unit_struct newtype_struct seq tuple
+			tuple_struct map struct enum identifier ignored_any
+		}
+	}
+
+	#[test]
+	fn key_certificate() {
+		let mut bytes = vec![1u8; 32];
+		bytes.extend_from_slice(&[0u8; 320]);
+		bytes.extend_from_slice(&[2u8; 32]);
+		bytes.extend_from_slice(&[5, 0, 4, 0, 7, 0, 4]);
+		let dest = Destination::from_bytes(&bytes).unwrap();
+		assert_eq!(dest.signature_type(), SignatureType::EdDSA_SHA512_Ed25519);
+		assert_eq!(dest.crypto_type(), 4);
+		assert_eq!(dest.encryption_public_key(), &[1u8; 32][..]);
+		assert_eq!(dest.signing_public_key(), vec![2u8; 32]);
+		assert_eq!(dest.certificate_type(), KEY_CERT_TYPE);
+		assert_eq!(dest.certificate(), &[0, 7, 0, 4][..]);
+
+		let b64 = dest.to_base64();
+		assert_eq!(b64.parse::<Destination>().unwrap(), dest);
+		assert_eq!(dest.addr(), I2pAddr::from_b64(&b64).unwrap());
+	}
+
+	#[test]
+	fn null_certificate() {
+		let mut bytes = vec![1u8; 256];
+		bytes.extend_from_slice(&[2u8; 128]);
+		bytes.extend_from_slice(&[0, 0, 0]);
+		let dest = Destination::from_bytes(&bytes).unwrap();
+		assert_eq!(dest.signature_type(), SignatureType::DSA_SHA1);
+		assert_eq!(dest.crypto_type(), 0);
+		assert_eq!(dest.encryption_public_key(), &bytes[..256]);
+		assert_eq!(dest.signing_public_key(), vec![2u8; 128]);
+		assert_eq!(dest.certificate_type(), NULL_CERT_TYPE);
+		assert!(dest.certificate().is_empty());
+	}
+
+	#[test]
+	fn excess_signing_key() {
+		let mut bytes = vec![1u8; 256];
+		bytes.extend_from_slice(&[2u8; 128]);
+		bytes.extend_from_slice(&[5, 0, 8, 0, 3, 0, 0, 3, 3, 3, 3]);
+		let dest = Destination::from_bytes(&bytes).unwrap();
+		assert_eq!(dest.signature_type(), SignatureType::ECDSA_SHA512_P521);
+		let key = dest.signing_public_key();
+		assert_eq!(key.len(), 132);
+		assert_eq!(&key[128..], &[3u8; 4][..]);
+
+		let truncated_len = bytes.len() - 2;
+		bytes.truncate(truncated_len);
+		bytes[386] = 6;
+		assert!(Destination::from_bytes(&bytes).is_err());
+	}
+
+	#[test]
+	fn malformed() {
+		assert!(Destination::from_bytes(&[0u8; 386]).is_err());
+		let mut bytes = vec![1u8; 384];
+		bytes.extend_from_slice(&[5, 0, 4, 0, 7, 0]);
+		assert!(Destination::from_bytes(&bytes).is_err());
+		bytes.push(4);
+		bytes.push(0);
+		assert!(Destination::from_bytes(&bytes).is_err());
+		bytes.pop();
+		bytes[386] = 2;
+		bytes.truncate(389);
+		assert!(Destination::from_bytes(&bytes).is_err());
+		assert!(Destination::from_base64("not base64!").is_err());
+	}
+
+	#[test]
+	fn private_key() {
+		let mut bytes = vec![1u8; 384];
+		bytes.extend_from_slice(&[5, 0, 4, 0, 7, 0, 4]);
+		let dest = Destination::from_bytes(&bytes).unwrap();
+		bytes.extend_from_slice(&[2u8; 32 + 32]);
+		let b64 = BASE64_I2P.encode(&bytes);
+
+		let key = I2pPrivateKey::from_base64(&b64).unwrap();
+		assert_eq!(key.as_str(), b64);
+		assert_eq!(key.destination().unwrap(), dest);
+		assert_eq!(format!("{:?}", key), "I2pPrivateKey(..)");
+
+		bytes.truncate(bytes.len() - 1);
+		assert!(I2pPrivateKey::from_base64(&BASE64_I2P.encode(&bytes)).is_err());
+		bytes.truncate(390);
+		assert!(I2pPrivateKey::from_base64(&BASE64_I2P.encode(&bytes)).is_err());
+	}
+
+	fn hex(s: &str) -> [u8; 32] {
+		let mut out = [0u8; 32];
+		for (i, byte) in out.iter_mut().enumerate() {
+			*byte = u8::from_str_radix(&s[2 * i..2 * i + 2], 16).unwrap();
+		}
+		out
+	}
+
+	#[test]
+	fn key_derivation() {
+		// RFC 8032, test 1
+		let seed = hex("9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60");
+		let public = ed25519_public_key(&seed);
+		assert_eq!(public, hex("d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a"));
+		// the X25519 public key of the clamped scalar of the seed
+		assert_eq!(
+			montgomery_u(&public),
+			hex("d85e07ec22b0ad881537c2f44d662d1a143cf830c57aca4305d85c7a90f6b62e")
+		);
+	}
+
+	#[test]
+	fn generated_key() {
+		let key = I2pPrivateKey::generate();
+		assert!(I2pPrivateKey::from_base64(key.as_str()).is_ok());
+		let dest = key.destination().unwrap();
+		assert_eq!(dest.signature_type(), SignatureType::EdDSA_SHA512_Ed25519);
+		assert_eq!(dest.crypto_type(), 4);
+
+		let bytes = BASE64_I2P.decode(key.as_str().as_bytes()).unwrap();
+		assert_eq!(bytes.len(), 391 + 64);
+		let mut seed = [0u8; 32];
+		seed.copy_from_slice(&bytes[423..]);
+		assert_eq!(dest.signing_public_key(), ed25519_public_key(&seed));
+		assert_ne!(I2pPrivateKey::generate(), key);
+	}
+
+	#[test]
+	fn key_files() {
+		let dir = std::env::temp_dir().join(format!("i2p-rs-keys-{}", std::process::id()));
+		std::fs::create_dir_all(&dir).unwrap();
+		let key = I2pPrivateKey::generate();
+
+		let plain = dir.join("plain.dat");
+		key.save(&plain).unwrap();
+		assert_eq!(std::fs::read(&plain).unwrap(), BASE64_I2P.decode(key.as_str().as_bytes()).unwrap());
+		assert_eq!(I2pPrivateKey::load(&plain).unwrap(), key);
+
+		let sealed = dir.join("sealed.dat");
+		let contents = std::fs::read(&plain).unwrap();
+		std::fs::write(&sealed, crate::keyfile::seal(&contents, "pass", crate::keyfile::MIN_ITERATIONS)).unwrap();
+		assert_eq!(I2pPrivateKey::load_encrypted(&sealed, "pass").unwrap(), key);
+		assert!(I2pPrivateKey::load_encrypted(&sealed, "wrong").is_err());
+		assert!(I2pPrivateKey::load(&sealed).is_err());
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn serde_forms() {
+		let mut bytes = vec![7u8; 384];
+		bytes.extend_from_slice(&[5, 0, 4, 0, 7, 0, 4]);
+		let dest = Destination::from_bytes(&bytes).unwrap();
+		let b64 = dest.to_base64();
+		let human: StrDeserializer<ValueError> = b64.as_str().into_deserializer();
+		assert_eq!(Destination::deserialize(human).unwrap(), dest);
+		assert_eq!(Destination::deserialize(Binary(&bytes)).unwrap(), dest);
+		assert!(Destination::deserialize(Binary(&bytes[1..])).is_err());
+
+		let addr = dest.addr();
+		let compact = addr.to_compact();
+		assert_eq!(compact.len(), 33);
+		assert_eq!(I2pAddr::deserialize(Binary(&compact)).unwrap(), addr);
+		let name = I2pAddr::new("example.i2p");
+		assert_eq!(name.to_compact(), b"\x01example.i2p");
+		assert_eq!(I2pAddr::deserialize(Binary(&name.to_compact())).unwrap(), name);
+		assert!(I2pAddr::deserialize(Binary(&compact[..20])).is_err());
+		assert!(I2pAddr::deserialize(Binary(b"")).is_err());
+	}
+}
@@ -1,8 +1,10 @@
+// failure_derive wraps its impls in a const, which trips this newer lint
+#![allow(non_local_definitions)]
+
 use std::fmt::{self, Display};
 use std::io;
 
 use failure::{Backtrace, Context, Fail};
-use nom;
 
 /// I2P/SAM error definition
 #[derive(Debug, Fail)]
@@ -19,7 +21,7 @@ pub enum ErrorKind {
 	/// Wraps nom parser errors
 	#[fail(display = "Failed to parse an I2P/SAM message")]
 	MessageParsing,
-	#[fail(display = "Failed to parse an I2P/SAM message")]
+	#[fail(display = "Unresolvable I2P address")]
 	UnresolvableAddress,
 	#[fail(display = "Invalid or unrecognized I2P/SAM message: {}", _0)]
 	SAMInvalidMessage(String),
@@ -31,6 +33,14 @@ pub enum ErrorKind {
 	SAMPeerNotFound(String),
 	#[fail(display = "Duplicate peer destination: {}", _0)]
 	SAMDuplicatedDest(String),
+	#[fail(display = "Duplicate session id: {}", _0)]
+	SAMDuplicatedId(String),
+	#[fail(display = "No SAM version supported by both sides: {}", _0)]
+	SAMNoVersion(String),
+	#[fail(display = "Already accepting on this session: {}", _0)]
+	SAMAlreadyAccepting(String),
+	#[fail(display = "Not supported by the SAM bridge: {}", _0)]
+	SAMUnsupported(String),
 	#[fail(display = "Invalid destination key: {}", _0)]
 	SAMInvalidKey(String),
 	#[fail(display = "Invalid stream id: {}", _0)]
@@ -41,12 +51,110 @@ pub enum ErrorKind {
 	SAMI2PError(String),
 	#[fail(display = "I2P address isn't a valid b32 or b64 encoding: {}", _0)]
 	BadAddressEncoding(String),
+	#[fail(display = "Invalid destination key material: {}", _0)]
+	BadKeyMaterial(String),
+	#[fail(display = "TLS error on the SAM connection: {}", _0)]
+	Tls(String),
+	/// A nonblocking operation couldn't complete yet and should be retried
+	#[fail(display = "Operation would block")]
+	WouldBlock,
 }
 
 impl ErrorKind {
 	pub fn to_err(self) -> Error {
 		Error{inner: Context::new(self)}
 	}
+
+	/// Why a stream couldn't be connected, for errors returned when opening
+	/// one, `None` for errors unrelated to connecting.
+	pub fn connect_failure(&self) -> Option<ConnectFailure> {
+		let mentions = |msg: &str, words: &[&str]| {
+			let msg = msg.to_lowercase();
+			words.iter().any(|w| msg.contains(w))
+		};
+		Some(match self {
+			ErrorKind::SAMCantReachPeer(msg) if mentions(msg, &["reset", "refused"]) => {
+				ConnectFailure::PeerRefused
+			}
+			ErrorKind::SAMCantReachPeer(msg) | ErrorKind::SAMI2PError(msg) | ErrorKind::SAMTimeout(msg)
+				if mentions(msg, &["tunnel"]) =>
+			{
+				ConnectFailure::TunnelsNotReady
+			}
+			ErrorKind::SAMCantReachPeer(_)
+			| ErrorKind::SAMPeerNotFound(_)
+			| ErrorKind::SAMKeyNotFound(_)
+			| ErrorKind::UnresolvableAddress => ConnectFailure::PeerNotFound,
+			ErrorKind::SAMTimeout(_) => ConnectFailure::Timeout,
+			ErrorKind::SAMInvalidKey(_) | ErrorKind::BadAddressEncoding(_) => ConnectFailure::InvalidPeer,
+			ErrorKind::Io(_)
+			| ErrorKind::Tls(_)
+			| ErrorKind::MessageParsing
+			| ErrorKind::SAMInvalidMessage(_)
+			| ErrorKind::SAMInvalidId(_)
+			| ErrorKind::SAMI2PError(_)
+			| ErrorKind::SAMUnsupported(_) => ConnectFailure::Bridge,
+			_ => return None,
+		})
+	}
+
+	/// Why a name couldn't be resolved, for errors returned by lookups, `None`
+	/// for errors unrelated to them.
+	pub fn lookup_failure(&self) -> Option<LookupFailure> {
+		Some(match self {
+			ErrorKind::BadAddressEncoding(_) | ErrorKind::SAMInvalidKey(_) => LookupFailure::InvalidName,
+			ErrorKind::SAMKeyNotFound(_) | ErrorKind::UnresolvableAddress => LookupFailure::NotFound,
+			ErrorKind::Io(_)
+			| ErrorKind::Tls(_)
+			| ErrorKind::MessageParsing
+			| ErrorKind::SAMInvalidMessage(_)
+			| ErrorKind::SAMInvalidId(_)
+			| ErrorKind::SAMI2PError(_)
+			| ErrorKind::SAMTimeout(_)
+			| ErrorKind::SAMUnsupported(_) => LookupFailure::Bridge,
+			_ => return None,
+		})
+	}
+}
+
+/// Categories of name lookup failures, see `ErrorKind::lookup_failure`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LookupFailure {
+	/// The name isn't a valid hostname, b32 address or destination, it was
+	/// never sent to the bridge.
+	InvalidName,
+	/// The router doesn't know the name: the only failure worth trying
+	/// again elsewhere, such as through a jump service.
+	NotFound,
+	/// The SAM bridge failed or couldn't be reached.
+	Bridge,
+}
+
+/// Categories of stream connection failures, see
+/// `ErrorKind::connect_failure`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ConnectFailure {
+	/// The name or the lease set of the peer couldn't be found, the peer
+	/// being offline or not published yet: worth retrying after backing off.
+	PeerNotFound,
+	/// The peer reset the connection, nothing listening on its end.
+	PeerRefused,
+	/// The tunnels of our session aren't built yet: worth retrying soon.
+	TunnelsNotReady,
+	/// The peer didn't answer in time, which may or may not be temporary.
+	Timeout,
+	/// The destination of the peer is malformed, retrying won't help.
+	InvalidPeer,
+	/// The SAM bridge failed or couldn't be reached, the session possibly
+	/// needing to be created again.
+	Bridge,
+}
+
+impl ConnectFailure {
+	/// Whether connecting again later may succeed.
+	pub fn is_transient(self) -> bool {
+		!matches!(self, ConnectFailure::PeerRefused | ConnectFailure::InvalidPeer)
+	}
 }
 
 impl Display for Error {
@@ -92,14 +200,18 @@ impl From<ErrorKind> for Error {
 
 impl From<Context<ErrorKind>> for Error {
 	fn from(inner: Context<ErrorKind>) -> Error {
-		Error { inner: inner }
+		Error { inner }
 	}
 }
 
 impl From<io::Error> for Error {
 	fn from(err: io::Error) -> Error {
+		let kind = match err.kind() {
+			io::ErrorKind::WouldBlock => ErrorKind::WouldBlock,
+			_ => ErrorKind::Io(err.to_string()),
+		};
 		Error {
-			inner: Context::new(ErrorKind::Io(err.to_string())),
+			inner: Context::new(kind),
 		}
 	}
 }
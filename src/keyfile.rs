@@ -0,0 +1,125 @@
+//! Passphrase-encrypted container for private keys, see
+//! `I2pPrivateKey::save_encrypted`.
+//!
+//! The container is the magic, a random salt, the PBKDF2-HMAC-SHA256
+//! iteration count and a random nonce, followed by the keys sealed with
+//! ChaCha20-Poly1305, the header being authenticated along. The key is
+//! derived from the passphrase, both primitives coming from `ring`.
+
+use std::num::NonZeroU32;
+
+use rand::RngCore;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305};
+use ring::pbkdf2;
+use zeroize::Zeroize;
+
+use crate::error::{Error, ErrorKind};
+
+const MAGIC: &[u8; 8] = b"I2PKEYS\x01";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+const HEADER_LEN: usize = MAGIC.len() + SALT_LEN + 4 + NONCE_LEN;
+
+/// PBKDF2 iterations of newly encrypted containers
+pub(crate) const DEFAULT_ITERATIONS: u32 = 100_000;
+/// Fewest PBKDF2 iterations accepted
+pub(crate) const MIN_ITERATIONS: u32 = 10_000;
+/// Most PBKDF2 iterations accepted, so a crafted file can't keep the caller
+/// deriving keys for hours
+pub(crate) const MAX_ITERATIONS: u32 = 10_000_000;
+
+/// Encrypts `plaintext` with a key derived from `passphrase`.
+pub(crate) fn seal(plaintext: &[u8], passphrase: &str, iterations: u32) -> Vec<u8> {
+	let mut rng = rand::thread_rng();
+	let mut salt = [0u8; SALT_LEN];
+	let mut nonce = [0u8; NONCE_LEN];
+	rng.fill_bytes(&mut salt);
+	rng.fill_bytes(&mut nonce);
+
+	let mut sealed = Vec::with_capacity(HEADER_LEN + plaintext.len() + TAG_LEN);
+	sealed.extend_from_slice(MAGIC);
+	sealed.extend_from_slice(&salt);
+	sealed.extend_from_slice(&iterations.to_be_bytes());
+	sealed.extend_from_slice(&nonce);
+	let mut content = plaintext.to_vec();
+	let key = derive_key(passphrase, &salt, iterations);
+	key.seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::from(&sealed[..]), &mut content)
+		.expect("keys fit in a ChaCha20-Poly1305 message");
+	sealed.extend_from_slice(&content);
+	content.zeroize();
+	sealed
+}
+
+/// Decrypts a container produced by `seal`, checking its iteration count
+/// before deriving the key.
+pub(crate) fn open(sealed: &[u8], passphrase: &str) -> Result<Vec<u8>, Error> {
+	if sealed.len() < HEADER_LEN + TAG_LEN || &sealed[..MAGIC.len()] != MAGIC {
+		return Err(ErrorKind::BadKeyMaterial("not an encrypted key file".to_string()).into());
+	}
+	let (header, content) = sealed.split_at(HEADER_LEN);
+	let salt = &header[MAGIC.len()..MAGIC.len() + SALT_LEN];
+	let mut iterations = [0u8; 4];
+	iterations.copy_from_slice(&header[MAGIC.len() + SALT_LEN..MAGIC.len() + SALT_LEN + 4]);
+	let iterations = u32::from_be_bytes(iterations);
+	if !(MIN_ITERATIONS..=MAX_ITERATIONS).contains(&iterations) {
+		return Err(ErrorKind::BadKeyMaterial(format!(
+			"{} PBKDF2 iterations, outside of {}..={}",
+			iterations, MIN_ITERATIONS, MAX_ITERATIONS
+		))
+		.into());
+	}
+	let mut nonce = [0u8; NONCE_LEN];
+	nonce.copy_from_slice(&header[HEADER_LEN - NONCE_LEN..]);
+
+	let key = derive_key(passphrase, salt, iterations);
+	let mut content = content.to_vec();
+	let plaintext = match key.open_in_place(Nonce::assume_unique_for_key(nonce), Aad::from(header), &mut content) {
+		Ok(plaintext) => plaintext.to_vec(),
+		Err(_) => {
+			return Err(ErrorKind::BadKeyMaterial(
+				"wrong passphrase or corrupted key file".to_string(),
+			)
+			.into());
+		}
+	};
+	content.zeroize();
+	Ok(plaintext)
+}
+
+fn derive_key(passphrase: &str, salt: &[u8], iterations: u32) -> LessSafeKey {
+	let iterations = NonZeroU32::new(iterations).expect("checked iteration count");
+	let mut key = [0u8; 32];
+	pbkdf2::derive(pbkdf2::PBKDF2_HMAC_SHA256, iterations, salt, passphrase.as_bytes(), &mut key);
+	let unbound = UnboundKey::new(&CHACHA20_POLY1305, &key).expect("32 bytes key");
+	key.zeroize();
+	LessSafeKey::new(unbound)
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::keyfile::{open, seal, MAX_ITERATIONS, MIN_ITERATIONS};
+
+	#[test]
+	fn sealed_container() {
+		let sealed = seal(b"secret keys", "passphrase", MIN_ITERATIONS);
+		assert_eq!(open(&sealed, "passphrase").unwrap(), b"secret keys");
+		assert!(open(&sealed, "wrong").is_err());
+		let mut corrupted = sealed.clone();
+		corrupted[45] ^= 1;
+		assert!(open(&corrupted, "passphrase").is_err());
+		assert!(open(b"secret keys", "passphrase").is_err());
+	}
+
+	#[test]
+	fn iteration_bounds() {
+		for &iterations in &[0, 1, MIN_ITERATIONS - 1, MAX_ITERATIONS + 1, u32::MAX] {
+			// rejected before deriving anything, or this would take hours
+			let sealed = seal(b"secret keys", "passphrase", iterations.clamp(1, 2));
+			let mut crafted = sealed.clone();
+			crafted[24..28].copy_from_slice(&iterations.to_be_bytes());
+			let err = open(&crafted, "passphrase").unwrap_err();
+			assert!(format!("{}", err).contains("iterations"), "{}", err);
+		}
+	}
+}
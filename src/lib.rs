@@ -1,8 +1,23 @@
+pub mod addressbook;
+#[cfg(feature = "async")]
+pub mod asynch;
+pub mod codec;
+pub mod destination;
 mod error;
+mod keyfile;
+pub mod naming;
 pub mod net;
-mod sam;
+pub mod offline;
+pub mod protocol;
+pub mod proxy;
+pub mod reply;
+pub mod sam;
+pub mod sam_options;
+mod transport;
+pub mod websocket;
 
 mod parsers;
 
-pub use crate::error::{Error, ErrorKind};
-pub use crate::sam::{Session, SamConnection};
+pub use crate::error::{ConnectFailure, Error, ErrorKind, LookupFailure};
+pub use crate::sam::{SamCapabilities, SamConnection, SamEndpoint, Session};
+pub use crate::sam_options::SessionOptions;
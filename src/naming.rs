@@ -0,0 +1,307 @@
+//! Resolution of I2P names to destinations.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::error::{Error, ErrorKind};
+use crate::net::{I2pAddr, B32_EXT};
+use crate::sam::SamConnection;
+
+/// Resolves names to base64 destinations before streams get connected. A
+/// resolver plugged into a `SamConnection` with `with_resolver` replaces the
+/// `NAMING LOOKUP` of the bridge, which stays the default.
+pub trait Resolver: Send + Sync {
+	/// The base64 destination of the provided hostname or b32 address.
+	fn resolve(&self, name: &str) -> Result<String, Error>;
+
+	/// A hostname known for the destination with the provided b32 address,
+	/// for display. Resolvers without local names return `None`.
+	fn reverse(&self, _addr: &I2pAddr) -> Option<String> {
+		None
+	}
+}
+
+/// The b32 address of a base64 destination or b32 address, the form reverse
+/// lookups compare destinations with.
+pub(crate) fn reverse_target(dest: &str) -> Option<I2pAddr> {
+	if dest.to_ascii_lowercase().ends_with(B32_EXT) {
+		I2pAddr::from_b32(dest).ok().filter(|addr| addr.to_hash().is_some())
+	} else {
+		I2pAddr::from_b64(dest).ok()
+	}
+}
+
+/// The first hostname among `entries` whose destination has the b32
+/// address `addr`.
+pub(crate) fn find_hostname<'a, I>(entries: I, addr: &I2pAddr) -> Option<String>
+where
+	I: IntoIterator<Item = (&'a String, &'a String)>,
+{
+	entries
+		.into_iter()
+		.filter(|(name, _)| name.ends_with(".i2p") && !name.ends_with(B32_EXT))
+		.find(|(_, dest)| I2pAddr::from_b64(dest).ok().as_ref() == Some(addr))
+		.map(|(name, _)| name.clone())
+}
+
+/// Resolves names with `NAMING LOOKUP` on a dedicated bridge connection.
+pub struct SamResolver {
+	sam: Mutex<SamConnection>,
+}
+
+impl SamResolver {
+	pub fn new(sam: SamConnection) -> SamResolver {
+		SamResolver { sam: Mutex::new(sam) }
+	}
+}
+
+impl Resolver for SamResolver {
+	fn resolve(&self, name: &str) -> Result<String, Error> {
+		self.sam
+			.lock()
+			.map_err(|_| ErrorKind::Io("SAM connection lock poisoned".to_string()))?
+			.naming_lookup(name)
+	}
+}
+
+/// Resolves names from a fixed map, for tests or a local address book.
+#[derive(Clone, Debug, Default)]
+pub struct StaticResolver {
+	names: HashMap<String, String>,
+}
+
+impl StaticResolver {
+	pub fn new() -> StaticResolver {
+		StaticResolver::default()
+	}
+
+	/// Adds the destination of a name.
+	pub fn insert(mut self, name: &str, dest: &str) -> StaticResolver {
+		self.names.insert(name.to_string(), dest.to_string());
+		self
+	}
+}
+
+impl Resolver for StaticResolver {
+	fn resolve(&self, name: &str) -> Result<String, Error> {
+		self.names
+			.get(name)
+			.cloned()
+			.ok_or_else(|| ErrorKind::SAMKeyNotFound(name.to_string()).into())
+	}
+
+	fn reverse(&self, addr: &I2pAddr) -> Option<String> {
+		find_hostname(&self.names, addr)
+	}
+}
+
+/// Tries resolvers in turn until one knows the name, for example a local
+/// address book before the bridge before a jump service.
+///
+/// # Examples
+///
+/// ```
+/// use i2p::naming::{Resolver, ResolverChain, StaticResolver};
+///
+/// let chain = ResolverChain::new()
+///     .then(StaticResolver::new().insert("a.i2p", "a"))
+///     .then(StaticResolver::new().insert("b.i2p", "b"));
+/// assert_eq!(chain.resolve("b.i2p").unwrap(), "b");
+/// assert!(chain.resolve("c.i2p").is_err());
+/// ```
+#[derive(Default)]
+pub struct ResolverChain {
+	resolvers: Vec<Box<dyn Resolver>>,
+}
+
+impl ResolverChain {
+	pub fn new() -> ResolverChain {
+		ResolverChain::default()
+	}
+
+	/// Adds a resolver, tried after the ones already in the chain.
+	pub fn then<R: Resolver + 'static>(mut self, resolver: R) -> ResolverChain {
+		self.resolvers.push(Box::new(resolver));
+		self
+	}
+}
+
+impl Resolver for ResolverChain {
+	/// The destination from the first resolver knowing the name, or the
+	/// error of the last one.
+	fn resolve(&self, name: &str) -> Result<String, Error> {
+		let mut last_err = ErrorKind::SAMKeyNotFound(name.to_string()).into();
+		for resolver in &self.resolvers {
+			match resolver.resolve(name) {
+				Ok(dest) => return Ok(dest),
+				Err(e) => last_err = e,
+			}
+		}
+		Err(last_err)
+	}
+
+	/// The hostname from the first resolver knowing the destination.
+	fn reverse(&self, addr: &I2pAddr) -> Option<String> {
+		self.resolvers.iter().find_map(|r| r.reverse(addr))
+	}
+}
+
+/// A cache of `NAMING LOOKUP` results, keyed by name, with a time to live
+/// and a maximum size. Clones share the same entries, so a cache attached
+/// to a `SamConnection` is used by all the connections derived from it.
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+/// use i2p::naming::NamingCache;
+///
+/// let cache = NamingCache::new(Duration::from_secs(600), 128);
+/// cache.insert("example.i2p", "dest");
+/// assert_eq!(cache.get("example.i2p"), Some("dest".to_string()));
+/// ```
+#[derive(Clone, Debug)]
+pub struct NamingCache {
+	ttl: Duration,
+	max_entries: usize,
+	entries: Arc<Mutex<HashMap<String, (String, Instant)>>>,
+}
+
+impl NamingCache {
+	/// Creates an empty cache keeping up to `max_entries` names for `ttl`.
+	pub fn new(ttl: Duration, max_entries: usize) -> NamingCache {
+		NamingCache {
+			ttl,
+			max_entries,
+			entries: Arc::new(Mutex::new(HashMap::new())),
+		}
+	}
+
+	/// The cached destination for the provided name, if it hasn't expired.
+	pub fn get(&self, name: &str) -> Option<String> {
+		let mut entries = self.entries.lock().ok()?;
+		match entries.get(name) {
+			Some((dest, added)) if added.elapsed() < self.ttl => Some(dest.clone()),
+			Some(_) => {
+				entries.remove(name);
+				None
+			}
+			None => None,
+		}
+	}
+
+	/// Caches the destination of the provided name, evicting expired entries
+	/// and then the oldest ones to stay within the maximum size.
+	pub fn insert(&self, name: &str, dest: &str) {
+		if self.max_entries == 0 {
+			return;
+		}
+		let mut entries = match self.entries.lock() {
+			Ok(entries) => entries,
+			Err(_) => return,
+		};
+		if entries.len() >= self.max_entries && !entries.contains_key(name) {
+			let ttl = self.ttl;
+			entries.retain(|_, (_, added)| added.elapsed() < ttl);
+			while entries.len() >= self.max_entries {
+				let oldest = entries
+					.iter()
+					.min_by_key(|(_, (_, added))| *added)
+					.map(|(name, _)| name.clone());
+				match oldest {
+					Some(oldest) => entries.remove(&oldest),
+					None => break,
+				};
+			}
+		}
+		entries.insert(name.to_string(), (dest.to_string(), Instant::now()));
+	}
+
+	/// A cached hostname of the destination with the provided b32 address.
+	pub fn reverse(&self, addr: &I2pAddr) -> Option<String> {
+		let entries = self.entries.lock().ok()?;
+		let ttl = self.ttl;
+		find_hostname(
+			entries
+				.iter()
+				.filter(|(_, (_, added))| added.elapsed() < ttl)
+				.map(|(name, (dest, _))| (name, dest)),
+			addr,
+		)
+	}
+
+	/// The names cached and not expired yet.
+	pub fn names(&self) -> Vec<String> {
+		let entries = match self.entries.lock() {
+			Ok(entries) => entries,
+			Err(_) => return vec![],
+		};
+		let ttl = self.ttl;
+		entries
+			.iter()
+			.filter(|(_, (_, added))| added.elapsed() < ttl)
+			.map(|(name, _)| name.clone())
+			.collect()
+	}
+
+	/// Forgets the cached destination of the provided name.
+	pub fn remove(&self, name: &str) {
+		if let Ok(mut entries) = self.entries.lock() {
+			entries.remove(name);
+		}
+	}
+
+	/// Forgets all cached destinations.
+	pub fn clear(&self) {
+		if let Ok(mut entries) = self.entries.lock() {
+			entries.clear();
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::thread;
+	use std::time::Duration;
+
+	use crate::naming::{reverse_target, NamingCache, Resolver, ResolverChain, StaticResolver};
+
+	#[test]
+	fn naming_cache() {
+		let cache = NamingCache::new(Duration::from_secs(60), 2);
+		cache.insert("a.i2p", "a");
+		cache.insert("b.i2p", "b");
+		cache.insert("c.i2p", "c");
+		assert_eq!(cache.get("a.i2p"), None);
+		assert_eq!(cache.get("b.i2p"), Some("b".to_string()));
+		assert_eq!(cache.clone().get("c.i2p"), Some("c".to_string()));
+		let mut names = cache.names();
+		names.sort();
+		assert_eq!(names, ["b.i2p", "c.i2p"]);
+
+		let cache = NamingCache::new(Duration::from_millis(10), 2);
+		cache.insert("a.i2p", "a");
+		thread::sleep(Duration::from_millis(20));
+		assert_eq!(cache.get("a.i2p"), None);
+	}
+
+	#[test]
+	fn reverse_lookup() {
+		let dest = "A".repeat(516);
+		let addr = reverse_target(&dest).unwrap();
+		assert_eq!(reverse_target(&addr.string()), Some(addr.clone()));
+
+		let cache = NamingCache::new(Duration::from_secs(60), 4);
+		cache.insert(&addr.string(), &dest);
+		assert_eq!(cache.reverse(&addr), None);
+		cache.insert("cached.i2p", &dest);
+		assert_eq!(cache.reverse(&addr), Some("cached.i2p".to_string()));
+
+		let chain = ResolverChain::new()
+			.then(StaticResolver::new().insert("other.i2p", "B"))
+			.then(StaticResolver::new().insert("static.i2p", &dest));
+		assert_eq!(chain.reverse(&addr), Some("static.i2p".to_string()));
+		assert_eq!(chain.reverse(&reverse_target(&"B".repeat(516)).unwrap()), None);
+	}
+}
@@ -3,12 +3,17 @@ use std::io;
 use std::iter;
 use std::option;
 use std::slice;
+use std::str::FromStr;
 use std::vec;
 
 use serde_derive::{Serialize, Deserialize};
 
+use crate::destination::Destination;
+use crate::error::{Error, ErrorKind};
 use crate::net::i2p::I2pAddr;
 
+/// An address and a port. Binary serialization formats get the compact
+/// form of the address, see `I2pAddr`.
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, Hash)]
 pub struct I2pSocketAddr {
 	port: u16,
@@ -28,10 +33,7 @@ impl I2pSocketAddr {
 	/// assert_eq!(socket.port(), 8080);
 	/// ```
 	pub fn new(dest: I2pAddr, port: u16) -> I2pSocketAddr {
-		I2pSocketAddr {
-			port: port,
-			dest: dest,
-		}
+		I2pSocketAddr { port, dest }
 	}
 
 	/// Returns the I2P address associated with this socket address.
@@ -99,6 +101,30 @@ impl fmt::Display for I2pSocketAddr {
 	}
 }
 
+/// Parses `<host>:<port>` strings, the host being a hostname, b32 address
+/// or base64 destination, which don't contain any `:`, validated as by
+/// `I2pAddr`'s `FromStr`.
+///
+/// # Examples
+///
+/// ```
+/// use i2p::net::{I2pAddr, I2pSocketAddr};
+///
+/// let socket: I2pSocketAddr = "example.i2p:8080".parse().unwrap();
+/// assert_eq!(socket, I2pSocketAddr::new(I2pAddr::new("example.i2p"), 8080));
+/// assert!("example.i2p".parse::<I2pSocketAddr>().is_err());
+/// ```
+impl FromStr for I2pSocketAddr {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<I2pSocketAddr, Error> {
+		let invalid = || ErrorKind::BadAddressEncoding(s.to_string());
+		let (host, port) = s.rsplit_once(':').ok_or_else(invalid)?;
+		let port = port.parse().map_err(|_| invalid())?;
+		Ok(I2pSocketAddr::new(host.parse()?, port))
+	}
+}
+
 /// A trait for objects which can be converted or resolved to one or more
 /// `I2pSocketAddr` values.
 ///
@@ -109,9 +135,11 @@ impl fmt::Display for I2pSocketAddr {
 ///
 ///  * `(I2pAddr, u16)` - `to_socket_addrs` constructs `I2pSocketAddr` trivially.
 ///
-///  * `(&str, u16)` - the string should be either a string representation of an
-///    I2P address expected by `FromStr` implementation for `I2pAddr` or a host
-///    name.
+///  * `(Destination, u16)` and `(&Destination, u16)` - the address keeps the
+///    full destination, which is connected to without any lookup.
+///
+///  * `(&str, u16)` and `(String, u16)` - the string should be a b32 address,
+///    a base64 destination or a host name.
 ///
 ///  * `&str` - the string should be either a string representation of a
 ///    `I2pSocketAddr` as expected by its `FromStr` implementation or a string like
@@ -132,6 +160,7 @@ impl fmt::Display for I2pSocketAddr {
 /// Some examples:
 ///
 /// ```no_run
+/// use i2p::destination::Destination;
 /// use i2p::net::{I2pSocketAddr, I2pStream, I2pDatagramSocket, I2pListener, I2pAddr};
 ///
 /// fn main() {
@@ -144,6 +173,10 @@ impl fmt::Display for I2pSocketAddr {
 ///     let i2p_s = I2pStream::connect(("example.i2p", port));
 ///     let i2p_s = I2pStream::connect("example.i2p:12345");
 ///
+///     // Parsed destinations need no lookup
+///     let peer: Destination = std::fs::read_to_string("peer.b64").unwrap().trim().parse().unwrap();
+///     let i2p_s = I2pStream::connect((&peer, port));
+///
 ///     // I2pListener::bind(), I2pDatagramSocket::bind() and I2pDatagramSocket::send_to()
 ///     // behave similarly
 ///     let i2p_l = I2pListener::bind();
@@ -186,7 +219,21 @@ impl ToI2pSocketAddrs for (I2pAddr, u16) {
 	}
 }
 
-impl<'a> ToI2pSocketAddrs for (&'a str, u16) {
+impl ToI2pSocketAddrs for (Destination, u16) {
+	type Iter = option::IntoIter<I2pSocketAddr>;
+	fn to_socket_addrs(&self) -> io::Result<option::IntoIter<I2pSocketAddr>> {
+		(&self.0, self.1).to_socket_addrs()
+	}
+}
+
+impl ToI2pSocketAddrs for (&Destination, u16) {
+	type Iter = option::IntoIter<I2pSocketAddr>;
+	fn to_socket_addrs(&self) -> io::Result<option::IntoIter<I2pSocketAddr>> {
+		I2pSocketAddr::new(self.0.addr(), self.1).to_socket_addrs()
+	}
+}
+
+impl ToI2pSocketAddrs for (&str, u16) {
 	type Iter = vec::IntoIter<I2pSocketAddr>;
 	fn to_socket_addrs(&self) -> io::Result<vec::IntoIter<I2pSocketAddr>> {
 		let (host, port) = *self;
@@ -195,25 +242,21 @@ impl<'a> ToI2pSocketAddrs for (&'a str, u16) {
 	}
 }
 
+impl ToI2pSocketAddrs for (String, u16) {
+	type Iter = vec::IntoIter<I2pSocketAddr>;
+	fn to_socket_addrs(&self) -> io::Result<vec::IntoIter<I2pSocketAddr>> {
+		(&*self.0, self.1).to_socket_addrs()
+	}
+}
+
 // accepts strings like 'example.i2p:12345'
 impl ToI2pSocketAddrs for str {
 	type Iter = vec::IntoIter<I2pSocketAddr>;
 	fn to_socket_addrs(&self) -> io::Result<vec::IntoIter<I2pSocketAddr>> {
-		macro_rules! try_opt {
-			($e:expr, $msg:expr) => {
-				match $e {
-					Some(r) => r,
-					None => return Err(io::Error::new(io::ErrorKind::InvalidInput, $msg)),
-					}
-			};
-		}
-
-		// split the string by ':' and convert the second part to u16
-		let mut parts_iter = self.rsplitn(2, ':');
-		let port_str = try_opt!(parts_iter.next(), "invalid I2P socket address");
-		let host = try_opt!(parts_iter.next(), "invalid I2P socket address");
-		let port: u16 = try_opt!(port_str.parse().ok(), "invalid port value");
-		(host, port).to_socket_addrs()
+		let addr: I2pSocketAddr = self
+			.parse()
+			.map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid I2P socket address"))?;
+		Ok(vec![addr].into_iter())
 	}
 }
 
@@ -225,7 +268,7 @@ impl<'a> ToI2pSocketAddrs for &'a [I2pSocketAddr] {
 	}
 }
 
-impl<'a, T: ToI2pSocketAddrs + ?Sized> ToI2pSocketAddrs for &'a T {
+impl<T: ToI2pSocketAddrs + ?Sized> ToI2pSocketAddrs for &T {
 	type Iter = T::Iter;
 	fn to_socket_addrs(&self) -> io::Result<T::Iter> {
 		(**self).to_socket_addrs()
@@ -235,7 +278,7 @@ impl<'a, T: ToI2pSocketAddrs + ?Sized> ToI2pSocketAddrs for &'a T {
 impl ToI2pSocketAddrs for String {
 	type Iter = vec::IntoIter<I2pSocketAddr>;
 	fn to_socket_addrs(&self) -> io::Result<vec::IntoIter<I2pSocketAddr>> {
-		(&**self).to_socket_addrs()
+		(**self).to_socket_addrs()
 	}
 }
 
@@ -252,6 +295,19 @@ mod tests {
 		assert_eq!(Ok(vec![e]), tsa((a, p)));
 	}
 
+	#[test]
+	fn to_socket_addr_destination_u16() {
+		use crate::destination::Destination;
+
+		let mut bytes = vec![9u8; 384];
+		bytes.extend_from_slice(&[5, 0, 4, 0, 7, 0, 4]);
+		let dest = Destination::from_bytes(&bytes).unwrap();
+		let addrs = tsa((&dest, 80)).unwrap();
+		assert_eq!(addrs, vec![isa(dest.addr(), 80)]);
+		assert_eq!(addrs[0].dest().destination_b64(), Some(&dest.to_base64()[..]));
+		assert_eq!(tsa((dest.clone(), 80)).unwrap(), addrs);
+	}
+
 	#[test]
 	fn to_socket_addr_str_u16() {
 		let a = isa(I2pAddr::new("example.i2p"), 24352);
@@ -271,6 +327,7 @@ mod tests {
 	}
 
 	#[test]
+	#[allow(clippy::needless_borrows_for_generic_args)]
 	fn to_socket_addr_string() {
 		let a = isa(I2pAddr::new("example.i2p"), 24352);
 		assert_eq!(
@@ -291,6 +348,23 @@ mod tests {
 		// s has been moved into the tsa call
 	}
 
+	#[test]
+	fn to_socket_addr_string_u16() {
+		let a = isa(I2pAddr::new("example.i2p"), 24352);
+		assert_eq!(Ok(vec![a]), tsa(("example.i2p".to_string(), 24352)));
+	}
+
+	#[test]
+	fn parse_socket_addr() {
+		let a = isa(I2pAddr::new("example.i2p"), 24352);
+		assert_eq!(a, "example.i2p:24352".parse().unwrap());
+		assert_eq!(a.to_string().parse::<I2pSocketAddr>().unwrap(), a);
+		assert!("example.i2p".parse::<I2pSocketAddr>().is_err());
+		assert!(":80".parse::<I2pSocketAddr>().is_err());
+		assert!("example.i2p:65536".parse::<I2pSocketAddr>().is_err());
+		assert!(tsa("example.i2p:http").is_err());
+	}
+
 	#[test]
 	fn set_dest() {
 		fn i2p(low: u8) -> I2pAddr {
@@ -1,15 +1,26 @@
+use std::io;
 use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use crate::error::{Error, ErrorKind};
-use crate::net::{I2pSocketAddr, ToI2pSocketAddrs};
-use crate::sam::DEFAULT_API;
+use crate::net::{socket_addr, I2pAddr, I2pSocketAddr, ToI2pSocketAddrs};
+use crate::sam::{DatagramSession, SamConnection, Session, SessionStyle, DEFAULT_API};
+use crate::sam_options::SessionOptions;
+
+/// Largest datagram payload I2P carries.
+const MAX_DATAGRAM_SIZE: usize = 65536;
+
+type Datagram = (Vec<u8>, I2pSocketAddr);
 
-/// Unimplemented
-///
 /// An I2P datagram socket.
 ///
-/// This is an implementation of a bound datagram socket. There is no
-/// corresponding notion of a server because is a datagram protocol.
+/// This is an implementation of a bound datagram socket, backed by a
+/// repliable datagram session. There is no corresponding notion of a server
+/// because is a datagram protocol.
+///
+/// The addresses of received datagrams hold the full base64 destination of
+/// their sender, so replying to them doesn't require a lookup.
 ///
 /// # Examples
 ///
@@ -33,13 +44,23 @@ use crate::sam::DEFAULT_API;
 /// } // the socket is closed here
 /// # }
 /// ```
-pub struct I2pDatagramSocket {}
+pub struct I2pDatagramSocket {
+	sender: Arc<Mutex<DatagramSession>>,
+	receiver: Arc<Mutex<DatagramSession>>,
+	// the next datagram, once peeked
+	peeked: Arc<Mutex<Option<Datagram>>>,
+	// the connected peer, along with its base64 destination
+	peer: Arc<Mutex<Option<(I2pSocketAddr, String)>>>,
+	local_port: u16,
+}
 
 impl I2pDatagramSocket {
 	/// Creates an I2P datagram socket from the given address.
 	///
 	/// The address type can be any implementor of [`ToI2pSocketAddrs`] trait. See
-	/// its documentation for concrete examples.
+	/// its documentation for concrete examples. A new transient destination
+	/// is created for the socket, only the port of the address being used, as
+	/// the port datagrams are sent from (SAM 3.2+, 0 leaving it unset).
 	///
 	/// [`ToI2pSocketAddrs`]: ../../i2p/net/trait.ToI2pSocketAddrs.html
 	///
@@ -58,15 +79,95 @@ impl I2pDatagramSocket {
 		sam_addr: A,
 		addr: B,
 	) -> Result<I2pDatagramSocket, Error> {
-		super::each_i2p_addr(sam_addr, addr, I2pDatagramSocket::bind_addr).map_err(|e| e.into())
+		super::each_i2p_addr(sam_addr, addr, I2pDatagramSocket::bind_addr)
 	}
 
-	fn bind_addr(_sam_addr: &SocketAddr, _addr: &I2pSocketAddr) -> Result<I2pDatagramSocket, Error> {
-		unimplemented!();
+	/// Same as `bind` but reuses an existing session, which must have been
+	/// created with a datagram style.
+	pub fn bind_with_session(session: &Session) -> Result<I2pDatagramSocket, Error> {
+		I2pDatagramSocket::from_session(DatagramSession::with_session(session)?, 0)
+	}
+
+	fn bind_addr(sam_addr: &SocketAddr, addr: &I2pSocketAddr) -> Result<I2pDatagramSocket, Error> {
+		let mut options = SessionOptions::new();
+		if addr.port() > 0 {
+			options = options.from_port(addr.port());
+		}
+		let session = DatagramSession::with_options(
+			sam_addr,
+			"TRANSIENT",
+			SessionStyle::Datagram,
+			&options,
+		)?;
+		I2pDatagramSocket::from_session(session, addr.port())
+	}
+
+	fn from_session(session: DatagramSession, local_port: u16) -> Result<I2pDatagramSocket, Error> {
+		// datagrams are received and sent over clones of the same control
+		// socket, so sending doesn't wait on a blocked receive
+		let receiver = session.duplicate()?;
+		Ok(I2pDatagramSocket {
+			sender: Arc::new(Mutex::new(session)),
+			receiver: Arc::new(Mutex::new(receiver)),
+			peeked: Arc::new(Mutex::new(None)),
+			peer: Arc::new(Mutex::new(None)),
+			local_port,
+		})
+	}
+
+	/// Receives the next datagram, skipping the ones not sent by the
+	/// connected peer, if any.
+	fn recv_datagram(&self) -> Result<Datagram, Error> {
+		let mut receiver = self.receiver.lock().unwrap_or_else(|e| e.into_inner());
+		loop {
+			let mut buf = vec![0; MAX_DATAGRAM_SIZE];
+			let (n, (dest, port)) = receiver.recv_from(&mut buf)?;
+			buf.truncate(n);
+			let addr = I2pSocketAddr::new(I2pAddr::new(&dest), port);
+			if self.is_from_peer(&addr) {
+				return Ok((buf, addr));
+			}
+		}
+	}
+
+	/// Whether a datagram from `addr` is for this socket, which is any
+	/// datagram for unconnected sockets.
+	fn is_from_peer(&self, addr: &I2pSocketAddr) -> bool {
+		match *self.peer.lock().unwrap_or_else(|e| e.into_inner()) {
+			Some((ref peer, ref peer_dest)) => {
+				addr.dest().string() == *peer_dest && (peer.port() == 0 || addr.port() == peer.port())
+			}
+			None => true,
+		}
+	}
+
+	/// Copies the next datagram into `buf`, leaving it queued if `peek`.
+	fn recv_impl(&self, buf: &mut [u8], peek: bool) -> Result<(usize, I2pSocketAddr), Error> {
+		let mut peeked = self.peeked.lock().unwrap_or_else(|e| e.into_inner());
+		let (datagram, addr) = match peeked.take() {
+			// the socket may have been connected since
+			Some((datagram, addr)) if self.is_from_peer(&addr) => (datagram, addr),
+			_ => self.recv_datagram()?,
+		};
+		let n = buf.len().min(datagram.len());
+		buf[..n].copy_from_slice(&datagram[..n]);
+		if peek {
+			*peeked = Some((datagram, addr.clone()));
+		}
+		Ok((n, addr))
+	}
+
+	fn connected_peer(&self) -> Result<(I2pSocketAddr, String), Error> {
+		self.peer
+			.lock()
+			.unwrap_or_else(|e| e.into_inner())
+			.clone()
+			.ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "Datagram socket not connected").into())
 	}
 
 	/// Receives data from the socket. On success, returns the number of bytes
-	/// read and the address from whence the data came.
+	/// read and the address from whence the data came. If the buffer is too
+	/// small to hold the datagram, the excess bytes are discarded.
 	///
 	/// # Examples
 	///
@@ -78,8 +179,8 @@ impl I2pDatagramSocket {
 	/// let (number_of_bytes, src_addr) = socket.recv_from(&mut buf)
 	///                                         .expect("Didn't receive data");
 	/// ```
-	pub fn recv_from(&self, _buf: &mut [u8]) -> Result<(usize, I2pSocketAddr), Error> {
-		unimplemented!()
+	pub fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, I2pSocketAddr), Error> {
+		self.recv_impl(buf, false)
 	}
 
 	/// Receives data from the socket, without removing it from the queue.
@@ -99,8 +200,8 @@ impl I2pDatagramSocket {
 	/// let (number_of_bytes, src_addr) = socket.peek_from(&mut buf)
 	///                                         .expect("Didn't receive data");
 	/// ```
-	pub fn peek_from(&self, _buf: &mut [u8]) -> Result<(usize, I2pSocketAddr), Error> {
-		unimplemented!()
+	pub fn peek_from(&self, buf: &mut [u8]) -> Result<(usize, I2pSocketAddr), Error> {
+		self.recv_impl(buf, true)
 	}
 
 	/// Sends data on the socket to the given address. On success, returns the
@@ -117,28 +218,39 @@ impl I2pDatagramSocket {
 	/// use i2p::net::I2pDatagramSocket;
 	///
 	/// let socket = I2pDatagramSocket::bind("127.0.0.1:34254").expect("couldn't bind to address");
-	/// socket.send_to(&[0; 10], "127.0.0.1:4242").expect("couldn't send data");
+	/// socket.send_to(&[0; 10], "example.i2p:4242").expect("couldn't send data");
 	/// ```
-	pub fn send_to<A: ToI2pSocketAddrs>(&self, _buf: &[u8], addr: A) -> Result<usize, Error> {
+	pub fn send_to<A: ToI2pSocketAddrs>(&self, buf: &[u8], addr: A) -> Result<usize, Error> {
 		match addr.to_socket_addrs()?.next() {
-			Some(_addr) => unimplemented!(),
+			Some(addr) => {
+				let dest = self.sender.lock().unwrap_or_else(|e| e.into_inner()).lookup(&addr.dest().target())?;
+				self.send_dest(buf, &dest, addr.port())
+			}
 			None => Err(ErrorKind::UnresolvableAddress.into()),
 		}
 	}
 
-	/// Returns the socket address that this socket was created from.
+	fn send_dest(&self, buf: &[u8], dest: &str, port: u16) -> Result<usize, Error> {
+		self.sender
+			.lock()
+			.unwrap_or_else(|e| e.into_inner())
+			.send_to(buf, dest, port)
+	}
+
+	/// Returns the socket address of this socket, the base32 address of its
+	/// destination along with the port it was bound to.
 	///
 	/// # Examples
 	///
 	/// ```no_run
-	/// use i2p::net::{I2pAddr, I2pSocketAddr, I2pDatagramSocket};
+	/// use i2p::net::I2pDatagramSocket;
 	///
 	/// let socket = I2pDatagramSocket::bind("127.0.0.1:34254").expect("couldn't bind to address");
-	/// assert_eq!(socket.local_addr().unwrap(),
-	///            I2pSocketAddr::new(I2pAddr::new("example.i2p"), 34254));
+	/// assert_eq!(socket.local_addr().unwrap().port(), 34254);
 	/// ```
 	pub fn local_addr(&self) -> Result<I2pSocketAddr, Error> {
-		unimplemented!()
+		let dest = self.sender.lock().unwrap_or_else(|e| e.into_inner()).local_addr()?;
+		Ok(socket_addr((dest, self.local_port)))
 	}
 
 	/// Creates a new independently owned handle to the underlying socket.
@@ -156,7 +268,51 @@ impl I2pDatagramSocket {
 	/// let socket_clone = socket.try_clone().expect("couldn't clone the socket");
 	/// ```
 	pub fn try_clone(&self) -> Result<I2pDatagramSocket, Error> {
-		unimplemented!()
+		Ok(I2pDatagramSocket {
+			sender: self.sender.clone(),
+			receiver: self.receiver.clone(),
+			peeked: self.peeked.clone(),
+			peer: self.peer.clone(),
+			local_port: self.local_port,
+		})
+	}
+
+	/// Sets the read timeout to the timeout specified.
+	///
+	/// If the value specified is `None`, then `recv` calls will block
+	/// indefinitely. Otherwise receives getting no datagram in time fail with
+	/// an error of kind `ErrorKind::SAMTimeout`.
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// use std::time::Duration;
+	/// use i2p::net::I2pDatagramSocket;
+	///
+	/// let socket = I2pDatagramSocket::bind("127.0.0.1:34254").expect("couldn't bind to address");
+	/// socket.set_read_timeout(Some(Duration::from_secs(5))).expect("set_read_timeout call failed");
+	/// ```
+	pub fn set_read_timeout(&self, dur: Option<Duration>) -> Result<(), Error> {
+		// both share the socket, and the receiver may be blocked receiving
+		self.sender.lock().unwrap_or_else(|e| e.into_inner()).set_read_timeout(dur)
+	}
+
+	/// Sets the write timeout to the timeout specified.
+	///
+	/// If the value specified is `None`, then `send` calls will block
+	/// indefinitely.
+	pub fn set_write_timeout(&self, dur: Option<Duration>) -> Result<(), Error> {
+		self.sender.lock().unwrap_or_else(|e| e.into_inner()).set_write_timeout(dur)
+	}
+
+	/// Returns the read timeout of this socket.
+	pub fn read_timeout(&self) -> Result<Option<Duration>, Error> {
+		self.sender.lock().unwrap_or_else(|e| e.into_inner()).read_timeout()
+	}
+
+	/// Returns the write timeout of this socket.
+	pub fn write_timeout(&self) -> Result<Option<Duration>, Error> {
+		self.sender.lock().unwrap_or_else(|e| e.into_inner()).write_timeout()
 	}
 
 	/// Connects this datagram socket to a remote address, allowing the `send` and
@@ -169,18 +325,36 @@ impl I2pDatagramSocket {
 	/// use i2p::net::I2pDatagramSocket;
 	///
 	/// let socket = I2pDatagramSocket::bind("127.0.0.1:34254").expect("couldn't bind to address");
-	/// socket.connect("127.0.0.1:8080").expect("connect function failed");
+	/// socket.connect("example.i2p:8080").expect("connect function failed");
 	/// ```
+	///
+	/// The peer's name is resolved once, through the bridge of the socket. A
+	/// port of 0 receives datagrams from any port of the peer.
 	pub fn connect<A: ToI2pSocketAddrs>(&self, addr: A) -> Result<(), Error> {
-		self.connect_via(DEFAULT_API, addr)
+		let addr = addr
+			.to_socket_addrs()?
+			.next()
+			.ok_or_else(|| Error::from(ErrorKind::UnresolvableAddress))?;
+		let dest = self.sender.lock().unwrap_or_else(|e| e.into_inner()).lookup(&addr.dest().target())?;
+		self.connected(addr, dest)
 	}
 
+	/// Same as `connect`, resolving the peer's name through the provided
+	/// bridge instead.
 	pub fn connect_via<A: ToSocketAddrs, B: ToI2pSocketAddrs>(
 		&self,
 		sam_addr: A,
 		addr: B,
 	) -> Result<(), Error> {
-		super::each_i2p_addr(sam_addr, addr, |_sam_addr, _addr| unimplemented!())
+		super::each_i2p_addr(sam_addr, addr, |sam_addr, addr| {
+			let dest = SamConnection::connect(sam_addr)?.resolve(&addr.dest().target())?;
+			self.connected(addr.clone(), dest)
+		})
+	}
+
+	fn connected(&self, addr: I2pSocketAddr, dest: String) -> Result<(), Error> {
+		*self.peer.lock().unwrap_or_else(|e| e.into_inner()) = Some((addr, dest));
+		Ok(())
 	}
 
 	/// Sends data on the socket to the remote address to which it is connected.
@@ -196,11 +370,12 @@ impl I2pDatagramSocket {
 	/// use i2p::net::I2pDatagramSocket;
 	///
 	/// let socket = I2pDatagramSocket::bind("127.0.0.1:34254").expect("couldn't bind to address");
-	/// socket.connect("127.0.0.1:8080").expect("connect function failed");
+	/// socket.connect("example.i2p:8080").expect("connect function failed");
 	/// socket.send(&[0, 1, 2]).expect("couldn't send message");
 	/// ```
-	pub fn send(&self, _buf: &[u8]) -> Result<usize, Error> {
-		unimplemented!()
+	pub fn send(&self, buf: &[u8]) -> Result<usize, Error> {
+		let (addr, dest) = self.connected_peer()?;
+		self.send_dest(buf, &dest, addr.port())
 	}
 
 	/// Receives data on the socket from the remote address to which it is
@@ -215,15 +390,16 @@ impl I2pDatagramSocket {
 	/// use i2p::net::I2pDatagramSocket;
 	///
 	/// let socket = I2pDatagramSocket::bind("127.0.0.1:34254").expect("couldn't bind to address");
-	/// socket.connect("127.0.0.1:8080").expect("connect function failed");
+	/// socket.connect("example.i2p:8080").expect("connect function failed");
 	/// let mut buf = [0; 10];
 	/// match socket.recv(&mut buf) {
 	///     Ok(received) => println!("received {} bytes", received),
 	///     Err(e) => println!("recv function failed: {:?}", e),
 	/// }
 	/// ```
-	pub fn recv(&self, _buf: &mut [u8]) -> Result<usize, Error> {
-		unimplemented!()
+	pub fn recv(&self, buf: &mut [u8]) -> Result<usize, Error> {
+		self.connected_peer()?;
+		self.recv_impl(buf, false).map(|(n, _)| n)
 	}
 
 	/// Receives data on the socket from the remote adress to which it is
@@ -243,14 +419,63 @@ impl I2pDatagramSocket {
 	/// use i2p::net::I2pDatagramSocket;
 	///
 	/// let socket = I2pDatagramSocket::bind("127.0.0.1:34254").expect("couldn't bind to address");
-	/// socket.connect("127.0.0.1:8080").expect("connect function failed");
+	/// socket.connect("example.i2p:8080").expect("connect function failed");
 	/// let mut buf = [0; 10];
 	/// match socket.peek(&mut buf) {
 	///     Ok(received) => println!("received {} bytes", received),
 	///     Err(e) => println!("peek function failed: {:?}", e),
 	/// }
 	/// ```
-	pub fn peek(&self, _buf: &mut [u8]) -> Result<usize, Error> {
-		unimplemented!()
+	pub fn peek(&self, buf: &mut [u8]) -> Result<usize, Error> {
+		self.connected_peer()?;
+		self.recv_impl(buf, true).map(|(n, _)| n)
+	}
+
+	/// Closes the session of the socket and all its handles, making receives
+	/// blocked on other threads fail right away.
+	pub fn shutdown(&self) -> Result<(), Error> {
+		self.sender.lock().unwrap_or_else(|e| e.into_inner()).shutdown()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::Read;
+
+	use crate::sam::tests::{scripted_bridge, session_script, Step};
+
+	#[test]
+	fn connected_datagrams() {
+		use crate::net::{I2pAddr, I2pDatagramSocket};
+
+		let peer = "A".repeat(516);
+		let other = "B".repeat(516);
+		let mut script = session_script("3.2");
+		for (dest, port, payload) in &[(&other, 1, "spam"), (&peer, 9, "ping")] {
+			let received = format!(
+				"DATAGRAM RECEIVED DESTINATION={} SIZE=4 FROM_PORT={} TO_PORT=0\n{}",
+				dest, port, payload
+			);
+			script.push(Step::Write(received.into_bytes()));
+		}
+		script.push(Step::Read(1));
+		let (addr, played) = scripted_bridge(vec![script]);
+
+		let socket = I2pDatagramSocket::bind_via(addr, "example.i2p:7").unwrap();
+		assert_eq!(socket.local_addr().unwrap().port(), 7);
+		socket.connect((I2pAddr::new(&peer), 9)).unwrap();
+		let mut buf = [0; 8];
+		// the datagram of the other peer is dropped
+		assert_eq!(socket.peek(&mut buf).unwrap(), 4);
+		assert_eq!(&buf[..4], b"ping");
+		assert_eq!(socket.try_clone().unwrap().recv(&mut buf).unwrap(), 4);
+		assert_eq!(&buf[..4], b"ping");
+		socket.send(b"pong").unwrap();
+
+		let mut control = played.recv().unwrap();
+		let mut payload = [0; 4];
+		control.reader.read_exact(&mut payload).unwrap();
+		assert_eq!(control.lines[3], format!("DATAGRAM SEND DESTINATION={} SIZE=4 TO_PORT=9\n", peer));
+		assert_eq!(&payload, b"pong");
 	}
 }
@@ -1,24 +1,56 @@
+use std::cmp::Ordering;
 use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
 
-use data_encoding::{BASE32, Encoding, Specification};
+use data_encoding::{Encoding, Specification};
 use lazy_static::lazy_static;
-use log::error;
-use serde_derive::{Serialize, Deserialize};
+use log::{debug, error};
+use serde::de::{self, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_derive::{Deserialize, Serialize};
 use sha2::{Sha256, Digest};
 
+use crate::destination::Destination;
 use crate::error::{Error, ErrorKind};
+use crate::sam_options::SignatureType;
 
-pub const B32_EXT: &'static str = ".b32.i2p";
+pub const B32_EXT: &str = ".b32.i2p";
 
 lazy_static! {
-	static ref BASE64_I2P: Encoding = {
+	pub(crate) static ref BASE64_I2P: Encoding = {
 		let mut spec = Specification::new();
 		spec.symbols.push_str("ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-~");
 		spec.padding = Some('=');
 		spec.encoding().unwrap()
 	};
+	pub(crate) static ref BASE32_I2P: Encoding = {
+		let mut spec = Specification::new();
+		spec.symbols.push_str("abcdefghijklmnopqrstuvwxyz234567");
+		spec.encoding().unwrap()
+	};
 }
 
+/// Longest hostname accepted, extension included
+const MAX_HOSTNAME_LEN: usize = 67;
+/// Longest label of a hostname
+const MAX_LABEL_LEN: usize = 63;
+/// Length of a base32 address, without its extension
+const B32_LEN: usize = 52;
+/// Shortest b33 address, in bytes: flags, one byte signature types and an
+/// Ed25519 key
+const B33_MIN_LEN: usize = 35;
+/// Flag of b33 addresses using two bytes signature types
+const B33_TWO_BYTES_SIGTYPES: u8 = 0x01;
+/// Flag of b33 addresses whose lease set is encrypted with a secret
+const B33_SECRET_REQUIRED: u8 = 0x02;
+/// Flag of b33 addresses whose lease set requires per-client authentication
+const B33_PER_CLIENT_AUTH: u8 = 0x04;
+
+/// Tags of the compact binary form of addresses
+const COMPACT_HASH: u8 = 0;
+const COMPACT_NAME: u8 = 1;
+
 /// An I2P address, as a Destination, B32 address or hostname.
 ///
 /// # Examples
@@ -38,13 +70,42 @@ lazy_static! {
 ///
 /// I2pAddr::new("abcdefghijklmnopqrstuvwxyz234567abcdefghijklmnopqrst.b32.i2p");
 /// ```
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, Hash)]
+///
+/// Base32 addresses are normalized to lowercase, so the same address in
+/// different cases compares and hashes equal:
+///
+/// ```
+/// use i2p::net::I2pAddr;
+///
+/// assert_eq!(
+///     I2pAddr::new("ABCDEFGHIJKLMNOPQRSTUVWXYZ234567ABCDEFGHIJKLMNOPQRST.B32.I2P"),
+///     I2pAddr::new("abcdefghijklmnopqrstuvwxyz234567abcdefghijklmnopqrst.b32.i2p"),
+/// );
+/// ```
+///
+/// Addresses made from a full destination, with `from_b64`, keep it along
+/// with their b32 form, so it can be connected to without looking the b32
+/// address up. It isn't serialized, and doesn't take part in comparisons.
+///
+/// Binary serialization formats, such as bincode, get a compact form: the
+/// 32 bytes hash of b32 addresses, or the other forms as is, tagged.
+#[derive(Debug, Clone)]
 pub struct I2pAddr {
 	inner: String,
+	dest: Option<String>,
+}
+
+/// Serialized form of `I2pAddr` for human-readable formats, normalized once
+/// deserialized
+#[derive(Serialize, Deserialize)]
+#[serde(rename = "I2pAddr")]
+struct RawI2pAddr {
+	inner: String,
 }
 
 impl I2pAddr {
-	/// Creates a new I2p address from a given string.
+	/// Creates a new I2p address from a given string. Base32 addresses are
+	/// lowercased.
 	///
 	/// # Examples
 	///
@@ -54,9 +115,12 @@ impl I2pAddr {
 	/// let addr = I2pAddr::new("example.i2p");
 	/// ```
 	pub fn new(dest: &str) -> I2pAddr {
-		I2pAddr {
-			inner: dest.to_string(),
-		}
+		let inner = if is_b32(dest) {
+			dest.to_ascii_lowercase()
+		} else {
+			dest.to_string()
+		};
+		I2pAddr { inner, dest: None }
 	}
 
 	/// Creates a new I2P address from a full base64 destination string. This
@@ -67,11 +131,182 @@ impl I2pAddr {
 			error!("Base64 decoding error: {:?}", e);
 			ErrorKind::BadAddressEncoding(dest.to_string()).to_err()
 		})?;
+		Ok(I2pAddr {
+			dest: Some(dest.to_string()),
+			..I2pAddr::from_dest_bytes(&bin_data)
+		})
+	}
+
+	/// The full base64 destination this address was made from, `None` unless
+	/// made with `from_b64`.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use i2p::net::I2pAddr;
+	///
+	/// let dest = "A".repeat(516);
+	/// let addr = I2pAddr::from_b64(&dest).unwrap();
+	/// assert!(addr.string().ends_with(".b32.i2p"));
+	/// assert_eq!(addr.destination_b64(), Some(&dest[..]));
+	/// assert_eq!(addr, I2pAddr::new(&addr.string()));
+	/// ```
+	pub fn destination_b64(&self) -> Option<&str> {
+		self.dest.as_deref()
+	}
+
+	/// The full destination this address was made from, parsed, `None` when
+	/// unknown or malformed.
+	pub fn destination(&self) -> Option<Destination> {
+		Destination::from_base64(self.dest.as_ref()?).ok()
+	}
+
+	/// The compact binary form of the address: `COMPACT_HASH` followed by
+	/// the hash of b32 addresses, `COMPACT_NAME` followed by any other form.
+	pub(crate) fn to_compact(&self) -> Vec<u8> {
+		match self.to_hash() {
+			Some(hash) => {
+				let mut compact = vec![COMPACT_HASH];
+				compact.extend_from_slice(&hash);
+				compact
+			}
+			None => {
+				let mut compact = vec![COMPACT_NAME];
+				compact.extend_from_slice(self.inner.as_bytes());
+				compact
+			}
+		}
+	}
+
+	pub(crate) fn from_compact(compact: &[u8]) -> Result<I2pAddr, Error> {
+		match compact.split_first() {
+			Some((&COMPACT_HASH, bytes)) if bytes.len() == 32 => {
+				let mut hash = [0u8; 32];
+				hash.copy_from_slice(bytes);
+				Ok(I2pAddr::from_hash(hash))
+			}
+			Some((&COMPACT_NAME, name)) => std::str::from_utf8(name)
+				.map(I2pAddr::new)
+				.map_err(|_| ErrorKind::BadAddressEncoding("address isn't UTF-8".to_string()).into()),
+			_ => Err(ErrorKind::BadAddressEncoding("invalid compact address".to_string()).into()),
+		}
+	}
+
+	/// What to give the bridge to reach this address: the full destination
+	/// when known, sparing a lookup, the address itself otherwise.
+	pub(crate) fn target(&self) -> String {
+		self.dest.clone().unwrap_or_else(|| self.inner.clone())
+	}
+
+	/// The base32 address of a binary destination
+	pub(crate) fn from_dest_bytes(dest: &[u8]) -> I2pAddr {
 		let mut hasher = Sha256::new();
-		hasher.input(bin_data);
-		let mut b32 = BASE32.encode(&hasher.result());
+		hasher.input(dest);
+		let mut hash = [0u8; 32];
+		hash.copy_from_slice(&hasher.result());
+		I2pAddr::from_hash(hash)
+	}
+
+	/// Creates the b32 address of a destination from its SHA-256 hash.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use i2p::net::I2pAddr;
+	///
+	/// let addr = I2pAddr::from_hash([0; 32]);
+	/// assert_eq!(addr.string(), "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa.b32.i2p");
+	/// assert_eq!(addr.to_hash(), Some([0; 32]));
+	/// ```
+	pub fn from_hash(hash: [u8; 32]) -> I2pAddr {
+		let mut b32 = BASE32_I2P.encode(&hash);
 		b32.push_str(B32_EXT);
-		Ok(I2pAddr{inner: b32})
+		I2pAddr { inner: b32, dest: None }
+	}
+
+	/// The destination hash of a b32 address, `None` for hostnames, b33
+	/// addresses and destinations.
+	pub fn to_hash(&self) -> Option<[u8; 32]> {
+		let b32 = self.inner.strip_suffix(B32_EXT)?;
+		if b32.len() != B32_LEN {
+			return None;
+		}
+		let bytes = BASE32_I2P.decode(b32.as_bytes()).ok()?;
+		let mut hash = [0u8; 32];
+		hash.copy_from_slice(&bytes);
+		Some(hash)
+	}
+
+	/// Creates a new I2P address from a base32 address, with or without the
+	/// b32.i2p extension and in any case. Both the 52 characters hash addresses and the
+	/// longer b33 addresses of encrypted lease sets are accepted, the latter
+	/// having their checksum and signature types validated.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use i2p::net::I2pAddr;
+	///
+	/// let addr = I2pAddr::from_b32("udhdrtrcetjm5sxzskjyr5ztpeszydbh4dpl3pl4utgqqw2v4jna").unwrap();
+	/// assert_eq!(addr.string(), "udhdrtrcetjm5sxzskjyr5ztpeszydbh4dpl3pl4utgqqw2v4jna.b32.i2p");
+	/// assert!(I2pAddr::from_b32("example.i2p").is_err());
+	/// ```
+	pub fn from_b32(addr: &str) -> Result<I2pAddr, Error> {
+		let lower = addr.to_ascii_lowercase();
+		let b32 = lower.strip_suffix(B32_EXT).unwrap_or(&lower);
+		let checked = if b32.len() == B32_LEN {
+			BASE32_I2P
+				.decode(b32.as_bytes())
+				.map(|_| ())
+				.map_err(|e| format!("base32 decoding error: {}", e))
+		} else if b32.len() < base32_len(B33_MIN_LEN) {
+			Err(format!(
+				"expected {} characters for a b32 address or at least {} for a b33 one, got {}",
+				B32_LEN,
+				base32_len(B33_MIN_LEN),
+				b32.len()
+			))
+		} else {
+			decode_b33(b32).map(|_| ())
+		};
+		checked.map_err(|e| {
+			debug!("Invalid b32 address {}: {}", addr, e);
+			ErrorKind::BadAddressEncoding(format!("{} ({})", addr, e))
+		})?;
+		Ok(I2pAddr {
+			inner: format!("{}{}", b32, B32_EXT),
+			dest: None,
+		})
+	}
+
+	/// Whether this is the b33 address of an encrypted lease set
+	pub fn is_b33(&self) -> bool {
+		self.blinded().is_some()
+	}
+
+	/// Whether this is a valid hostname, rather than a b32 address, base64
+	/// destination or malformed name, see `is_valid_hostname`.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use i2p::net::I2pAddr;
+	///
+	/// assert!(I2pAddr::new("example.i2p").is_valid_hostname());
+	/// assert!(!I2pAddr::new("-example.i2p").is_valid_hostname());
+	/// ```
+	pub fn is_valid_hostname(&self) -> bool {
+		is_valid_hostname(&self.inner)
+	}
+
+	/// The flags and key embedded in a b33 address, `None` for any other
+	/// form of address.
+	pub fn blinded(&self) -> Option<BlindedAddress> {
+		let b32 = self.inner.strip_suffix(B32_EXT)?;
+		if b32.len() <= B32_LEN {
+			return None;
+		}
+		decode_b33(b32).ok()
 	}
 
 	/// Returns the String that makes up this address.
@@ -89,8 +324,502 @@ impl I2pAddr {
 	}
 }
 
+impl Serialize for I2pAddr {
+	fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		if serializer.is_human_readable() {
+			RawI2pAddr {
+				inner: self.inner.clone(),
+			}
+			.serialize(serializer)
+		} else {
+			serializer.serialize_bytes(&self.to_compact())
+		}
+	}
+}
+
+impl<'de> Deserialize<'de> for I2pAddr {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<I2pAddr, D::Error> {
+		if deserializer.is_human_readable() {
+			RawI2pAddr::deserialize(deserializer).map(|raw| I2pAddr::new(&raw.inner))
+		} else {
+			let compact = deserializer.deserialize_bytes(BytesVisitor)?;
+			I2pAddr::from_compact(&compact).map_err(|e| de::Error::custom(e.kind()))
+		}
+	}
+}
+
+/// Collects the bytes of compact binary forms, from formats handing them
+/// out as bytes or as sequences of bytes.
+pub(crate) struct BytesVisitor;
+
+impl<'de> Visitor<'de> for BytesVisitor {
+	type Value = Vec<u8>;
+
+	fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str("bytes")
+	}
+
+	fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> Result<Vec<u8>, E> {
+		Ok(bytes.to_vec())
+	}
+
+	fn visit_byte_buf<E: de::Error>(self, bytes: Vec<u8>) -> Result<Vec<u8>, E> {
+		Ok(bytes)
+	}
+
+	fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<u8>, A::Error> {
+		let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+		while let Some(byte) = seq.next_element()? {
+			bytes.push(byte);
+		}
+		Ok(bytes)
+	}
+}
+
+impl PartialEq for I2pAddr {
+	fn eq(&self, other: &I2pAddr) -> bool {
+		self.inner == other.inner
+	}
+}
+
+impl Eq for I2pAddr {}
+
+impl PartialOrd for I2pAddr {
+	fn partial_cmp(&self, other: &I2pAddr) -> Option<Ordering> {
+		Some(self.cmp(other))
+	}
+}
+
+impl Ord for I2pAddr {
+	fn cmp(&self, other: &I2pAddr) -> Ordering {
+		self.inner.cmp(&other.inner)
+	}
+}
+
+impl Hash for I2pAddr {
+	fn hash<H: Hasher>(&self, state: &mut H) {
+		self.inner.hash(state)
+	}
+}
+
 impl fmt::Display for I2pAddr {
 	fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
 		write!(fmt, "{}", self.inner)
 	}
 }
+
+/// Parses a hostname, b32 or b33 address, or full base64 destination,
+/// validating the given form. Destinations are made into their b32 address
+/// with `from_b64`, keeping the full destination so they can be connected to
+/// without any lookup.
+///
+/// # Examples
+///
+/// ```
+/// use i2p::net::I2pAddr;
+///
+/// let addr: I2pAddr = "example.i2p".parse().unwrap();
+/// assert_eq!(addr, I2pAddr::new("example.i2p"));
+/// assert!("udhdrtrcetjm5sxzskjyr5ztpeszydbh4dpl3pl4utgqqw2v4jna.b32.i2p".parse::<I2pAddr>().is_ok());
+/// assert!("example.com".parse::<I2pAddr>().is_err());
+/// assert!("bad..i2p".parse::<I2pAddr>().is_err());
+/// ```
+impl FromStr for I2pAddr {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<I2pAddr, Error> {
+		if is_b32(s) {
+			return I2pAddr::from_b32(s);
+		}
+		if s.ends_with(".i2p") {
+			if !is_valid_hostname(s) {
+				return Err(ErrorKind::BadAddressEncoding(s.to_string()).into());
+			}
+			return Ok(I2pAddr::new(s));
+		}
+		Destination::from_base64(s).map_err(|_| ErrorKind::BadAddressEncoding(s.to_string()))?;
+		I2pAddr::from_b64(s)
+	}
+}
+
+/// Whether a name has the b32.i2p extension, in any case
+pub(crate) fn is_b32(name: &str) -> bool {
+	name.len() >= B32_EXT.len()
+		&& name.as_bytes()[name.len() - B32_EXT.len()..].eq_ignore_ascii_case(B32_EXT.as_bytes())
+}
+
+/// Whether a name is a valid I2P hostname, the way routers and address
+/// books accept them, so user input can be rejected before any lookup:
+///
+/// * it ends with the .i2p top-level domain, and isn't a b32 address,
+/// * it's at most 67 characters long and its labels at most 63,
+/// * labels are letters, digits and dashes, neither starting nor ending
+///   with a dash,
+/// * `--` only appears in the `xn--` prefix of internationalized labels,
+///   which must then be valid punycode. Unicode names must be converted to
+///   punycode beforehand.
+///
+/// Letters are compared without case, as routers lowercase hostnames.
+///
+/// # Examples
+///
+/// ```
+/// use i2p::net::is_valid_hostname;
+///
+/// assert!(is_valid_hostname("example.i2p"));
+/// assert!(is_valid_hostname("xn--bcher-kva.i2p"));
+/// assert!(!is_valid_hostname("example.com"));
+/// assert!(!is_valid_hostname("a--b.i2p"));
+/// assert!(!is_valid_hostname("bücher.i2p"));
+/// ```
+pub fn is_valid_hostname(name: &str) -> bool {
+	if name.len() > MAX_HOSTNAME_LEN || is_b32(name) {
+		return false;
+	}
+	let name = name.to_ascii_lowercase();
+	let host = match name.strip_suffix(".i2p") {
+		Some(host) if !host.is_empty() => host,
+		_ => return false,
+	};
+	host.split('.').all(|label| {
+		let bytes = label.as_bytes();
+		!label.is_empty()
+			&& label.len() <= MAX_LABEL_LEN
+			&& bytes.iter().all(|&c| c.is_ascii_alphanumeric() || c == b'-')
+			&& bytes[0] != b'-'
+			&& bytes[bytes.len() - 1] != b'-'
+			&& match label.strip_prefix("xn--") {
+				Some(encoded) => !encoded.contains("--") && valid_punycode(encoded),
+				None => !label.contains("--"),
+			}
+	})
+}
+
+/// Whether a lowercase label, without its xn-- prefix, is punycode (RFC
+/// 3492) encoding at least one non-ASCII character.
+fn valid_punycode(encoded: &str) -> bool {
+	const BASE: u32 = 36;
+	const TMIN: u32 = 1;
+	const TMAX: u32 = 26;
+
+	let (basic, extended) = match encoded.rfind('-') {
+		Some(i) => (&encoded[..i], &encoded[i + 1..]),
+		None => ("", encoded),
+	};
+	if extended.is_empty() {
+		return false;
+	}
+	let mut len = basic.len() as u32;
+	let (mut n, mut i, mut bias) = (128u32, 0u32, 72u32);
+	let mut digits = extended.bytes();
+	while digits.len() > 0 {
+		let old_i = i;
+		let mut w = 1u32;
+		let mut k = BASE;
+		loop {
+			let digit = match digits.next() {
+				Some(c @ b'a'..=b'z') => u32::from(c - b'a'),
+				Some(c @ b'0'..=b'9') => u32::from(c - b'0') + 26,
+				_ => return false,
+			};
+			i = match digit.checked_mul(w).and_then(|d| i.checked_add(d)) {
+				Some(i) => i,
+				None => return false,
+			};
+			let t = if k <= bias {
+				TMIN
+			} else if k >= bias + TMAX {
+				TMAX
+			} else {
+				k - bias
+			};
+			if digit < t {
+				break;
+			}
+			w = match w.checked_mul(BASE - t) {
+				Some(w) => w,
+				None => return false,
+			};
+			k += BASE;
+		}
+		len += 1;
+		bias = punycode_bias(i - old_i, len, old_i == 0);
+		n = match n.checked_add(i / len) {
+			Some(n) if std::char::from_u32(n).is_some() => n,
+			_ => return false,
+		};
+		i = i % len + 1;
+	}
+	true
+}
+
+/// The bias adaptation function of punycode
+fn punycode_bias(delta: u32, len: u32, first: bool) -> u32 {
+	let mut delta = if first { delta / 700 } else { delta / 2 };
+	delta += delta / len;
+	let mut k = 0;
+	while delta > 35 * 26 / 2 {
+		delta /= 35;
+		k += 36;
+	}
+	k + 36 * delta / (delta + 38)
+}
+
+/// The content of a b33 address: the unblinded public signing key of an
+/// encrypted lease set and how it's blinded and encrypted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlindedAddress {
+	flags: u8,
+	sig_type: SignatureType,
+	blinded_type: SignatureType,
+	public_key: Vec<u8>,
+}
+
+impl BlindedAddress {
+	/// Type of the unblinded signing key
+	pub fn signature_type(&self) -> SignatureType {
+		self.sig_type
+	}
+
+	/// Type of the blinded signing key
+	pub fn blinded_signature_type(&self) -> SignatureType {
+		self.blinded_type
+	}
+
+	/// The unblinded public signing key
+	pub fn public_key(&self) -> &[u8] {
+		&self.public_key
+	}
+
+	/// Whether a secret is needed to decrypt the lease set
+	pub fn secret_required(&self) -> bool {
+		self.flags & B33_SECRET_REQUIRED != 0
+	}
+
+	/// Whether the lease set is only readable by authorized clients
+	pub fn per_client_auth(&self) -> bool {
+		self.flags & B33_PER_CLIENT_AUTH != 0
+	}
+}
+
+fn decode_b33(b33: &str) -> Result<BlindedAddress, String> {
+	let mut data = BASE32_I2P
+		.decode(b33.as_bytes())
+		.map_err(|e| format!("base32 decoding error: {}", e))?;
+	if data.len() < B33_MIN_LEN {
+		return Err(format!("a b33 address is at least {} bytes long, got {}", B33_MIN_LEN, data.len()));
+	}
+	let crc = crc32(&data[3..]);
+	data[0] ^= crc as u8;
+	data[1] ^= (crc >> 8) as u8;
+	data[2] ^= (crc >> 16) as u8;
+
+	let flags = data[0];
+	if flags & B33_TWO_BYTES_SIGTYPES != 0 {
+		return Err("two bytes signature types are unsupported".to_string());
+	}
+	let sig_type = match SignatureType::from_code(u16::from(data[1])) {
+		Some(t @ SignatureType::EdDSA_SHA512_Ed25519) | Some(t @ SignatureType::RedDSA_SHA512_Ed25519) => t,
+		_ => return Err(format!("bad checksum or unsupported signature type {}", data[1])),
+	};
+	let blinded_type = match SignatureType::from_code(u16::from(data[2])) {
+		Some(t @ SignatureType::RedDSA_SHA512_Ed25519) => t,
+		_ => return Err(format!("bad checksum or unsupported blinded signature type {}", data[2])),
+	};
+	let expected = base32_len(3 + sig_type.public_key_len());
+	if b33.len() != expected {
+		return Err(format!(
+			"expected {} characters for a b33 address of a {} key, got {}",
+			expected,
+			sig_type.name(),
+			b33.len()
+		));
+	}
+	Ok(BlindedAddress {
+		flags,
+		sig_type,
+		blinded_type,
+		public_key: data.split_off(3),
+	})
+}
+
+/// Number of unpadded base32 characters encoding `len` bytes
+fn base32_len(len: usize) -> usize {
+	(len * 8).div_ceil(5)
+}
+
+/// The CRC-32 (ISO 3309) used as b33 checksum
+fn crc32(data: &[u8]) -> u32 {
+	let mut crc = !0u32;
+	for &b in data {
+		crc ^= u32::from(b);
+		for _ in 0..8 {
+			crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+		}
+	}
+	!crc
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::net::i2p::{crc32, BASE32_I2P, BASE64_I2P};
+	use crate::net::I2pAddr;
+	use crate::sam_options::SignatureType;
+
+	fn b33(flags: u8, sig_type: u8, blinded_type: u8, key: &[u8]) -> String {
+		let mut data = vec![flags, sig_type, blinded_type];
+		data.extend_from_slice(key);
+		let crc = crc32(key);
+		data[0] ^= crc as u8;
+		data[1] ^= (crc >> 8) as u8;
+		data[2] ^= (crc >> 16) as u8;
+		format!("{}.b32.i2p", BASE32_I2P.encode(&data))
+	}
+
+	#[test]
+	fn parse_addresses() {
+		let addr: I2pAddr = "sub.example.i2p".parse().unwrap();
+		assert_eq!(addr.string(), "sub.example.i2p");
+		assert!("-example.i2p".parse::<I2pAddr>().is_err());
+		assert!(".i2p".parse::<I2pAddr>().is_err());
+		assert!("exa mple.i2p".parse::<I2pAddr>().is_err());
+		assert!(format!("{}.i2p", "a".repeat(64)).parse::<I2pAddr>().is_err());
+		assert!("abcd.b32.i2p".parse::<I2pAddr>().is_err());
+
+		let b32 = I2pAddr::from_b64(&"A".repeat(516)).unwrap();
+		assert_eq!(b32.string().parse::<I2pAddr>().unwrap(), b32);
+
+		let mut dest = vec![1u8; 384];
+		dest.extend_from_slice(&[5, 0, 4, 0, 7, 0, 4]);
+		let b64 = BASE64_I2P.encode(&dest);
+		let parsed = b64.parse::<I2pAddr>().unwrap();
+		assert_eq!(parsed, I2pAddr::from_b64(&b64).unwrap());
+		assert_eq!(parsed.destination_b64(), Some(&b64[..]));
+		assert!(b64[4..].parse::<I2pAddr>().is_err());
+	}
+
+	#[test]
+	fn hostnames() {
+		use crate::net::is_valid_hostname;
+
+		for name in &["a.i2p", "Example.I2P", "a-b.c.i2p", "xn--bcher-kva.i2p", "xn--maana-pta.i2p", "xn--ls8h.i2p"] {
+			assert!(is_valid_hostname(name), "{}", name);
+		}
+		let long_label = format!("{}.i2p", "a".repeat(64));
+		let long_name = format!("{}.{}.i2p", "a".repeat(40), "b".repeat(30));
+		for name in &[
+			"i2p",
+			".i2p",
+			"a.com",
+			"a..i2p",
+			"a-.i2p",
+			"a--b.i2p",
+			"xn--.i2p",
+			"xn--a--b.i2p",
+			"xn--99999999.i2p",
+			"a_b.i2p",
+			"bücher.i2p",
+			&long_label,
+			&long_name,
+			"udhdrtrcetjm5sxzskjyr5ztpeszydbh4dpl3pl4utgqqw2v4jna.b32.i2p",
+		] {
+			assert!(!is_valid_hostname(name), "{}", name);
+		}
+	}
+
+	#[test]
+	fn address_lengths() {
+		let err = I2pAddr::from_b32("abcd.b32.i2p").unwrap_err().to_string();
+		assert!(err.contains("expected 52 characters for a b32 address or at least 56 for a b33 one, got 4"), "{}", err);
+		let s = b33(0, 7, 11, &[7u8; 40]);
+		let err = I2pAddr::from_b32(&s).unwrap_err().to_string();
+		assert!(err.contains("expected 56 characters for a b33 address of a EdDSA_SHA512_Ed25519 key, got 69"), "{}", err);
+	}
+
+	#[test]
+	fn hashes() {
+		let hash = [0xa5u8; 32];
+		let addr = I2pAddr::from_hash(hash);
+		assert_eq!(addr.to_hash(), Some(hash));
+		assert_eq!(I2pAddr::from_b32(&addr.string()).unwrap(), addr);
+		assert_eq!(I2pAddr::new("example.i2p").to_hash(), None);
+		assert_eq!(I2pAddr::new(&b33(0, 7, 11, &[7u8; 32])).to_hash(), None);
+	}
+
+	#[test]
+	fn full_destination() {
+		use std::collections::HashSet;
+		use crate::destination::Destination;
+
+		let mut bytes = vec![3u8; 384];
+		bytes.extend_from_slice(&[5, 0, 4, 0, 7, 0, 4]);
+		let b64 = BASE64_I2P.encode(&bytes);
+		let addr = I2pAddr::from_b64(&b64).unwrap();
+		assert_eq!(addr.destination_b64(), Some(&b64[..]));
+		assert_eq!(addr.destination().unwrap().to_bytes(), bytes);
+		assert_eq!(addr.target(), b64);
+		assert_eq!(Destination::from_bytes(&bytes).unwrap().addr().destination_b64(), Some(&b64[..]));
+
+		// parsing the destination keeps it just the same
+		let parsed: I2pAddr = b64.parse().unwrap();
+		assert_eq!(parsed.string(), addr.string());
+		assert_eq!(parsed.destination_b64(), Some(&b64[..]));
+		assert_eq!(parsed.destination().unwrap().to_bytes(), bytes);
+		assert_eq!(parsed.target(), b64);
+
+		// the b32 form alone is the same address
+		let b32 = I2pAddr::new(&addr.string());
+		assert_eq!(b32.destination_b64(), None);
+		assert_eq!(b32.target(), addr.string());
+		assert_eq!(b32, addr);
+		let set: HashSet<I2pAddr> = vec![addr, b32].into_iter().collect();
+		assert_eq!(set.len(), 1);
+	}
+
+	#[test]
+	fn crc() {
+		assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+	}
+
+	#[test]
+	fn b32_addresses() {
+		let addr = I2pAddr::from_b64(&"A".repeat(516)).unwrap();
+		let b32 = addr.string();
+		assert_eq!(b32.len(), 60);
+		assert_eq!(I2pAddr::from_b32(&b32).unwrap(), addr);
+		assert_eq!(I2pAddr::from_b32(&b32[..52]).unwrap(), addr);
+		assert!(!addr.is_b33());
+		assert!(I2pAddr::from_b32(&b32[1..]).is_err());
+		assert_eq!(I2pAddr::from_b32(&b32.to_uppercase()).unwrap(), addr);
+		assert_eq!(I2pAddr::new(&b32.to_uppercase()), addr);
+		assert_eq!(b32.to_uppercase().parse::<I2pAddr>().unwrap(), addr);
+		assert_eq!(I2pAddr::new("Example.i2p").string(), "Example.i2p");
+	}
+
+	#[test]
+	fn b33_addresses() {
+		let key = [7u8; 32];
+		let s = b33(0x02, 7, 11, &key);
+		assert_eq!(s.len(), 56 + 8);
+		let addr = I2pAddr::from_b32(&s).unwrap();
+		assert!(addr.is_b33());
+		let blinded = addr.blinded().unwrap();
+		assert_eq!(blinded.signature_type(), SignatureType::EdDSA_SHA512_Ed25519);
+		assert_eq!(blinded.blinded_signature_type(), SignatureType::RedDSA_SHA512_Ed25519);
+		assert_eq!(blinded.public_key(), &key[..]);
+		assert!(blinded.secret_required());
+		assert!(!blinded.per_client_auth());
+
+		// corrupted checksum
+		let mut corrupted = key;
+		corrupted[0] = 8;
+		let mut data = BASE32_I2P.decode(&s.as_bytes()[..56]).unwrap();
+		data[3..].copy_from_slice(&corrupted);
+		assert!(I2pAddr::from_b32(&BASE32_I2P.encode(&data)).is_err());
+		// unsupported types and two bytes signature types
+		assert!(I2pAddr::from_b32(&b33(0, 1, 11, &key)).is_err());
+		assert!(I2pAddr::from_b32(&b33(0, 7, 7, &key)).is_err());
+		assert!(I2pAddr::from_b32(&b33(1, 7, 11, &key)).is_err());
+		assert!(I2pAddr::from_b32(&b33(0, 7, 11, &key[1..])).is_err());
+	}
+}
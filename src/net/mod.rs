@@ -3,8 +3,11 @@ use crate::error::{Error, ErrorKind};
 
 pub use self::addr::{I2pSocketAddr, ToI2pSocketAddrs};
 pub use self::datagram::I2pDatagramSocket;
-pub use self::i2p::I2pAddr;
-pub use self::streaming::{I2pListener, I2pStream};
+pub use self::i2p::{is_valid_hostname, BlindedAddress, I2pAddr, B32_EXT};
+pub(crate) use self::i2p::{is_b32, BytesVisitor, BASE64_I2P};
+pub use self::streaming::{
+	I2pForward, I2pListener, I2pStream, OwnedReadHalf, OwnedWriteHalf, ReadHalf, WriteHalf,
+};
 
 mod addr;
 mod datagram;
@@ -13,6 +16,13 @@ mod streaming;
 #[cfg(test)]
 mod test;
 
+/// The socket address of a base64 destination and port, as hashed to its
+/// base32 address.
+fn socket_addr((dest, port): (String, u16)) -> I2pSocketAddr {
+	let addr = I2pAddr::from_b64(&dest).unwrap_or_else(|_| I2pAddr::new(&dest));
+	I2pSocketAddr::new(addr, port)
+}
+
 fn each_i2p_addr<A: ToSocketAddrs, B: ToI2pSocketAddrs, F, T>(
 	sam_addr: A,
 	addr: B,
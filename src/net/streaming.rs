@@ -1,12 +1,30 @@
 use std::io::prelude::*;
 
+use std::collections::HashMap;
 use std::fmt;
-use std::io;
+use std::io::{self, IoSlice, IoSliceMut};
 use std::net::{Shutdown, SocketAddr, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use lazy_static::lazy_static;
 
 use crate::error::{Error, ErrorKind};
-use crate::net::{I2pAddr, I2pSocketAddr, ToI2pSocketAddrs};
-use crate::sam::{Session, StreamConnect, StreamForward, DEFAULT_API};
+use crate::net::{I2pSocketAddr, ToI2pSocketAddrs};
+use crate::net::socket_addr;
+#[cfg(feature = "async")]
+use crate::sam::AcceptAbort;
+use crate::sam::{AcceptPool, Session, StreamConnect, StreamForward, StreamForwarding, DEFAULT_API};
+use crate::sam_options::{StreamingOptions, StreamingProfile};
+
+lazy_static! {
+	/// Transient sessions backing the streams opened with `I2pStream::connect`
+	/// and `connect_via`, one per SAM bridge, so their tunnels only get
+	/// built once.
+	static ref SHARED_SESSIONS: Mutex<HashMap<SocketAddr, Session>> = Mutex::new(HashMap::new());
+}
 
 /// A structure which represents an I2P stream between a local socket and a
 /// remote socket.
@@ -45,6 +63,19 @@ pub struct Incoming<'a> {
 	listener: &'a I2pListener,
 }
 
+/// The shared session for the provided bridge, created if needed. The lock
+/// is held during creation so concurrent callers don't create several.
+fn shared_session(sam_addr: &SocketAddr) -> Result<Session, Error> {
+	let mut sessions = SHARED_SESSIONS.lock().unwrap_or_else(|e| e.into_inner());
+	if let Some(session) = sessions.get(sam_addr) {
+		return session.duplicate();
+	}
+	let session = Session::transient(sam_addr)?;
+	let shared = session.duplicate()?;
+	sessions.insert(*sam_addr, session);
+	Ok(shared)
+}
+
 impl I2pStream {
 	/// Opens a TCP-like connection to a remote host.
 	///
@@ -66,10 +97,41 @@ impl I2pStream {
 	///     println!("Couldn't connect to server...");
 	/// }
 	/// ```
+	///
+	/// Streams opened with `connect` and `connect_via` share a transient
+	/// session per SAM bridge, created on first use.
 	pub fn connect<A: ToI2pSocketAddrs>(addr: A) -> Result<I2pStream, Error> {
 		I2pStream::connect_via(DEFAULT_API, addr)
 	}
 
+	/// Opens a TCP-like connection to a remote host with a timeout.
+	///
+	/// Unlike `connect`, `connect_timeout` takes a single address, as
+	/// connecting to several would spread the timeout between them. The
+	/// timeout bounds resolving the peer's name and waiting for the SAM bridge
+	/// to reach the peer, but not creating the shared session on first use.
+	/// A resolver can't be interrupted, one answering too late fails the
+	/// connection once it returns.
+	/// Connections that aren't established in time fail with an error of kind
+	/// `ErrorKind::SAMTimeout`.
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// use std::time::Duration;
+	/// use i2p::net::I2pStream;
+	///
+	/// let stream = I2pStream::connect_timeout("example.i2p:8080", Duration::from_secs(30));
+	/// ```
+	pub fn connect_timeout<A: ToI2pSocketAddrs>(addr: A, timeout: Duration) -> Result<I2pStream, Error> {
+		let addr: Result<_, Error> = addr.to_socket_addrs()?.next()
+			.ok_or(ErrorKind::UnresolvableAddress.into());
+		let addr = addr?;
+		super::each_addr(DEFAULT_API, |sam_addr| {
+			I2pStream::connect_addr_timeout(sam_addr, &addr, Some(timeout))
+		})
+	}
+
 	/// Same as `connect` but reuses an existing SAM session.
 	pub fn connect_with_session<A: ToI2pSocketAddrs>(
 		session: &Session,
@@ -80,77 +142,363 @@ impl I2pStream {
 		I2pStream::connect_addr_with_session(session, &addr?)
 	}
 
+	/// Same as `connect_with_session`, additionally setting the local port the
+	/// connection originates from. This lets the remote peer tell apart
+	/// several services using the same destination.
+	pub fn connect_with_session_from_port<A: ToI2pSocketAddrs>(
+		session: &Session,
+		from_port: u16,
+		addr: A,
+	) -> Result<I2pStream, Error> {
+		let addr: Result<_, Error> = addr.to_socket_addrs()?.next()
+			.ok_or(ErrorKind::UnresolvableAddress.into());
+		let addr = addr?;
+		let stream =
+			StreamConnect::with_session_ports(session, &addr.dest().target(), from_port, addr.port())?;
+
+		Ok(I2pStream { inner: stream })
+	}
+
+	/// Same as `connect_with_session` but in silent mode: the outcome of the
+	/// connection isn't reported by the SAM bridge and this returns as soon as
+	/// the request is sent. A failed connection shows up as the stream being
+	/// closed on first read or write.
+	pub fn connect_with_session_silent<A: ToI2pSocketAddrs>(
+		session: &Session,
+		addr: A,
+	) -> Result<I2pStream, Error> {
+		let addr: Result<_, Error> = addr.to_socket_addrs()?.next()
+			.ok_or(ErrorKind::UnresolvableAddress.into());
+		let addr = addr?;
+		let stream = StreamConnect::with_session_silent(session, &addr.dest().target(), addr.port())?;
+
+		Ok(I2pStream { inner: stream })
+	}
+
+	/// Same as `connect_with_session`, overriding the streaming options of
+	/// the session for this connection, e.g. to use the bulk profile for a
+	/// transfer on a session otherwise carrying interactive traffic.
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// use i2p::net::I2pStream;
+	/// use i2p::sam::Session;
+	/// use i2p::sam_options::{StreamingOptions, StreamingProfile};
+	///
+	/// let session = Session::transient("127.0.0.1:7656").unwrap();
+	/// let bulk = StreamingOptions::new().profile(StreamingProfile::Bulk);
+	/// let stream = I2pStream::connect_with_session_options(&session, "example.i2p:80", &bulk);
+	/// ```
+	pub fn connect_with_session_options<A: ToI2pSocketAddrs>(
+		session: &Session,
+		addr: A,
+		options: &StreamingOptions,
+	) -> Result<I2pStream, Error> {
+		let addr: Result<_, Error> = addr.to_socket_addrs()?.next()
+			.ok_or(ErrorKind::UnresolvableAddress.into());
+		let addr = addr?;
+		let stream =
+			StreamConnect::with_session_options(session, &addr.dest().target(), addr.port(), options)?;
+
+		Ok(I2pStream { inner: stream })
+	}
+
+	/// Opens a stream tuned for a traffic profile, with the streaming
+	/// options of `StreamingProfile::options`, for those who'd rather pick
+	/// interactive or bulk than learn the options of the streaming library.
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// use i2p::net::I2pStream;
+	/// use i2p::sam::Session;
+	/// use i2p::sam_options::StreamingProfile;
+	///
+	/// let session = Session::transient("127.0.0.1:7656").unwrap();
+	/// let stream = I2pStream::connect_with_profile(&session, "example.i2p:22", StreamingProfile::Interactive);
+	/// ```
+	pub fn connect_with_profile<A: ToI2pSocketAddrs>(
+		session: &Session,
+		addr: A,
+		profile: StreamingProfile,
+	) -> Result<I2pStream, Error> {
+		I2pStream::connect_with_session_options(session, addr, &profile.options())
+	}
+
 	pub fn connect_via<A: ToSocketAddrs, B: ToI2pSocketAddrs>(
 		sam_addr: A,
 		addr: B,
 	) -> Result<I2pStream, Error> {
-		super::each_i2p_addr(sam_addr, addr, I2pStream::connect_addr).map_err(|e| e.into())
+		super::each_i2p_addr(sam_addr, addr, I2pStream::connect_addr)
 	}
 
 	fn connect_addr(sam_addr: &SocketAddr, addr: &I2pSocketAddr) -> Result<I2pStream, Error> {
-		let stream = StreamConnect::new(sam_addr, &addr.dest().string(), addr.port())?;
+		I2pStream::connect_addr_timeout(sam_addr, addr, None)
+	}
 
-		Ok(I2pStream { inner: stream })
+	fn connect_addr_timeout(
+		sam_addr: &SocketAddr,
+		addr: &I2pSocketAddr,
+		timeout: Option<Duration>,
+	) -> Result<I2pStream, Error> {
+		let session = shared_session(sam_addr)?;
+		let dest = addr.dest().target();
+		let res = match timeout {
+			Some(timeout) => StreamConnect::with_session_timeout(&session, &dest, addr.port(), timeout),
+			None => StreamConnect::with_session(&session, &dest, addr.port()),
+		};
+		match res {
+			Ok(stream) => Ok(I2pStream { inner: stream }),
+			Err(e) => {
+				// the bridge may have dropped the session, start afresh next time
+				match e.kind() {
+					ErrorKind::Io(_) | ErrorKind::SAMInvalidId(_) => {
+						SHARED_SESSIONS.lock().unwrap_or_else(|e| e.into_inner()).remove(sam_addr);
+					}
+					_ => {}
+				}
+				Err(e)
+			}
+		}
 	}
 
 	fn connect_addr_with_session(session: &Session, addr: &I2pSocketAddr) -> Result<I2pStream, Error> {
-		let stream = StreamConnect::with_session(session, &addr.dest().string(), addr.port())?;
+		let stream = StreamConnect::with_session(session, &addr.dest().target(), addr.port())?;
 
 		Ok(I2pStream { inner: stream })
 	}
 
-	/// Returns the socket address of the remote peer of this I2P connection.
+	/// Returns the socket address of the remote peer of this I2P connection,
+	/// the base32 address of its destination. For accepted streams, this is
+	/// the address `I2pListener::accept` returns along with the stream.
 	///
 	/// # Examples
 	///
 	/// ```no_run
-	/// use i2p::net::{I2pAddr, I2pSocketAddr, I2pStream};
+	/// use i2p::net::I2pStream;
 	///
 	/// let stream = I2pStream::connect("example.i2p:8080")
 	///                        .expect("Couldn't connect to the server...");
-	/// assert_eq!(stream.peer_addr().unwrap(),
-	///            I2pSocketAddr::new(I2pAddr::new("example.i2p"), 8080));
+	/// println!("connected to {}", stream.peer_addr().unwrap());
 	/// ```
 	pub fn peer_addr(&self) -> Result<I2pSocketAddr, Error> {
-		self.inner
-			.peer_addr()
-			.map(|(d, p)| I2pSocketAddr::new(I2pAddr::new(&d), p))
+		self.inner.peer_addr().map(socket_addr)
 	}
 
-	/// Returns the socket address of the local half of this I2P connection.
+	/// Returns the socket address of the local half of this I2P connection,
+	/// the base32 address of our destination along with the port the
+	/// connection originates from, or the port it was addressed to for
+	/// accepted streams. Ports are 0 when unset or unsupported by the bridge.
 	///
 	/// # Examples
 	///
 	/// ```no_run
-	/// use i2p::net::{I2pAddr, I2pSocketAddr, I2pStream};
+	/// use i2p::net::I2pStream;
 	///
 	/// let stream = I2pStream::connect("example.i2p:8080")
 	///                        .expect("Couldn't connect to the server...");
-	/// assert_eq!(stream.local_addr().unwrap(),
-	///            I2pSocketAddr::new(I2pAddr::new("example.i2p"), 8080));
+	/// println!("connected from {}", stream.local_addr().unwrap());
 	/// ```
 	pub fn local_addr(&self) -> Result<I2pSocketAddr, Error> {
-		self.inner
-			.local_addr()
-			.map(|(d, p)| I2pSocketAddr::new(I2pAddr::new(&d), p))
+		self.inner.local_addr().map(socket_addr)
 	}
 
 	/// Moves this I2P stream into or out of nonblocking mode. This will
 	/// result in read, write, recv and send operations becoming nonblocking,
 	/// i.e., immediately returning from their calls. If the IO operation is
 	/// successful, Ok is returned and no further action is required. If the
-	/// IO operation could not be completed and needs to be retried, a wrapped
-	/// Io error with kind io::ErrorKind::WouldBlock is returned.
+	/// IO operation could not be completed and needs to be retried, an io
+	/// error with kind `io::ErrorKind::WouldBlock` is returned, or an error
+	/// with kind `ErrorKind::WouldBlock` for the methods returning the crate
+	/// `Error`.
 	pub fn set_nonblocking(&self, nonblocking: bool) -> Result<(), Error> {
 		self.inner.set_nonblocking(nonblocking)
 	}
 
+	/// Receives data on the socket from the remote address to which it is
+	/// connected, without removing that data from the queue. On success,
+	/// returns the number of bytes peeked.
+	///
+	/// Successive calls return the same data, which lets protocols be
+	/// sniffed on accepted streams.
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// use i2p::net::I2pStream;
+	///
+	/// let stream = I2pStream::connect("example.i2p:8080")
+	///                        .expect("Couldn't connect to the server...");
+	/// let mut buf = [0; 10];
+	/// let len = stream.peek(&mut buf).expect("peek failed");
+	/// ```
+	pub fn peek(&self, buf: &mut [u8]) -> Result<usize, Error> {
+		self.inner.peek(buf)
+	}
+
+	/// Sets the read timeout of the underlying socket.
+	///
+	/// If the value specified is `None`, then `read` calls will block
+	/// indefinitely. An error is returned if the zero duration is passed to
+	/// this method.
+	///
+	/// Reads timing out return an io error of kind `WouldBlock` or
+	/// `TimedOut`, depending on the platform.
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// use std::time::Duration;
+	/// use i2p::net::I2pStream;
+	///
+	/// let stream = I2pStream::connect("example.i2p:8080")
+	///                        .expect("Couldn't connect to the server...");
+	/// stream.set_read_timeout(Some(Duration::from_secs(30))).expect("set_read_timeout call failed");
+	/// ```
+	pub fn set_read_timeout(&self, dur: Option<Duration>) -> Result<(), Error> {
+		self.inner.set_read_timeout(dur)
+	}
+
+	/// Sets the write timeout of the underlying socket.
+	///
+	/// If the value specified is `None`, then `write` calls will block
+	/// indefinitely. An error is returned if the zero duration is passed to
+	/// this method.
+	pub fn set_write_timeout(&self, dur: Option<Duration>) -> Result<(), Error> {
+		self.inner.set_write_timeout(dur)
+	}
+
+	/// Returns the read timeout of this socket, `None` meaning reads block
+	/// indefinitely.
+	pub fn read_timeout(&self) -> Result<Option<Duration>, Error> {
+		self.inner.read_timeout()
+	}
+
+	/// Returns the write timeout of this socket, `None` meaning writes block
+	/// indefinitely.
+	pub fn write_timeout(&self) -> Result<Option<Duration>, Error> {
+		self.inner.write_timeout()
+	}
+
+	/// Shuts the stream down after `dur` without data read or written, in
+	/// either direction, so connections to peers that vanished don't hang
+	/// around forever. Reads then return end of file and writes fail. The
+	/// timeout is shared with the clones of the stream, `None`, the default,
+	/// disabling it.
+	///
+	/// Keepalives are left to the streaming library of the router, see
+	/// `StreamingOptions::inactivity` and `connect_with_session_options`.
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// use std::time::Duration;
+	/// use i2p::net::I2pStream;
+	///
+	/// let stream = I2pStream::connect("example.i2p:8080")
+	///                        .expect("Couldn't connect to the server...");
+	/// stream.set_idle_timeout(Some(Duration::from_secs(600))).expect("set_idle_timeout call failed");
+	/// ```
+	pub fn set_idle_timeout(&self, dur: Option<Duration>) -> Result<(), Error> {
+		self.inner.set_idle_timeout(dur)
+	}
+
+	/// Returns the idle timeout of this stream, see `set_idle_timeout`.
+	pub fn idle_timeout(&self) -> Option<Duration> {
+		self.inner.idle_timeout()
+	}
+
+	/// Returns the nickname of the SAM session this stream belongs to.
+	pub fn session_id(&self) -> &str {
+		self.inner.session_id()
+	}
+
+	/// Returns the SAM version agreed upon with the bridge for this stream.
+	pub fn sam_version(&self) -> &str {
+		self.inner.sam_version()
+	}
+
+	/// Returns the local TCP address of the socket connecting this stream to
+	/// the SAM bridge, `None` for bridges reached through a Unix domain
+	/// socket.
+	pub fn socket_addr(&self) -> Result<Option<SocketAddr>, Error> {
+		self.inner.socket_addr()
+	}
+
+	/// Returns how long ago this stream was connected, or accepted.
+	pub fn connected_for(&self) -> Duration {
+		self.inner.connected_for()
+	}
+
+	/// Returns the number of bytes read from this stream and its clones,
+	/// counting the ones read ahead and not consumed yet.
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// use i2p::net::I2pStream;
+	///
+	/// let stream = I2pStream::connect("example.i2p:8080")
+	///                        .expect("Couldn't connect to the server...");
+	/// println!(
+	///     "{} in, {} out in {:?} over {:?}",
+	///     stream.bytes_read(),
+	///     stream.bytes_written(),
+	///     stream.connected_for(),
+	///     stream.socket_addr().unwrap(),
+	/// );
+	/// ```
+	pub fn bytes_read(&self) -> u64 {
+		self.inner.bytes_read()
+	}
+
+	/// Returns the number of bytes written to this stream and its clones.
+	pub fn bytes_written(&self) -> u64 {
+		self.inner.bytes_written()
+	}
+
+	/// Buffers small reads: reads asking for less than `capacity` bytes
+	/// read `capacity` bytes from the SAM bridge at once, the following ones
+	/// being served from the buffer. This saves a system call per read for
+	/// parsers reading a few bytes at a time, without wrapping the stream in a
+	/// `BufReader`. Like other options, the buffer is shared with the clones
+	/// of the stream, and a capacity of 0, the default, disables buffering.
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// use std::io::Read;
+	/// use i2p::net::I2pStream;
+	///
+	/// let mut stream = I2pStream::connect("example.i2p:8080")
+	///                            .expect("Couldn't connect to the server...");
+	/// stream.set_read_buffer_capacity(8 * 1024);
+	/// let mut len = [0; 2];
+	/// stream.read_exact(&mut len).unwrap();
+	/// ```
+	pub fn set_read_buffer_capacity(&self, capacity: usize) {
+		self.inner.set_read_buffer_capacity(capacity)
+	}
+
+	/// Returns the capacity of the read buffer of this stream, 0 meaning
+	/// reads aren't buffered.
+	pub fn read_buffer_capacity(&self) -> usize {
+		self.inner.read_buffer_capacity()
+	}
+
 	/// Shuts down the read, write, or both halves of this connection.
 	///
 	/// This function will cause all pending and future I/O on the specified
 	/// portions to return immediately with an appropriate value (see the
 	/// documentation of [`Shutdown`]).
 	///
+	/// Shutting down the write half tells the peer no more data is coming,
+	/// as protocols like HTTP/1.0 expect, while its reply can still be read.
+	/// The SAM bridge closes the I2P stream in that direction, the peer
+	/// reading the end of the stream. Likewise, once the peer closes its
+	/// side, reads return `Ok(0)`, as do the ones following them.
+	///
 	/// [`Shutdown`]: ../../std/net/enum.Shutdown.html
 	///
 	/// # Examples
@@ -172,7 +520,8 @@ impl I2pStream {
 	/// The returned `I2pStream` is a reference to the same stream that this
 	/// object references. Both handles will read and write the same stream of
 	/// data, and options set on one stream will be propagated to the other
-	/// stream.
+	/// stream. One handle can be read from while another is written to from a
+	/// different thread, including over TLS bridges.
 	///
 	/// # Examples
 	///
@@ -186,23 +535,261 @@ impl I2pStream {
 	pub fn try_clone(&self) -> Result<I2pStream, Error> {
 		self.inner.duplicate().map(|s| I2pStream { inner: s })
 	}
+
+	/// Splits the stream into a read half and a write half borrowing it,
+	/// which can be used concurrently, such as from scoped threads.
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// use std::io::{self, Write};
+	/// use i2p::net::I2pStream;
+	///
+	/// let mut stream = I2pStream::connect("example.i2p:8080")
+	///                            .expect("Couldn't connect to the server...");
+	/// let (mut reader, mut writer) = stream.split();
+	/// writer.write_all(b"ping").unwrap();
+	/// io::copy(&mut reader, &mut io::stdout()).unwrap();
+	/// ```
+	pub fn split(&mut self) -> (ReadHalf<'_>, WriteHalf<'_>) {
+		(ReadHalf { stream: self }, WriteHalf { stream: self })
+	}
+
+	/// Splits the stream into a read half and a write half owning it, so
+	/// that reading and writing can be moved to different threads. Dropping
+	/// the write half shuts the write direction of the stream down.
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// use std::io::{self, Write};
+	/// use std::thread;
+	/// use i2p::net::I2pStream;
+	///
+	/// let stream = I2pStream::connect("example.i2p:8080")
+	///                        .expect("Couldn't connect to the server...");
+	/// let (mut reader, mut writer) = stream.into_split();
+	/// thread::spawn(move || {
+	///     writer.write_all(b"ping").unwrap();
+	/// });
+	/// io::copy(&mut reader, &mut io::stdout()).unwrap();
+	/// ```
+	pub fn into_split(self) -> (OwnedReadHalf, OwnedWriteHalf) {
+		let stream = Arc::new(self);
+		(
+			OwnedReadHalf {
+				stream: stream.clone(),
+			},
+			OwnedWriteHalf { stream },
+		)
+	}
+}
+
+/// The socket carrying the stream, so poll-based event loops can register
+/// it along with their other sockets. Streams don't implement
+/// `mio::event::Source` themselves, the crate not depending on mio, but
+/// register through mio's `SourceFd`, on Unix only:
+///
+/// ```ignore
+/// use mio::unix::SourceFd;
+///
+/// stream.set_nonblocking(true)?;
+/// poll.registry()
+///     .register(&mut SourceFd(&stream.as_raw_fd()), STREAM, Interest::READABLE)?;
+/// ```
+///
+/// Data already read ahead by the stream, after a `peek` or with a read
+/// buffer, doesn't make the socket readable again, so streams must be read
+/// until they return `WouldBlock`, as edge-triggered polling requires
+/// anyway. Listeners have no such socket, as each accepted stream arrives
+/// on a new one: poll-based servers can `forward` them to a local TCP
+/// listener instead, which registers like any other.
+#[cfg(unix)]
+impl AsRawFd for I2pStream {
+	fn as_raw_fd(&self) -> RawFd {
+		self.inner.as_raw_fd()
+	}
 }
 
 impl Read for I2pStream {
 	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
 		self.inner.read(buf)
 	}
+	fn read_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> io::Result<usize> {
+		self.inner.read_vectored(bufs)
+	}
 }
 
 impl Write for I2pStream {
 	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
 		self.inner.write(buf)
 	}
+	fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+		self.inner.write_vectored(bufs)
+	}
+	fn flush(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+}
+
+impl Read for &I2pStream {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		(&self.inner).read(buf)
+	}
+	fn read_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> io::Result<usize> {
+		(&self.inner).read_vectored(bufs)
+	}
+}
+
+impl Write for &I2pStream {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		(&self.inner).write(buf)
+	}
+	fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+		(&self.inner).write_vectored(bufs)
+	}
 	fn flush(&mut self) -> io::Result<()> {
 		Ok(())
 	}
 }
 
+/// The read half of an `I2pStream`, borrowed from it.
+///
+/// This `struct` is created by the [`split`] method on [`I2pStream`].
+///
+/// [`split`]: struct.I2pStream.html#method.split
+/// [`I2pStream`]: struct.I2pStream.html
+#[derive(Debug)]
+pub struct ReadHalf<'a> {
+	stream: &'a I2pStream,
+}
+
+/// The write half of an `I2pStream`, borrowed from it.
+///
+/// This `struct` is created by the [`split`] method on [`I2pStream`].
+///
+/// [`split`]: struct.I2pStream.html#method.split
+/// [`I2pStream`]: struct.I2pStream.html
+#[derive(Debug)]
+pub struct WriteHalf<'a> {
+	stream: &'a I2pStream,
+}
+
+/// The owned read half of an `I2pStream`, which can be moved to another
+/// thread than its write half.
+///
+/// This `struct` is created by the [`into_split`] method on [`I2pStream`].
+///
+/// [`into_split`]: struct.I2pStream.html#method.into_split
+/// [`I2pStream`]: struct.I2pStream.html
+#[derive(Debug)]
+pub struct OwnedReadHalf {
+	stream: Arc<I2pStream>,
+}
+
+/// The owned write half of an `I2pStream`, which can be moved to another
+/// thread than its read half.
+///
+/// Dropping the write half shuts the write direction of the stream down, the
+/// peer reading the end of the stream, while the read half keeps working.
+///
+/// This `struct` is created by the [`into_split`] method on [`I2pStream`].
+///
+/// [`into_split`]: struct.I2pStream.html#method.into_split
+/// [`I2pStream`]: struct.I2pStream.html
+#[derive(Debug)]
+pub struct OwnedWriteHalf {
+	stream: Arc<I2pStream>,
+}
+
+impl<'a> ReadHalf<'a> {
+	/// Returns the socket address of the remote peer of the stream.
+	pub fn peer_addr(&self) -> Result<I2pSocketAddr, Error> {
+		self.stream.peer_addr()
+	}
+}
+
+impl<'a> WriteHalf<'a> {
+	/// Returns the socket address of the remote peer of the stream.
+	pub fn peer_addr(&self) -> Result<I2pSocketAddr, Error> {
+		self.stream.peer_addr()
+	}
+
+	/// Shuts the write direction of the stream down, the peer reading the
+	/// end of the stream, see `I2pStream::shutdown`.
+	pub fn shutdown(&self) -> Result<(), Error> {
+		self.stream.shutdown(Shutdown::Write)
+	}
+}
+
+impl OwnedReadHalf {
+	/// Returns the socket address of the remote peer of the stream.
+	pub fn peer_addr(&self) -> Result<I2pSocketAddr, Error> {
+		self.stream.peer_addr()
+	}
+}
+
+impl OwnedWriteHalf {
+	/// Returns the socket address of the remote peer of the stream.
+	pub fn peer_addr(&self) -> Result<I2pSocketAddr, Error> {
+		self.stream.peer_addr()
+	}
+
+	/// Shuts the write direction of the stream down without dropping the
+	/// write half, as dropping it does.
+	pub fn shutdown(&self) -> Result<(), Error> {
+		self.stream.shutdown(Shutdown::Write)
+	}
+}
+
+impl<'a> Read for ReadHalf<'a> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		self.stream.read(buf)
+	}
+	fn read_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> io::Result<usize> {
+		self.stream.read_vectored(bufs)
+	}
+}
+
+impl<'a> Write for WriteHalf<'a> {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		self.stream.write(buf)
+	}
+	fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+		self.stream.write_vectored(bufs)
+	}
+	fn flush(&mut self) -> io::Result<()> {
+		self.stream.flush()
+	}
+}
+
+impl Read for OwnedReadHalf {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		(&*self.stream).read(buf)
+	}
+	fn read_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> io::Result<usize> {
+		(&*self.stream).read_vectored(bufs)
+	}
+}
+
+impl Write for OwnedWriteHalf {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		(&*self.stream).write(buf)
+	}
+	fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+		(&*self.stream).write_vectored(bufs)
+	}
+	fn flush(&mut self) -> io::Result<()> {
+		(&*self.stream).flush()
+	}
+}
+
+impl Drop for OwnedWriteHalf {
+	fn drop(&mut self) {
+		let _ = self.stream.shutdown(Shutdown::Write);
+	}
+}
+
 impl fmt::Debug for I2pStream {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
 		let mut res = f.debug_struct("I2pStream");
@@ -244,6 +831,7 @@ impl fmt::Debug for I2pStream {
 /// ```
 pub struct I2pListener {
 	forward: StreamForward,
+	pool: Option<Arc<AcceptPool>>,
 }
 
 impl I2pListener {
@@ -270,33 +858,51 @@ impl I2pListener {
 
 	pub fn bind_with_session(session: &Session) -> Result<I2pListener, Error> {
 		let forward = StreamForward::with_session(session)?;
-		Ok(I2pListener{forward})
+		Ok(I2pListener { forward, pool: None })
+	}
+
+	/// Creates a listener only accepting the streams addressed to `port` on
+	/// the destination of a primary session, so that several listeners can
+	/// share a destination, one per port. Needs SAM 3.3+.
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// use i2p::net::I2pListener;
+	/// use i2p::sam::Session;
+	///
+	/// let mut primary = Session::primary("127.0.0.1:7656", "TRANSIENT").unwrap();
+	/// let http = I2pListener::bind_with_session_port(&mut primary, 80).unwrap();
+	/// let irc = I2pListener::bind_with_session_port(&mut primary, 6667).unwrap();
+	/// assert_eq!(http.local_addr().unwrap().port(), 80);
+	/// ```
+	pub fn bind_with_session_port(primary: &mut Session, port: u16) -> Result<I2pListener, Error> {
+		let forward = StreamForward::with_primary_port(primary, port)?;
+		Ok(I2pListener { forward, pool: None })
 	}
 
 	pub fn bind_via<A: ToSocketAddrs>(sam_addr: A) -> Result<I2pListener, Error> {
-		super::each_addr(sam_addr, I2pListener::bind_addr).map_err(|e| e.into())
+		super::each_addr(sam_addr, I2pListener::bind_addr)
 	}
 
 	fn bind_addr(sam_addr: &SocketAddr) -> Result<I2pListener, Error> {
 		let forward = StreamForward::new(sam_addr)?;
-		Ok(I2pListener{forward})
+		Ok(I2pListener { forward, pool: None })
 	}
 
-	/// Returns the local socket address of this listener.
+	/// Returns the local socket address of this listener, the base32 address
+	/// of its destination that peers can connect to.
 	///
 	/// # Examples
 	///
 	/// ```no_run
-	/// use i2p::net::{I2pAddr, I2pSocketAddr, I2pListener};
+	/// use i2p::net::I2pListener;
 	///
 	/// let listener = I2pListener::bind().unwrap();
-	/// assert_eq!(listener.local_addr().unwrap(),
-	///            I2pSocketAddr::new(I2pAddr::new("example.i2p"), 8080));
+	/// println!("listening on {}", listener.local_addr().unwrap());
 	/// ```
 	pub fn local_addr(&self) -> Result<I2pSocketAddr, Error> {
-		self.forward
-			.local_addr()
-			.map(|(d, p)| I2pSocketAddr::new(I2pAddr::new(&d), p))
+		self.forward.local_addr().map(socket_addr)
 	}
 
 	/// Creates a new independently owned handle to the underlying socket.
@@ -315,7 +921,35 @@ impl I2pListener {
 	/// ```
 	pub fn try_clone(&self) -> Result<I2pListener, Error> {
 		let forward = self.forward.duplicate()?;
-		Ok(I2pListener{forward})
+		Ok(I2pListener {
+			forward,
+			pool: self.pool.clone(),
+		})
+	}
+
+	/// Keeps `size` accept requests pending on the SAM bridge from now on,
+	/// so that bursts of incoming connections are accepted concurrently
+	/// rather than one handshake at a time. `accept` then takes the streams
+	/// from the pool, which is shared with clones of this listener. A size
+	/// of 0 stops the pool.
+	///
+	/// More than one pending accept requires SAM 3.2+.
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// use i2p::net::I2pListener;
+	///
+	/// let mut listener = I2pListener::bind().unwrap();
+	/// listener.set_accept_pool(4).unwrap();
+	/// let (_socket, addr) = listener.accept().unwrap();
+	/// ```
+	pub fn set_accept_pool(&mut self, size: usize) -> Result<(), Error> {
+		self.pool = None;
+		if size > 0 {
+			self.pool = Some(Arc::new(self.forward.accept_pool(size)?));
+		}
+		Ok(())
 	}
 
 	/// Accept a new incoming connection from this listener.
@@ -336,15 +970,138 @@ impl I2pListener {
 	/// }
 	/// ```
 	pub fn accept(&self) -> Result<(I2pStream, I2pSocketAddr), Error> {
-		let (i2p_stream, addr) = self.forward.accept()?;
+		let (i2p_stream, addr) = match self.pool {
+			Some(ref pool) if self.forward.is_nonblocking() => pool.try_accept()?,
+			Some(ref pool) => pool.accept()?,
+			None => self.forward.accept()?,
+		};
+		Ok((I2pStream{inner: i2p_stream}, addr))
+	}
+
+	/// Same as `accept`, but returns early with an error once `abort` is
+	/// aborted, without leaving an accept request pending on the bridge.
+	#[cfg(feature = "async")]
+	pub(crate) fn accept_abortable(&self, abort: &AcceptAbort) -> Result<(I2pStream, I2pSocketAddr), Error> {
+		let (i2p_stream, addr) = match self.pool {
+			Some(ref pool) => pool.accept_abortable(abort)?,
+			None => self.forward.accept_abortable(abort)?,
+		};
 		Ok((I2pStream{inner: i2p_stream}, addr))
 	}
 
+	/// Same as `accept`, but gives up with an error of kind
+	/// `ErrorKind::SAMTimeout` when no peer connects within `timeout`.
+	///
+	/// The accept request stays pending on the SAM bridge, so peers
+	/// connecting right after the timeout are returned by the next call.
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// use std::time::Duration;
+	/// use i2p::net::I2pListener;
+	/// use i2p::ErrorKind;
+	///
+	/// let listener = I2pListener::bind().unwrap();
+	/// loop {
+	///     match listener.accept_timeout(Duration::from_secs(1)) {
+	///         Ok((_socket, addr)) => println!("new client: {:?}", addr),
+	///         // time to check whether the server should be shut down
+	///         Err(ref e) if matches!(e.kind(), ErrorKind::SAMTimeout(_)) => continue,
+	///         Err(e) => println!("couldn't get client: {:?}", e),
+	///     }
+	/// }
+	/// ```
+	pub fn accept_timeout(&self, timeout: Duration) -> Result<(I2pStream, I2pSocketAddr), Error> {
+		let (i2p_stream, addr) = match self.pool {
+			Some(ref pool) => pool.accept_timeout(timeout)?,
+			None => self.forward.accept_timeout(timeout)?,
+		};
+		Ok((I2pStream{inner: i2p_stream}, addr))
+	}
+
+	/// Moves this I2P listener into or out of nonblocking mode.
+	///
+	/// This will result in the `accept` operation becoming nonblocking,
+	/// i.e., immediately returning from its call. If the IO operation is
+	/// successful, `Ok` is returned and no further action is required. If the
+	/// IO operation could not be completed and needs to be retried, an error
+	/// with kind `ErrorKind::WouldBlock` is returned.
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// use i2p::net::I2pListener;
+	/// use i2p::ErrorKind;
+	///
+	/// let listener = I2pListener::bind().unwrap();
+	/// listener.set_nonblocking(true).expect("Cannot set non-blocking");
+	///
+	/// loop {
+	///     match listener.accept() {
+	///         Ok((_socket, addr)) => println!("new client: {:?}", addr),
+	///         Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
+	///             // wait until the event loop reports the listener ready
+	///             continue;
+	///         }
+	///         Err(e) => panic!("encountered IO error: {}", e),
+	///     }
+	/// }
+	/// ```
+	pub fn set_nonblocking(&self, nonblocking: bool) -> Result<(), Error> {
+		self.forward.set_nonblocking(nonblocking)
+	}
+
+	/// Accept a new incoming connection from this listener in silent mode.
+	///
+	/// Unlike `accept`, the accepted stream isn't prefixed with the remote
+	/// peer's destination, which is needed for binary protocols, but the
+	/// peer's address is then unknown. This returns as soon as the SAM bridge
+	/// is asked to accept, the first read on the stream blocks until a peer
+	/// actually connects.
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// use i2p::net::I2pListener;
+	///
+	/// let listener = I2pListener::bind().unwrap();
+	/// let stream = listener.accept_silent().unwrap();
+	/// ```
+	pub fn accept_silent(&self) -> Result<I2pStream, Error> {
+		let i2p_stream = self.forward.accept_silent()?;
+		Ok(I2pStream{inner: i2p_stream})
+	}
+
+	/// Forwards all connections received by this listener to a local TCP
+	/// server, with the bridge opening a new TCP connection to `addr` for
+	/// each incoming stream. Each forwarded connection starts with the base64
+	/// destination of the peer, followed by a newline.
+	///
+	/// Forwarding stops when the returned `I2pForward` is dropped.
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// use i2p::net::I2pListener;
+	///
+	/// let listener = I2pListener::bind().unwrap();
+	/// let forward = listener.forward("127.0.0.1:8080").unwrap();
+	/// ```
+	pub fn forward<A: ToSocketAddrs>(&self, addr: A) -> Result<I2pForward, Error> {
+		super::each_addr(addr, |addr| {
+			self.forward
+				.forward(addr)
+				.map(|inner| I2pForward { inner })
+		})
+	}
+
 	/// Returns an iterator over the connections being received on this
 	/// listener.
 	///
 	/// The returned iterator will never return [`None`] and will also not yield
-	/// the peer's [`I2pSocketAddr`] structure.
+	/// the peer's [`I2pSocketAddr`] structure, which each stream provides
+	/// through its `peer_addr` method.
 	///
 	/// [`None`]: ../../std/option/enum.Option.html#variant.None
 	/// [`I2pSocketAddr`]: ../../std/net/struct.I2pSocketAddr.html
@@ -359,20 +1116,216 @@ impl I2pListener {
 	/// for stream in listener.incoming() {
 	///     match stream {
 	///         Ok(stream) => {
-	///             println!("new client!");
+	///             println!("new client: {}", stream.peer_addr().unwrap());
 	///         }
 	///         Err(e) => { /* connection failed */ }
 	///     }
 	/// }
 	/// ```
-	pub fn incoming(&self) -> Incoming {
+	pub fn incoming(&self) -> Incoming<'_> {
 		Incoming { listener: self }
 	}
 }
 
+/// Incoming streams being forwarded to a local TCP server.
+///
+/// This `struct` is created by the [`forward`] method on [`I2pListener`].
+/// Forwarding stops when the value is dropped.
+///
+/// [`forward`]: struct.I2pListener.html#method.forward
+/// [`I2pListener`]: struct.I2pListener.html
+pub struct I2pForward {
+	inner: StreamForwarding,
+}
+
+impl I2pForward {
+	/// Returns the local TCP address streams are forwarded to.
+	pub fn target_addr(&self) -> SocketAddr {
+		self.inner.target_addr()
+	}
+
+	/// Returns the local socket address of the forwarded listener.
+	pub fn local_addr(&self) -> Result<I2pSocketAddr, Error> {
+		self.inner.local_addr().map(socket_addr)
+	}
+
+	/// Stops forwarding, reporting any error closing the forward.
+	pub fn close(self) -> Result<(), Error> {
+		self.inner.close()
+	}
+}
+
 impl<'a> Iterator for Incoming<'a> {
 	type Item = Result<I2pStream, Error>;
 	fn next(&mut self) -> Option<Result<I2pStream, Error>> {
 		Some(self.listener.accept().map(|p| p.0))
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use std::io::{IoSlice, IoSliceMut, Read, Write};
+	use std::thread;
+
+	use crate::sam::tests::fake_bridge;
+
+	#[test]
+	fn into_split() {
+		use crate::net::I2pStream;
+		use crate::sam::Session;
+
+		let session = Session::transient(fake_bridge(b"hi ")).unwrap();
+		let stream = I2pStream::connect_with_session(&session, "peer.i2p:80").unwrap();
+		let (mut reader, mut writer) = stream.into_split();
+		thread::spawn(move || {
+			writer.write_all(b"ping").unwrap();
+			// dropping the write half ends the echo
+		});
+		let mut echoed = vec![];
+		reader.read_to_end(&mut echoed).unwrap();
+		assert_eq!(echoed, b"hi ping");
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn raw_fd() {
+		use std::os::unix::io::AsRawFd;
+		use crate::net::I2pStream;
+		use crate::sam::Session;
+
+		let session = Session::transient(fake_bridge(b"")).unwrap();
+		let stream = I2pStream::connect_with_session(&session, "peer.i2p:80").unwrap();
+		let other = I2pStream::connect_with_session(&session, "peer.i2p:80").unwrap();
+		assert!(stream.as_raw_fd() >= 0);
+		assert_ne!(stream.as_raw_fd(), other.as_raw_fd());
+		assert_eq!(stream.as_raw_fd(), stream.as_raw_fd());
+	}
+
+	#[test]
+	fn half_close() {
+		use std::net::Shutdown;
+		use crate::net::I2pStream;
+		use crate::sam::Session;
+
+		let session = Session::transient(fake_bridge(b"hi ")).unwrap();
+		let mut stream = I2pStream::connect_with_session(&session, "peer.i2p:80").unwrap();
+		stream.set_read_buffer_capacity(64);
+		stream.write_all(b"request").unwrap();
+		stream.shutdown(Shutdown::Write).unwrap();
+		assert!(stream.write_all(b"more").is_err());
+		// the echo only ends once the bridge read the end of the stream
+		let mut reply = vec![];
+		stream.read_to_end(&mut reply).unwrap();
+		assert_eq!(reply, b"hi request");
+		assert_eq!(stream.read(&mut [0; 8]).unwrap(), 0);
+
+		let mut stream = I2pStream::connect_with_session(&session, "peer.i2p:80").unwrap();
+		let (mut reader, mut writer) = stream.split();
+		writer.write_all(b"ping").unwrap();
+		writer.shutdown().unwrap();
+		let mut reply = vec![];
+		reader.read_to_end(&mut reply).unwrap();
+		assert_eq!(reply, b"hi ping");
+	}
+
+	#[test]
+	fn diagnostics() {
+		use crate::net::I2pStream;
+		use crate::sam::Session;
+
+		let session = Session::transient(fake_bridge(b"hi ")).unwrap();
+		assert_eq!(session.sam_version(), "3.2");
+		assert!(session.socket_addr().unwrap().unwrap().ip().is_loopback());
+		let mut stream = I2pStream::connect_with_session(&session, "peer.i2p:80").unwrap();
+		assert_eq!(stream.session_id(), session.id());
+		assert_eq!(stream.sam_version(), "3.2");
+		assert_ne!(stream.socket_addr().unwrap(), session.socket_addr().unwrap());
+		let clone = stream.try_clone().unwrap();
+		stream.write_all(b"ping").unwrap();
+		let mut buf = [0; 7];
+		stream.read_exact(&mut buf).unwrap();
+		assert_eq!((clone.bytes_read(), clone.bytes_written()), (7, 4));
+		assert!(clone.connected_for() <= stream.connected_for());
+	}
+
+	#[test]
+	fn idle_timeout() {
+		use std::time::{Duration, Instant};
+		use crate::net::I2pStream;
+		use crate::sam::Session;
+
+		let session = Session::transient(fake_bridge(b"")).unwrap();
+		let mut stream = I2pStream::connect_with_session(&session, "peer.i2p:80").unwrap();
+		stream.set_idle_timeout(Some(Duration::from_millis(300))).unwrap();
+		assert_eq!(stream.try_clone().unwrap().idle_timeout(), Some(Duration::from_millis(300)));
+		let start = Instant::now();
+		// traffic keeps the stream up
+		let mut buf = [0u8; 4];
+		for _ in 0..4 {
+			stream.write_all(b"ping").unwrap();
+			stream.read_exact(&mut buf).unwrap();
+			thread::sleep(Duration::from_millis(150));
+		}
+		assert_eq!(stream.read(&mut buf).unwrap(), 0);
+		assert!(start.elapsed() >= Duration::from_millis(700));
+		assert!(start.elapsed() < Duration::from_secs(2));
+
+		let stream = I2pStream::connect_with_session(&session, "peer.i2p:80").unwrap();
+		stream.set_idle_timeout(Some(Duration::from_millis(100))).unwrap();
+		stream.set_idle_timeout(None).unwrap();
+		thread::sleep(Duration::from_millis(200));
+		(&stream).write_all(b"ping").unwrap();
+		(&stream).read_exact(&mut buf).unwrap();
+	}
+
+	#[test]
+	fn vectored() {
+		use crate::net::I2pStream;
+		use crate::sam::Session;
+
+		let session = Session::transient(fake_bridge(b"")).unwrap();
+		let mut stream = I2pStream::connect_with_session(&session, "peer.i2p:80").unwrap();
+		let bufs = [IoSlice::new(b"len:"), IoSlice::new(b"4"), IoSlice::new(b"ping")];
+		let written = stream.write_vectored(&bufs).unwrap();
+		// short writes are completed the usual way
+		stream.write_all(&b"len:4ping"[written..]).unwrap();
+		let mut peeked = [0; 1];
+		stream.peek(&mut peeked).unwrap();
+		let (mut header, mut payload) = ([0; 5], [0; 4]);
+		let mut read = 0;
+		while read < 9 {
+			let n = if read < 5 {
+				let mut bufs = [IoSliceMut::new(&mut header[read..]), IoSliceMut::new(&mut payload)];
+				stream.read_vectored(&mut bufs).unwrap()
+			} else {
+				stream.read(&mut payload[read - 5..]).unwrap()
+			};
+			assert!(n > 0);
+			read += n;
+		}
+		assert_eq!(&header, b"len:4");
+		assert_eq!(&payload, b"ping");
+	}
+
+	#[test]
+	fn read_buffer() {
+		use crate::net::I2pStream;
+		use crate::sam::Session;
+
+		let session = Session::transient(fake_bridge(b"\x00\x04pingpong")).unwrap();
+		let mut stream = I2pStream::connect_with_session(&session, "peer.i2p:80").unwrap();
+		stream.set_read_buffer_capacity(64);
+		assert_eq!(stream.read_buffer_capacity(), 64);
+		let mut len = [0; 2];
+		stream.read_exact(&mut len).unwrap();
+		assert_eq!(len, [0, 4]);
+		// the rest got buffered, and is shared by clones
+		let mut ping = [0; 4];
+		stream.try_clone().unwrap().read_exact(&mut ping).unwrap();
+		assert_eq!(&ping, b"ping");
+		stream.set_read_buffer_capacity(0);
+		let mut pong = [0; 4];
+		stream.read_exact(&mut pong).unwrap();
+		assert_eq!(&pong, b"pong");
+	}
+}
@@ -0,0 +1,222 @@
+//! Destinations using offline signatures.
+//!
+//! With offline signatures the long-term signing key of a destination never
+//! has to be present on the machine running the service. That key is only
+//! used, elsewhere, to sign a short-lived transient signing key, and the
+//! router signs lease sets with the transient key until it expires.
+//!
+//! The long-term key signs the bytes returned by `OfflineSignature::signed_data`,
+//! after which `OfflineKeys` assembles the private key blob the SAM bridge
+//! expects in `SESSION CREATE`.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use zeroize::Zeroize;
+
+use crate::destination::{Destination, I2pPrivateKey, ELGAMAL_CRYPTO_TYPE, X25519_CRYPTO_TYPE};
+use crate::error::{Error, ErrorKind};
+use crate::net::BASE64_I2P;
+use crate::sam_options::SignatureType;
+
+/// A transient signing key, signed by the long-term signing key of a
+/// destination.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OfflineSignature {
+	expires: u32,
+	transient_type: SignatureType,
+	transient_public_key: Vec<u8>,
+	signature: Vec<u8>,
+}
+
+impl OfflineSignature {
+	/// The bytes the long-term signing key has to sign to authorize the
+	/// provided transient public key until `expires`.
+	pub fn signed_data(
+		expires: SystemTime,
+		transient_type: SignatureType,
+		transient_public_key: &[u8],
+	) -> Result<Vec<u8>, Error> {
+		let expires = expiration_secs(expires)?;
+		check_len("transient public key", transient_public_key, transient_type.public_key_len())?;
+		Ok(signed_data(expires, transient_type, transient_public_key))
+	}
+
+	/// Wraps the signature produced by the long-term signing key over
+	/// `signed_data` for the same parameters.
+	pub fn new(
+		expires: SystemTime,
+		transient_type: SignatureType,
+		transient_public_key: &[u8],
+		signature: &[u8],
+	) -> Result<OfflineSignature, Error> {
+		let expires = expiration_secs(expires)?;
+		check_len("transient public key", transient_public_key, transient_type.public_key_len())?;
+		Ok(OfflineSignature {
+			expires,
+			transient_type,
+			transient_public_key: transient_public_key.to_vec(),
+			signature: signature.to_vec(),
+		})
+	}
+
+	/// When the transient key stops being valid
+	pub fn expires(&self) -> SystemTime {
+		UNIX_EPOCH + Duration::from_secs(u64::from(self.expires))
+	}
+
+	/// Signature type of the transient key
+	pub fn transient_type(&self) -> SignatureType {
+		self.transient_type
+	}
+
+	fn to_bytes(&self) -> Vec<u8> {
+		let mut bytes = signed_data(self.expires, self.transient_type, &self.transient_public_key);
+		bytes.extend_from_slice(&self.signature);
+		bytes
+	}
+}
+
+/// The private keys of a destination whose long-term signing key is kept
+/// offline, ready to be handed to `Session::from_offline_keys`.
+#[derive(Clone, PartialEq, Eq)]
+pub struct OfflineKeys {
+	keys: I2pPrivateKey,
+}
+
+impl OfflineKeys {
+	/// Assembles the private keys from the base64 public destination, its
+	/// encryption private key, the offline signature and the private half of
+	/// the transient signing key.
+	pub fn new(
+		destination: &str,
+		encryption_private_key: &[u8],
+		offline: &OfflineSignature,
+		transient_private_key: &[u8],
+	) -> Result<OfflineKeys, Error> {
+		let dest = Destination::from_base64(destination)?;
+		let sig_type = dest.signature_type();
+		let enc_len = match dest.crypto_type() {
+			ELGAMAL_CRYPTO_TYPE => 256,
+			X25519_CRYPTO_TYPE => 32,
+			crypto_type => {
+				return Err(ErrorKind::BadKeyMaterial(format!(
+					"unsupported encryption type {}",
+					crypto_type
+				))
+				.into());
+			}
+		};
+		check_len("encryption private key", encryption_private_key, enc_len)?;
+		check_len("offline signature", &offline.signature, sig_type.signature_len())?;
+		check_len(
+			"transient private key",
+			transient_private_key,
+			offline.transient_type.private_key_len(),
+		)?;
+
+		let mut keys = dest.to_bytes();
+		keys.extend_from_slice(encryption_private_key);
+		// an all-zero signing private key marks the offline signature section
+		keys.extend(std::iter::repeat_n(0, sig_type.private_key_len()));
+		keys.extend(offline.to_bytes());
+		keys.extend_from_slice(transient_private_key);
+		let encoded = BASE64_I2P.encode(&keys);
+		keys.zeroize();
+		Ok(OfflineKeys {
+			keys: I2pPrivateKey::new(encoded),
+		})
+	}
+
+	/// The base64 private keys, as passed in `DESTINATION=`
+	pub fn as_str(&self) -> &str {
+		self.keys.as_str()
+	}
+}
+
+impl std::fmt::Debug for OfflineKeys {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str("OfflineKeys(..)")
+	}
+}
+
+fn signed_data(expires: u32, transient_type: SignatureType, transient_public_key: &[u8]) -> Vec<u8> {
+	let mut data = Vec::with_capacity(6 + transient_public_key.len());
+	data.extend_from_slice(&expires.to_be_bytes());
+	data.extend_from_slice(&transient_type.code().to_be_bytes());
+	data.extend_from_slice(transient_public_key);
+	data
+}
+
+fn expiration_secs(expires: SystemTime) -> Result<u32, Error> {
+	let secs = expires
+		.duration_since(UNIX_EPOCH)
+		.map_err(|_| ErrorKind::BadKeyMaterial("expiration before the epoch".to_string()))?
+		.as_secs();
+	if secs > u64::from(u32::MAX) {
+		return Err(ErrorKind::BadKeyMaterial("expiration too far in the future".to_string()).into());
+	}
+	Ok(secs as u32)
+}
+
+fn check_len(what: &str, bytes: &[u8], expected: usize) -> Result<(), Error> {
+	if bytes.len() != expected {
+		return Err(ErrorKind::BadKeyMaterial(format!(
+			"{} is {} bytes long, expected {}",
+			what,
+			bytes.len(),
+			expected
+		))
+		.into());
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use std::time::{Duration, UNIX_EPOCH};
+
+	use crate::net::BASE64_I2P;
+	use crate::offline::{OfflineKeys, OfflineSignature};
+	use crate::sam_options::SignatureType;
+
+	fn ed25519_x25519_dest() -> Vec<u8> {
+		let mut dest = vec![1u8; 384];
+		dest.extend_from_slice(&[5, 0, 4, 0, 7, 0, 4]);
+		dest
+	}
+
+	#[test]
+	fn offline_keys() {
+		let expires = UNIX_EPOCH + Duration::from_secs(0x0102_0304);
+		let transient_pub = [2u8; 32];
+		let data = OfflineSignature::signed_data(expires, SignatureType::EdDSA_SHA512_Ed25519, &transient_pub)
+			.unwrap();
+		assert_eq!(&data[..6], &[1, 2, 3, 4, 0, 7]);
+		assert_eq!(&data[6..], &transient_pub[..]);
+
+		let offline =
+			OfflineSignature::new(expires, SignatureType::EdDSA_SHA512_Ed25519, &transient_pub, &[3u8; 64])
+				.unwrap();
+		let dest = ed25519_x25519_dest();
+		let keys = OfflineKeys::new(&BASE64_I2P.encode(&dest), &[4u8; 32], &offline, &[5u8; 32]).unwrap();
+
+		let bytes = BASE64_I2P.decode(keys.as_str().as_bytes()).unwrap();
+		assert_eq!(bytes.len(), 391 + 32 + 32 + 6 + 32 + 64 + 32);
+		assert_eq!(&bytes[..391], &dest[..]);
+		assert_eq!(&bytes[391..423], &[4u8; 32][..]);
+		assert_eq!(&bytes[423..455], &[0u8; 32][..]);
+		assert_eq!(&bytes[455..461], &data[..6]);
+		assert_eq!(&bytes[bytes.len() - 32..], &[5u8; 32][..]);
+	}
+
+	#[test]
+	fn offline_keys_lengths() {
+		let expires = UNIX_EPOCH + Duration::from_secs(1);
+		let offline =
+			OfflineSignature::new(expires, SignatureType::EdDSA_SHA512_Ed25519, &[2u8; 32], &[3u8; 40])
+				.unwrap();
+		let dest = BASE64_I2P.encode(&ed25519_x25519_dest());
+		assert!(OfflineKeys::new(&dest, &[4u8; 32], &offline, &[5u8; 32]).is_err());
+		assert!(OfflineSignature::new(expires, SignatureType::EdDSA_SHA512_Ed25519, &[2u8; 31], &[]).is_err());
+	}
+}
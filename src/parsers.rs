@@ -1,5 +1,8 @@
+use std::borrow::Cow;
+
 use nom::{
-	alphanumeric, alt, do_parse, named, separated_list, space, tag_s, take_till_s,
+	alt, do_parse, named, opt, preceded, separated_list, space, tag, take_till, take_while1,
+	Context, Err, ErrorKind, IResult, Needed,
 };
 
 fn is_space(chr: char) -> bool {
@@ -14,171 +17,284 @@ fn is_space_or_next_line(chr: char) -> bool {
 	is_space(chr) || is_next_line(chr)
 }
 
-fn is_double_quote(chr: char) -> bool {
-	chr == '\"'
+fn is_key_char(chr: char) -> bool {
+	!is_space_or_next_line(chr) && chr != '=' && chr != '"'
 }
 
-named!(quoted_value <&str, &str>,
-    do_parse!(
-             tag_s!("\"")                  >>
-        val: take_till_s!(is_double_quote) >>
-             tag_s!("\"")                  >>
-        (val)
-    )
-);
+/// Whether `key` can be the key of a field: not empty, without spaces,
+/// `=` or double quotes.
+pub fn is_key(key: &str) -> bool {
+	!key.is_empty() && key.chars().all(is_key_char)
+}
+
+/// A double quoted value, in which double quotes and backslashes are
+/// escaped with a backslash. The value is returned still escaped, see
+/// `unescape`.
+fn quoted_value(input: &str) -> IResult<&str, &str> {
+	if !input.starts_with('"') {
+		return Err(Err::Error(Context::Code(input, ErrorKind::Tag)));
+	}
+	let mut escaped = false;
+	for (i, chr) in input.char_indices().skip(1) {
+		match chr {
+			_ if escaped => escaped = false,
+			'\\' => escaped = true,
+			'"' => return Ok((&input[i + 1..], &input[1..i])),
+			_ => {}
+		}
+	}
+	Err(Err::Error(Context::Code(input, ErrorKind::Tag)))
+}
+
+/// Removes the escaping of a quoted value.
+pub fn unescape(value: &str) -> Cow<'_, str> {
+	if !value.contains('\\') {
+		return Cow::Borrowed(value);
+	}
+	let mut unescaped = String::with_capacity(value.len());
+	let mut chars = value.chars();
+	while let Some(chr) = chars.next() {
+		match chr {
+			'\\' => match chars.next() {
+				Some(next @ '"') | Some(next @ '\\') => unescaped.push(next),
+				Some(next) => {
+					unescaped.push(chr);
+					unescaped.push(next);
+				}
+				None => unescaped.push(chr),
+			},
+			_ => unescaped.push(chr),
+		}
+	}
+	Cow::Owned(unescaped)
+}
 
-named!(value <&str, &str>, take_till_s!(is_space_or_next_line));
+/// Quotes a value sent to the bridge if it would otherwise break the
+/// key/value list, escaping double quotes and backslashes.
+pub fn quote(value: &str) -> Cow<'_, str> {
+	let needs_quotes = value.is_empty()
+		|| value
+			.chars()
+			.any(|c| is_space_or_next_line(c) || c == '"' || c == '\\' || c == '=');
+	if !needs_quotes {
+		return Cow::Borrowed(value);
+	}
+	let mut quoted = String::with_capacity(value.len() + 2);
+	quoted.push('"');
+	for chr in value.chars() {
+		if chr == '"' || chr == '\\' {
+			quoted.push('\\');
+		}
+		quoted.push(chr);
+	}
+	quoted.push('"');
+	Cow::Owned(quoted)
+}
 
+named!(value <&str, &str>, take_till!(is_space_or_next_line));
+
+// keys without a value, which newer bridges may send, get an empty one
 named!(key_value <&str, (&str, &str)>,
     do_parse!(
-        key: alphanumeric               >>
-             tag_s!("=")                >>
-        val: alt!(quoted_value | value) >>
-        (key, val)
+        key: take_while1!(is_key_char)                              >>
+        val: opt!(preceded!(tag!("="), alt!(quoted_value | value))) >>
+        (key, val.unwrap_or(""))
     )
 );
 
-named!(keys_and_values<&str, Vec<(&str, &str)> >, separated_list!(space, key_value));
-
-named!(pub sam_hello <&str, Vec<(&str, &str)> >,
+named!(keys_and_values<&str, Vec<(&str, &str)> >,
     do_parse!(
-              tag_s!("HELLO REPLY ") >>
-        opts: keys_and_values        >>
-              tag_s!("\n")           >>
+        opts: separated_list!(space, key_value) >>
+              opt!(space)                       >>
         (opts)
     )
 );
 
-named!(pub sam_session_status <&str, Vec<(&str, &str)> >,
+named!(pub sam_datagram_received <&str, Vec<(&str, &str)> >,
     do_parse!(
-              tag_s!("SESSION STATUS ") >>
-        opts: keys_and_values           >>
-              tag_s!("\n")              >>
+              tag!("DATAGRAM RECEIVED ") >>
+        opts: keys_and_values            >>
+              tag!("\n")                 >>
         (opts)
     )
 );
 
-named!(pub sam_stream_status <&str, Vec<(&str, &str)> >,
+named!(pub sam_raw_received <&str, Vec<(&str, &str)> >,
     do_parse!(
-              tag_s!("STREAM STATUS ") >>
-        opts: keys_and_values          >>
-              tag_s!("\n")             >>
+              tag!("RAW RECEIVED ") >>
+        opts: keys_and_values       >>
+              tag!("\n")            >>
         (opts)
     )
 );
 
-named!(pub sam_naming_reply <&str, Vec<(&str, &str)> >,
+named!(pub sam_raw_header <&str, Vec<(&str, &str)> >,
     do_parse!(
-              tag_s!("NAMING REPLY ") >>
-        opts: keys_and_values         >>
-              tag_s!("\n")            >>
+        opts: keys_and_values >>
+              tag!("\n")      >>
         (opts)
     )
 );
 
-named!(pub sam_dest_reply <&str, Vec<(&str, &str)> >,
+named!(pub sam_dest_line <&str, (&str, Vec<(&str, &str)>)>,
     do_parse!(
-              tag_s!("DEST REPLY ") >>
-        opts: keys_and_values       >>
-              tag_s!("\n")          >>
-        (opts)
+        dest: take_till!(is_space_or_next_line)           >>
+        opts: opt!(preceded!(space, keys_and_values))     >>
+              tag!("\n")                                  >>
+        (dest, opts.unwrap_or_default())
+    )
+);
+
+/// The leading words of a reply line and its fields
+pub type ReplyLine<'a> = (&'a str, Vec<(&'a str, &'a str)>);
+
+/// Any reply line, split into its leading words, such as `NAMING REPLY`,
+/// and its fields, which start at the first word holding a `=`.
+pub fn sam_reply(input: &str) -> IResult<&str, ReplyLine<'_>> {
+	let line_len = match input.find('\n') {
+		Some(len) => len,
+		None => return Err(Err::Incomplete(Needed::Unknown)),
+	};
+	let mut command_len = 0;
+	for word in input[..line_len].split(' ') {
+		if word.contains('=') {
+			break;
+		}
+		command_len += word.len() + 1;
+	}
+	let command_len = command_len.min(line_len);
+	let command = input[..command_len].trim_end();
+	if command.is_empty() {
+		return Err(Err::Error(Context::Code(input, ErrorKind::Tag)));
+	}
+	let (rest, opts) = keys_and_values(&input[command_len..])?;
+	let (rest, _) = tag!(rest, "\n")?;
+	Ok((rest, (command, opts)))
+}
+
+named!(pub sam_ping <&str, &str>,
+    do_parse!(
+              tag!("PING")                                   >>
+        data: opt!(preceded!(space, take_till!(is_next_line))) >>
+              tag!("\n")                                     >>
+        (data.unwrap_or(""))
+    )
+);
+
+named!(pub sam_pong <&str, &str>,
+    do_parse!(
+              tag!("PONG")                                   >>
+        data: opt!(preceded!(space, take_till!(is_next_line))) >>
+              tag!("\n")                                     >>
+        (data.unwrap_or(""))
     )
 );
 
 #[cfg(test)]
 mod tests {
-	use nom::ErrorKind;
-
 	#[test]
-	fn hello() {
-		use crate::parsers::sam_hello;
+	fn any_reply() {
+		use crate::parsers::sam_reply;
 
 		assert_eq!(
-			sam_hello("HELLO REPLY RESULT=OK VERSION=3.1\n"),
-			Ok(("", vec![("RESULT", "OK"), ("VERSION", "3.1")]))
-		);
-		assert_eq!(
-			sam_hello("HELLO REPLY RESULT=NOVERSION\n"),
-			Ok(("", vec![("RESULT", "NOVERSION")]))
-		);
-		assert_eq!(
-			sam_hello("HELLO REPLY RESULT=I2P_ERROR MESSAGE=\"Something failed\"\n"),
-			Ok((
-				"",
-				vec![("RESULT", "I2P_ERROR"), ("MESSAGE", "Something failed")]
-			))
+			sam_reply("NAMING REPLY RESULT=OK NAME=\"a b\" VALUE=dest\n"),
+			Ok(("", ("NAMING REPLY", vec![("RESULT", "OK"), ("NAME", "a b"), ("VALUE", "dest")])))
 		);
+		assert_eq!(sam_reply("PONG data\n"), Ok(("", ("PONG data", vec![]))));
+		assert_eq!(sam_reply("FOO STATUS \n"), Ok(("", ("FOO STATUS", vec![]))));
+		assert!(sam_reply("RESULT=OK\n").is_err());
+		assert!(sam_reply("NAMING REPLY").is_err());
 	}
 
 	#[test]
-	fn session_status() {
-		use crate::parsers::sam_session_status;
+	fn quoted() {
+		use crate::parsers::{quote, sam_reply, unescape};
 
+		let (_, (_, opts)) =
+			sam_reply("HELLO REPLY RESULT=I2P_ERROR MESSAGE=\"says \\\"no\\\" \\\\o/\" VERSION=\"\"\n")
+				.unwrap();
+		assert_eq!(opts[1], ("MESSAGE", "says \\\"no\\\" \\\\o/"));
+		assert_eq!(unescape(opts[1].1), "says \"no\" \\o/");
+		assert_eq!(opts[2], ("VERSION", ""));
+		// an unterminated quote is kept as part of a plain value
 		assert_eq!(
-			sam_session_status("SESSION STATUS RESULT=OK DESTINATION=privkey\n"),
-			Ok(("", vec![("RESULT", "OK"), ("DESTINATION", "privkey")]))
-		);
-		assert_eq!(
-			sam_session_status("SESSION STATUS RESULT=DUPLICATED_ID\n"),
-			Ok(("", vec![("RESULT", "DUPLICATED_ID")]))
+			sam_reply("HELLO REPLY MESSAGE=\"unterminated\n"),
+			Ok(("", ("HELLO REPLY", vec![("MESSAGE", "\"unterminated")])))
 		);
+
+		assert_eq!(quote("plain"), "plain");
+		assert_eq!(quote(""), "\"\"");
+		assert_eq!(quote("two words"), "\"two words\"");
+		assert_eq!(quote("a \"b\" \\"), "\"a \\\"b\\\" \\\\\"");
+		let line = format!("HELLO REPLY MESSAGE={}\n", quote("a \"b\" \\"));
+		assert_eq!(unescape((sam_reply(&line).unwrap().1).1[0].1), "a \"b\" \\");
 	}
 
 	#[test]
-	fn stream_status() {
-		use crate::parsers::sam_stream_status;
+	fn unknown_keys() {
+		use crate::parsers::sam_reply;
 
 		assert_eq!(
-			sam_stream_status("STREAM STATUS RESULT=OK\n"),
-			Ok(("", vec![("RESULT", "OK")]))
-		);
-		assert_eq!(
-			sam_stream_status(
-				"STREAM STATUS RESULT=CANT_REACH_PEER MESSAGE=\"Can't reach peer\"\n"
-			),
-			Ok((
-				"",
-				vec![
-					("RESULT", "CANT_REACH_PEER"),
-					("MESSAGE", "Can't reach peer")
-				]
-			))
+			sam_reply("STREAM STATUS RESULT=OK NEW-KEY=x:y FLAG  \n"),
+			Ok(("", ("STREAM STATUS", vec![("RESULT", "OK"), ("NEW-KEY", "x:y"), ("FLAG", "")])))
 		);
 	}
 
 	#[test]
-	fn naming_reply() {
-		use crate::parsers::sam_naming_reply;
+	fn datagram_received() {
+		use crate::parsers::sam_datagram_received;
 
 		assert_eq!(
-			sam_naming_reply("NAMING REPLY RESULT=OK NAME=name VALUE=dest\n"),
-			Ok((
-				"",
-				vec![("RESULT", "OK"), ("NAME", "name"), ("VALUE", "dest")]
-			))
-		);
-		assert_eq!(
-			sam_naming_reply("NAMING REPLY RESULT=KEY_NOT_FOUND\n"),
-			Ok(("", vec![("RESULT", "KEY_NOT_FOUND")]))
+			sam_datagram_received("DATAGRAM RECEIVED DESTINATION=dest SIZE=42\n"),
+			Ok(("", vec![("DESTINATION", "dest"), ("SIZE", "42")]))
 		);
+	}
+
+	#[test]
+	fn raw_received() {
+		use crate::parsers::sam_raw_received;
 
 		assert_eq!(
-			sam_naming_reply("NAMINGREPLY RESULT=KEY_NOT_FOUND\n").unwrap_err().into_error_kind(),
-			ErrorKind::Tag
+			sam_raw_received("RAW RECEIVED SIZE=42\n"),
+			Ok(("", vec![("SIZE", "42")]))
 		);
+	}
+
+	#[test]
+	fn raw_header() {
+		use crate::parsers::sam_raw_header;
+
 		assert_eq!(
-			sam_naming_reply("NAMING  REPLY RESULT=KEY_NOT_FOUND\n").unwrap_err().into_error_kind(),
-			ErrorKind::Tag
+			sam_raw_header("FROM_PORT=1 TO_PORT=2 PROTOCOL=18\n"),
+			Ok(("", vec![("FROM_PORT", "1"), ("TO_PORT", "2"), ("PROTOCOL", "18")]))
 		);
 	}
 
 	#[test]
-	fn dest_reply() {
-		use crate::parsers::sam_dest_reply;
+	fn dest_line() {
+		use crate::parsers::sam_dest_line;
 
+		assert_eq!(sam_dest_line("dest\n"), Ok(("", ("dest", vec![]))));
 		assert_eq!(
-			sam_dest_reply("DEST REPLY PUB=foo PRIV=foobar\n"),
-			Ok(("", vec![("PUB", "foo"), ("PRIV", "foobar")]))
+			sam_dest_line("dest FROM_PORT=1234 TO_PORT=80\n"),
+			Ok(("", ("dest", vec![("FROM_PORT", "1234"), ("TO_PORT", "80")])))
 		);
 	}
+
+	#[test]
+	fn ping() {
+		use crate::parsers::sam_ping;
+
+		assert_eq!(sam_ping("PING 1234\n"), Ok(("", "1234")));
+		assert_eq!(sam_ping("PING\n"), Ok(("", "")));
+		assert!(sam_ping("PONG\n").is_err());
+	}
+
+	#[test]
+	fn pong() {
+		use crate::parsers::sam_pong;
+
+		assert_eq!(sam_pong("PONG foo\n"), Ok(("", "foo")));
+		assert_eq!(sam_pong("PONG\n"), Ok(("", "")));
+	}
 }
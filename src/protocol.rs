@@ -0,0 +1,335 @@
+//! The client side of the SAM control protocol as a state machine doing no
+//! I/O, for driving it over any transport or runtime.
+//!
+//! `SamProtocol` turns commands into the bytes to send, and the bytes read
+//! from the bridge into events: the replies to the commands, in order, and
+//! the PONGs to the PINGs. Whoever owns the socket writes the former and
+//! feeds the latter, which is all the blocking `SamConnection` does.
+//!
+//! ```
+//! use i2p::codec::SamLine;
+//! use i2p::protocol::{Event, SamProtocol};
+//!
+//! let mut sam = SamProtocol::new();
+//! let mut out = sam.hello(None).unwrap();
+//! out.extend(sam.command(&SamLine::new("NAMING LOOKUP").field("NAME", "me")).unwrap());
+//! // write `out` to the bridge, then feed what it answers
+//! sam.receive(b"HELLO REPLY RESULT=OK VERSION=3.3\nNAMING REPLY RESULT=OK NAME=me VALUE=dest\n");
+//! assert!(sam.poll_event().unwrap().is_some());
+//! assert_eq!(sam.version(), Some("3.3"));
+//! match sam.poll_event().unwrap() {
+//!     Some(Event::Reply { line, result }) => {
+//!         assert!(result.is_ok());
+//!         assert_eq!(line.get("VALUE"), Some("dest"));
+//!     }
+//!     event => panic!("unexpected event: {:?}", event),
+//! }
+//! ```
+
+use std::collections::{HashMap, VecDeque};
+
+use crate::codec::{SamCodec, SamLine};
+use crate::error::{Error, ErrorKind};
+use crate::parsers::{sam_ping, sam_pong};
+use crate::reply::{SamReply, TypedReply};
+use crate::sam::{SAM_MAX, SAM_MIN};
+
+/// What the bytes received from the bridge amount to.
+#[derive(Debug)]
+pub enum Event {
+	/// The reply to the oldest command still waiting for one. Failures the
+	/// bridge reports in `RESULT` are turned into errors, the line keeping
+	/// all the fields either way.
+	Reply {
+		line: SamLine,
+		result: Result<SamReply, Error>,
+	},
+	/// The PONG answering the oldest PING still waiting for one, with its
+	/// data.
+	Pong(String),
+}
+
+/// A SAM control connection, see the module documentation.
+#[derive(Clone, Debug, Default)]
+pub struct SamProtocol {
+	codec: SamCodec,
+	hello_sent: bool,
+	version: Option<String>,
+	/// The commands waiting for a reply, oldest first
+	pending: VecDeque<String>,
+	/// The data of the PINGs waiting for a PONG, oldest first
+	pings: VecDeque<String>,
+	received: Vec<u8>,
+	transmit: Vec<u8>,
+}
+
+impl SamProtocol {
+	/// A connection that hasn't sent HELLO yet.
+	pub fn new() -> SamProtocol {
+		SamProtocol::default()
+	}
+
+	/// A connection whose HELLO already got `version` negotiated, such as
+	/// one established by other means.
+	pub fn with_version(version: &str) -> SamProtocol {
+		SamProtocol {
+			hello_sent: true,
+			version: Some(version.to_string()),
+			..SamProtocol::default()
+		}
+	}
+
+	/// The HELLO opening the connection, with the credentials SAM 3.2+
+	/// bridges may require. Commands can follow it right away, without
+	/// waiting for its reply.
+	pub fn hello(&mut self, credentials: Option<(&str, &str)>) -> Result<Vec<u8>, Error> {
+		if self.hello_sent {
+			return Err(ErrorKind::SAMInvalidMessage("HELLO already sent".to_string()).into());
+		}
+		let mut hello = SamLine::new("HELLO VERSION")
+			.field("MIN", SAM_MIN)
+			.field("MAX", SAM_MAX);
+		if let Some((user, password)) = credentials {
+			hello = hello.field("USER", user).field("PASSWORD", password);
+		}
+		let msg = self.command(&hello)?;
+		self.hello_sent = true;
+		Ok(msg)
+	}
+
+	/// A command getting a reply, which comes as an `Event::Reply` in the
+	/// order the commands were sent in.
+	pub fn command(&mut self, line: &SamLine) -> Result<Vec<u8>, Error> {
+		let mut msg = vec![];
+		self.codec.encode(line, &mut msg)?;
+		self.expect_reply(line.command.trim())?;
+		Ok(msg)
+	}
+
+	/// Same as `command` for a line written elsewhere, which is sent as is
+	/// apart from its line feed.
+	pub fn raw_command(&mut self, line: &str) -> Result<Vec<u8>, Error> {
+		let line = line.trim_end_matches('\n');
+		if line.contains('\n') {
+			return Err(ErrorKind::SAMInvalidMessage("Line feed in a SAM command".to_string()).into());
+		}
+		self.expect_reply(line)?;
+		Ok(format!("{}\n", line).into_bytes())
+	}
+
+	/// A PING, answered by an `Event::Pong` (SAM 3.2+).
+	pub fn ping(&mut self, data: &str) -> Result<Vec<u8>, Error> {
+		if data.contains('\n') {
+			return Err(ErrorKind::SAMInvalidMessage("Line feed in PING data".to_string()).into());
+		}
+		self.pings.push_back(data.to_string());
+		Ok(format!("PING {}\n", data).into_bytes())
+	}
+
+	/// Feeds bytes read from the bridge.
+	pub fn receive(&mut self, data: &[u8]) {
+		self.received.extend_from_slice(data);
+	}
+
+	/// The next event of the bytes received, `None` until a whole line came.
+	/// The PINGs of the bridge are answered on the way, see `transmit`.
+	/// Lines that can't be parsed, and replies or PONGs nothing waits for,
+	/// fail with `SAMInvalidMessage` or `MessageParsing`. Lines that can't
+	/// be parsed are dropped, taking the place of the reply to the oldest
+	/// command, so the replies that follow still match their commands.
+	pub fn poll_event(&mut self) -> Result<Option<Event>, Error> {
+		loop {
+			let line_len = match self.received.iter().position(|&b| b == b'\n') {
+				Some(pos) => pos + 1,
+				None => return self.codec.decode(&self.received).map(|_| None),
+			};
+			let text = match std::str::from_utf8(&self.received[..line_len]) {
+				Ok(text) => text.to_string(),
+				Err(_) => return Err(self.drop_line(line_len, ErrorKind::MessageParsing.into())),
+			};
+
+			// the bridge checking on us
+			if let Ok((_, data)) = sam_ping(&text) {
+				self.received.drain(..line_len);
+				self.transmit.extend_from_slice(format!("PONG {}\n", data).as_bytes());
+				continue;
+			}
+			if let Ok((_, data)) = sam_pong(&text) {
+				self.received.drain(..line_len);
+				return match self.pings.pop_front() {
+					Some(ref ping) if ping == data => Ok(Some(Event::Pong(data.to_string()))),
+					_ => Err(ErrorKind::SAMInvalidMessage(format!("Unexpected PONG: {}", data)).into()),
+				};
+			}
+
+			let (line, len) = match self.codec.decode(&self.received) {
+				Ok(Some(decoded)) => decoded,
+				Ok(None) => return Ok(None),
+				Err(e) => return Err(self.drop_line(line_len, e)),
+			};
+			self.received.drain(..len);
+			if self.pending.pop_front().is_none() {
+				return Err(ErrorKind::SAMInvalidMessage(format!("Unexpected reply: {}", line.command)).into());
+			}
+			let result = self.reply(&line);
+			return Ok(Some(Event::Reply { line, result }));
+		}
+	}
+
+	/// The bytes to send on behalf of the protocol itself, the PONGs
+	/// answering the PINGs of the bridge, to be sent once `poll_event`
+	/// returns.
+	pub fn transmit(&mut self) -> Vec<u8> {
+		std::mem::take(&mut self.transmit)
+	}
+
+	/// The version negotiated by HELLO, once its reply came.
+	pub fn version(&self) -> Option<&str> {
+		self.version.as_deref()
+	}
+
+	/// The number of commands still waiting for a reply.
+	pub fn pending(&self) -> usize {
+		self.pending.len()
+	}
+
+	/// The bytes received after the last event, such as the beginning of
+	/// the data of a stream once its `STREAM STATUS` came.
+	pub fn into_remaining(self) -> Vec<u8> {
+		self.received
+	}
+
+	fn expect_reply(&mut self, command: &str) -> Result<(), Error> {
+		if !self.hello_sent && !command.starts_with("HELLO") {
+			return Err(ErrorKind::SAMInvalidMessage("No HELLO sent".to_string()).into());
+		}
+		self.pending.push_back(command.to_string());
+		Ok(())
+	}
+
+	fn drop_line(&mut self, len: usize, e: Error) -> Error {
+		self.received.drain(..len);
+		self.pending.pop_front();
+		e
+	}
+
+	fn reply(&mut self, line: &SamLine) -> Result<SamReply, Error> {
+		check_result(line.get("RESULT"), line.get("MESSAGE").unwrap_or(""))?;
+		let fields: HashMap<String, String> = line.fields.iter().cloned().collect();
+		let reply = SamReply::new(&line.command, fields)?;
+		if let TypedReply::Hello(ref hello) = reply.reply {
+			self.version = Some(hello.version.clone());
+		}
+		Ok(reply)
+	}
+}
+
+/// The error matching the `RESULT` of a reply, none when it's missing, and
+/// its unescaped `MESSAGE`.
+pub(crate) fn check_result(result: Option<&str>, msg: &str) -> Result<(), Error> {
+	match result.unwrap_or("OK") {
+		"OK" => Ok(()),
+		"CANT_REACH_PEER" => Err(ErrorKind::SAMCantReachPeer(msg.to_string()).into()),
+		"KEY_NOT_FOUND" => Err(ErrorKind::SAMKeyNotFound(msg.to_string()).into()),
+		"PEER_NOT_FOUND" => Err(ErrorKind::SAMPeerNotFound(msg.to_string()).into()),
+		"DUPLICATED_DEST" => Err(ErrorKind::SAMDuplicatedDest(msg.to_string()).into()),
+		"DUPLICATED_ID" => Err(ErrorKind::SAMDuplicatedId(msg.to_string()).into()),
+		"NOVERSION" => Err(ErrorKind::SAMNoVersion(msg.to_string()).into()),
+		"ALREADY_ACCEPTING" => Err(ErrorKind::SAMAlreadyAccepting(msg.to_string()).into()),
+		"INVALID_KEY" => Err(ErrorKind::SAMInvalidKey(msg.to_string()).into()),
+		"INVALID_ID" => Err(ErrorKind::SAMInvalidId(msg.to_string()).into()),
+		"TIMEOUT" => Err(ErrorKind::SAMTimeout(msg.to_string()).into()),
+		"I2P_ERROR" => Err(ErrorKind::SAMI2PError(msg.to_string()).into()),
+		res => Err(ErrorKind::SAMInvalidMessage(format!("{} {}", res, msg).trim_end().to_string()).into()),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::codec::SamLine;
+	use crate::error::{Error, ErrorKind};
+	use crate::protocol::{Event, SamProtocol};
+	use crate::reply::{SamReply, TypedReply};
+
+	fn reply(sam: &mut SamProtocol) -> (SamLine, Result<SamReply, Error>) {
+		match sam.poll_event().unwrap() {
+			Some(Event::Reply { line, result }) => (line, result),
+			event => panic!("unexpected event: {:?}", event),
+		}
+	}
+
+	#[test]
+	fn pipelined() {
+		let mut sam = SamProtocol::new();
+		let hello = sam.hello(Some(("me", "a secret"))).unwrap();
+		assert_eq!(hello, &b"HELLO VERSION MIN=3.0 MAX=3.3 USER=me PASSWORD=\"a secret\"\n"[..]);
+		assert!(sam.hello(None).is_err());
+		sam.raw_command("DEST GENERATE\n").unwrap();
+		sam.command(&SamLine::new("NAMING LOOKUP").field("NAME", "nope")).unwrap();
+		assert_eq!(sam.pending(), 3);
+
+		// replies come in pieces
+		sam.receive(b"HELLO REPLY RESULT=OK VERSION=3.2\nDEST REPLY PUB=pub");
+		let (_, result) = reply(&mut sam);
+		assert_eq!(sam.version(), Some("3.2"));
+		assert!(matches!(result.unwrap().reply, TypedReply::Hello(_)));
+		assert!(sam.poll_event().unwrap().is_none());
+		sam.receive(b" PRIV=priv\nNAMING REPLY RESULT=KEY_NOT_FOUND NAME=nope MESSAGE=\"not \\\"here\\\"\"\n");
+		match reply(&mut sam).1.unwrap().reply {
+			TypedReply::Dest(dest) => assert_eq!(dest.private, "priv"),
+			reply => panic!("unexpected reply: {:?}", reply),
+		}
+		let (line, result) = reply(&mut sam);
+		assert_eq!(line.get("NAME"), Some("nope"));
+		match result.unwrap_err().kind() {
+			ErrorKind::SAMKeyNotFound(msg) => assert_eq!(msg, "not \"here\""),
+			kind => panic!("unexpected error: {:?}", kind),
+		}
+		assert_eq!(sam.pending(), 0);
+
+		sam.receive(b"NAMING REPLY RESULT=OK\n");
+		assert!(sam.poll_event().is_err());
+
+		// a garbled reply doesn't get in the way of the next ones
+		sam.raw_command("NAMING LOOKUP NAME=a").unwrap();
+		sam.raw_command("NAMING LOOKUP NAME=b").unwrap();
+		sam.receive(b"\xff\nNAMING REPLY RESULT=OK NAME=b VALUE=dest\n");
+		assert_eq!(sam.poll_event().unwrap_err().kind(), ErrorKind::MessageParsing);
+		assert_eq!(sam.pending(), 1);
+		assert_eq!(reply(&mut sam).0.get("NAME"), Some("b"));
+	}
+
+	#[test]
+	fn pings() {
+		let mut sam = SamProtocol::with_version("3.3");
+		assert_eq!(sam.ping("mine").unwrap(), b"PING mine\n");
+		sam.receive(b"PING theirs\nPONG mine\nSTREAM STATUS RESULT=OK\n");
+		match sam.poll_event().unwrap() {
+			Some(Event::Pong(data)) => assert_eq!(data, "mine"),
+			event => panic!("unexpected event: {:?}", event),
+		}
+		assert_eq!(sam.transmit(), b"PONG theirs\n");
+		assert!(sam.transmit().is_empty());
+		assert!(sam.poll_event().is_err());
+
+		let mut sam = SamProtocol::with_version("3.3");
+		sam.ping("mine").unwrap();
+		sam.receive(b"PONG other\n");
+		assert!(sam.poll_event().is_err());
+	}
+
+	#[test]
+	fn before_hello() {
+		let mut sam = SamProtocol::new();
+		assert!(sam.command(&SamLine::new("DEST GENERATE")).is_err());
+		assert!(sam.command(&SamLine::new("")).is_err());
+		assert_eq!(sam.pending(), 0);
+		sam.hello(None).unwrap();
+		sam.raw_command("STREAM CONNECT ID=s DESTINATION=dest").unwrap();
+		sam.receive(b"HELLO REPLY RESULT=OK VERSION=3.1\nSTREAM STATUS RESULT=OK\nstream data");
+		reply(&mut sam).1.unwrap();
+		reply(&mut sam).1.unwrap();
+		assert!(sam.poll_event().unwrap().is_none());
+		assert_eq!(sam.into_remaining(), b"stream data");
+	}
+}
@@ -0,0 +1,305 @@
+//! Local SOCKS5 proxy opening its connections as streams of a session, for
+//! clients which can be given a proxy URL but not a connector.
+//!
+//! ```no_run
+//! use i2p::proxy::Socks5Proxy;
+//! use i2p::Session;
+//!
+//! let session = Session::transient("127.0.0.1:7656").unwrap();
+//! let proxy = Socks5Proxy::bind(session).unwrap();
+//! // for example reqwest::Proxy::all(proxy.url())
+//! println!("proxy listening on {}", proxy.url());
+//! ```
+
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+
+use log::debug;
+
+use crate::error::{ConnectFailure, Error};
+use crate::net::I2pStream;
+use crate::sam::Session;
+
+const SOCKS_VERSION: u8 = 5;
+const NO_AUTH: u8 = 0;
+const NO_ACCEPTABLE_METHOD: u8 = 0xff;
+const CMD_CONNECT: u8 = 1;
+const ATYP_IPV4: u8 = 1;
+const ATYP_DOMAIN: u8 = 3;
+const ATYP_IPV6: u8 = 4;
+
+/// Replies of the SOCKS5 protocol, RFC 1928
+const REPLY_SUCCEEDED: u8 = 0;
+const REPLY_GENERAL_FAILURE: u8 = 1;
+const REPLY_NOT_ALLOWED: u8 = 2;
+const REPLY_NETWORK_UNREACHABLE: u8 = 3;
+const REPLY_HOST_UNREACHABLE: u8 = 4;
+const REPLY_CONNECTION_REFUSED: u8 = 5;
+const REPLY_TTL_EXPIRED: u8 = 6;
+const REPLY_COMMAND_NOT_SUPPORTED: u8 = 7;
+const REPLY_ADDRESS_NOT_SUPPORTED: u8 = 8;
+
+/// A SOCKS5 proxy on a local TCP port, each `CONNECT` to an I2P host
+/// opening a stream of the session.
+///
+/// Only hostnames can be connected to, so clients must leave name
+/// resolution to the proxy, which the `socks5h` scheme of `url` asks for.
+/// No authentication is required: the proxy should only listen on the
+/// loopback interface. It stops once `shutdown` is called or it's dropped,
+/// closing the connections still open.
+pub struct Socks5Proxy {
+	addr: SocketAddr,
+	shared: Arc<Shared>,
+	acceptor: Option<JoinHandle<()>>,
+}
+
+struct Shared {
+	session: Session,
+	closed: AtomicBool,
+	clients: Mutex<Clients>,
+}
+
+#[derive(Default)]
+struct Clients {
+	next_id: u64,
+	open: HashMap<u64, TcpStream>,
+}
+
+impl Socks5Proxy {
+	/// Starts a proxy on an ephemeral port of the loopback interface.
+	pub fn bind(session: Session) -> Result<Socks5Proxy, Error> {
+		Socks5Proxy::bind_addr(session, "127.0.0.1:0")
+	}
+
+	/// Starts a proxy on the given local address.
+	pub fn bind_addr<A: ToSocketAddrs>(session: Session, addr: A) -> Result<Socks5Proxy, Error> {
+		let listener = TcpListener::bind(addr)?;
+		let addr = listener.local_addr()?;
+		let shared = Arc::new(Shared {
+			session,
+			closed: AtomicBool::new(false),
+			clients: Mutex::new(Clients::default()),
+		});
+		let accepting = shared.clone();
+		let acceptor = thread::spawn(move || accept_clients(&listener, &accepting));
+		Ok(Socks5Proxy {
+			addr,
+			shared,
+			acceptor: Some(acceptor),
+		})
+	}
+
+	/// The local address the proxy listens on.
+	pub fn local_addr(&self) -> SocketAddr {
+		self.addr
+	}
+
+	/// The URL of the proxy, as HTTP clients take it.
+	pub fn url(&self) -> String {
+		format!("socks5h://{}", self.addr)
+	}
+
+	/// The session the streams are opened on.
+	pub fn session(&self) -> &Session {
+		&self.shared.session
+	}
+
+	/// Stops accepting clients and closes the open connections.
+	pub fn shutdown(mut self) -> Result<(), Error> {
+		self.stop()
+	}
+
+	fn stop(&mut self) -> Result<(), Error> {
+		let acceptor = match self.acceptor.take() {
+			Some(acceptor) => acceptor,
+			None => return Ok(()),
+		};
+		self.shared.closed.store(true, Ordering::SeqCst);
+		// wakes the acceptor up
+		let woken = TcpStream::connect(self.addr);
+		for client in clients(&self.shared).open.values() {
+			let _ = client.shutdown(Shutdown::Both);
+		}
+		woken?;
+		let _ = acceptor.join();
+		Ok(())
+	}
+}
+
+impl Drop for Socks5Proxy {
+	fn drop(&mut self) {
+		let _ = self.stop();
+	}
+}
+
+fn clients(shared: &Shared) -> MutexGuard<'_, Clients> {
+	shared.clients.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn accept_clients(listener: &TcpListener, shared: &Arc<Shared>) {
+	for client in listener.incoming() {
+		if shared.closed.load(Ordering::SeqCst) {
+			return;
+		}
+		let client = match client {
+			Ok(client) => client,
+			Err(e) => {
+				debug!("SOCKS proxy couldn't accept a client: {}", e);
+				continue;
+			}
+		};
+		let id = {
+			let mut clients = clients(shared);
+			let id = clients.next_id;
+			clients.next_id += 1;
+			if let Ok(client) = client.try_clone() {
+				clients.open.insert(id, client);
+			}
+			id
+		};
+		let shared = shared.clone();
+		thread::spawn(move || {
+			if let Err(e) = serve(client, &shared.session) {
+				debug!("SOCKS client failed: {}", e);
+			}
+			clients(&shared).open.remove(&id);
+		});
+	}
+}
+
+/// Negotiates the connection of a client, then relays its data.
+fn serve(mut client: TcpStream, session: &Session) -> io::Result<()> {
+	let mut header = [0; 2];
+	client.read_exact(&mut header)?;
+	let mut methods = vec![0; header[1] as usize];
+	client.read_exact(&mut methods)?;
+	if header[0] != SOCKS_VERSION || !methods.contains(&NO_AUTH) {
+		client.write_all(&[SOCKS_VERSION, NO_ACCEPTABLE_METHOD])?;
+		return Ok(());
+	}
+	client.write_all(&[SOCKS_VERSION, NO_AUTH])?;
+
+	let mut request = [0; 4];
+	client.read_exact(&mut request)?;
+	let host = match request[3] {
+		ATYP_DOMAIN => {
+			let mut len = [0; 1];
+			client.read_exact(&mut len)?;
+			let mut host = vec![0; len[0] as usize];
+			client.read_exact(&mut host)?;
+			Some(String::from_utf8_lossy(&host).to_ascii_lowercase())
+		}
+		ATYP_IPV4 => skip(&mut client, 4).map(|_| None)?,
+		ATYP_IPV6 => skip(&mut client, 16).map(|_| None)?,
+		_ => return reply(&mut client, REPLY_ADDRESS_NOT_SUPPORTED),
+	};
+	let mut port = [0; 2];
+	client.read_exact(&mut port)?;
+	let port = u16::from_be_bytes(port);
+
+	if request[0] != SOCKS_VERSION || request[1] != CMD_CONNECT {
+		return reply(&mut client, REPLY_COMMAND_NOT_SUPPORTED);
+	}
+	let host = match host {
+		Some(host) if host.ends_with(".i2p") => host,
+		Some(_) => return reply(&mut client, REPLY_NOT_ALLOWED),
+		None => return reply(&mut client, REPLY_ADDRESS_NOT_SUPPORTED),
+	};
+	let stream = match I2pStream::connect_with_session(session, (&*host, port)) {
+		Ok(stream) => stream,
+		Err(e) => {
+			debug!("SOCKS proxy couldn't connect to {}: {}", host, e);
+			return reply(&mut client, failure_reply(&e));
+		}
+	};
+	reply(&mut client, REPLY_SUCCEEDED)?;
+	relay(client, stream)
+}
+
+fn skip(client: &mut TcpStream, len: usize) -> io::Result<()> {
+	client.read_exact(&mut vec![0; len])
+}
+
+/// Sends a reply, the bound address left unspecified as it's meaningless
+/// for I2P streams.
+fn reply(client: &mut TcpStream, code: u8) -> io::Result<()> {
+	client.write_all(&[SOCKS_VERSION, code, 0, ATYP_IPV4, 0, 0, 0, 0, 0, 0])
+}
+
+fn failure_reply(e: &Error) -> u8 {
+	match e.kind().connect_failure() {
+		Some(ConnectFailure::PeerNotFound) | Some(ConnectFailure::InvalidPeer) => REPLY_HOST_UNREACHABLE,
+		Some(ConnectFailure::PeerRefused) => REPLY_CONNECTION_REFUSED,
+		Some(ConnectFailure::TunnelsNotReady) => REPLY_NETWORK_UNREACHABLE,
+		Some(ConnectFailure::Timeout) => REPLY_TTL_EXPIRED,
+		Some(ConnectFailure::Bridge) | None => REPLY_GENERAL_FAILURE,
+	}
+}
+
+/// Copies data both ways until either side closes.
+fn relay(client: TcpStream, stream: I2pStream) -> io::Result<()> {
+	let (mut client_read, mut client_write) = (client.try_clone()?, client);
+	let (mut stream_read, mut stream_write) = stream.into_split();
+	let upload = thread::spawn(move || {
+		let _ = io::copy(&mut client_read, &mut stream_write);
+		let _ = stream_write.shutdown();
+	});
+	let res = io::copy(&mut stream_read, &mut client_write);
+	let _ = client_write.shutdown(Shutdown::Both);
+	let _ = upload.join();
+	res.map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::{Read, Write};
+	use std::net::TcpStream;
+
+	use crate::proxy::Socks5Proxy;
+	use crate::sam::tests::fake_bridge;
+	use crate::sam::Session;
+
+	fn handshake(proxy: &Socks5Proxy, request: &[u8]) -> (TcpStream, [u8; 10]) {
+		let mut client = TcpStream::connect(proxy.local_addr()).unwrap();
+		client.write_all(&[5, 1, 0]).unwrap();
+		let mut method = [0; 2];
+		client.read_exact(&mut method).unwrap();
+		assert_eq!(method, [5, 0]);
+		client.write_all(request).unwrap();
+		let mut reply = [0; 10];
+		client.read_exact(&mut reply).unwrap();
+		(client, reply)
+	}
+
+	#[test]
+	fn connect() {
+		let session = Session::transient(fake_bridge(b"")).unwrap();
+		let proxy = Socks5Proxy::bind(session).unwrap();
+		assert_eq!(proxy.url(), format!("socks5h://{}", proxy.local_addr()));
+
+		let mut request = vec![5, 1, 0, 3, 11];
+		request.extend_from_slice(b"example.i2p");
+		request.extend_from_slice(&80u16.to_be_bytes());
+		let (mut client, reply) = handshake(&proxy, &request);
+		assert_eq!(reply[1], 0);
+		client.write_all(b"ping").unwrap();
+		let mut echoed = [0; 4];
+		client.read_exact(&mut echoed).unwrap();
+		assert_eq!(&echoed, b"ping");
+
+		let (_, reply) = handshake(&proxy, &[5, 1, 0, 1, 127, 0, 0, 1, 0, 80]);
+		assert_eq!(reply[1], 8);
+		let mut request = vec![5, 1, 0, 3, 11];
+		request.extend_from_slice(b"example.com");
+		request.extend_from_slice(&80u16.to_be_bytes());
+		assert_eq!(handshake(&proxy, &request).1[1], 2);
+
+		// open connections are closed along with the proxy
+		proxy.shutdown().unwrap();
+		assert_eq!(client.read(&mut [0; 1]).unwrap_or(0), 0);
+	}
+}
@@ -0,0 +1,192 @@
+//! Typed replies to SAM commands.
+//!
+//! Replies are only built once the bridge reported success, failures being
+//! turned into errors beforehand. The raw fields of the last reply stay
+//! available through `SamConnection::last_reply`.
+
+use std::collections::HashMap;
+
+use crate::error::{Error, ErrorKind};
+use crate::sam::SAM_MIN;
+
+/// Reply to `HELLO VERSION`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HelloReply {
+	/// The protocol version agreed upon with the bridge
+	pub version: String,
+}
+
+/// Reply to `SESSION CREATE`, `SESSION ADD` and `SESSION REMOVE`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SessionStatus {
+	/// The private key of the session destination, only sent on creation
+	pub destination: Option<String>,
+	/// Additional information provided by the bridge
+	pub message: Option<String>,
+}
+
+/// Reply to `STREAM CONNECT`, `STREAM ACCEPT` and `STREAM FORWARD`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StreamStatus {
+	/// Additional information provided by the bridge
+	pub message: Option<String>,
+}
+
+/// Reply to `NAMING LOOKUP`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NamingReply {
+	/// The name that was looked up
+	pub name: String,
+	/// The base64 destination the name resolved to
+	pub value: String,
+}
+
+/// Reply to `DEST GENERATE`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DestReply {
+	/// The base64 public destination
+	pub public: String,
+	/// The base64 private key, including the public destination
+	pub private: String,
+}
+
+/// Reply to a command sent with `SamConnection::send_command`, holding the
+/// typed reply when the crate knows about it, as well as all of its fields.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SamReply {
+	/// The leading words of the reply, such as `NAMING REPLY`
+	pub command: String,
+	/// The typed reply, `TypedReply::Other` for replies unknown to the crate
+	pub reply: TypedReply,
+	/// All the fields of the reply, with their values unescaped
+	pub fields: HashMap<String, String>,
+}
+
+/// Typed replies known to the crate, see `SamReply`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TypedReply {
+	Hello(HelloReply),
+	Session(SessionStatus),
+	Stream(StreamStatus),
+	Naming(NamingReply),
+	Dest(DestReply),
+	Other,
+}
+
+impl SamReply {
+	pub(crate) fn new(command: &str, fields: HashMap<String, String>) -> Result<SamReply, Error> {
+		let reply = match command {
+			"HELLO REPLY" => TypedReply::Hello(HelloReply::from_fields(&fields)?),
+			"SESSION STATUS" => TypedReply::Session(SessionStatus::from_fields(&fields)?),
+			"STREAM STATUS" => TypedReply::Stream(StreamStatus::from_fields(&fields)?),
+			"NAMING REPLY" => TypedReply::Naming(NamingReply::from_fields(&fields)?),
+			"DEST REPLY" => TypedReply::Dest(DestReply::from_fields(&fields)?),
+			_ => TypedReply::Other,
+		};
+		Ok(SamReply {
+			command: command.to_string(),
+			reply,
+			fields,
+		})
+	}
+
+	/// The value of the provided field, if the reply has it.
+	pub fn get(&self, key: &str) -> Option<&str> {
+		self.fields.get(key).map(|v| v.as_str())
+	}
+}
+
+fn required(fields: &HashMap<String, String>, reply: &str, key: &str) -> Result<String, Error> {
+	fields.get(key).cloned().ok_or_else(|| {
+		ErrorKind::SAMInvalidMessage(format!("No {} in {}", key, reply)).into()
+	})
+}
+
+impl HelloReply {
+	pub(crate) fn from_fields(fields: &HashMap<String, String>) -> Result<HelloReply, Error> {
+		// bridges leaving the version out only speak the first one
+		Ok(HelloReply {
+			version: fields.get("VERSION").cloned().unwrap_or_else(|| SAM_MIN.to_string()),
+		})
+	}
+}
+
+impl SessionStatus {
+	pub(crate) fn from_fields(fields: &HashMap<String, String>) -> Result<SessionStatus, Error> {
+		Ok(SessionStatus {
+			destination: fields.get("DESTINATION").cloned(),
+			message: fields.get("MESSAGE").cloned(),
+		})
+	}
+}
+
+impl StreamStatus {
+	pub(crate) fn from_fields(fields: &HashMap<String, String>) -> Result<StreamStatus, Error> {
+		Ok(StreamStatus {
+			message: fields.get("MESSAGE").cloned(),
+		})
+	}
+}
+
+impl NamingReply {
+	pub(crate) fn from_fields(fields: &HashMap<String, String>) -> Result<NamingReply, Error> {
+		Ok(NamingReply {
+			name: required(fields, "NAMING REPLY", "NAME")?,
+			value: required(fields, "NAMING REPLY", "VALUE")?,
+		})
+	}
+}
+
+impl DestReply {
+	pub(crate) fn from_fields(fields: &HashMap<String, String>) -> Result<DestReply, Error> {
+		Ok(DestReply {
+			public: required(fields, "DEST REPLY", "PUB")?,
+			private: required(fields, "DEST REPLY", "PRIV")?,
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::HashMap;
+
+	fn fields(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+		pairs.iter().map(|&(k, v)| (k.to_string(), v.to_string())).collect()
+	}
+
+	#[test]
+	fn from_fields() {
+		use crate::error::ErrorKind;
+		use crate::reply::{DestReply, HelloReply, NamingReply, SamReply, SessionStatus, TypedReply};
+
+		assert_eq!(HelloReply::from_fields(&fields(&[])).unwrap().version, "3.0");
+		assert_eq!(
+			SessionStatus::from_fields(&fields(&[("RESULT", "OK"), ("DESTINATION", "priv")])).unwrap(),
+			SessionStatus {
+				destination: Some("priv".to_string()),
+				message: None,
+			}
+		);
+		assert_eq!(
+			NamingReply::from_fields(&fields(&[("NAME", "ME"), ("VALUE", "dest")])).unwrap(),
+			NamingReply {
+				name: "ME".to_string(),
+				value: "dest".to_string(),
+			}
+		);
+		let reply = SamReply::new("NAMING REPLY", fields(&[("NAME", "ME"), ("VALUE", "dest")])).unwrap();
+		assert_eq!(reply.get("NAME"), Some("ME"));
+		match reply.reply {
+			TypedReply::Naming(naming) => assert_eq!(naming.value, "dest"),
+			reply => panic!("unexpected reply: {:?}", reply),
+		}
+		let reply = SamReply::new("FOO STATUS", fields(&[("BAR", "baz")])).unwrap();
+		assert_eq!(reply.reply, TypedReply::Other);
+		assert_eq!(reply.get("BAR"), Some("baz"));
+
+		match DestReply::from_fields(&fields(&[("PUB", "pub")])).unwrap_err().kind() {
+			ErrorKind::SAMInvalidMessage(msg) => assert_eq!(msg, "No PRIV in DEST REPLY"),
+			kind => panic!("unexpected error: {:?}", kind),
+		}
+	}
+}
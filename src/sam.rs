@@ -2,8 +2,17 @@ use std::io::prelude::*;
 
 use std::clone::Clone;
 use std::collections::HashMap;
-use std::io::{self, BufReader};
-use std::net::{Shutdown, SocketAddr, TcpStream, ToSocketAddrs};
+use std::io::{self, IoSlice, IoSliceMut};
+use std::net::{IpAddr, Ipv4Addr, Shutdown, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, RawFd};
+#[cfg(unix)]
+use std::path::Path;
 
 use log::debug;
 use nom::IResult;
@@ -11,29 +20,281 @@ use rand::distributions::Alphanumeric;
 use rand::{self, Rng};
 
 
+use crate::destination::I2pPrivateKey;
 use crate::error::{Error, ErrorKind};
-use crate::net::{I2pAddr, I2pSocketAddr};
-use crate::parsers::{sam_hello, sam_naming_reply, sam_session_status, sam_stream_status, sam_dest_reply};
+use crate::naming::{reverse_target, NamingCache, Resolver};
+use crate::net::{is_b32, is_valid_hostname, I2pAddr, I2pSocketAddr};
+use crate::offline::OfflineKeys;
+use crate::sam_options::{SessionOptions, SignatureType, StreamingOptions};
+use crate::transport::SamStream;
+pub use crate::transport::SamEndpoint;
+#[cfg(feature = "tls")]
+pub use crate::transport::TlsEndpoint;
+use crate::codec::SamLine;
+use crate::reply::{DestReply, HelloReply, NamingReply, SamReply, SessionStatus, StreamStatus, TypedReply};
+use crate::protocol::{Event, SamProtocol};
+use crate::parsers::{sam_dest_line, sam_datagram_received, sam_raw_received, sam_raw_header, quote,
+	unescape};
 
-pub static DEFAULT_API: &'static str = "127.0.0.1:7656";
+pub static DEFAULT_API: &str = "127.0.0.1:7656";
 
-static SAM_MIN: &'static str = "3.0";
-static SAM_MAX: &'static str = "3.1";
+/// Environment variable naming the SAM bridge to use, see `SamEndpoint`'s
+/// `FromStr` implementation for the format
+pub static SAM_ADDRESS_VAR: &str = "I2P_SAM_ADDRESS";
 
+/// Endpoints probed by `SamConnection::discover` after the environment:
+/// the TCP port both Java I2P and i2pd default to, then Unix sockets
+/// commonly configured for i2pd
+static STANDARD_ENDPOINTS: &[&str] = &[
+	"127.0.0.1:7656",
+	"[::1]:7656",
+	"unix:/run/i2pd/sam.sock",
+	"unix:/var/run/i2pd/sam.sock",
+];
+
+/// How long `SamConnection::discover` waits for each candidate bridge
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+pub static DEFAULT_UDP_API: &str = "127.0.0.1:7655";
+
+/// Delay before an accept socket of an `AcceptPool` is reopened after failing
+const ACCEPT_RETRY: Duration = Duration::from_secs(1);
+/// How often an accept waiting on an accept pool checks whether it was
+/// aborted
+#[cfg(feature = "async")]
+const ACCEPT_ABORT_POLL: Duration = Duration::from_millis(100);
+
+/// Number of generated nicknames tried before giving up on DUPLICATED_ID
+const NICKNAME_ATTEMPTS: usize = 3;
+
+/// Length of the shortest base64 destination, without certificate
+const MIN_DEST_B64_LEN: usize = 516;
+
+/// Largest datagram payload accepted from the bridge, above what I2P carries
+const MAX_PAYLOAD_SIZE: usize = 64 * 1024;
+
+pub(crate) static SAM_MIN: &str = "3.0";
+pub(crate) static SAM_MAX: &str = "3.3";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum SessionStyle {
 	Datagram,
+	/// Repliable datagrams that are authenticated and protected against
+	/// replay, lighter than `Datagram` to verify (SAM 3.3, routers 0.9.66+)
+	Datagram2,
+	/// Repliable datagrams that aren't authenticated, the sender being only
+	/// identified by the base64 hash of its destination (SAM 3.3, routers
+	/// 0.9.66+)
+	Datagram3,
 	Raw,
 	Stream,
+	/// A SAM 3.3 primary session, which only carries subsessions
+	Primary,
 }
 
 pub struct SamConnection {
-	conn: TcpStream,
+	conn: SamStream,
+	hello: HelloReply,
+	credentials: Option<(String, String)>,
+	naming_cache: Option<NamingCache>,
+	resolver: Option<Arc<dyn Resolver>>,
+	command_timeout: Option<Duration>,
+	last_reply: HashMap<String, String>,
+	teardown: Option<Arc<Teardown>>,
+	endpoint: Option<SamEndpoint>,
+}
+
+/// Closes a control socket once the last handle sharing it is dropped,
+/// asking SAM 3.2+ bridges to tear the associated session down with QUIT
+/// instead of waiting for the socket to be reaped.
+struct Teardown {
+	conn: Mutex<SamStream>,
+	quit: bool,
+	closed: AtomicBool,
+}
+
+/// Features available on a SAM bridge, derived from the protocol version
+/// agreed upon during HELLO.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SamCapabilities {
+	/// `SIGNATURE_TYPE` in `DEST GENERATE` and `SESSION CREATE` (SAM 3.1+)
+	pub signature_types: bool,
+	/// `FROM_PORT` and `TO_PORT` on sessions, streams and datagrams (SAM 3.2+)
+	pub ports: bool,
+	/// `USER`/`PASSWORD` in HELLO and the `AUTH` commands (SAM 3.2+)
+	pub auth: bool,
+	/// `PING`/`PONG` and `QUIT` (SAM 3.2+)
+	pub ping: bool,
+	/// Primary sessions and `SESSION ADD`/`SESSION REMOVE` (SAM 3.3+)
+	pub primary_sessions: bool,
+	/// Several `STREAM ACCEPT` pending at once on a session (SAM 3.2+)
+	pub concurrent_accepts: bool,
+	/// The `DATAGRAM2` and `DATAGRAM3` styles (SAM 3.3+, although routers
+	/// older than 0.9.66 reject them)
+	pub datagram2: bool,
+}
+
+impl SamCapabilities {
+	/// The capabilities of a bridge speaking the provided protocol version.
+	pub fn from_version(version: &str) -> SamCapabilities {
+		SamCapabilities {
+			signature_types: version_at_least(version, "3.1"),
+			ports: version_at_least(version, "3.2"),
+			auth: version_at_least(version, "3.2"),
+			ping: version_at_least(version, "3.2"),
+			primary_sessions: version_at_least(version, "3.3"),
+			concurrent_accepts: version_at_least(version, "3.2"),
+			datagram2: version_at_least(version, "3.3"),
+		}
+	}
+}
+
+/// Handle to a running keepalive, see `SamConnection::keepalive`. Pinging
+/// stops when this is dropped.
+pub struct Keepalive {
+	_stop: mpsc::Sender<()>,
+}
+
+/// How a watched session gets re-established, see `Session::watch`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReconnectPolicy {
+	attempts: u32,
+	delay: Duration,
+}
+
+impl Default for ReconnectPolicy {
+	fn default() -> ReconnectPolicy {
+		ReconnectPolicy {
+			attempts: 5,
+			delay: Duration::from_secs(10),
+		}
+	}
+}
+
+impl ReconnectPolicy {
+	/// 5 attempts, 10 seconds apart.
+	pub fn new() -> ReconnectPolicy {
+		ReconnectPolicy::default()
+	}
+
+	/// Number of attempts before giving up, at least one.
+	pub fn attempts(mut self, attempts: u32) -> Self {
+		self.attempts = attempts.max(1);
+		self
+	}
+
+	/// Delay between two attempts.
+	pub fn delay(mut self, delay: Duration) -> Self {
+		self.delay = delay;
+		self
+	}
+}
+
+/// Changes of a watched session, see `Session::watch`.
+#[derive(Debug)]
+pub enum SessionEvent {
+	/// The bridge stopped answering, re-establishing the session starts
+	Disconnected(Error),
+	/// The session was created again, on new tunnels
+	Reestablished { attempts: u32 },
+	/// All attempts failed, the last error is provided and watching stops
+	GaveUp(Error),
+}
+
+/// What it takes to create a session again on the same destination after a
+/// restart, see `Session::to_config` and `Session::restore`: the private
+/// key, the style and the options of the session, ports included.
+///
+/// Saved as a single line of SAM-style `KEY=value` pairs through `Display`
+/// and read back with `FromStr`. The line holds the private key and must
+/// be stored accordingly.
+#[derive(Clone, PartialEq, Eq)]
+pub struct SessionConfig {
+	private_key: I2pPrivateKey,
+	style: SessionStyle,
+	options: Vec<(String, String)>,
+}
+
+impl SessionConfig {
+	/// The base64 private key of the session destination.
+	pub fn private_key(&self) -> &str {
+		self.private_key.as_str()
+	}
+
+	pub fn style(&self) -> SessionStyle {
+		self.style
+	}
+
+	/// The options passed in `SESSION CREATE`.
+	pub fn options(&self) -> &[(String, String)] {
+		&self.options
+	}
+
+	fn session_options(&self) -> SessionOptions {
+		self.options
+			.iter()
+			.fold(SessionOptions::new(), |opts, (k, v)| opts.option(k, v))
+	}
+}
+
+impl std::fmt::Display for SessionConfig {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "STYLE={} DESTINATION={}", self.style.string(), quote(self.private_key.as_str()))?;
+		for (key, value) in &self.options {
+			write!(f, " {}={}", key, quote(value))?;
+		}
+		Ok(())
+	}
+}
+
+impl std::str::FromStr for SessionConfig {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<SessionConfig, Error> {
+		let line = format!("{}\n", s.trim());
+		let mut style = None;
+		let mut private_key = None;
+		let mut options = vec![];
+		for (key, value) in sam_raw_header(&line)?.1 {
+			let value = unescape(value).into_owned();
+			match key {
+				"STYLE" => style = SessionStyle::from_string(&value),
+				"DESTINATION" => private_key = Some(value),
+				_ => options.push((key.to_string(), value)),
+			}
+		}
+		match (style, private_key) {
+			(Some(style), Some(private_key)) => Ok(SessionConfig {
+				private_key: I2pPrivateKey::new(private_key),
+				style,
+				options,
+			}),
+			_ => Err(ErrorKind::SAMInvalidMessage(
+				"Session config needs a STYLE and a DESTINATION".to_string(),
+			)
+			.into()),
+		}
+	}
+}
+
+impl std::fmt::Debug for SessionConfig {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("SessionConfig")
+			.field("private_key", &self.private_key)
+			.field("style", &self.style)
+			.field("options", &self.options)
+			.finish()
+	}
 }
 
 pub struct Session {
 	sam: SamConnection,
 	local_dest: String,
 	nickname: String,
+	style: SessionStyle,
+	subsession: bool,
+	status: SessionStatus,
+	destination: String,
+	options: SessionOptions,
 }
 
 pub struct StreamConnect {
@@ -42,302 +303,3651 @@ pub struct StreamConnect {
 	peer_dest: String,
 	peer_port: u16,
 	local_port: u16,
+	/// Data read ahead, by `peek` or read buffering, and not consumed yet,
+	/// shared by all the handles of the stream like a socket receive buffer
+	buffer: Arc<Mutex<Vec<u8>>>,
+	/// Size of the reads filling the buffer, 0 not buffering reads
+	buffer_capacity: Arc<AtomicUsize>,
+	/// When data was last read or written on any handle of the stream
+	last_active: Arc<Mutex<Instant>>,
+	/// Idle timeout, along with the handle stopping its watching thread
+	idle_watch: Arc<Mutex<Option<IdleWatch>>>,
+	/// When the stream was connected, or accepted
+	connected_at: Instant,
+	/// Bytes read from and written to the bridge by all the handles
+	bytes_read: Arc<AtomicU64>,
+	bytes_written: Arc<AtomicU64>,
+}
+
+type IdleWatch = (Duration, mpsc::Sender<()>);
+
+/// The leading words of the reply expected to a command, such as
+/// `SESSION STATUS`
+type ExpectedReply = &'static str;
+
+impl Teardown {
+	fn close(&self) -> Result<(), Error> {
+		if self.closed.swap(true, Ordering::SeqCst) {
+			return Ok(());
+		}
+		let mut conn = self
+			.conn
+			.lock()
+			.map_err(|_| ErrorKind::Io("SAM connection lock poisoned".to_string()))?;
+		if self.quit {
+			debug!("-> QUIT");
+			conn.write_all(b"QUIT\n")?;
+		}
+		conn.shutdown(Shutdown::Both).map_err(|e| e.into())
+	}
+
+	/// Makes sure the socket is left alone, once it carries stream data.
+	fn disarm(&self) {
+		self.closed.store(true, Ordering::SeqCst);
+	}
+}
+
+impl Drop for Teardown {
+	fn drop(&mut self) {
+		if let Err(e) = self.close() {
+			debug!("Failed to close SAM connection: {}", e);
+		}
+	}
 }
 
 impl SessionStyle {
+	/// Whether sessions of this style send and receive repliable datagrams.
+	pub fn is_datagram(&self) -> bool {
+		matches!(
+			*self,
+			SessionStyle::Datagram | SessionStyle::Datagram2 | SessionStyle::Datagram3
+		)
+	}
+
+	fn from_string(style: &str) -> Option<SessionStyle> {
+		match style {
+			"DATAGRAM" => Some(SessionStyle::Datagram),
+			"DATAGRAM2" => Some(SessionStyle::Datagram2),
+			"DATAGRAM3" => Some(SessionStyle::Datagram3),
+			"RAW" => Some(SessionStyle::Raw),
+			"STREAM" => Some(SessionStyle::Stream),
+			"PRIMARY" => Some(SessionStyle::Primary),
+			_ => None,
+		}
+	}
+
 	fn string(&self) -> &str {
 		match *self {
 			SessionStyle::Datagram => "DATAGRAM",
+			SessionStyle::Datagram2 => "DATAGRAM2",
+			SessionStyle::Datagram3 => "DATAGRAM3",
 			SessionStyle::Raw => "RAW",
 			SessionStyle::Stream => "STREAM",
+			SessionStyle::Primary => "PRIMARY",
 		}
 	}
 }
 
-fn verify_response<'a>(vec: &'a [(&str, &str)]) -> Result<HashMap<&'a str, &'a str>, Error> {
-	let new_vec = vec.clone();
-	let map: HashMap<&str, &str> = new_vec.iter().map(|&(k, v)| (k, v)).collect();
-	let res = map.get("RESULT").unwrap_or(&"OK").clone();
-	let msg = map.get("MESSAGE").unwrap_or(&"").clone();
-	match res {
-		"OK" => Ok(map),
-		"CANT_REACH_PEER" => Err(ErrorKind::SAMCantReachPeer(msg.to_string()).into()),
-		"KEY_NOT_FOUND" => Err(ErrorKind::SAMKeyNotFound(msg.to_string()).into()),
-		"PEER_NOT_FOUND" => Err(ErrorKind::SAMPeerNotFound(msg.to_string()).into()),
-		"DUPLICATED_DEST" => Err(ErrorKind::SAMDuplicatedDest(msg.to_string()).into()),
-		"INVALID_KEY" => Err(ErrorKind::SAMInvalidKey(msg.to_string()).into()),
-		"INVALID_ID" => Err(ErrorKind::SAMInvalidId(msg.to_string()).into()),
-		"TIMEOUT" => Err(ErrorKind::SAMTimeout(msg.to_string()).into()),
-		"I2P_ERROR" => Err(ErrorKind::SAMI2PError(msg.to_string()).into()),
-		_ => Err(ErrorKind::SAMInvalidMessage(msg.to_string()).into()),
+impl SamConnection {
+	/// Sends a command and reads its reply, turning errors reported by the
+	/// bridge into errors. All the fields of the reply are kept, see
+	/// `last_reply`.
+	fn send(&mut self, msg: String, reply: ExpectedReply) -> Result<HashMap<String, String>, Error> {
+		let mut protocol = SamProtocol::with_version(&self.hello.version);
+		self.write_pipelined(&mut protocol, std::iter::once(msg.as_str()))?;
+		self.read_reply_to(&mut protocol, reply)
 	}
-}
 
-impl SamConnection {
-	fn send<F>(&mut self, msg: String, reply_parser: F) -> Result<HashMap<String, String>, Error>
-	where
-		F: Fn(&str) -> IResult<&str, Vec<(&str, &str)>>,
-	{
-		debug!("-> {}", &msg);
-		self.conn.write_all(&msg.into_bytes())?;
+	/// Sends commands and reads their replies, either back to back when
+	/// `pipelined`, saving the round trips in between, or each once the
+	/// previous one got its reply.
+	fn send_all(
+		&mut self,
+		msgs: &[(String, ExpectedReply)],
+		pipelined: bool,
+	) -> Result<Vec<HashMap<String, String>>, Error> {
+		if !pipelined {
+			return msgs.iter().map(|(msg, reply)| self.send(msg.clone(), reply)).collect();
+		}
+		let mut protocol = SamProtocol::with_version(&self.hello.version);
+		self.write_pipelined(&mut protocol, msgs.iter().map(|(msg, _)| msg.as_str()))?;
+		self.read_replies(&mut protocol, msgs)
+	}
 
-		let mut reader = BufReader::new(&self.conn);
-		let mut buffer = String::new();
-		reader.read_line(&mut buffer)?;
-		debug!("<- {}", &buffer);
+	/// Sends commands through `protocol` back to back, their replies being
+	/// read afterwards.
+	fn write_pipelined<'a, I: Iterator<Item = &'a str>>(
+		&mut self,
+		protocol: &mut SamProtocol,
+		msgs: I,
+	) -> Result<(), Error> {
+		let mut pipelined = vec![];
+		for msg in msgs {
+			debug!("-> {}", msg);
+			pipelined.extend(protocol.raw_command(msg)?);
+		}
+		self.conn.write_all(&pipelined)?;
+		Ok(())
+	}
 
-		let vec_opts = reply_parser(&buffer)?.1;
-		verify_response(&vec_opts).map(|m| {
-			m.iter()
-				.map(|(k, v)| (k.to_string(), v.to_string()))
-				.collect()
-		})
+	/// Reads the replies to pipelined commands in order, failing as soon as
+	/// one does: the bridge may close the connection right after a failure,
+	/// which must not hide it.
+	fn read_replies(
+		&mut self,
+		protocol: &mut SamProtocol,
+		msgs: &[(String, ExpectedReply)],
+	) -> Result<Vec<HashMap<String, String>>, Error> {
+		msgs.iter().map(|(_, reply)| self.read_reply_to(protocol, reply)).collect()
 	}
 
-	fn handshake(&mut self) -> Result<HashMap<String, String>, Error> {
-		let hello_msg = format!(
-			"HELLO VERSION MIN={min} MAX={max} \n",
-			min = SAM_MIN,
-			max = SAM_MAX
-		);
-		self.send(hello_msg, sam_hello)
+	/// Reads the reply to the oldest command sent through `protocol`, see
+	/// `reply_fields`.
+	fn read_reply_to(
+		&mut self,
+		protocol: &mut SamProtocol,
+		reply: ExpectedReply,
+	) -> Result<HashMap<String, String>, Error> {
+		let event = self.drive(protocol)?;
+		self.reply_fields(event, reply)
 	}
 
-	pub fn connect<A: ToSocketAddrs>(addr: A) -> Result<SamConnection, Error> {
-		let tcp_stream = TcpStream::connect(addr)?;
+	/// The fields of a reply, which must be the `reply` expected, failures
+	/// reported by the bridge being turned into errors. All the fields are
+	/// kept either way, see `last_reply`.
+	fn reply_fields(
+		&mut self,
+		event: Event,
+		reply: ExpectedReply,
+	) -> Result<HashMap<String, String>, Error> {
+		match event {
+			Event::Reply { line, result } if line.command == reply => {
+				self.keep_reply(line, result).map(|reply| reply.fields)
+			}
+			Event::Reply { .. } => Err(ErrorKind::MessageParsing.into()),
+			Event::Pong(data) => Err(ErrorKind::SAMInvalidMessage(format!("Unexpected PONG: {}", data)).into()),
+		}
+	}
 
-		let mut socket = SamConnection { conn: tcp_stream };
-		socket.handshake()?;
+	/// Sends a command the bridge won't reply to, such as silent STREAM
+	/// commands.
+	fn send_silent(&mut self, msg: String) -> Result<(), Error> {
+		debug!("-> {}", &msg);
+		self.conn.write_all(&msg.into_bytes())?;
+		Ok(())
+	}
 
-		Ok(socket)
+	/// Feeds the lines read from the bridge to `protocol` until they make an
+	/// event, sending what it transmits meanwhile.
+	fn drive(&mut self, protocol: &mut SamProtocol) -> Result<Event, Error> {
+		loop {
+			if let Some(event) = protocol.poll_event()? {
+				return Ok(event);
+			}
+			let transmit = protocol.transmit();
+			if !transmit.is_empty() {
+				debug!("-> {}", String::from_utf8_lossy(&transmit));
+				self.conn.write_all(&transmit)?;
+			}
+			let buffer = self.read_reply()?;
+			debug!("<- {}", String::from_utf8_lossy(&buffer));
+			protocol.receive(&buffer);
+		}
 	}
 
-	// TODO: Implement a lookup table
-	pub fn naming_lookup(&mut self, name: &str) -> Result<String, Error> {
-		let naming_lookup_msg = format!("NAMING LOOKUP NAME={name} \n", name = name);
-		let ret = self.send(naming_lookup_msg, sam_naming_reply)?;
-		Ok(ret["VALUE"].clone())
+	/// Keeps all the fields of a reply of the protocol, see `last_reply`.
+	fn keep_reply(&mut self, line: SamLine, result: Result<SamReply, Error>) -> Result<SamReply, Error> {
+		self.last_reply = line.fields.into_iter().collect();
+		result
 	}
 
-	pub fn generate_destination(&mut self) -> Result<(String, String), Error> {
-		let dest_gen_msg = format!("DEST GENERATE \n");
-		let ret = self.send(dest_gen_msg, sam_dest_reply)?;
-		Ok((ret["PUB"].clone(), ret["PRIV"].clone()))
+	/// Reads the reply line to a command, within the command timeout. The
+	/// read timeout set on the socket beforehand is restored afterwards.
+	fn read_reply(&mut self) -> Result<Vec<u8>, Error> {
+		if self.command_timeout.is_none() {
+			return self.read_line_bytes();
+		}
+		let previous = self.conn.read_timeout()?;
+		self.conn.set_read_timeout(self.command_timeout)?;
+		let res = self.read_line_bytes();
+		self.conn.set_read_timeout(previous)?;
+		res
 	}
 
-	pub fn set_nonblocking(&self, nonblocking: bool) -> Result<(), Error> {
-		self.conn.set_nonblocking(nonblocking).map_err(|e| e.into())
+	/// Reads a single reply line from the bridge. Deliberately unbuffered, as
+	/// binary payloads (datagrams, stream data) can directly follow a reply
+	/// line and must be left on the socket.
+	fn read_line(&mut self) -> Result<String, Error> {
+		String::from_utf8(self.read_line_bytes()?).map_err(|_| ErrorKind::MessageParsing.into())
 	}
 
-	pub fn duplicate(&self) -> Result<SamConnection, Error> {
-		self.conn.try_clone().map(|s| SamConnection { conn: s }).map_err(|e| e.into())
+	/// Same as `read_line`, leaving the line as bytes.
+	fn read_line_bytes(&mut self) -> Result<Vec<u8>, Error> {
+		let mut line = Vec::new();
+		let mut byte = [0; 1];
+		while line.last() != Some(&b'\n') {
+			match self.conn.read(&mut byte) {
+				Ok(0) => {
+					return Err(ErrorKind::Io("SAM bridge closed the connection".to_string()).into());
+				}
+				Ok(_) => {}
+				Err(ref e) if is_timeout(e) => {
+					return Err(ErrorKind::SAMTimeout("No reply from the SAM bridge".to_string()).into());
+				}
+				Err(e) => return Err(e.into()),
+			}
+			line.push(byte[0]);
+		}
+		Ok(line)
 	}
-}
 
-impl Session {
-	/// Create a new session using all provided parameters
-	pub fn create<A: ToSocketAddrs>(
-		sam_addr: A,
-		destination: &str,
-		nickname: &str,
-		style: SessionStyle,
-	) -> Result<Session, Error> {
-		let mut sam = SamConnection::connect(sam_addr)?;
-		let create_session_msg = format!(
-			"SESSION CREATE STYLE={style} ID={nickname} DESTINATION={destination} \n",
-			style = style.string(),
-			nickname = nickname,
-			destination = destination
+	/// Writes a `DATAGRAM SEND` or `RAW SEND` command directly followed by
+	/// its payload. Options left to 0, such as ports, aren't sent.
+	fn send_payload(
+		&mut self,
+		command: &str,
+		dest: &str,
+		opts: &[(&str, u16)],
+		buf: &[u8],
+	) -> Result<usize, Error> {
+		let mut send_msg = format!(
+			"{command} SEND DESTINATION={destination} SIZE={size}",
+			command = command,
+			destination = dest,
+			size = buf.len(),
 		);
+		push_payload_opts(&mut send_msg, opts);
+		send_msg.push('\n');
+		debug!("-> {}", &send_msg);
+		self.conn.write_all(send_msg.as_bytes())?;
+		self.conn.write_all(buf)?;
+		Ok(buf.len())
+	}
 
-		sam.send(create_session_msg, sam_session_status)?;
+	/// Reads a `DATAGRAM RECEIVED` or `RAW RECEIVED` header and the payload
+	/// following it, copying as much of the payload as fits into the buffer.
+	/// Payloads announced larger than `MAX_PAYLOAD_SIZE` are refused.
+	fn recv_payload<F>(
+		&mut self,
+		header_parser: F,
+		buf: &mut [u8],
+	) -> Result<(usize, HashMap<String, String>), Error>
+	where
+		F: Fn(&str) -> IResult<&str, Vec<(&str, &str)>>,
+	{
+		let header = self.read_line()?;
+		debug!("<- {}", &header);
 
-		let local_dest = sam.naming_lookup("ME")?;
+		let opts: HashMap<String, String> = header_parser(&header)?
+			.1
+			.into_iter()
+			.map(|(k, v)| (k.to_string(), unescape(v).into_owned()))
+			.collect();
+		let size: usize = opts
+			.get("SIZE")
+			.and_then(|s| s.parse().ok())
+			.filter(|&size| size <= MAX_PAYLOAD_SIZE)
+			.ok_or_else(|| ErrorKind::SAMInvalidMessage("Bad payload size".to_string()))?;
 
-		Ok(Session {
-			sam: sam,
-			local_dest: local_dest,
-			nickname: nickname.to_string(),
-		})
+		let mut payload = vec![0; size];
+		self.conn.read_exact(&mut payload)?;
+		let n = size.min(buf.len());
+		buf[..n].copy_from_slice(&payload[..n]);
+		Ok((n, opts))
 	}
 
-	/// Create a new session identified by the provided destination. Auto-generates
-	/// a nickname uniquely associated with the new session.
-	pub fn from_destination<A: ToSocketAddrs>(sam_addr: A, destination: &str) -> Result<Session, Error> {
-		Self::create(sam_addr, destination, &nickname(), SessionStyle::Stream)
+	pub fn connect<A: ToSocketAddrs>(addr: A) -> Result<SamConnection, Error> {
+		Self::connect_impl(SamStream::connect_tcp(addr)?, None, None)
 	}
 
-	/// Convenience constructor to create a new transient session with an
-	/// auto-generated nickname.
-	pub fn transient<A: ToSocketAddrs>(sam_addr: A) -> Result<Session, Error> {
-		Self::create(sam_addr, "TRANSIENT", &nickname(), SessionStyle::Stream)
+	/// Connects to a SAM bridge which requires authentication, passing the
+	/// provided credentials along with the HELLO message (SAM 3.2+).
+	pub fn connect_with_auth<A: ToSocketAddrs>(
+		addr: A,
+		user: &str,
+		password: &str,
+	) -> Result<SamConnection, Error> {
+		Self::connect_impl(SamStream::connect_tcp(addr)?, Some((user, password)), None)
 	}
 
-	pub fn sam_api(&self) -> Result<SocketAddr, Error> {
-		self.sam.conn.peer_addr().map_err(|e| e.into())
+	/// Connects to a SAM bridge within the provided timeout, which then
+	/// applies to every command sent on the connection, see
+	/// `set_command_timeout`.
+	pub fn connect_timeout<A: ToSocketAddrs>(addr: A, timeout: Duration) -> Result<SamConnection, Error> {
+		let stream = SamStream::connect_tcp_timeout(addr, timeout).map_err(connect_error)?;
+		Self::connect_impl(stream, None, Some(timeout))
 	}
 
-	pub fn naming_lookup(&mut self, name: &str) -> Result<String, Error> {
-		self.sam.naming_lookup(name)
+	/// Connects to a SAM bridge listening on a Unix domain socket.
+	#[cfg(unix)]
+	pub fn connect_unix<P: AsRef<Path>>(path: P) -> Result<SamConnection, Error> {
+		Self::connect_endpoint(&SamEndpoint::Unix(path.as_ref().to_path_buf()))
 	}
 
-	pub fn duplicate(&self) -> Result<Session, Error> {
-		self.sam.duplicate().map(|s| Session {
-			sam: s,
-			local_dest: self.local_dest.clone(),
-			nickname: self.nickname.clone(),
-		}).map_err(|e| e.into())
+	/// Connects to a SAM bridge wrapped in TLS.
+	///
+	/// Duplicated handles of a TLS connection share a single TLS session, so
+	/// a blocking read on one of them holds up writes on the others.
+	#[cfg(feature = "tls")]
+	pub fn connect_tls(endpoint: TlsEndpoint) -> Result<SamConnection, Error> {
+		Self::connect_endpoint(&SamEndpoint::Tls(endpoint))
 	}
-}
 
-impl StreamConnect {
+	/// Connects to a SAM bridge at the provided endpoint.
+	pub fn connect_endpoint(endpoint: &SamEndpoint) -> Result<SamConnection, Error> {
+		Self::connect_impl(SamStream::connect(endpoint)?, None, None)
+	}
 
-	/// Create a new SAM client connection to the provided destination and port.
-	/// Also creates a new transient session to support the connection.
-	pub fn new<A: ToSocketAddrs>(
-		sam_addr: A,
-		destination: &str,
-		port: u16,
-	) -> Result<StreamConnect, Error> {
-		let session = Session::transient(sam_addr)?;
-		Self::with_session(&session, destination, port)
+	/// Connects to a SAM bridge at the provided endpoint which requires
+	/// authentication.
+	pub fn connect_endpoint_with_auth(
+		endpoint: &SamEndpoint,
+		user: &str,
+		password: &str,
+	) -> Result<SamConnection, Error> {
+		Self::connect_impl(SamStream::connect(endpoint)?, Some((user, password)), None)
 	}
 
-	/// Create a new SAM client connection to the provided destination and port
-	/// using the provided session.
-	pub fn with_session(session: &Session, dest: &str, port: u16) -> Result<StreamConnect, Error> {
-		let mut sam = SamConnection::connect(session.sam_api()?).unwrap();
-		let dest = sam.naming_lookup(dest)?;
+	/// Connects to the first working bridge: the one named by the
+	/// `I2P_SAM_ADDRESS` environment variable, or `SAM_HOST` and `SAM_PORT`,
+	/// if any, then the standard endpoints. A bridge works if it answers
+	/// HELLO within a couple of seconds. See `endpoint` for the bridge found.
+	pub fn discover() -> Result<SamConnection, Error> {
+		let mut candidates = vec![];
+		if let Ok(addr) = std::env::var(SAM_ADDRESS_VAR) {
+			candidates.push(addr);
+		}
+		if let Ok(host) = std::env::var("SAM_HOST") {
+			let port = std::env::var("SAM_PORT").unwrap_or_else(|_| "7656".to_string());
+			candidates.push(format!("{}:{}", host, port));
+		}
+		candidates.extend(STANDARD_ENDPOINTS.iter().map(|e| e.to_string()));
 
-		let mut stream_msg = format!(
-			"STREAM CONNECT ID={nickname} DESTINATION={destination} SILENT=false\n",
-			nickname = session.nickname,
-			destination = dest,
-		);
-		if port > 0 {
-			stream_msg.push_str(&format!(" TO_PORT={port}\n", port = port));
-		} else {
-			stream_msg.push_str("\n");
+		let mut last_err = None;
+		for candidate in candidates {
+			let res = candidate.parse().and_then(|endpoint| {
+				let stream = SamStream::connect_timeout(&endpoint, Some(PROBE_TIMEOUT))?;
+				Self::connect_impl(stream, None, Some(PROBE_TIMEOUT))
+			});
+			match res {
+				Ok(mut sam) => {
+					sam.command_timeout = None;
+					return Ok(sam);
+				}
+				Err(e) => {
+					debug!("No SAM bridge at {}: {}", candidate, e);
+					last_err = Some(e);
+				}
+			}
 		}
+		Err(last_err.unwrap_or_else(|| ErrorKind::Io("No SAM bridge found".to_string()).into()))
+	}
 
-		sam.send(stream_msg, sam_stream_status)?;
+	/// Sends HELLO on a stream to the bridge, waiting at most `timeout` for
+	/// its reply. The connection is left without command timeout.
+	#[cfg(feature = "async")]
+	pub(crate) fn hello_on(stream: SamStream, timeout: Option<Duration>) -> Result<SamConnection, Error> {
+		let mut sam = Self::connect_impl(stream, None, timeout)?;
+		sam.command_timeout = None;
+		Ok(sam)
+	}
 
-		Ok(StreamConnect {
-			sam: sam,
-			session: session.duplicate()?,
-			peer_dest: dest,
-			peer_port: port,
-			local_port: 0,
-		})
+	fn connect_impl(
+		stream: SamStream,
+		credentials: Option<(&str, &str)>,
+		command_timeout: Option<Duration>,
+	) -> Result<SamConnection, Error> {
+		Self::connect_pipelined(stream, credentials, command_timeout, &[]).map(|(sam, _)| sam)
 	}
 
-	pub fn peer_addr(&self) -> Result<(String, u16), Error> {
-		Ok((self.peer_dest.clone(), self.peer_port))
+	/// Connects, sending the provided commands right after HELLO without
+	/// waiting for its reply, and returns the fields of their replies, see
+	/// `read_replies`. The commands must all get a reply.
+	fn connect_pipelined(
+		stream: SamStream,
+		credentials: Option<(&str, &str)>,
+		command_timeout: Option<Duration>,
+		msgs: &[(String, ExpectedReply)],
+	) -> Result<(SamConnection, Vec<HashMap<String, String>>), Error> {
+		let mut socket = SamConnection {
+			conn: stream,
+			hello: HelloReply {
+				version: SAM_MIN.to_string(),
+			},
+			credentials: credentials.map(|(u, p)| (u.to_string(), p.to_string())),
+			naming_cache: None,
+			resolver: None,
+			command_timeout,
+			last_reply: HashMap::new(),
+			teardown: None,
+			endpoint: None,
+		};
+		// kept so the bridge can be reached again once this socket is dead
+		socket.endpoint = socket.conn.endpoint().ok();
+		let mut protocol = SamProtocol::new();
+		let mut hello_msg = protocol.hello(credentials)?;
+		debug!("-> {}", String::from_utf8_lossy(&hello_msg));
+		for (msg, _) in msgs {
+			debug!("-> {}", msg);
+			hello_msg.extend(protocol.raw_command(msg)?);
+		}
+		socket.conn.write_all(&hello_msg)?;
+
+		// a failed HELLO gets the connection closed, report it first
+		socket.hello = match socket.drive(&mut protocol)? {
+			Event::Reply { line, result } => match socket.keep_reply(line, result)?.reply {
+				TypedReply::Hello(hello) => hello,
+				_ => return Err(ErrorKind::MessageParsing.into()),
+			},
+			Event::Pong(_) => return Err(ErrorKind::MessageParsing.into()),
+		};
+		socket.teardown = Some(Arc::new(Teardown {
+			conn: Mutex::new(socket.conn.try_clone()?),
+			quit: socket.capabilities().ping,
+			closed: AtomicBool::new(false),
+		}));
+
+		let replies = socket.read_replies(&mut protocol, msgs)?;
+		Ok((socket, replies))
 	}
 
-	pub fn local_addr(&self) -> Result<(String, u16), Error> {
-		Ok((self.session.local_dest.clone(), self.local_port))
+	/// The endpoint of the bridge this connection is established with.
+	pub fn endpoint(&self) -> Result<SamEndpoint, Error> {
+		match self.endpoint {
+			Some(ref endpoint) => Ok(endpoint.clone()),
+			None => self.conn.endpoint().map_err(|e| e.into()),
+		}
 	}
 
-	pub fn set_nonblocking(&self, nonblocking: bool) -> Result<(), Error> {
-		self.sam.set_nonblocking(nonblocking)
+	/// Opens a new connection to the same bridge, authenticating with the
+	/// same credentials if any were provided.
+	pub fn new_connection(&self) -> Result<SamConnection, Error> {
+		self.new_connection_pipelined(&[]).map(|(sam, _)| sam)
 	}
 
-	pub fn shutdown(&self, how: Shutdown) -> Result<(), Error> {
-		self.sam.conn.shutdown(how).map_err(|e| e.into())
+	/// Same as `new_connection`, sending the provided commands right after
+	/// HELLO, see `connect_pipelined`.
+	fn new_connection_pipelined(
+		&self,
+		msgs: &[(String, ExpectedReply)],
+	) -> Result<(SamConnection, Vec<HashMap<String, String>>), Error> {
+		self.new_connection_timeout(msgs, self.command_timeout)
 	}
 
-	pub fn duplicate(&self) -> Result<StreamConnect, Error> {
-		Ok(StreamConnect {
-			sam: self.sam.duplicate()?,
-			session: self.session.duplicate()?,
-			peer_dest: self.peer_dest.clone(),
-			peer_port: self.peer_port,
-			local_port: self.local_port,
-		})
+	/// Same as `new_connection_pipelined`, with the provided command timeout
+	/// instead of the one of this connection.
+	fn new_connection_timeout(
+		&self,
+		msgs: &[(String, ExpectedReply)],
+		command_timeout: Option<Duration>,
+	) -> Result<(SamConnection, Vec<HashMap<String, String>>), Error> {
+		let credentials = self
+			.credentials
+			.as_ref()
+			.map(|(u, p)| (u.as_str(), p.as_str()));
+		let stream = SamStream::connect_timeout(&self.endpoint()?, command_timeout)
+			.map_err(connect_error)?;
+		let (mut sam, replies) =
+			Self::connect_pipelined(stream, credentials, command_timeout, msgs)?;
+		sam.naming_cache = self.naming_cache.clone();
+		sam.resolver = self.resolver.clone();
+		Ok((sam, replies))
 	}
-}
 
-impl Read for StreamConnect {
-	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-		self.sam.conn.read(buf)
+	/// Fails commands whose reply takes longer than `timeout` to come with
+	/// `ErrorKind::SAMTimeout`, `None` waiting forever. Connections derived
+	/// from this one, such as the ones opened by sessions, inherit the
+	/// timeout, which also applies to establishing them.
+	pub fn set_command_timeout(&mut self, timeout: Option<Duration>) {
+		self.command_timeout = timeout;
 	}
-}
 
-impl Write for StreamConnect {
-	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-		self.sam.conn.write(buf)
+	/// Caches the results of `naming_lookup` in the provided cache, shared
+	/// with the connections derived from this one, such as the ones opened
+	/// by sessions to connect streams.
+	pub fn with_naming_cache(mut self, cache: NamingCache) -> SamConnection {
+		self.naming_cache = Some(cache);
+		self
 	}
-	fn flush(&mut self) -> io::Result<()> {
-		self.sam.conn.flush()
+
+	/// Resolves the names of the peers streams get connected to with the
+	/// provided resolver instead of `naming_lookup`. Shared with the
+	/// connections derived from this one.
+	pub fn with_resolver<R: Resolver + 'static>(mut self, resolver: R) -> SamConnection {
+		self.resolver = Some(Arc::new(resolver));
+		self
 	}
-}
 
-pub struct StreamForward {
-	session: Session,
-}
+	/// Resolves a name through the resolver of this connection, falling back
+	/// to `naming_lookup` if there's none. Full base64 destinations are
+	/// returned as is.
+	pub fn resolve(&mut self, name: &str) -> Result<String, Error> {
+		match self.resolve_locally(name) {
+			Some(res) => res,
+			None => self.naming_lookup(name),
+		}
+	}
 
-impl StreamForward {
-	pub fn new<A: ToSocketAddrs>(
-		sam_addr: A,
-	) -> Result<StreamForward, Error> {
-		Ok(StreamForward {session: Session::transient(sam_addr)?})
+	/// A hostname for a base64 destination or b32 address, from the resolver
+	/// and naming cache of this connection, so peers can be displayed under
+	/// a friendlier name. The bridge has no reverse lookup, `None` is
+	/// returned for destinations that weren't resolved locally.
+	pub fn lookup_reverse(&self, dest: &str) -> Option<String> {
+		let addr = reverse_target(dest)?;
+		self.resolver
+			.as_ref()
+			.and_then(|r| r.reverse(&addr))
+			.or_else(|| self.naming_cache.as_ref().and_then(|c| c.reverse(&addr)))
 	}
 
-	/// Create a new SAM client connection to the provided destination and port
-	/// using the provided session.
-	pub fn with_session(session: &Session) -> Result<StreamForward, Error> {
-		Ok(StreamForward {session: session.duplicate()?})
+	/// Resolves a name without asking the bridge, if possible.
+	fn resolve_locally(&self, name: &str) -> Option<Result<String, Error>> {
+		if let Err(e) = check_lookup_name(name) {
+			return Some(Err(e));
+		}
+		if !name.ends_with(".i2p") && name.len() >= MIN_DEST_B64_LEN {
+			return Some(Ok(name.to_string()));
+		}
+		if let Some(ref resolver) = self.resolver {
+			return Some(resolver.resolve(name));
+		}
+		self.naming_cache
+			.as_ref()
+			.filter(|_| name != "ME")
+			.and_then(|c| c.get(name))
+			.map(Ok)
 	}
 
-	pub fn accept(&self) -> Result<(StreamConnect, I2pSocketAddr), Error> {
-		let mut sam_conn = SamConnection::connect(self.session.sam_api()?).unwrap();
+	/// Enables authentication on the bridge. Users must have been added with
+	/// `auth_add` beforehand.
+	pub fn auth_enable(&mut self) -> Result<(), Error> {
+		self.send(String::from("AUTH ENABLE\n"), "AUTH STATUS").map(|_| ())
+	}
 
-		let accept_stream_msg = format!(
-			"STREAM ACCEPT ID={nickname} SILENT=false\n",
-			nickname = self.session.nickname,
+	/// Disables authentication on the bridge.
+	pub fn auth_disable(&mut self) -> Result<(), Error> {
+		self.send(String::from("AUTH DISABLE\n"), "AUTH STATUS").map(|_| ())
+	}
+
+	/// Adds a user allowed to authenticate on the bridge.
+	pub fn auth_add(&mut self, user: &str, password: &str) -> Result<(), Error> {
+		let auth_msg = format!(
+			"AUTH ADD USER={user} PASSWORD={password}\n",
+			user = quote(user),
+			password = quote(password)
 		);
-		sam_conn.send(accept_stream_msg, sam_stream_status)?;
+		self.send(auth_msg, "AUTH STATUS").map(|_| ())
+	}
 
-		let mut stream = StreamConnect {
-			sam: sam_conn,
-			session: self.session.duplicate()?,
-			peer_dest: "".to_string(),
-			// port only provided with SAM v3.2+ (not on i2pd)
-			peer_port: 0,
-			local_port: 0,
-		};
+	/// Removes a user from the ones allowed to authenticate on the bridge.
+	pub fn auth_remove(&mut self, user: &str) -> Result<(), Error> {
+		let auth_msg = format!("AUTH REMOVE USER={user}\n", user = quote(user));
+		self.send(auth_msg, "AUTH STATUS").map(|_| ())
+	}
 
-		// TODO use a parser combinator, perhaps move down to sam.rs
-		let destination: String = {
-			let mut buf_read = io::BufReader::new(stream.duplicate()?);
-			let mut dest_line = String::new();
-			buf_read.read_line(&mut dest_line)?;
-			dest_line.split(" ").next().unwrap_or("").trim().to_string()
-		};
-		if destination.is_empty() {
-			return Err(ErrorKind::SAMKeyNotFound("No b64 destination in accept".to_string()).into());
+	/// The SAM protocol version agreed upon with the bridge during HELLO.
+	pub fn version(&self) -> &str {
+		&self.hello.version
+	}
+
+	/// The reply of the bridge to HELLO.
+	pub fn hello(&self) -> &HelloReply {
+		&self.hello
+	}
+
+	/// The local address of the socket to the bridge, `None` for bridges
+	/// reached through a Unix domain socket.
+	pub fn socket_addr(&self) -> Result<Option<SocketAddr>, Error> {
+		self.conn.local_addr().map_err(|e| e.into())
+	}
+
+	/// All the fields of the last reply received from the bridge, including
+	/// the ones this crate doesn't know about and the ones of error replies.
+	pub fn last_reply(&self) -> &HashMap<String, String> {
+		&self.last_reply
+	}
+
+	/// The features of the bridge, for callers to branch on rather than
+	/// having commands fail.
+	pub fn capabilities(&self) -> SamCapabilities {
+		SamCapabilities::from_version(&self.hello.version)
+	}
+
+	/// Sends a PING to the bridge and waits for the matching PONG (SAM 3.2+).
+	pub fn ping(&mut self, data: &str) -> Result<(), Error> {
+		if !self.capabilities().ping {
+			return Err(self.unsupported("PING"));
+		}
+		let mut protocol = SamProtocol::with_version(&self.hello.version);
+		let ping_msg = protocol.ping(data)?;
+		debug!("-> {}", String::from_utf8_lossy(&ping_msg));
+		self.conn.write_all(&ping_msg)?;
+		match self.drive(&mut protocol)? {
+			Event::Pong(_) => Ok(()),
+			Event::Reply { line, .. } => {
+				Err(ErrorKind::SAMInvalidMessage(format!("Expected PONG: {}", line.command)).into())
+			}
 		}
+	}
 
-		let addr = I2pSocketAddr::new(I2pAddr::from_b64(&destination)?, 0);
-		stream.peer_dest = destination;
-	
-		Ok((stream, addr))
+	/// Starts pinging the bridge every `interval` from a background thread,
+	/// telling whether it's still up. If the bridge fails to answer a PING
+	/// within `interval`, `on_failure` is called with the error and pinging
+	/// stops.
+	///
+	/// Pinging stops when the returned `Keepalive` is dropped. The PINGs go
+	/// over a new connection to the bridge, so this one can keep being used
+	/// meanwhile without their replies getting mixed up.
+	pub fn keepalive<F>(&self, interval: Duration, on_failure: F) -> Result<Keepalive, Error>
+	where
+		F: FnOnce(Error) + Send + 'static,
+	{
+		let mut sam = self.new_connection()?;
+		let (stop, stopped) = mpsc::channel::<()>();
+		thread::spawn(move || {
+			let mut count: u64 = 0;
+			while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+				count += 1;
+				if let Err(e) = sam.ping_timeout(&format!("keepalive-{}", count), interval) {
+					// a stop requested while waiting for the PONG isn't a failure
+					if let Err(TryRecvError::Empty) = stopped.try_recv() {
+						on_failure(e);
+					}
+					return;
+				}
+			}
+		});
+		Ok(Keepalive { _stop: stop })
 	}
 
-	pub fn local_addr(&self) -> Result<(String, u16), Error> {
-		Ok((self.session.local_dest.clone(), 0))
+	fn ping_timeout(&mut self, data: &str, timeout: Duration) -> Result<(), Error> {
+		let command_timeout = self.command_timeout.replace(timeout);
+		let res = self.ping(data).map_err(|e| match e.kind() {
+			ErrorKind::Io(_) => ErrorKind::SAMTimeout("No PONG from the SAM bridge".to_string()).into(),
+			_ => e,
+		});
+		self.command_timeout = command_timeout;
+		res
 	}
 
-	pub fn duplicate(&self) -> Result<StreamForward, Error> {
-		Ok(StreamForward {session: self.session.duplicate()?})
+	pub fn naming_lookup(&mut self, name: &str) -> Result<String, Error> {
+		// ME depends on the session the connection belongs to
+		let cache = self.naming_cache.clone().filter(|_| name != "ME");
+		if let Some(dest) = cache.as_ref().and_then(|c| c.get(name)) {
+			return Ok(dest);
+		}
+		let dest = self.lookup(name)?.value;
+		if let Some(cache) = cache {
+			cache.insert(name, &dest);
+		}
+		Ok(dest)
 	}
-}
 
-fn nickname() -> String {
-	let suffix: String = rand::thread_rng()
-		.sample_iter(&Alphanumeric)
-		.take(8)
-		.collect();
-	format!("i2prs-{}", suffix)
+	/// Resolves several names at once, like `resolve`, sending the lookups
+	/// left to the bridge back to back rather than waiting for each reply.
+	/// The results are in the order of `names`, each name failing on its
+	/// own, while failures of the connection itself fail the whole batch and
+	/// close the connection, as the replies still due would otherwise be
+	/// read as those of the commands that follow.
+	pub fn lookup_many(&mut self, names: &[&str]) -> Result<Vec<Result<String, Error>>, Error> {
+		let mut results: Vec<_> = names.iter().map(|name| self.resolve_locally(name)).collect();
+		let pending: Vec<usize> = (0..names.len()).filter(|&i| results[i].is_none()).collect();
+		if pending.is_empty() {
+			return Ok(results.into_iter().flatten().collect());
+		}
+		let lookup_msgs: Vec<String> = pending
+			.iter()
+			.map(|&i| format!("NAMING LOOKUP NAME={name} \n", name = names[i]))
+			.collect();
+		let mut protocol = SamProtocol::with_version(&self.hello.version);
+		self.write_pipelined(&mut protocol, lookup_msgs.iter().map(|msg| msg.as_str()))?;
+		for &i in &pending {
+			let event = match self.drive(&mut protocol) {
+				Ok(event) => event,
+				// the line was read whole, the next replies can still be
+				Err(e) if e.kind() == ErrorKind::MessageParsing => {
+					results[i] = Some(Err(e));
+					continue;
+				}
+				Err(e) => {
+					let _ = self.conn.shutdown(Shutdown::Both);
+					return Err(e);
+				}
+			};
+			let dest = self
+				.reply_fields(event, "NAMING REPLY")
+				.and_then(|fields| NamingReply::from_fields(&fields))
+				.map(|reply| reply.value);
+			if let (Ok(dest), Some(cache)) = (&dest, self.naming_cache.as_ref()) {
+				if names[i] != "ME" {
+					cache.insert(names[i], dest);
+				}
+			}
+			results[i] = Some(dest);
+		}
+		Ok(results.into_iter().flatten().collect())
+	}
+
+	/// Sends an arbitrary command, such as one specific to a bridge or not
+	/// supported by the crate yet, and reads its reply line. Failures the
+	/// bridge reports in `RESULT` are turned into errors like for any other
+	/// command. The command must get a reply, or this blocks until the
+	/// command timeout.
+	pub fn send_command(&mut self, command: &str) -> Result<SamReply, Error> {
+		let mut protocol = SamProtocol::with_version(&self.hello.version);
+		let msg = protocol.raw_command(command)?;
+		debug!("-> {}", String::from_utf8_lossy(&msg));
+		self.conn.write_all(&msg)?;
+		match self.drive(&mut protocol)? {
+			Event::Reply { line, result } => self.keep_reply(line, result),
+			Event::Pong(data) => Err(ErrorKind::SAMInvalidMessage(format!("Unexpected PONG: {}", data)).into()),
+		}
+	}
+
+	/// Sends `NAMING LOOKUP` for the provided name, bypassing the naming
+	/// cache and resolver of the connection. Names that can't be valid are
+	/// rejected with `BadAddressEncoding` without asking the bridge, so
+	/// `ErrorKind::lookup_failure` tells them apart from unknown ones.
+	pub fn lookup(&mut self, name: &str) -> Result<NamingReply, Error> {
+		check_lookup_name(name)?;
+		let naming_lookup_msg = format!("NAMING LOOKUP NAME={name} \n", name = name);
+		NamingReply::from_fields(&self.send(naming_lookup_msg, "NAMING REPLY")?)
+	}
+
+	/// Sends `DEST GENERATE`, with the provided signature type if any.
+	pub fn dest_generate(&mut self, sig_type: Option<SignatureType>) -> Result<DestReply, Error> {
+		let dest_gen_msg = match sig_type {
+			Some(sig_type) => format!(
+				"DEST GENERATE SIGNATURE_TYPE={sig_type} \n",
+				sig_type = sig_type.name()
+			),
+			None => String::from("DEST GENERATE \n"),
+		};
+		DestReply::from_fields(&self.send(dest_gen_msg, "DEST REPLY")?)
+	}
+
+	pub fn generate_destination(&mut self) -> Result<(String, String), Error> {
+		let reply = self.dest_generate(None)?;
+		Ok((reply.public, reply.private))
+	}
+
+	/// Generates a new destination using the provided signature type,
+	/// returning its public and private keys.
+	pub fn generate_destination_with_signature(
+		&mut self,
+		sig_type: SignatureType,
+	) -> Result<(String, String), Error> {
+		let reply = self.dest_generate(Some(sig_type))?;
+		Ok((reply.public, reply.private))
+	}
+
+	pub fn set_nonblocking(&self, nonblocking: bool) -> Result<(), Error> {
+		self.conn.set_nonblocking(nonblocking).map_err(|e| e.into())
+	}
+
+	/// Closes the connection, sending QUIT to SAM 3.2+ bridges first, for
+	/// all the handles sharing it. Connections are otherwise closed when the
+	/// last of their handles is dropped.
+	pub fn close(self) -> Result<(), Error> {
+		match self.teardown {
+			Some(ref teardown) => teardown.close(),
+			None => self.conn.shutdown(Shutdown::Both).map_err(|e| e.into()),
+		}
+	}
+
+	fn unsupported(&self, feature: &str) -> Error {
+		ErrorKind::SAMUnsupported(format!("{} with SAM {}", feature, self.hello.version)).into()
+	}
+
+	/// Marks the connection as carrying stream data rather than commands.
+	fn carry_stream_data(&mut self) {
+		if let Some(teardown) = self.teardown.take() {
+			teardown.disarm();
+		}
+	}
+
+	pub fn duplicate(&self) -> Result<SamConnection, Error> {
+		let conn = self.conn.try_clone()?;
+		Ok(SamConnection {
+			conn,
+			hello: self.hello.clone(),
+			credentials: self.credentials.clone(),
+			naming_cache: self.naming_cache.clone(),
+			resolver: self.resolver.clone(),
+			command_timeout: self.command_timeout,
+			last_reply: HashMap::new(),
+			teardown: self.teardown.clone(),
+			endpoint: self.endpoint.clone(),
+		})
+	}
+}
+
+impl Session {
+	/// Create a new session using all provided parameters
+	pub fn create<A: ToSocketAddrs>(
+		sam_addr: A,
+		destination: &str,
+		nickname: &str,
+		style: SessionStyle,
+	) -> Result<Session, Error> {
+		Self::create_with_options(sam_addr, destination, nickname, style, &SessionOptions::default())
+	}
+
+	/// Create a new session using all provided parameters, passing the
+	/// provided I2CP options to the router.
+	pub fn create_with_options<A: ToSocketAddrs>(
+		sam_addr: A,
+		destination: &str,
+		nickname: &str,
+		style: SessionStyle,
+		options: &SessionOptions,
+	) -> Result<Session, Error> {
+		let stream = SamStream::connect_tcp(sam_addr)?;
+		// styles only recent bridges support wait for the version HELLO
+		// negotiates
+		let recent = matches!(
+			style,
+			SessionStyle::Primary | SessionStyle::Datagram2 | SessionStyle::Datagram3
+		);
+		if !options.pipelines() || recent {
+			let sam = SamConnection::connect_impl(stream, None, None)?;
+			return Self::with_connection(sam, destination, nickname, style, options);
+		}
+		// no round trip between HELLO, SESSION CREATE and the lookup of the
+		// local destination
+		let msgs = Self::create_msgs(destination, nickname, style, options);
+		let (sam, replies) = SamConnection::connect_pipelined(stream, None, None, &msgs)?;
+		Self::created(sam, &replies, destination, nickname, style, options)
+	}
+
+	/// Create a new session over an already established SAM connection, for
+	/// example one authenticated through `SamConnection::connect_with_auth`.
+	/// Other connections needed by the session reuse the same credentials.
+	pub fn with_connection(
+		mut sam: SamConnection,
+		destination: &str,
+		nickname: &str,
+		style: SessionStyle,
+		options: &SessionOptions,
+	) -> Result<Session, Error> {
+		if style == SessionStyle::Primary && !sam.capabilities().primary_sessions {
+			return Err(sam.unsupported("Primary sessions"));
+		}
+		if (style == SessionStyle::Datagram2 || style == SessionStyle::Datagram3)
+			&& !sam.capabilities().datagram2
+		{
+			return Err(sam.unsupported(&format!("{} sessions", style.string())));
+		}
+		let msgs = Self::create_msgs(destination, nickname, style, options);
+		let replies = sam.send_all(&msgs, options.pipelines())?;
+		Self::created(sam, &replies, destination, nickname, style, options)
+	}
+
+	fn create_msg(
+		destination: &str,
+		nickname: &str,
+		style: SessionStyle,
+		options: &SessionOptions,
+	) -> String {
+		let mut create_session_msg = format!(
+			"SESSION CREATE STYLE={style} ID={nickname} DESTINATION={destination}",
+			style = style.string(),
+			nickname = nickname,
+			destination = destination
+		);
+		for (key, value) in options.to_options() {
+			create_session_msg.push_str(&format!(" {}={}", key, quote(&value)));
+		}
+		create_session_msg.push_str(" \n");
+		create_session_msg
+	}
+
+	/// SESSION CREATE, then the lookup of the local destination, along with
+	/// the replies they expect.
+	fn create_msgs(
+		destination: &str,
+		nickname: &str,
+		style: SessionStyle,
+		options: &SessionOptions,
+	) -> [(String, ExpectedReply); 2] {
+		[
+			(Self::create_msg(destination, nickname, style, options), "SESSION STATUS"),
+			(String::from("NAMING LOOKUP NAME=ME \n"), "NAMING REPLY"),
+		]
+	}
+
+	/// Builds the session from the replies to SESSION CREATE and the lookup
+	/// of the local destination.
+	fn created(
+		sam: SamConnection,
+		replies: &[HashMap<String, String>],
+		destination: &str,
+		nickname: &str,
+		style: SessionStyle,
+		options: &SessionOptions,
+	) -> Result<Session, Error> {
+		let status = SessionStatus::from_fields(&replies[0])?;
+		let local_dest = NamingReply::from_fields(&replies[1])?.value;
+
+		Ok(Session {
+			sam,
+			local_dest,
+			nickname: nickname.to_string(),
+			style,
+			subsession: false,
+			status,
+			destination: destination.to_string(),
+			options: options.clone(),
+		})
+	}
+
+	/// Create a new session with a randomly generated nickname, retrying
+	/// with a new one should the bridge already know it. See `id` for the
+	/// nickname in use.
+	pub fn create_auto<A: ToSocketAddrs>(
+		sam_addr: A,
+		destination: &str,
+		style: SessionStyle,
+		options: &SessionOptions,
+	) -> Result<Session, Error> {
+		let addrs: Vec<SocketAddr> = sam_addr.to_socket_addrs()?.collect();
+		let mut attempts = 1;
+		loop {
+			match Self::create_with_options(&addrs[..], destination, &nickname(), style, options) {
+				Err(e) => match e.kind() {
+					ErrorKind::SAMDuplicatedId(_) if attempts < NICKNAME_ATTEMPTS => {
+						debug!("Session nickname already in use, retrying");
+						attempts += 1;
+					}
+					_ => return Err(e),
+				},
+				res => return res,
+			}
+		}
+	}
+
+	/// Create a new session identified by the provided destination. Auto-generates
+	/// a nickname uniquely associated with the new session.
+	pub fn from_destination<A: ToSocketAddrs>(sam_addr: A, destination: &str) -> Result<Session, Error> {
+		Self::create_auto(sam_addr, destination, SessionStyle::Stream, &SessionOptions::default())
+	}
+
+	/// Create a new session of the provided style for a destination whose
+	/// long-term signing key is kept offline. Auto-generates a nickname
+	/// uniquely associated with the new session.
+	pub fn from_offline_keys<A: ToSocketAddrs>(
+		sam_addr: A,
+		keys: &OfflineKeys,
+		style: SessionStyle,
+	) -> Result<Session, Error> {
+		Self::create_auto(sam_addr, keys.as_str(), style, &SessionOptions::default())
+	}
+
+	/// Convenience constructor to create a new transient session with an
+	/// auto-generated nickname.
+	pub fn transient<A: ToSocketAddrs>(sam_addr: A) -> Result<Session, Error> {
+		Self::create_auto(sam_addr, "TRANSIENT", SessionStyle::Stream, &SessionOptions::default())
+	}
+
+	/// Convenience constructor to create a new primary session (SAM 3.3+)
+	/// identified by the provided destination, with an auto-generated
+	/// nickname, failing with `SAMUnsupported` on older bridges. Use
+	/// `add_subsession` to attach streaming, datagram or raw subsessions
+	/// sharing the same destination.
+	pub fn primary<A: ToSocketAddrs>(sam_addr: A, destination: &str) -> Result<Session, Error> {
+		Self::create_auto(sam_addr, destination, SessionStyle::Primary, &SessionOptions::default())
+	}
+
+	/// Attach a new subsession of the provided style to this primary session.
+	/// The returned session shares the primary control socket and destination
+	/// and can be used like any other session of that style.
+	pub fn add_subsession(&mut self, style: SessionStyle, nickname: &str) -> Result<Session, Error> {
+		self.add_subsession_with_options(style, nickname, &SessionOptions::default())
+	}
+
+	/// Same as `add_subsession`, passing the provided options along. Ports
+	/// and protocols (`from_port`, `listen_port`, `listen_protocol`) decide
+	/// which traffic of the shared destination the subsession gets. Datagram
+	/// and raw subsessions only receive through UDP, see
+	/// `SessionOptions::forward_to`.
+	pub fn add_subsession_with_options(
+		&mut self,
+		style: SessionStyle,
+		nickname: &str,
+		options: &SessionOptions,
+	) -> Result<Session, Error> {
+		if style == SessionStyle::Primary {
+			return Err(ErrorKind::SAMInvalidMessage(
+				"A primary session can't be a subsession".to_string(),
+			)
+			.into());
+		}
+		if self.style != SessionStyle::Primary {
+			return Err(ErrorKind::SAMInvalidMessage(
+				"Subsessions can only be added to a primary session".to_string(),
+			)
+			.into());
+		}
+		let mut add_session_msg = format!(
+			"SESSION ADD STYLE={style} ID={nickname}",
+			style = style.string(),
+			nickname = nickname,
+		);
+		for (key, value) in options.to_options() {
+			add_session_msg.push_str(&format!(" {}={}", key, quote(&value)));
+		}
+		add_session_msg.push_str(" \n");
+		let status = SessionStatus::from_fields(&self.sam.send(add_session_msg, "SESSION STATUS")?)?;
+
+		Ok(Session {
+			sam: self.sam.duplicate()?,
+			local_dest: self.local_dest.clone(),
+			nickname: nickname.to_string(),
+			style,
+			subsession: true,
+			status,
+			destination: self.destination.clone(),
+			options: options.clone(),
+		})
+	}
+
+	/// Detaches the subsession with the provided nickname from this primary
+	/// session. The tunnels of the primary session stay up.
+	pub fn remove_subsession(&mut self, nickname: &str) -> Result<(), Error> {
+		let remove_session_msg = format!("SESSION REMOVE ID={nickname} \n", nickname = nickname);
+		self.sam.send(remove_session_msg, "SESSION STATUS").map(|_| ())
+	}
+
+	/// Opens a new connection to the bridge of this session.
+	fn connect_sam(&self) -> Result<SamConnection, Error> {
+		self.sam.new_connection()
+	}
+
+	/// The TCP address of the bridge of this session. Fails for bridges
+	/// reached through a Unix domain socket, see `sam_endpoint`.
+	pub fn sam_api(&self) -> Result<SocketAddr, Error> {
+		match self.sam.endpoint()? {
+			SamEndpoint::Tcp(addr) => Ok(addr),
+			#[cfg(feature = "tls")]
+			SamEndpoint::Tls(tls) => Ok(tls.addr()),
+			#[cfg(unix)]
+			SamEndpoint::Unix(_) => {
+				Err(ErrorKind::Io("SAM bridge isn't reached over TCP".to_string()).into())
+			}
+		}
+	}
+
+	/// The endpoint of the bridge of this session.
+	pub fn sam_endpoint(&self) -> Result<SamEndpoint, Error> {
+		self.sam.endpoint()
+	}
+
+	pub fn naming_lookup(&mut self, name: &str) -> Result<String, Error> {
+		self.sam.naming_lookup(name)
+	}
+
+	/// Resolves several names at once on the control socket of this session,
+	/// see `SamConnection::lookup_many`. The control socket being closed on
+	/// failures of the connection, they end the session.
+	pub fn lookup_many(&mut self, names: &[&str]) -> Result<Vec<Result<String, Error>>, Error> {
+		self.sam.lookup_many(names)
+	}
+
+	/// Pings the bridge of this session from a background thread, see
+	/// `SamConnection::keepalive`.
+	pub fn keepalive<F>(&self, interval: Duration, on_failure: F) -> Result<Keepalive, Error>
+	where
+		F: FnOnce(Error) + Send + 'static,
+	{
+		self.sam.keepalive(interval, on_failure)
+	}
+
+	/// Creates the session again on a new control connection, with the same
+	/// destination, nickname and options, for example after the bridge was
+	/// restarted. Handles duplicated from this session reach the new one as
+	/// they only refer to it by nickname.
+	///
+	/// Transient sessions keep their address as long as the bridge sent its
+	/// private key on creation. Subsessions are lost with their primary
+	/// session and have to be added again.
+	pub fn reestablish(&mut self) -> Result<(), Error> {
+		if self.subsession {
+			return Err(ErrorKind::SAMInvalidMessage(
+				"Subsessions are re-added to their primary session".to_string(),
+			)
+			.into());
+		}
+		let destination = self.status.destination.clone().unwrap_or_else(|| self.destination.clone());
+		let sam = self.sam.new_connection()?;
+		let session = Session::with_connection(sam, &destination, &self.nickname, self.style, &self.options)?;
+		if session.local_dest != self.local_dest {
+			debug!("Session {} came back with a new destination", self.nickname);
+		}
+		*self = session;
+		Ok(())
+	}
+
+	/// The configuration this session can be restored from, see
+	/// `SessionConfig`. Fails for transient sessions when the bridge didn't
+	/// send the private key of the generated destination.
+	pub fn to_config(&self) -> Result<SessionConfig, Error> {
+		let private_key = match self.status.destination {
+			Some(ref key) => key.clone(),
+			None if self.destination != "TRANSIENT" => self.destination.clone(),
+			None => {
+				return Err(ErrorKind::SAMKeyNotFound(
+					"The bridge didn't send the private key of the session".to_string(),
+				)
+				.into())
+			}
+		};
+		Ok(SessionConfig {
+			private_key: I2pPrivateKey::new(private_key),
+			style: self.style,
+			options: self.options.to_options(),
+		})
+	}
+
+	/// Creates a session from a saved configuration, coming back on the same
+	/// destination with the same options.
+	pub fn restore<A: ToSocketAddrs>(sam_addr: A, config: &SessionConfig) -> Result<Session, Error> {
+		Self::create_auto(sam_addr, config.private_key(), config.style, &config.session_options())
+	}
+
+	/// Pings the bridge every `interval` from a background thread like
+	/// `keepalive`, re-establishing the session according to `policy` when
+	/// the bridge stops answering. `on_event` is told about the session
+	/// going down and coming back, the latter meaning new tunnels had to be
+	/// built.
+	///
+	/// Watching stops when the returned `Keepalive` is dropped, which also
+	/// closes a re-established session: keep it for as long as the session.
+	/// Like with `keepalive`, the PINGs go over a connection of their own,
+	/// opened again along with the session.
+	pub fn watch<F>(&self, interval: Duration, policy: ReconnectPolicy, mut on_event: F) -> Result<Keepalive, Error>
+	where
+		F: FnMut(SessionEvent) + Send + 'static,
+	{
+		let mut session = self.duplicate()?;
+		let mut pinged = self.sam.new_connection()?;
+		let (stop, stopped) = mpsc::channel::<()>();
+		thread::spawn(move || {
+			let mut count: u64 = 0;
+			while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+				count += 1;
+				let err = match pinged.ping_timeout(&format!("watch-{}", count), interval) {
+					Ok(()) => continue,
+					Err(e) => e,
+				};
+				if let Err(TryRecvError::Empty) = stopped.try_recv() {
+					on_event(SessionEvent::Disconnected(err));
+				} else {
+					return;
+				}
+				let mut attempts = 0;
+				loop {
+					attempts += 1;
+					match session.reestablish().and_then(|()| session.sam.new_connection()) {
+						Ok(sam) => {
+							pinged = sam;
+							on_event(SessionEvent::Reestablished { attempts });
+							break;
+						}
+						Err(e) if attempts >= policy.attempts => {
+							on_event(SessionEvent::GaveUp(e));
+							return;
+						}
+						Err(e) => debug!("Failed to re-establish session: {}", e),
+					}
+					if let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(policy.delay) {
+						continue;
+					}
+					return;
+				}
+			}
+		});
+		Ok(Keepalive { _stop: stop })
+	}
+
+	pub fn style(&self) -> SessionStyle {
+		self.style
+	}
+
+	/// Opens a stream to the provided destination and port over the tunnels
+	/// of this session, on a new socket referencing the session ID. May be
+	/// called any number of times, see `StreamConnect::with_session`.
+	pub fn connect(&self, dest: &str, port: u16) -> Result<StreamConnect, Error> {
+		StreamConnect::with_session(self, dest, port)
+	}
+
+	/// The reply of the bridge to the creation of this session.
+	pub fn status(&self) -> &SessionStatus {
+		&self.status
+	}
+
+	/// The nickname (`ID`) this session is known by on the bridge.
+	pub fn id(&self) -> &str {
+		&self.nickname
+	}
+
+	/// The base64 public destination of this session, as looked up with
+	/// `NAMING LOOKUP NAME=ME` when the session was created.
+	pub fn my_destination(&self) -> &str {
+		&self.local_dest
+	}
+
+	/// The b32 address of this session, for servers to share.
+	pub fn my_address(&self) -> Result<I2pAddr, Error> {
+		I2pAddr::from_b64(&self.local_dest)
+	}
+
+	/// Sets the timeout of the commands sent for this session, including
+	/// the ones connecting and accepting streams, see
+	/// `SamConnection::set_command_timeout`.
+	pub fn set_command_timeout(&mut self, timeout: Option<Duration>) {
+		self.sam.set_command_timeout(timeout)
+	}
+
+	/// All the fields of the last reply received on the control socket of
+	/// this session, see `SamConnection::last_reply`.
+	pub fn last_reply(&self) -> &HashMap<String, String> {
+		self.sam.last_reply()
+	}
+
+	/// The features of the bridge of this session.
+	pub fn capabilities(&self) -> SamCapabilities {
+		self.sam.capabilities()
+	}
+
+	/// The SAM version agreed upon with the bridge of this session.
+	pub fn sam_version(&self) -> &str {
+		self.sam.version()
+	}
+
+	/// The local address of the control socket of this session, see
+	/// `SamConnection::socket_addr`.
+	pub fn socket_addr(&self) -> Result<Option<SocketAddr>, Error> {
+		self.sam.socket_addr()
+	}
+
+	/// Closes the session, tearing down its tunnels, for all its handles.
+	/// Sessions are otherwise closed once their last handle, including the
+	/// ones held by streams, is dropped. Closing a subsession removes it from
+	/// its primary session, which stays up.
+	pub fn close(mut self) -> Result<(), Error> {
+		if self.subsession {
+			let remove_session_msg =
+				format!("SESSION REMOVE ID={nickname} \n", nickname = self.nickname);
+			return self.sam.send(remove_session_msg, "SESSION STATUS").map(|_| ());
+		}
+		self.sam.close()
+	}
+
+	pub fn duplicate(&self) -> Result<Session, Error> {
+		self.sam.duplicate().map(|s| Session {
+			sam: s,
+			local_dest: self.local_dest.clone(),
+			nickname: self.nickname.clone(),
+			style: self.style,
+			subsession: self.subsession,
+			status: self.status.clone(),
+			destination: self.destination.clone(),
+			options: self.options.clone(),
+		})
+	}
+}
+
+impl StreamConnect {
+
+	/// Create a new SAM client connection to the provided destination and port.
+	/// Also creates a new transient session to support the connection, which
+	/// builds a new set of tunnels: use `Session::connect` to open several
+	/// streams from the same destination.
+	pub fn new<A: ToSocketAddrs>(
+		sam_addr: A,
+		destination: &str,
+		port: u16,
+	) -> Result<StreamConnect, Error> {
+		let session = Session::transient(sam_addr)?;
+		Self::with_session(&session, destination, port)
+	}
+
+	/// Create a new SAM client connection to the provided destination and port
+	/// using the provided session. Failures can be told apart with
+	/// `ErrorKind::connect_failure`.
+	pub fn with_session(session: &Session, dest: &str, port: u16) -> Result<StreamConnect, Error> {
+		Self::connect_impl(session, dest, 0, port, false, None, &[])
+	}
+
+	/// Same as `with_session`, failing with `ErrorKind::SAMTimeout` when the
+	/// connection isn't established within `timeout`, name lookup included.
+	/// Peers whose tunnels can't be reached otherwise take minutes to be
+	/// reported by the bridge.
+	pub fn with_session_timeout(
+		session: &Session,
+		dest: &str,
+		port: u16,
+		timeout: Duration,
+	) -> Result<StreamConnect, Error> {
+		Self::connect_impl(session, dest, 0, port, false, Some(Instant::now() + timeout), &[])
+	}
+
+	/// Same as `with_session`, also setting the local port the connection
+	/// originates from, so the peer can tell services sharing our
+	/// destination apart (SAM 3.2+).
+	pub fn with_session_ports(
+		session: &Session,
+		dest: &str,
+		from_port: u16,
+		to_port: u16,
+	) -> Result<StreamConnect, Error> {
+		Self::connect_impl(session, dest, from_port, to_port, false, None, &[])
+	}
+
+	/// Same as `with_session`, but in silent mode: the bridge doesn't report
+	/// the outcome of the connection, so this returns as soon as the request
+	/// is sent. A failed connection shows up as the stream being closed.
+	pub fn with_session_silent(
+		session: &Session,
+		dest: &str,
+		port: u16,
+	) -> Result<StreamConnect, Error> {
+		Self::connect_impl(session, dest, 0, port, true, None, &[])
+	}
+
+	/// Same as `with_session`, overriding the streaming options of the
+	/// session for this connection only, so that one session can carry both
+	/// interactive and bulk streams. Accepted streams always use the options
+	/// of their session.
+	pub fn with_session_options(
+		session: &Session,
+		dest: &str,
+		port: u16,
+		options: &StreamingOptions,
+	) -> Result<StreamConnect, Error> {
+		Self::connect_impl(session, dest, 0, port, false, None, &options.to_options())
+	}
+
+	fn connect_impl(
+		session: &Session,
+		dest: &str,
+		from_port: u16,
+		to_port: u16,
+		silent: bool,
+		deadline: Option<Instant>,
+		options: &[(String, String)],
+	) -> Result<StreamConnect, Error> {
+		if (from_port > 0 || to_port > 0) && !session.sam.capabilities().ports {
+			return Err(session.sam.unsupported("Stream ports"));
+		}
+		let stream_msg = |dest: &str| {
+			let mut stream_msg = format!(
+				"STREAM CONNECT ID={nickname} DESTINATION={destination} SILENT={silent}",
+				nickname = session.nickname,
+				destination = dest,
+				silent = silent,
+			);
+			if from_port > 0 {
+				stream_msg.push_str(&format!(" FROM_PORT={port}", port = from_port));
+			}
+			if to_port > 0 {
+				stream_msg.push_str(&format!(" TO_PORT={port}", port = to_port));
+			}
+			for (key, value) in options {
+				stream_msg.push_str(&format!(" {}={}", key, quote(value)));
+			}
+			stream_msg.push('\n');
+			stream_msg
+		};
+
+		// each step only gets the time left before the deadline
+		let timeout = || match deadline {
+			Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+				Some(left) if left > Duration::from_secs(0) => Ok(Some(left)),
+				_ => Err(Error::from(ErrorKind::SAMTimeout("Couldn't connect to the peer in time".to_string()))),
+			},
+			None => Ok(session.sam.command_timeout),
+		};
+
+		// resolving locally counts against the deadline as well, a resolver
+		// can't be interrupted but one returning too late fails the connection
+		timeout()?;
+		let known = session.sam.resolve_locally(dest).transpose()?;
+		timeout()?;
+		let (mut sam, dest) = match known {
+			// the peer is already known, connect right after HELLO
+			Some(dest) if !silent && session.options.pipelines() => {
+				let msgs = [(stream_msg(&dest), "STREAM STATUS")];
+				let (sam, replies) = session.sam.new_connection_timeout(&msgs, timeout()?)?;
+				StreamStatus::from_fields(&replies[0])?;
+				(sam, dest)
+			}
+			known => {
+				let (mut sam, _) = session.sam.new_connection_timeout(&[], timeout()?)?;
+				let dest = match known {
+					Some(dest) => dest,
+					None => {
+						sam.command_timeout = timeout()?;
+						sam.naming_lookup(dest)?
+					}
+				};
+				sam.command_timeout = timeout()?;
+				if silent {
+					sam.send_silent(stream_msg(&dest))?;
+				} else {
+					StreamStatus::from_fields(&sam.send(stream_msg(&dest), "STREAM STATUS")?)?;
+				}
+				(sam, dest)
+			}
+		};
+		sam.command_timeout = session.sam.command_timeout;
+		sam.carry_stream_data();
+
+		Ok(StreamConnect {
+			sam,
+			session: session.duplicate()?,
+			peer_dest: dest,
+			peer_port: to_port,
+			local_port: from_port,
+			buffer: Arc::new(Mutex::new(vec![])),
+			buffer_capacity: Arc::new(AtomicUsize::new(0)),
+			last_active: Arc::new(Mutex::new(Instant::now())),
+			idle_watch: Arc::new(Mutex::new(None)),
+			connected_at: Instant::now(),
+			bytes_read: Arc::new(AtomicU64::new(0)),
+			bytes_written: Arc::new(AtomicU64::new(0)),
+		})
+	}
+
+	pub fn peer_addr(&self) -> Result<(String, u16), Error> {
+		Ok((self.peer_dest.clone(), self.peer_port))
+	}
+
+	pub fn local_addr(&self) -> Result<(String, u16), Error> {
+		Ok((self.session.local_dest.clone(), self.local_port))
+	}
+
+	pub fn set_nonblocking(&self, nonblocking: bool) -> Result<(), Error> {
+		self.sam.set_nonblocking(nonblocking)
+	}
+
+	pub fn shutdown(&self, how: Shutdown) -> Result<(), Error> {
+		self.sam.conn.shutdown(how).map_err(|e| e.into())
+	}
+
+	/// Reads data without consuming it, the next reads returning it again.
+	/// Blocks until data is available, like `read`.
+	pub fn peek(&self, buf: &mut [u8]) -> Result<usize, Error> {
+		let mut buffer = self.buffer.lock().unwrap_or_else(|e| e.into_inner());
+		if buffer.is_empty() && !buf.is_empty() {
+			self.fill_buffer(&mut buffer, buf.len().max(self.read_buffer_capacity()))?;
+		}
+		let n = buf.len().min(buffer.len());
+		buf[..n].copy_from_slice(&buffer[..n]);
+		Ok(n)
+	}
+
+	/// Buffers reads smaller than `capacity` by reading `capacity` bytes at
+	/// once instead, sparing one read from the socket each time for parsers
+	/// reading a few bytes at a time. The buffer is shared by all the handles
+	/// of the stream, 0 disabling buffering, the default.
+	pub fn set_read_buffer_capacity(&self, capacity: usize) {
+		self.buffer_capacity.store(capacity, Ordering::SeqCst);
+	}
+
+	pub fn read_buffer_capacity(&self) -> usize {
+		self.buffer_capacity.load(Ordering::SeqCst)
+	}
+
+	/// Reads up to `len` bytes from the socket into the empty buffer.
+	fn fill_buffer(&self, buffer: &mut Vec<u8>, len: usize) -> io::Result<usize> {
+		buffer.resize(len, 0);
+		let res = self.active((&self.sam.conn).read(buffer), &self.bytes_read);
+		buffer.truncate(*res.as_ref().unwrap_or(&0));
+		res
+	}
+
+	/// Records the stream as active when `res` is a success, adding the
+	/// bytes transferred to `counter`.
+	fn active(&self, res: io::Result<usize>, counter: &AtomicU64) -> io::Result<usize> {
+		if let Ok(n) = res {
+			*self.last_active.lock().unwrap_or_else(|e| e.into_inner()) = Instant::now();
+			counter.fetch_add(n as u64, Ordering::SeqCst);
+		}
+		res
+	}
+
+	/// The nickname of the session the stream belongs to.
+	pub fn session_id(&self) -> &str {
+		&self.session.nickname
+	}
+
+	/// The SAM version agreed upon with the bridge on the data socket.
+	pub fn sam_version(&self) -> &str {
+		self.sam.version()
+	}
+
+	/// The local address of the socket to the bridge carrying the stream,
+	/// `None` for bridges reached through a Unix domain socket.
+	pub fn socket_addr(&self) -> Result<Option<SocketAddr>, Error> {
+		self.sam.socket_addr()
+	}
+
+	/// How long ago the stream was connected, or accepted.
+	pub fn connected_for(&self) -> Duration {
+		self.connected_at.elapsed()
+	}
+
+	/// The bytes read from the stream, including the ones read ahead by
+	/// `peek` or read buffering, by all its handles.
+	pub fn bytes_read(&self) -> u64 {
+		self.bytes_read.load(Ordering::SeqCst)
+	}
+
+	/// The bytes written to the stream by all its handles.
+	pub fn bytes_written(&self) -> u64 {
+		self.bytes_written.load(Ordering::SeqCst)
+	}
+
+	/// Shuts the stream down once no data was read or written on it for
+	/// `timeout`, from a background thread, so that streams to peers gone
+	/// without closing them don't linger forever. Reads and writes then fail
+	/// like on a stream closed by the peer. `None` stops watching the stream,
+	/// the default. Applies to all the handles of the stream.
+	///
+	/// The streaming library of the router can do the same, or send
+	/// keepalives instead, see `StreamingOptions::inactivity`.
+	pub fn set_idle_timeout(&self, timeout: Option<Duration>) -> Result<(), Error> {
+		let mut watch = self.idle_watch.lock().unwrap_or_else(|e| e.into_inner());
+		// dropping the sender stops the previous thread
+		*watch = None;
+		let timeout = match timeout {
+			Some(timeout) => timeout,
+			None => return Ok(()),
+		};
+		let conn = self.sam.conn.try_clone()?;
+		let last_active = self.last_active.clone();
+		let (stop, stopped) = mpsc::channel::<()>();
+		thread::spawn(move || loop {
+			let idle = last_active.lock().unwrap_or_else(|e| e.into_inner()).elapsed();
+			if idle >= timeout {
+				debug!("Stream idle for {:?}, shutting it down", idle);
+				let _ = conn.shutdown(Shutdown::Both);
+				return;
+			}
+			if let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(timeout - idle) {
+				continue;
+			}
+			return;
+		});
+		*watch = Some((timeout, stop));
+		Ok(())
+	}
+
+	pub fn idle_timeout(&self) -> Option<Duration> {
+		self.idle_watch.lock().unwrap_or_else(|e| e.into_inner()).as_ref().map(|w| w.0)
+	}
+
+	/// Fails reads that get no data within `timeout` with a `WouldBlock` or
+	/// `TimedOut` io error, `None` blocking forever.
+	pub fn set_read_timeout(&self, timeout: Option<Duration>) -> Result<(), Error> {
+		self.sam.conn.set_read_timeout(timeout).map_err(|e| e.into())
+	}
+
+	/// Fails writes that can't complete within `timeout`, `None` blocking
+	/// forever.
+	pub fn set_write_timeout(&self, timeout: Option<Duration>) -> Result<(), Error> {
+		self.sam.conn.set_write_timeout(timeout).map_err(|e| e.into())
+	}
+
+	pub fn read_timeout(&self) -> Result<Option<Duration>, Error> {
+		self.sam.conn.read_timeout().map_err(|e| e.into())
+	}
+
+	pub fn write_timeout(&self) -> Result<Option<Duration>, Error> {
+		self.sam.conn.write_timeout().map_err(|e| e.into())
+	}
+
+	/// Closes the stream, reporting errors that dropping it would ignore.
+	pub fn close(self) -> Result<(), Error> {
+		self.sam.close()
+	}
+
+	pub fn duplicate(&self) -> Result<StreamConnect, Error> {
+		Ok(StreamConnect {
+			sam: self.sam.duplicate()?,
+			session: self.session.duplicate()?,
+			peer_dest: self.peer_dest.clone(),
+			peer_port: self.peer_port,
+			local_port: self.local_port,
+			buffer: self.buffer.clone(),
+			buffer_capacity: self.buffer_capacity.clone(),
+			last_active: self.last_active.clone(),
+			idle_watch: self.idle_watch.clone(),
+			connected_at: self.connected_at,
+			bytes_read: self.bytes_read.clone(),
+			bytes_written: self.bytes_written.clone(),
+		})
+	}
+}
+
+#[cfg(unix)]
+impl AsRawFd for StreamConnect {
+	fn as_raw_fd(&self) -> RawFd {
+		self.sam.conn.as_raw_fd()
+	}
+}
+
+impl Read for StreamConnect {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		(&*self).read(buf)
+	}
+	fn read_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> io::Result<usize> {
+		(&*self).read_vectored(bufs)
+	}
+}
+
+impl Write for StreamConnect {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		(&*self).write(buf)
+	}
+	fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+		(&*self).write_vectored(bufs)
+	}
+	fn flush(&mut self) -> io::Result<()> {
+		(&*self).flush()
+	}
+}
+
+impl Read for &StreamConnect {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		if buf.is_empty() {
+			return Ok(0);
+		}
+		let mut buffer = self.buffer.lock().unwrap_or_else(|e| e.into_inner());
+		if buffer.is_empty() {
+			let capacity = self.read_buffer_capacity();
+			if buf.len() >= capacity {
+				drop(buffer);
+				return self.active((&self.sam.conn).read(buf), &self.bytes_read);
+			}
+			self.fill_buffer(&mut buffer, capacity)?;
+		}
+		let n = buf.len().min(buffer.len());
+		buf[..n].copy_from_slice(&buffer[..n]);
+		buffer.drain(..n);
+		Ok(n)
+	}
+	fn read_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> io::Result<usize> {
+		// buffered data goes first, reading it is enough
+		let buffering =
+			self.read_buffer_capacity() > 0 || !self.buffer.lock().unwrap_or_else(|e| e.into_inner()).is_empty();
+		if buffering {
+			return match bufs.iter_mut().find(|b| !b.is_empty()) {
+				Some(buf) => self.read(buf),
+				None => Ok(0),
+			};
+		}
+		self.active((&self.sam.conn).read_vectored(bufs), &self.bytes_read)
+	}
+}
+
+impl Write for &StreamConnect {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		self.active((&self.sam.conn).write(buf), &self.bytes_written)
+	}
+	fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+		self.active((&self.sam.conn).write_vectored(bufs), &self.bytes_written)
+	}
+	fn flush(&mut self) -> io::Result<()> {
+		(&self.sam.conn).flush()
+	}
+}
+
+pub struct StreamForward {
+	session: Session,
+	nonblocking: Arc<AtomicBool>,
+	// accepted stream whose peer destination line is still being read, along
+	// with the part of the line read so far
+	pending: Arc<Mutex<Option<PendingAccept>>>,
+	// the port streams are addressed to, when bound to one
+	local_port: u16,
+}
+
+type PendingAccept = (StreamConnect, Vec<u8>);
+
+impl StreamForward {
+	pub fn new<A: ToSocketAddrs>(
+		sam_addr: A,
+	) -> Result<StreamForward, Error> {
+		Self::with_session(&Session::transient(sam_addr)?)
+	}
+
+	/// Create a new SAM client connection to the provided destination and port
+	/// using the provided session.
+	pub fn with_session(session: &Session) -> Result<StreamForward, Error> {
+		Ok(StreamForward {
+			session: session.duplicate()?,
+			nonblocking: Arc::new(AtomicBool::new(false)),
+			pending: Arc::new(Mutex::new(None)),
+			local_port: 0,
+		})
+	}
+
+	/// Accepts the streams of the destination of a primary session addressed
+	/// to `port` only, through a new stream subsession listening on it. Each
+	/// port of the destination can have its own `StreamForward` this way,
+	/// the subsession staying up as long as the primary session (SAM 3.3+).
+	pub fn with_primary_port(primary: &mut Session, port: u16) -> Result<StreamForward, Error> {
+		let options = SessionOptions::new().listen_port(port);
+		let session = primary.add_subsession_with_options(SessionStyle::Stream, &nickname(), &options)?;
+		Ok(StreamForward {
+			session,
+			nonblocking: Arc::new(AtomicBool::new(false)),
+			pending: Arc::new(Mutex::new(None)),
+			local_port: port,
+		})
+	}
+
+	pub fn accept(&self) -> Result<(StreamConnect, I2pSocketAddr), Error> {
+		let nonblocking = self.nonblocking.load(Ordering::SeqCst);
+		if nonblocking || self.pending.lock().unwrap_or_else(|e| e.into_inner()).is_some() {
+			return self.accept_pending(None, nonblocking);
+		}
+		Self::accepted(self.accept_impl(false)?)
+	}
+
+	/// Same as `accept`, but fails with `ErrorKind::SAMTimeout` when no
+	/// peer connects within `timeout`, so that accept loops can wake up
+	/// regularly. The accept request stays pending on the session, the next
+	/// call picking it up.
+	pub fn accept_timeout(&self, timeout: Duration) -> Result<(StreamConnect, I2pSocketAddr), Error> {
+		self.accept_pending(Some(timeout), false)
+	}
+
+	/// In nonblocking mode, `accept` fails with `ErrorKind::WouldBlock`
+	/// until a peer connects instead of blocking. The accept request itself
+	/// stays pending on the session between calls, only its handshake with
+	/// the bridge blocking.
+	pub fn set_nonblocking(&self, nonblocking: bool) -> Result<(), Error> {
+		self.nonblocking.store(nonblocking, Ordering::SeqCst);
+		Ok(())
+	}
+
+	pub(crate) fn is_nonblocking(&self) -> bool {
+		self.nonblocking.load(Ordering::SeqCst)
+	}
+
+	/// Accepts on the pending accept socket, opening one if needed, and
+	/// reads the peer destination line for at most `timeout`, or only what's
+	/// already available in nonblocking mode. The socket is kept pending
+	/// along with the partial line when the peer doesn't show up in time.
+	fn accept_pending(
+		&self,
+		timeout: Option<Duration>,
+		nonblocking: bool,
+	) -> Result<(StreamConnect, I2pSocketAddr), Error> {
+		let mut pending = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+		if pending.is_none() {
+			*pending = Some((self.accept_impl(false)?, vec![]));
+		}
+		if let Some((ref stream, _)) = *pending {
+			stream.set_nonblocking(nonblocking)?;
+		}
+		let deadline = timeout.map(|t| Instant::now() + t);
+		let mut byte = [0; 1];
+		loop {
+			let read = match *pending {
+				Some((ref mut stream, ref mut line)) if line.last() != Some(&b'\n') => {
+					let remaining = deadline.map(|d| d.saturating_duration_since(Instant::now()));
+					if remaining == Some(Duration::from_secs(0)) {
+						Err(io::ErrorKind::TimedOut.into())
+					} else {
+						stream.sam.conn.set_read_timeout(remaining).and_then(|_| {
+							stream.sam.conn.read(&mut byte).inspect(|&n| {
+								line.extend_from_slice(&byte[..n]);
+							})
+						})
+					}
+				}
+				_ => break,
+			};
+			match read {
+				Ok(0) => {
+					*pending = None;
+					return Err(ErrorKind::Io("SAM bridge closed the connection".to_string()).into());
+				}
+				Ok(_) => {}
+				Err(ref e) if is_timeout(e) && nonblocking => {
+					return Err(ErrorKind::WouldBlock.into());
+				}
+				Err(ref e) if is_timeout(e) => {
+					return Err(ErrorKind::SAMTimeout("No peer connected in time".to_string()).into());
+				}
+				Err(e) => {
+					*pending = None;
+					return Err(e.into());
+				}
+			}
+		}
+		let (stream, line) = pending.take().unwrap();
+		stream.set_nonblocking(false)?;
+		stream.set_read_timeout(None)?;
+		let line = String::from_utf8(line).map_err(|_| Error::from(ErrorKind::MessageParsing))?;
+		Self::accepted_line(stream, &line)
+	}
+
+	/// Starts `size` accept sockets that stay pending on the session, so
+	/// that peers connecting in bursts don't have to wait for each accept
+	/// handshake in turn. Accepted streams are handed out by the returned
+	/// `AcceptPool` and the pool stops once dropped.
+	///
+	/// More than one pending accept requires SAM 3.2+.
+	pub fn accept_pool(&self, size: usize) -> Result<AcceptPool, Error> {
+		if size > 1 && !self.session.capabilities().concurrent_accepts {
+			return Err(self.session.sam.unsupported("Concurrent accepts"));
+		}
+		let (sender, receiver) = mpsc::channel();
+		let stopped = Arc::new(AtomicBool::new(false));
+		let pending: Arc<Vec<_>> = Arc::new((0..size).map(|_| Mutex::new(None)).collect());
+		for slot in 0..size {
+			let forward = self.duplicate()?;
+			let sender = sender.clone();
+			let stopped = stopped.clone();
+			let pending = pending.clone();
+			thread::spawn(move || forward.accept_worker(&sender, &stopped, &pending, slot));
+		}
+		Ok(AcceptPool {
+			accepted: Mutex::new(receiver),
+			pending,
+			stopped,
+		})
+	}
+
+	/// Accepts streams into the channel of an `AcceptPool` until the pool is
+	/// stopped, the pending stream being kept in its `slot` of `pending` so
+	/// stopping can shut it down. Failures other than timeouts mean the
+	/// session is gone: the first one is sent, and stops the whole pool.
+	fn accept_worker(
+		&self,
+		sender: &mpsc::Sender<Result<(StreamConnect, I2pSocketAddr), Error>>,
+		stopped: &AtomicBool,
+		pending: &[Mutex<Option<StreamConnect>>],
+		slot: usize,
+	) {
+		while !stopped.load(Ordering::SeqCst) {
+			let res = self.accept_impl(false).and_then(|stream| {
+				*pending[slot].lock().unwrap_or_else(|e| e.into_inner()) = Some(stream.duplicate()?);
+				// the pool may have been stopped before the stream got kept
+				if stopped.load(Ordering::SeqCst) {
+					return Err(ErrorKind::Io("Accept pool stopped".to_string()).into());
+				}
+				Self::accepted(stream)
+			});
+			pending[slot].lock().unwrap_or_else(|e| e.into_inner()).take();
+			if stopped.load(Ordering::SeqCst) {
+				return;
+			}
+			let (failed, fatal) = match res {
+				Ok(_) => (false, false),
+				Err(ref e) => (true, !matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::SAMTimeout(_))),
+			};
+			if fatal {
+				stop_accept_pool(stopped, pending);
+			}
+			if sender.send(res).is_err() || fatal {
+				return;
+			}
+			if failed {
+				thread::sleep(ACCEPT_RETRY);
+			}
+		}
+	}
+
+	/// Same as `accept`, but returns early with an error once `abort` is
+	/// aborted, the accept socket being shut down so the bridge drops the
+	/// accept request.
+	#[cfg(feature = "async")]
+	pub(crate) fn accept_abortable(&self, abort: &AcceptAbort) -> Result<(StreamConnect, I2pSocketAddr), Error> {
+		if self.pending.lock().unwrap_or_else(|e| e.into_inner()).is_some() {
+			return self.accept();
+		}
+		abort.check()?;
+		let stream = self.accept_impl(false)?;
+		*abort.socket.lock().unwrap_or_else(|e| e.into_inner()) = Some(stream.duplicate()?);
+		let res = abort.check().and_then(|_| Self::accepted(stream));
+		abort.socket.lock().unwrap_or_else(|e| e.into_inner()).take();
+		res
+	}
+
+	/// Reads the peer destination prefixing an accepted stream.
+	fn accepted(mut stream: StreamConnect) -> Result<(StreamConnect, I2pSocketAddr), Error> {
+		let dest_line = stream.sam.read_line()?;
+		Self::accepted_line(stream, &dest_line)
+	}
+
+	fn accepted_line(
+		mut stream: StreamConnect,
+		dest_line: &str,
+	) -> Result<(StreamConnect, I2pSocketAddr), Error> {
+		debug!("<- {}", dest_line);
+		let (destination, opts) = sam_dest_line(dest_line)
+			.map_err(|_| ErrorKind::SAMKeyNotFound("No b64 destination in accept".to_string()))?
+			.1;
+		// ports only provided with SAM v3.2+
+		let port = |key| {
+			opts.iter()
+				.find(|&&(k, _)| k == key)
+				.and_then(|(_, v)| v.parse().ok())
+				.unwrap_or(0)
+		};
+		stream.peer_port = port("FROM_PORT");
+		stream.local_port = port("TO_PORT");
+		stream.connected_at = Instant::now();
+
+		let addr = I2pSocketAddr::new(I2pAddr::from_b64(destination)?, stream.peer_port);
+		stream.peer_dest = destination.to_string();
+
+		Ok((stream, addr))
+	}
+
+	/// Same as `accept`, but in silent mode: the bridge doesn't prefix the
+	/// stream with the peer destination, so the peer address stays unknown.
+	/// Returns as soon as the accept request is sent, reading the stream
+	/// blocks until a peer connects.
+	pub fn accept_silent(&self) -> Result<StreamConnect, Error> {
+		self.accept_impl(true)
+	}
+
+	fn accept_impl(&self, silent: bool) -> Result<StreamConnect, Error> {
+		let mut sam_conn = self.session.connect_sam()?;
+
+		let accept_stream_msg = format!(
+			"STREAM ACCEPT ID={nickname} SILENT={silent}\n",
+			nickname = self.session.nickname,
+			silent = silent,
+		);
+		if silent {
+			sam_conn.send_silent(accept_stream_msg)?;
+		} else {
+			StreamStatus::from_fields(&sam_conn.send(accept_stream_msg, "STREAM STATUS")?)?;
+		}
+		sam_conn.carry_stream_data();
+
+		Ok(StreamConnect {
+			sam: sam_conn,
+			session: self.session.duplicate()?,
+			peer_dest: "".to_string(),
+			peer_port: 0,
+			local_port: self.local_port,
+			buffer: Arc::new(Mutex::new(vec![])),
+			buffer_capacity: Arc::new(AtomicUsize::new(0)),
+			last_active: Arc::new(Mutex::new(Instant::now())),
+			idle_watch: Arc::new(Mutex::new(None)),
+			connected_at: Instant::now(),
+			bytes_read: Arc::new(AtomicU64::new(0)),
+			bytes_written: Arc::new(AtomicU64::new(0)),
+		})
+	}
+
+	/// Ask the bridge to forward all incoming streams of the session to the
+	/// provided local TCP address, instead of accepting them one by one.
+	/// Forwarding stops once the returned value is dropped.
+	pub fn forward(&self, target: &SocketAddr) -> Result<StreamForwarding, Error> {
+		let mut sam_conn = self.session.connect_sam()?;
+
+		let forward_stream_msg = format!(
+			"STREAM FORWARD ID={nickname} PORT={port} HOST={host} SILENT=false\n",
+			nickname = self.session.nickname,
+			port = target.port(),
+			host = target.ip(),
+		);
+		StreamStatus::from_fields(&sam_conn.send(forward_stream_msg, "STREAM STATUS")?)?;
+
+		Ok(StreamForwarding {
+			sam: sam_conn,
+			session: self.session.duplicate()?,
+			target: *target,
+		})
+	}
+
+	pub fn local_addr(&self) -> Result<(String, u16), Error> {
+		Ok((self.session.local_dest.clone(), self.local_port))
+	}
+
+	pub fn duplicate(&self) -> Result<StreamForward, Error> {
+		Ok(StreamForward {
+			session: self.session.duplicate()?,
+			nonblocking: self.nonblocking.clone(),
+			pending: self.pending.clone(),
+			local_port: self.local_port,
+		})
+	}
+}
+
+/// Accept sockets kept pending on a session by background threads, see
+/// `StreamForward::accept_pool`. Dropping the pool shuts the pending
+/// sockets down.
+pub struct AcceptPool {
+	accepted: Mutex<mpsc::Receiver<Result<(StreamConnect, I2pSocketAddr), Error>>>,
+	pending: Arc<Vec<Mutex<Option<StreamConnect>>>>,
+	stopped: Arc<AtomicBool>,
+}
+
+impl AcceptPool {
+	/// Blocks until one of the accept sockets gets a stream, returning it
+	/// along with the address of the peer. Timeouts of the accept sockets
+	/// are returned as they happen, the socket being reopened, while any
+	/// other failure stops the pool once returned.
+	pub fn accept(&self) -> Result<(StreamConnect, I2pSocketAddr), Error> {
+		self.accepted
+			.lock()
+			.unwrap_or_else(|e| e.into_inner())
+			.recv()
+			.unwrap_or_else(|_| Err(ErrorKind::Io("Accept pool stopped".to_string()).into()))
+	}
+
+	/// Same as `accept`, but fails with `ErrorKind::WouldBlock` instead of
+	/// blocking when no stream got accepted yet.
+	pub fn try_accept(&self) -> Result<(StreamConnect, I2pSocketAddr), Error> {
+		match self.accepted.lock().unwrap_or_else(|e| e.into_inner()).try_recv() {
+			Ok(res) => res,
+			Err(TryRecvError::Empty) => Err(ErrorKind::WouldBlock.into()),
+			Err(TryRecvError::Disconnected) => {
+				Err(ErrorKind::Io("Accept pool stopped".to_string()).into())
+			}
+		}
+	}
+
+	/// Same as `accept`, but fails with `ErrorKind::SAMTimeout` when no
+	/// stream got accepted within `timeout`.
+	pub fn accept_timeout(&self, timeout: Duration) -> Result<(StreamConnect, I2pSocketAddr), Error> {
+		match self.accepted.lock().unwrap_or_else(|e| e.into_inner()).recv_timeout(timeout) {
+			Ok(res) => res,
+			Err(RecvTimeoutError::Timeout) => {
+				Err(ErrorKind::SAMTimeout("No stream accepted in time".to_string()).into())
+			}
+			Err(RecvTimeoutError::Disconnected) => {
+				Err(ErrorKind::Io("Accept pool stopped".to_string()).into())
+			}
+		}
+	}
+
+	/// Same as `accept`, but returns early with an error once `abort` is
+	/// aborted. Streams accepted meanwhile stay in the pool.
+	#[cfg(feature = "async")]
+	pub(crate) fn accept_abortable(&self, abort: &AcceptAbort) -> Result<(StreamConnect, I2pSocketAddr), Error> {
+		loop {
+			abort.check()?;
+			match self.accept_timeout(ACCEPT_ABORT_POLL) {
+				Err(ref e) if matches!(e.kind(), ErrorKind::SAMTimeout(_)) => continue,
+				res => return res,
+			}
+		}
+	}
+
+	/// The number of accept sockets of the pool.
+	pub fn size(&self) -> usize {
+		self.pending.len()
+	}
+}
+
+impl Drop for AcceptPool {
+	fn drop(&mut self) {
+		stop_accept_pool(&self.stopped, &self.pending);
+	}
+}
+
+/// Stops the workers of an `AcceptPool`, shutting their pending sockets
+/// down.
+fn stop_accept_pool(stopped: &AtomicBool, pending: &[Mutex<Option<StreamConnect>>]) {
+	stopped.store(true, Ordering::SeqCst);
+	for slot in pending {
+		if let Some(ref stream) = *slot.lock().unwrap_or_else(|e| e.into_inner()) {
+			let _ = stream.shutdown(Shutdown::Both);
+		}
+	}
+}
+
+/// Lets a pending accept be aborted from another thread, see
+/// `StreamForward::accept_abortable`.
+#[cfg(feature = "async")]
+#[derive(Default)]
+pub(crate) struct AcceptAbort {
+	aborted: AtomicBool,
+	// duplicate of the accept socket while waiting for a peer
+	socket: Mutex<Option<StreamConnect>>,
+}
+
+#[cfg(feature = "async")]
+impl AcceptAbort {
+	/// Makes the accept fail, shutting its socket down if a peer is awaited.
+	pub(crate) fn abort(&self) {
+		self.aborted.store(true, Ordering::SeqCst);
+		if let Some(stream) = self.socket.lock().unwrap_or_else(|e| e.into_inner()).take() {
+			let _ = stream.shutdown(Shutdown::Both);
+		}
+	}
+
+	fn check(&self) -> Result<(), Error> {
+		if self.aborted.load(Ordering::SeqCst) {
+			return Err(ErrorKind::Io("Accept aborted".to_string()).into());
+		}
+		Ok(())
+	}
+}
+
+/// An active `STREAM FORWARD`. The bridge keeps forwarding incoming streams
+/// for as long as the socket the command was issued on stays open.
+pub struct StreamForwarding {
+	sam: SamConnection,
+	session: Session,
+	target: SocketAddr,
+}
+
+impl StreamForwarding {
+	/// The local TCP address incoming streams are forwarded to.
+	pub fn target_addr(&self) -> SocketAddr {
+		self.target
+	}
+
+	pub fn local_addr(&self) -> Result<(String, u16), Error> {
+		Ok((self.session.local_dest.clone(), 0))
+	}
+
+	/// Stops forwarding incoming streams.
+	pub fn close(self) -> Result<(), Error> {
+		self.sam.close()
+	}
+}
+
+/// Sends datagrams of a session through the UDP port of the bridge, which
+/// performs much better than the control socket.
+struct UdpSender {
+	socket: UdpSocket,
+	bridge: SocketAddr,
+}
+
+impl UdpSender {
+	fn new<A: ToSocketAddrs>(udp_addr: A) -> Result<UdpSender, Error> {
+		let bridge = udp_addr
+			.to_socket_addrs()?
+			.next()
+			.ok_or_else(|| ErrorKind::UnresolvableAddress.to_err())?;
+		let local: SocketAddr = if bridge.is_ipv4() {
+			([0, 0, 0, 0], 0).into()
+		} else {
+			([0u16; 8], 0).into()
+		};
+		let socket = UdpSocket::bind(local)?;
+		Ok(UdpSender { socket, bridge })
+	}
+
+	fn send(&self, nickname: &str, dest: &str, opts: &[(&str, u16)], buf: &[u8]) -> Result<usize, Error> {
+		let mut header = format!(
+			"3.0 {nickname} {destination}",
+			nickname = nickname,
+			destination = dest
+		);
+		push_payload_opts(&mut header, opts);
+		header.push('\n');
+
+		let mut packet = header.into_bytes();
+		packet.extend_from_slice(buf);
+		self.socket.send_to(&packet, self.bridge)?;
+		Ok(buf.len())
+	}
+
+	fn duplicate(&self) -> Result<UdpSender, Error> {
+		Ok(UdpSender {
+			socket: self.socket.try_clone()?,
+			bridge: self.bridge,
+		})
+	}
+}
+
+/// A repliable datagram session. Datagrams are sent and received over the
+/// session's control socket, each one carrying the sender's destination.
+pub struct DatagramSession {
+	session: Session,
+	udp: Option<UdpSender>,
+}
+
+impl DatagramSession {
+	/// Create a new transient datagram session.
+	pub fn new<A: ToSocketAddrs>(sam_addr: A) -> Result<DatagramSession, Error> {
+		Self::from_destination(sam_addr, "TRANSIENT")
+	}
+
+	/// Create a new datagram session identified by the provided destination.
+	pub fn from_destination<A: ToSocketAddrs>(
+		sam_addr: A,
+		destination: &str,
+	) -> Result<DatagramSession, Error> {
+		let session = Session::create_auto(
+			sam_addr,
+			destination,
+			SessionStyle::Datagram,
+			&SessionOptions::default(),
+		)?;
+		Ok(DatagramSession { session, udp: None })
+	}
+
+	/// Create a new datagram session identified by the provided destination,
+	/// with the bridge forwarding received datagrams over UDP to a socket
+	/// bound to `local_addr` rather than to the control socket. Received
+	/// datagrams are read from the returned `DatagramReceiver`.
+	pub fn forwarded<A: ToSocketAddrs, B: ToSocketAddrs>(
+		sam_addr: A,
+		destination: &str,
+		local_addr: B,
+	) -> Result<(DatagramSession, DatagramReceiver), Error> {
+		let receiver = DatagramReceiver::bind(local_addr)?;
+		let options = SessionOptions::new().forward_to(receiver.local_addr()?);
+		let session = Self::with_options(sam_addr, destination, SessionStyle::Datagram, &options)?;
+		Ok((session, receiver))
+	}
+
+	/// Create a new datagram session identified by the provided destination,
+	/// using one of the datagram styles, such as `Datagram2` for datagrams
+	/// protected against replay, and passing the provided options along.
+	pub fn with_options<A: ToSocketAddrs>(
+		sam_addr: A,
+		destination: &str,
+		style: SessionStyle,
+		options: &SessionOptions,
+	) -> Result<DatagramSession, Error> {
+		if !style.is_datagram() {
+			return Err(ErrorKind::SAMInvalidMessage(format!(
+				"{} isn't a datagram style",
+				style.string()
+			))
+			.into());
+		}
+		let session = Session::create_auto(sam_addr, destination, style, options)?;
+		Ok(DatagramSession { session, udp: None })
+	}
+
+	/// Use an existing session, which must have been created with the datagram
+	/// style (such as a datagram subsession of a primary session).
+	pub fn with_session(session: &Session) -> Result<DatagramSession, Error> {
+		if !session.style.is_datagram() {
+			return Err(ErrorKind::SAMInvalidMessage(
+				"Datagrams require a DATAGRAM, DATAGRAM2 or DATAGRAM3 session".to_string(),
+			)
+			.into());
+		}
+		Ok(DatagramSession {
+			session: session.duplicate()?,
+			udp: None,
+		})
+	}
+
+	/// Sends a datagram to the provided destination and port, the
+	/// destination being a full base64 destination or any name the bridge is
+	/// able to resolve.
+	pub fn send_to(&mut self, buf: &[u8], dest: &str, port: u16) -> Result<usize, Error> {
+		match self.udp {
+			Some(ref udp) => udp.send(&self.session.nickname, dest, &[("TO_PORT", port)], buf),
+			None => self.session.sam.send_payload("DATAGRAM", dest, &[("TO_PORT", port)], buf),
+		}
+	}
+
+	/// Receives a single datagram, returning its size and the base64
+	/// destination and port of its sender. If the buffer is too small to hold
+	/// the datagram, the excess bytes are discarded.
+	///
+	/// With the `Datagram3` style the sender is identified by the base64
+	/// hash of its destination instead, which has to be looked up to reply.
+	pub fn recv_from(&mut self, buf: &mut [u8]) -> Result<(usize, (String, u16)), Error> {
+		let (n, opts) = self.session.sam.recv_payload(sam_datagram_received, buf)?;
+		let dest = opts.get("DESTINATION").ok_or_else(|| {
+			ErrorKind::SAMInvalidMessage("No destination in received datagram".to_string())
+		})?;
+		let port = opts.get("FROM_PORT").and_then(|p| p.parse().ok()).unwrap_or(0);
+		Ok((n, (dest.clone(), port)))
+	}
+
+	pub fn local_addr(&self) -> Result<String, Error> {
+		Ok(self.session.local_dest.clone())
+	}
+
+	/// The datagram style of the session.
+	pub fn style(&self) -> SessionStyle {
+		self.session.style
+	}
+
+	/// Resolves a name to its base64 destination. Names that can't be
+	/// resolved locally are looked up over a new connection to the bridge,
+	/// the session's own connection carrying datagrams.
+	pub fn lookup(&self, name: &str) -> Result<String, Error> {
+		match self.session.sam.resolve_locally(name) {
+			Some(res) => res,
+			None => self.session.connect_sam()?.resolve(name),
+		}
+	}
+
+	/// Fails receives that get no datagram within `timeout` with
+	/// `ErrorKind::SAMTimeout`, `None` blocking forever. Shared by all the
+	/// duplicates of the session.
+	pub fn set_read_timeout(&self, timeout: Option<Duration>) -> Result<(), Error> {
+		self.session.sam.conn.set_read_timeout(timeout).map_err(|e| e.into())
+	}
+
+	/// Fails sends over the control socket that can't complete within
+	/// `timeout`, `None` blocking forever.
+	pub fn set_write_timeout(&self, timeout: Option<Duration>) -> Result<(), Error> {
+		self.session.sam.conn.set_write_timeout(timeout).map_err(|e| e.into())
+	}
+
+	pub fn read_timeout(&self) -> Result<Option<Duration>, Error> {
+		self.session.sam.conn.read_timeout().map_err(|e| e.into())
+	}
+
+	pub fn write_timeout(&self) -> Result<Option<Duration>, Error> {
+		self.session.sam.conn.write_timeout().map_err(|e| e.into())
+	}
+
+	/// Shuts the control socket down, ending the session. Receives blocked
+	/// on the duplicates of the session fail right away.
+	pub fn shutdown(&self) -> Result<(), Error> {
+		self.session.sam.conn.shutdown(Shutdown::Both).map_err(|e| e.into())
+	}
+
+	/// Sends datagrams through the UDP port of the bridge from now on,
+	/// instead of the control socket. Usually `DEFAULT_UDP_API`.
+	pub fn send_via_udp<A: ToSocketAddrs>(&mut self, udp_addr: A) -> Result<(), Error> {
+		self.udp = Some(UdpSender::new(udp_addr)?);
+		Ok(())
+	}
+
+	pub fn duplicate(&self) -> Result<DatagramSession, Error> {
+		Ok(DatagramSession {
+			session: self.session.duplicate()?,
+			udp: self.udp.as_ref().map(|u| u.duplicate()).transpose()?,
+		})
+	}
+}
+
+/// Receives the datagrams a bridge forwards over UDP, for sessions created
+/// with `SessionOptions::forward_to`.
+pub struct DatagramReceiver {
+	socket: UdpSocket,
+}
+
+impl DatagramReceiver {
+	/// Binds the UDP socket datagrams are forwarded to. The bridge must be
+	/// able to reach the bound address.
+	pub fn bind<A: ToSocketAddrs>(addr: A) -> Result<DatagramReceiver, Error> {
+		Ok(DatagramReceiver {
+			socket: UdpSocket::bind(addr)?,
+		})
+	}
+
+	/// The address to give the bridge for forwarding, an unspecified bound IP
+	/// being replaced by the loopback address.
+	pub fn local_addr(&self) -> Result<SocketAddr, Error> {
+		forward_addr(&self.socket)
+	}
+
+	/// Receives a single forwarded repliable datagram, returning its size and
+	/// the base64 destination and port of its sender, or the base64 hash of
+	/// its destination for `Datagram3` sessions. If the buffer is too small
+	/// to hold the datagram, the excess bytes are discarded.
+	pub fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, (String, u16)), Error> {
+		let mut packet = vec![0; 65536];
+		let size = self.socket.recv(&mut packet)?;
+		let packet = &packet[..size];
+
+		let header_end = packet
+			.iter()
+			.position(|&b| b == b'\n')
+			.ok_or_else(|| ErrorKind::SAMInvalidMessage("No header in forwarded datagram".to_string()))?;
+		let header = std::str::from_utf8(&packet[..=header_end])
+			.map_err(|_| ErrorKind::MessageParsing.to_err())?;
+		let (dest, opts) = sam_dest_line(header)?.1;
+		let port = opts
+			.iter()
+			.find(|&&(k, _)| k == "FROM_PORT")
+			.and_then(|(_, v)| v.parse().ok())
+			.unwrap_or(0);
+
+		let payload = &packet[header_end + 1..];
+		let n = payload.len().min(buf.len());
+		buf[..n].copy_from_slice(&payload[..n]);
+		Ok((n, (dest.to_string(), port)))
+	}
+
+	pub fn duplicate(&self) -> Result<DatagramReceiver, Error> {
+		Ok(DatagramReceiver {
+			socket: self.socket.try_clone()?,
+		})
+	}
+}
+
+/// An anonymous (raw) datagram session. Raw datagrams are neither signed
+/// nor repliable, so received ones carry no sender destination.
+pub struct RawSession {
+	session: Session,
+	udp: Option<UdpSender>,
+}
+
+impl RawSession {
+	/// Create a new transient raw session.
+	pub fn new<A: ToSocketAddrs>(sam_addr: A) -> Result<RawSession, Error> {
+		Self::from_destination(sam_addr, "TRANSIENT")
+	}
+
+	/// Create a new raw session identified by the provided destination.
+	pub fn from_destination<A: ToSocketAddrs>(
+		sam_addr: A,
+		destination: &str,
+	) -> Result<RawSession, Error> {
+		Self::with_options(sam_addr, destination, &SessionOptions::default())
+	}
+
+	/// Create a new raw session identified by the provided destination,
+	/// passing the provided options along, such as the protocol numbers to
+	/// send and receive with (`SessionOptions::protocol`, `listen_protocol`),
+	/// letting several raw protocols share a destination.
+	pub fn with_options<A: ToSocketAddrs>(
+		sam_addr: A,
+		destination: &str,
+		options: &SessionOptions,
+	) -> Result<RawSession, Error> {
+		let session = Session::create_auto(sam_addr, destination, SessionStyle::Raw, options)?;
+		Ok(RawSession { session, udp: None })
+	}
+
+	/// Create a new raw session identified by the provided destination that
+	/// has the bridge forward received datagrams, along with their header,
+	/// to a UDP socket bound on `local_addr`, returned alongside.
+	pub fn forwarded<A: ToSocketAddrs, B: ToSocketAddrs>(
+		sam_addr: A,
+		destination: &str,
+		local_addr: B,
+	) -> Result<(RawSession, RawReceiver), Error> {
+		let receiver = RawReceiver::bind(local_addr)?;
+		let options = SessionOptions::new()
+			.forward_to(receiver.local_addr()?)
+			.header(true);
+		let raw = Self::with_options(sam_addr, destination, &options)?;
+		Ok((raw, receiver))
+	}
+
+	/// Use an existing session, which must have been created with the raw
+	/// style (such as a raw subsession of a primary session).
+	pub fn with_session(session: &Session) -> Result<RawSession, Error> {
+		if session.style != SessionStyle::Raw {
+			return Err(
+				ErrorKind::SAMInvalidMessage("Raw datagrams require a RAW session".to_string()).into(),
+			);
+		}
+		Ok(RawSession {
+			session: session.duplicate()?,
+			udp: None,
+		})
+	}
+
+	/// Sends a raw datagram to the provided destination and port, the
+	/// destination being a full base64 destination or any name the bridge is
+	/// able to resolve.
+	pub fn send_to(&mut self, buf: &[u8], dest: &str, port: u16) -> Result<usize, Error> {
+		self.send_to_protocol(buf, dest, port, 0)
+	}
+
+	/// Same as `send_to`, overriding the I2CP protocol number of the session
+	/// (see `SessionOptions::protocol`), protocol 0 keeping it (SAM 3.2+).
+	pub fn send_to_protocol(
+		&mut self,
+		buf: &[u8],
+		dest: &str,
+		port: u16,
+		protocol: u8,
+	) -> Result<usize, Error> {
+		let opts = [("TO_PORT", port), ("PROTOCOL", u16::from(protocol))];
+		match self.udp {
+			Some(ref udp) => udp.send(&self.session.nickname, dest, &opts, buf),
+			None => self.session.sam.send_payload("RAW", dest, &opts, buf),
+		}
+	}
+
+	/// Receives a single raw datagram, returning its size. If the buffer is
+	/// too small to hold the datagram, the excess bytes are discarded.
+	pub fn recv(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+		self.recv_with_header(buf).map(|(n, _)| n)
+	}
+
+	/// Same as `recv`, also returning the ports and protocol of the datagram
+	/// (SAM 3.2+).
+	pub fn recv_with_header(&mut self, buf: &mut [u8]) -> Result<(usize, RawHeader), Error> {
+		let (n, opts) = self.session.sam.recv_payload(sam_raw_received, buf)?;
+		let opts: Vec<(&str, &str)> = opts.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+		Ok((n, RawHeader::from_opts(&opts)?))
+	}
+
+	pub fn local_addr(&self) -> Result<String, Error> {
+		Ok(self.session.local_dest.clone())
+	}
+
+	/// Sends datagrams through the UDP port of the bridge from now on,
+	/// instead of the control socket. Usually `DEFAULT_UDP_API`.
+	pub fn send_via_udp<A: ToSocketAddrs>(&mut self, udp_addr: A) -> Result<(), Error> {
+		self.udp = Some(UdpSender::new(udp_addr)?);
+		Ok(())
+	}
+
+	pub fn duplicate(&self) -> Result<RawSession, Error> {
+		Ok(RawSession {
+			session: self.session.duplicate()?,
+			udp: self.udp.as_ref().map(|u| u.duplicate()).transpose()?,
+		})
+	}
+}
+
+/// Ports and protocol of a received raw datagram, 0 when unknown.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RawHeader {
+	pub from_port: u16,
+	pub to_port: u16,
+	pub protocol: u8,
+}
+
+impl RawHeader {
+	/// Reads the ports and protocol of a header, missing ones being 0 and
+	/// ones out of range failing with `SAMInvalidMessage`.
+	fn from_opts(opts: &[(&str, &str)]) -> Result<RawHeader, Error> {
+		fn value<T: std::str::FromStr + Default>(opts: &[(&str, &str)], key: &str) -> Result<T, Error> {
+			match opts.iter().find(|&&(k, _)| k == key) {
+				Some((_, v)) => v
+					.parse()
+					.map_err(|_| ErrorKind::SAMInvalidMessage(format!("Bad {}: {}", key, v)).into()),
+				None => Ok(T::default()),
+			}
+		}
+		Ok(RawHeader {
+			from_port: value(opts, "FROM_PORT")?,
+			to_port: value(opts, "TO_PORT")?,
+			protocol: value(opts, "PROTOCOL")?,
+		})
+	}
+}
+
+/// Receives the raw datagrams a raw session has the bridge forward over
+/// UDP, see `RawSession::forwarded`. The session must have been created
+/// with `SessionOptions::header(true)`.
+pub struct RawReceiver {
+	socket: UdpSocket,
+}
+
+impl RawReceiver {
+	/// Binds the UDP socket raw datagrams are forwarded to. The bridge must
+	/// be able to reach the bound address.
+	pub fn bind<A: ToSocketAddrs>(addr: A) -> Result<RawReceiver, Error> {
+		Ok(RawReceiver {
+			socket: UdpSocket::bind(addr)?,
+		})
+	}
+
+	/// The address to give the bridge for forwarding, an unspecified bound IP
+	/// being replaced by the loopback address.
+	pub fn local_addr(&self) -> Result<SocketAddr, Error> {
+		forward_addr(&self.socket)
+	}
+
+	/// Receives a single forwarded raw datagram, returning its size and
+	/// header. If the buffer is too small to hold the datagram, the excess
+	/// bytes are discarded.
+	pub fn recv(&self, buf: &mut [u8]) -> Result<(usize, RawHeader), Error> {
+		let mut packet = vec![0; 65536];
+		let size = self.socket.recv(&mut packet)?;
+		let packet = &packet[..size];
+
+		let header_end = packet
+			.iter()
+			.position(|&b| b == b'\n')
+			.ok_or_else(|| ErrorKind::SAMInvalidMessage("No header in forwarded datagram".to_string()))?;
+		let header = std::str::from_utf8(&packet[..=header_end])
+			.map_err(|_| ErrorKind::MessageParsing.to_err())?;
+		let header = RawHeader::from_opts(&sam_raw_header(header)?.1)?;
+
+		let payload = &packet[header_end + 1..];
+		let n = payload.len().min(buf.len());
+		buf[..n].copy_from_slice(&payload[..n]);
+		Ok((n, header))
+	}
+
+	pub fn duplicate(&self) -> Result<RawReceiver, Error> {
+		Ok(RawReceiver {
+			socket: self.socket.try_clone()?,
+		})
+	}
+}
+
+/// Rejects names no bridge can resolve: empty, breaking the command line, or malformed .i2p names.
+fn check_lookup_name(name: &str) -> Result<(), Error> {
+	let breaks_line = |c: char| c.is_whitespace() || c.is_control() || c == '=' || c == '"';
+	let lower = name.to_ascii_lowercase();
+	let malformed = if is_b32(&lower) {
+		I2pAddr::from_b32(&lower).is_err()
+	} else {
+		lower.ends_with(".i2p") && !is_valid_hostname(&lower)
+	};
+	if name.is_empty() || name.chars().any(breaks_line) || malformed {
+		return Err(ErrorKind::BadAddressEncoding(name.to_string()).into());
+	}
+	Ok(())
+}
+
+/// The local address of a socket datagrams are forwarded to, an unspecified
+/// bound IP being replaced by the loopback address.
+fn forward_addr(socket: &UdpSocket) -> Result<SocketAddr, Error> {
+	let mut addr = socket.local_addr()?;
+	if addr.ip().is_unspecified() {
+		addr.set_ip(IpAddr::V4(Ipv4Addr::LOCALHOST));
+	}
+	Ok(addr)
+}
+
+/// Appends the options of a datagram to its command or header, ports and
+/// protocols only being understood by SAM 3.2+ bridges.
+fn push_payload_opts(msg: &mut String, opts: &[(&str, u16)]) {
+	for &(key, value) in opts {
+		if value > 0 {
+			msg.push_str(&format!(" {}={}", key, value));
+		}
+	}
+}
+
+fn is_timeout(e: &io::Error) -> bool {
+	e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut
+}
+
+fn connect_error(e: io::Error) -> Error {
+	if is_timeout(&e) {
+		ErrorKind::SAMTimeout("Couldn't connect to the SAM bridge in time".to_string()).into()
+	} else {
+		e.into()
+	}
+}
+
+/// Whether a `major.minor` SAM version is at least `min`.
+fn version_at_least(version: &str, min: &str) -> bool {
+	let parse = |v: &str| -> (u32, u32) {
+		let mut parts = v.split('.').map(|p| p.parse().unwrap_or(0));
+		(parts.next().unwrap_or(0), parts.next().unwrap_or(0))
+	};
+	parse(version) >= parse(min)
+}
+
+pub(crate) fn nickname() -> String {
+	let suffix: String = rand::thread_rng()
+		.sample_iter(&Alphanumeric)
+		.take(8)
+		.collect();
+	format!("i2prs-{}", suffix)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+	use std::io::{BufRead, BufReader, Write};
+	use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, UdpSocket};
+	use std::sync::mpsc;
+	use std::thread;
+	use std::time::Duration;
+
+	use crate::error::ErrorKind;
+
+	/// Starts a bridge answering HELLO, SESSION CREATE, NAMING LOOKUP and
+	/// STREAM CONNECT with success. Streams get `data` first, then have
+	/// everything written to them echoed back.
+	pub(crate) fn fake_bridge(data: &'static [u8]) -> SocketAddr {
+		scripted_bridge(vec![vec![Step::Answer, Step::Write(data.to_vec()), Step::Echo]]).0
+	}
+
+	/// Same as `fake_bridge`, but streams read an HTTP request, up to its
+	/// empty line, before getting `data` and being closed.
+	pub(crate) fn fake_http_bridge(data: &'static [u8]) -> SocketAddr {
+		scripted_bridge(vec![vec![
+			Step::Answer,
+			Step::ReadUntil("\r\n"),
+			Step::Write(data.to_vec()),
+			Step::Close,
+		]])
+		.0
+	}
+
+	/// What a connection to `scripted_bridge` does, in order.
+	#[derive(Clone, Debug)]
+	pub(crate) enum Step {
+		/// Reads a line and writes its reply, which may span several lines
+		Reply(String),
+		/// Reads the provided number of lines without replying
+		Read(usize),
+		/// Reads lines until one is the provided one
+		ReadUntil(&'static str),
+		/// Writes without reading anything
+		Write(Vec<u8>),
+		/// Answers commands with success until a STREAM one, or the end
+		Answer,
+		/// Echoes everything back until the end
+		Echo,
+		/// Closes the connection, reading what's left so that nothing
+		/// already sent gets lost
+		Close,
+	}
+
+	/// A connection to `scripted_bridge` once its script is over, with the
+	/// lines it got and a reader of what's left.
+	pub(crate) struct Played {
+		pub(crate) lines: Vec<String>,
+		pub(crate) stream: TcpStream,
+		pub(crate) reader: BufReader<TcpStream>,
+	}
+
+	pub(crate) fn reply(line: &str) -> Step {
+		Step::Reply(line.to_string())
+	}
+
+	/// The script of the control connection of a session, on a bridge
+	/// speaking SAM `version`, whether the commands are pipelined or not.
+	pub(crate) fn session_script(version: &str) -> Vec<Step> {
+		vec![
+			reply(&format!("HELLO REPLY RESULT=OK VERSION={}\n", version)),
+			reply("SESSION STATUS RESULT=OK DESTINATION=priv\n"),
+			reply("NAMING REPLY RESULT=OK NAME=ME VALUE=dest\n"),
+		]
+	}
+
+	/// Starts a bridge playing a script on each connection it accepts, in
+	/// order, the last one being played on all the connections that follow.
+	/// The connections are sent over the returned channel as their script
+	/// ends, and stay open until dropped.
+	pub(crate) fn scripted_bridge(scripts: Vec<Vec<Step>>) -> (SocketAddr, mpsc::Receiver<Played>) {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+		let (played, receiver) = mpsc::channel();
+		thread::spawn(move || {
+			for (i, stream) in listener.incoming().enumerate() {
+				let script = scripts[i.min(scripts.len() - 1)].clone();
+				let played = played.clone();
+				let stream = stream.unwrap();
+				thread::spawn(move || {
+					let _ = played.send(play(stream, script));
+				});
+			}
+		});
+		(addr, receiver)
+	}
+
+	fn play(mut stream: TcpStream, script: Vec<Step>) -> Played {
+		let mut reader = BufReader::new(stream.try_clone().unwrap());
+		let mut lines = vec![];
+		let mut read_line = |reader: &mut BufReader<TcpStream>| {
+			let mut line = String::new();
+			match reader.read_line(&mut line) {
+				Ok(n) if n > 0 => {
+					lines.push(line.clone());
+					Some(line)
+				}
+				_ => None,
+			}
+		};
+		for step in script {
+			match step {
+				Step::Reply(reply) => {
+					if read_line(&mut reader).is_none() {
+						break;
+					}
+					let _ = stream.write_all(reply.as_bytes());
+				}
+				Step::Read(count) => {
+					for _ in 0..count {
+						if read_line(&mut reader).is_none() {
+							break;
+						}
+					}
+				}
+				Step::ReadUntil(last) => while read_line(&mut reader).is_some_and(|line| line != last) {},
+				Step::Write(data) => {
+					let _ = stream.write_all(&data);
+				}
+				Step::Answer => {
+					while let Some(line) = read_line(&mut reader) {
+						let reply = match line.split(' ').next() {
+							Some("HELLO") => "HELLO REPLY RESULT=OK VERSION=3.2\n",
+							Some("SESSION") => "SESSION STATUS RESULT=OK DESTINATION=priv\n",
+							Some("NAMING") => "NAMING REPLY RESULT=OK NAME=ME VALUE=dest\n",
+							Some("STREAM") => "STREAM STATUS RESULT=OK\n",
+							_ => "",
+						};
+						let _ = stream.write_all(reply.as_bytes());
+						if line.starts_with("STREAM") {
+							break;
+						}
+					}
+				}
+				Step::Echo => {
+					let _ = std::io::copy(&mut reader, &mut stream);
+				}
+				Step::Close => {
+					let _ = stream.shutdown(Shutdown::Write);
+					let mut drained = reader.get_ref().try_clone().unwrap();
+					thread::spawn(move || std::io::copy(&mut drained, &mut std::io::sink()));
+				}
+			}
+		}
+		Played { lines, stream, reader }
+	}
+
+	#[test]
+	fn check_result() {
+		use crate::protocol::check_result;
+
+		assert!(check_result(Some("OK"), "").is_ok());
+		assert!(check_result(None, "").is_ok());
+
+		let err = |res| check_result(Some(res), "failed").unwrap_err().kind();
+		assert_eq!(err("DUPLICATED_ID"), ErrorKind::SAMDuplicatedId("failed".to_string()));
+		assert_eq!(err("CANT_REACH_PEER"), ErrorKind::SAMCantReachPeer("failed".to_string()));
+		assert_eq!(err("TIMEOUT"), ErrorKind::SAMTimeout("failed".to_string()));
+		assert_eq!(err("NOVERSION"), ErrorKind::SAMNoVersion("failed".to_string()));
+		assert_eq!(err("WEIRD"), ErrorKind::SAMInvalidMessage("WEIRD failed".to_string()));
+	}
+
+	#[test]
+	fn connect_failures() {
+		use crate::error::ConnectFailure;
+		use crate::protocol::check_result;
+
+		let failure = |res, msg| check_result(Some(res), msg).unwrap_err().kind().connect_failure();
+		assert_eq!(failure("CANT_REACH_PEER", "Connection was reset"), Some(ConnectFailure::PeerRefused));
+		assert_eq!(failure("CANT_REACH_PEER", "No leaseset found"), Some(ConnectFailure::PeerNotFound));
+		assert_eq!(failure("KEY_NOT_FOUND", "peer.i2p"), Some(ConnectFailure::PeerNotFound));
+		assert_eq!(failure("I2P_ERROR", "Tunnels not ready"), Some(ConnectFailure::TunnelsNotReady));
+		assert_eq!(failure("I2P_ERROR", "Session closed"), Some(ConnectFailure::Bridge));
+		assert_eq!(failure("TIMEOUT", ""), Some(ConnectFailure::Timeout));
+		assert_eq!(failure("INVALID_KEY", ""), Some(ConnectFailure::InvalidPeer));
+		assert_eq!(failure("DUPLICATED_ID", ""), None);
+		assert!(ConnectFailure::TunnelsNotReady.is_transient());
+		assert!(!ConnectFailure::PeerRefused.is_transient());
+	}
+
+	#[test]
+	fn lookup_failures() {
+		use crate::error::LookupFailure;
+		use crate::protocol::check_result;
+		use crate::sam::check_lookup_name;
+
+		let failure = |res| check_result(Some(res), "").unwrap_err().kind().lookup_failure();
+		assert_eq!(failure("KEY_NOT_FOUND"), Some(LookupFailure::NotFound));
+		assert_eq!(failure("INVALID_KEY"), Some(LookupFailure::InvalidName));
+		assert_eq!(failure("I2P_ERROR"), Some(LookupFailure::Bridge));
+		let bad = ["", "bad..i2p", "BAD..I2P", "-bad.i2p", "a b.i2p", "NAME=x.i2p", "bad\n.i2p", "a.b32.i2p"];
+		for name in &bad {
+			let kind = check_lookup_name(name).unwrap_err().kind();
+			assert_eq!(kind.lookup_failure(), Some(LookupFailure::InvalidName));
+		}
+		let b32 = "UDHDRTRCETJM5SXZSKJYR5ZTPESZYDBH4DPL3PL4UTGQQW2V4JNA.b32.i2p";
+		for name in &["ME", "example.i2p", "Sub.Example.I2P", "myhost", b32] {
+			assert!(check_lookup_name(name).is_ok());
+		}
+	}
+
+	#[test]
+	fn version_at_least() {
+		use crate::sam::version_at_least;
+
+		assert!(version_at_least("3.2", "3.2"));
+		assert!(version_at_least("3.10", "3.2"));
+		assert!(!version_at_least("3.1", "3.2"));
+	}
+
+	#[test]
+	fn capabilities() {
+		use crate::sam::SamCapabilities;
+
+		let caps = SamCapabilities::from_version("3.2");
+		assert!(caps.ports && caps.auth && caps.ping);
+		assert!(!caps.primary_sessions);
+		assert!(!SamCapabilities::from_version("3.0").signature_types);
+		assert!(SamCapabilities::from_version("3.3").primary_sessions);
+		assert!(!SamCapabilities::from_version("3.2").datagram2);
+		assert!(SamCapabilities::from_version("3.3").datagram2);
+	}
+
+	#[test]
+	fn command_timeout() {
+		use crate::sam::SamConnection;
+
+		// HELLO never gets a reply
+		let (addr, _played) = scripted_bridge(vec![vec![Step::Read(1)]]);
+		let err = SamConnection::connect_timeout(addr, Duration::from_millis(50))
+			.err()
+			.unwrap();
+		assert_eq!(err.kind(), ErrorKind::SAMTimeout("No reply from the SAM bridge".to_string()));
+
+		// the read timeout of the socket is left as it was
+		let (addr, _played) = scripted_bridge(vec![vec![
+			reply("HELLO REPLY RESULT=OK VERSION=3.1\n"),
+			reply("NAMING REPLY RESULT=OK NAME=a.i2p VALUE=dest\n"),
+		]]);
+		let mut sam = SamConnection::connect_timeout(addr, Duration::from_secs(5)).unwrap();
+		sam.conn.set_read_timeout(Some(Duration::from_secs(60))).unwrap();
+		assert_eq!(sam.naming_lookup("a.i2p").unwrap(), "dest");
+		assert_eq!(sam.conn.read_timeout().unwrap(), Some(Duration::from_secs(60)));
+	}
+
+	#[test]
+	fn lookup_many() {
+		use crate::naming::NamingCache;
+		use crate::sam::SamConnection;
+
+		let (addr, played) = scripted_bridge(vec![vec![
+			reply("HELLO REPLY RESULT=OK VERSION=3.1\n"),
+			// both lookups arrive before any reply is sent
+			Step::Read(2),
+			Step::Write(
+				b"NAMING REPLY RESULT=OK NAME=a.i2p VALUE=dest-a\n\
+				NAMING REPLY RESULT=KEY_NOT_FOUND NAME=b.i2p\n"
+					.to_vec(),
+			),
+			Step::Read(1),
+		]]);
+
+		let cache = NamingCache::new(Duration::from_secs(60), 16);
+		let mut sam = SamConnection::connect(addr).unwrap().with_naming_cache(cache.clone());
+		let dest = "A".repeat(516);
+		let results = sam.lookup_many(&["a.i2p", &dest, "b.i2p"]).unwrap();
+		assert_eq!(results[0].as_ref().unwrap(), "dest-a");
+		assert_eq!(results[1].as_ref().unwrap(), &dest);
+		assert_eq!(results[2].as_ref().unwrap_err().kind(), ErrorKind::SAMKeyNotFound("".to_string()));
+		assert_eq!(cache.get("a.i2p"), Some("dest-a".to_string()));
+		let results = sam.lookup_many(&["a.i2p"]).unwrap();
+		assert_eq!(results[0].as_ref().unwrap(), "dest-a");
+		drop(sam);
+
+		// nothing left to look up from the cache
+		let lines = played.recv().unwrap().lines;
+		assert_eq!(lines[1..], ["NAMING LOOKUP NAME=a.i2p \n", "NAMING LOOKUP NAME=b.i2p \n"]);
+	}
+
+	#[test]
+	fn command_replies() {
+		use crate::sam::SamConnection;
+
+		let (addr, played) = scripted_bridge(vec![vec![
+			reply("HELLO REPLY RESULT=OK VERSION=3.2\n"),
+			// the bridge checks on us before replying
+			reply("PING hi\nNAMING REPLY RESULT=OK NAME=a.i2p VALUE=dest-a\n"),
+			Step::Read(1),
+			reply("SESSION STATUS RESULT=OK\n"),
+		]]);
+
+		let mut sam = SamConnection::connect(addr).unwrap();
+		assert_eq!(sam.lookup("a.i2p").unwrap().value, "dest-a");
+		// not the reply to the command
+		assert_eq!(sam.dest_generate(None).unwrap_err().kind(), ErrorKind::MessageParsing);
+		let lines = played.recv().unwrap().lines;
+		assert_eq!(lines[2..], ["PONG hi\n", "DEST GENERATE \n"]);
+	}
+
+	#[test]
+	fn lookup_many_failure() {
+		use crate::sam::SamConnection;
+
+		let (addr, played) = scripted_bridge(vec![vec![
+			reply("HELLO REPLY RESULT=OK VERSION=3.1\n"),
+			// the reply to the last lookup never comes
+			Step::Read(3),
+			Step::Write(b"NAMING REPLY RESULT=OK NAME=a.i2p VALUE=dest-a\n\xff\n".to_vec()),
+			Step::Read(1),
+		]]);
+
+		let mut sam = SamConnection::connect(addr).unwrap();
+		sam.set_command_timeout(Some(Duration::from_millis(200)));
+		match sam.lookup_many(&["a.i2p", "b.i2p", "c.i2p"]).err().map(|e| e.kind()) {
+			Some(ErrorKind::SAMTimeout(_)) => {}
+			kind => panic!("unexpected result: {:?}", kind),
+		}
+		// closed rather than left with a reply due
+		let lines = played.recv_timeout(Duration::from_secs(5)).unwrap().lines;
+		assert_eq!(lines.len(), 4);
+		assert!(sam.naming_lookup("d.i2p").is_err());
+
+		// an unreadable reply only fails its own name
+		let (addr, _played) = scripted_bridge(vec![vec![
+			reply("HELLO REPLY RESULT=OK VERSION=3.1\n"),
+			Step::Read(2),
+			Step::Write(b"\xff\nNAMING REPLY RESULT=OK NAME=b.i2p VALUE=dest-b\n".to_vec()),
+			Step::Read(1),
+		]]);
+		let mut sam = SamConnection::connect(addr).unwrap();
+		let results = sam.lookup_many(&["a.i2p", "b.i2p"]).unwrap();
+		assert_eq!(results[0].as_ref().unwrap_err().kind(), ErrorKind::MessageParsing);
+		assert_eq!(results[1].as_ref().unwrap(), "dest-b");
+	}
+
+	#[test]
+	fn pipelined_session() {
+		use crate::sam::{Session, SessionStyle};
+		use crate::sam_options::SessionOptions;
+
+		// all commands arrive before any reply is sent
+		let (addr, played) = scripted_bridge(vec![vec![
+			Step::Read(3),
+			Step::Write(
+				b"HELLO REPLY RESULT=OK VERSION=3.1\n\
+				SESSION STATUS RESULT=OK DESTINATION=priv\n\
+				NAMING REPLY RESULT=OK NAME=ME VALUE=dest\n"
+					.to_vec(),
+			),
+		]]);
+		let options = SessionOptions::new().pipelining(true);
+		let session =
+			Session::create_with_options(addr, "TRANSIENT", "piped", SessionStyle::Stream, &options)
+				.unwrap();
+		let lines = played.recv().unwrap().lines;
+		assert!(lines[0].starts_with("HELLO VERSION"));
+		assert!(lines[1].starts_with("SESSION CREATE STYLE=STREAM"));
+		assert!(lines[2].starts_with("NAMING LOOKUP NAME=ME"));
+		assert_eq!(session.my_destination(), "dest");
+		assert_eq!(session.last_reply()["NAME"], "ME");
+		assert_eq!(session.style(), SessionStyle::Stream);
+
+		// the bridge closing the connection right after a failure
+		let (addr, _played) = scripted_bridge(vec![vec![
+			Step::Read(3),
+			Step::Write(b"HELLO REPLY RESULT=OK VERSION=3.1\nSESSION STATUS RESULT=DUPLICATED_ID\n".to_vec()),
+			Step::Close,
+		]]);
+		let err =
+			Session::create_with_options(addr, "TRANSIENT", "piped", SessionStyle::Stream, &options)
+				.err()
+				.unwrap();
+		assert_eq!(err.kind(), ErrorKind::SAMDuplicatedId("".to_string()));
+	}
+
+	#[test]
+	fn primary_version() {
+		use crate::sam::Session;
+
+		let (addr, played) =
+			scripted_bridge(vec![vec![reply("HELLO REPLY RESULT=OK VERSION=3.2\n"), Step::Read(1)]]);
+		let err = Session::primary(addr, "TRANSIENT").err().unwrap();
+		assert_eq!(err.kind(), ErrorKind::SAMUnsupported("Primary sessions with SAM 3.2".to_string()));
+		// the connection is closed without creating the session
+		assert_eq!(played.recv().unwrap().lines[1..], ["QUIT\n"]);
+	}
+
+	#[test]
+	fn duplicated_id_retry() {
+		use crate::sam::{Session, SessionStyle};
+		use crate::sam_options::SessionOptions;
+
+		let (addr, played) = scripted_bridge(vec![
+			vec![
+				reply("HELLO REPLY RESULT=OK VERSION=3.1\n"),
+				reply("SESSION STATUS RESULT=DUPLICATED_ID\n"),
+			],
+			session_script("3.1"),
+		]);
+
+		let session =
+			Session::create_auto(addr, "TRANSIENT", SessionStyle::Stream, &SessionOptions::new())
+				.unwrap();
+		let ids: Vec<String> = played
+			.iter()
+			.take(2)
+			.map(|create| create.lines[1].split(' ').nth(3).unwrap().to_string())
+			.collect();
+		assert_ne!(ids[0], ids[1]);
+		assert_eq!(format!("ID={}", session.id()), ids[1]);
+	}
+
+	#[test]
+	fn quit_on_drop() {
+		use crate::sam::SamConnection;
+
+		let (addr, played) =
+			scripted_bridge(vec![vec![reply("HELLO REPLY RESULT=OK VERSION=3.3\n"), Step::Read(2)]]);
+
+		let sam = SamConnection::connect(addr).unwrap();
+		let dup = sam.duplicate().unwrap();
+		drop(sam);
+		dup.close().unwrap();
+		// sent once, for both handles
+		assert_eq!(played.recv().unwrap().lines[1..], ["QUIT\n"]);
+	}
+
+	#[test]
+	fn udp_sender() {
+		use crate::sam::UdpSender;
+
+		let bridge = UdpSocket::bind("127.0.0.1:0").unwrap();
+		let sender = UdpSender::new(bridge.local_addr().unwrap()).unwrap();
+		sender
+			.send("nick", "dest", &[("TO_PORT", 80), ("PROTOCOL", 0)], b"hi")
+			.unwrap();
+		sender.send("nick", "dest", &[("PROTOCOL", 19)], b"hi").unwrap();
+
+		let mut buf = [0; 64];
+		let n = bridge.recv(&mut buf).unwrap();
+		assert_eq!(&buf[..n], b"3.0 nick dest TO_PORT=80\nhi");
+		let n = bridge.recv(&mut buf).unwrap();
+		assert_eq!(&buf[..n], b"3.0 nick dest PROTOCOL=19\nhi");
+	}
+
+	#[test]
+	fn raw_receiver() {
+		use crate::sam::{RawHeader, RawReceiver};
+
+		let receiver = RawReceiver::bind("0.0.0.0:0").unwrap();
+		assert!(receiver.local_addr().unwrap().ip().is_loopback());
+		let bridge = UdpSocket::bind("127.0.0.1:0").unwrap();
+		bridge
+			.send_to(b"FROM_PORT=1 TO_PORT=2 PROTOCOL=18\nhello", receiver.local_addr().unwrap())
+			.unwrap();
+
+		let mut buf = [0; 16];
+		let (n, header) = receiver.recv(&mut buf).unwrap();
+		assert_eq!(&buf[..n], b"hello");
+		assert_eq!(
+			header,
+			RawHeader {
+				from_port: 1,
+				to_port: 2,
+				protocol: 18
+			}
+		);
+
+		// protocols don't go past 255
+		bridge
+			.send_to(b"FROM_PORT=1 TO_PORT=2 PROTOCOL=274\nhello", receiver.local_addr().unwrap())
+			.unwrap();
+		let err = receiver.recv(&mut buf).err().unwrap();
+		assert_eq!(err.kind(), ErrorKind::SAMInvalidMessage("Bad PROTOCOL: 274".to_string()));
+	}
+
+	#[test]
+	fn keepalive() {
+		use crate::sam::SamConnection;
+
+		// the PINGs get their own connection, the bridge going away on the second one
+		let (addr, played) = scripted_bridge(vec![
+			vec![
+				reply("HELLO REPLY RESULT=OK VERSION=3.2\n"),
+				reply("NAMING REPLY RESULT=OK NAME=a.i2p VALUE=dest-a\n"),
+			],
+			vec![reply("HELLO REPLY RESULT=OK VERSION=3.2\n"), reply("PONG keepalive-1\n"), Step::Close],
+		]);
+
+		let mut sam = SamConnection::connect(addr).unwrap();
+		let (failures, failed) = mpsc::channel();
+		let _keepalive = sam
+			.keepalive(Duration::from_millis(20), move |e| {
+				let _ = failures.send(e);
+			})
+			.unwrap();
+		assert_eq!(sam.naming_lookup("a.i2p").unwrap(), "dest-a");
+		failed.recv_timeout(Duration::from_secs(5)).unwrap();
+
+		let mut lines: Vec<_> = played.iter().take(2).map(|played| played.lines).collect();
+		lines.sort();
+		assert_eq!(lines[0][1], "NAMING LOOKUP NAME=a.i2p \n");
+		assert_eq!(lines[1][1], "PING keepalive-1\n");
+	}
+
+	#[test]
+	fn watch_reestablish() {
+		use crate::sam::{ReconnectPolicy, Session, SessionEvent};
+		use std::sync::mpsc;
+
+		// the bridge goes away right after the session is created, closing
+		// the connection it gets pinged on
+		let hello = reply("HELLO REPLY RESULT=OK VERSION=3.2\n");
+		let (addr, played) = scripted_bridge(vec![
+			session_script("3.2"),
+			vec![hello.clone(), Step::Close],
+			session_script("3.2"),
+			vec![hello],
+		]);
+
+		let session = Session::transient(addr).unwrap();
+		let (events, received) = mpsc::channel();
+		let _watch = session
+			.watch(Duration::from_millis(20), ReconnectPolicy::new(), move |event| {
+				let _ = events.send(event);
+			})
+			.unwrap();
+		match received.recv().unwrap() {
+			SessionEvent::Disconnected(_) => {}
+			event => panic!("unexpected event: {:?}", event),
+		}
+		match received.recv().unwrap() {
+			SessionEvent::Reestablished { attempts } => assert_eq!(attempts, 1),
+			event => panic!("unexpected event: {:?}", event),
+		}
+		// the second session created is the re-established one
+		let create = played
+			.iter()
+			.filter_map(|played| played.lines.get(1).cloned())
+			.filter(|line| line.starts_with("SESSION CREATE"))
+			.nth(1)
+			.unwrap();
+		assert!(create.contains(&format!("ID={} ", session.id())));
+		assert!(create.contains("DESTINATION=priv "));
+	}
+
+	#[test]
+	fn discover() {
+		use crate::sam::{SamConnection, SamEndpoint, SAM_ADDRESS_VAR};
+
+		let endpoint: SamEndpoint = " 127.0.0.1:7656 ".parse().unwrap();
+		assert_eq!(endpoint, SamEndpoint::Tcp("127.0.0.1:7656".parse().unwrap()));
+		#[cfg(unix)]
+		assert_eq!(
+			"unix:/run/sam.sock".parse::<SamEndpoint>().unwrap(),
+			SamEndpoint::Unix("/run/sam.sock".into())
+		);
+		assert!("nowhere".parse::<SamEndpoint>().is_err());
+
+		let (addr, _played) = scripted_bridge(vec![vec![reply("HELLO REPLY RESULT=OK VERSION=3.1\n")]]);
+		std::env::set_var(SAM_ADDRESS_VAR, addr.to_string());
+		let sam = SamConnection::discover().unwrap();
+		std::env::remove_var(SAM_ADDRESS_VAR);
+		assert_eq!(sam.endpoint().unwrap(), SamEndpoint::Tcp(addr));
+		assert_eq!(sam.version(), "3.1");
+	}
+
+	#[test]
+	fn session_config() {
+		use crate::sam::{Session, SessionConfig, SessionStyle};
+		use crate::sam_options::SessionOptions;
+
+		// a fake bridge creating a session, which gets the SESSION CREATE line
+		let bridge = || {
+			scripted_bridge(vec![vec![
+				reply("HELLO REPLY RESULT=OK VERSION=3.1\n"),
+				reply("SESSION STATUS RESULT=OK DESTINATION=priv==\n"),
+				reply("NAMING REPLY RESULT=OK NAME=ME VALUE=dest\n"),
+			]])
+		};
+
+		let (addr, created) = bridge();
+		let options = SessionOptions::new().from_port(8080).option("inbound.nickname", "my service");
+		let session =
+			Session::create_auto(addr, "TRANSIENT", SessionStyle::Stream, &options).unwrap();
+		created.recv().unwrap();
+		let config = session.to_config().unwrap();
+		assert_eq!(config.private_key(), "priv==");
+
+		let saved = config.to_string();
+		assert!(!format!("{:?}", config).contains("priv=="));
+		let config: SessionConfig = saved.parse().unwrap();
+		assert_eq!(config.style(), SessionStyle::Stream);
+		assert_eq!(config.options(), &options.to_options()[..]);
+
+		let (addr, created) = bridge();
+		Session::restore(addr, &config).unwrap();
+		let create = &created.recv().unwrap().lines[1];
+		assert!(create.contains(" DESTINATION=priv== "));
+		assert!(create.contains(" FROM_PORT=8080 inbound.nickname=\"my service\" "));
+	}
+
+	#[test]
+	fn stream_timeouts() {
+		use crate::sam::{Session, StreamConnect};
+		use std::io::Read;
+
+		let session = Session::transient(fake_bridge(b"")).unwrap();
+		let mut stream = StreamConnect::with_session(&session, "peer.i2p", 80).unwrap();
+		assert_eq!(stream.read_timeout().unwrap(), None);
+		stream.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+		stream.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
+		assert_eq!(stream.read_timeout().unwrap(), Some(Duration::from_secs(2)));
+		assert_eq!(stream.write_timeout().unwrap(), Some(Duration::from_secs(5)));
+
+		stream.set_read_timeout(Some(Duration::from_millis(50))).unwrap();
+
+		let err = stream.read(&mut [0; 16]).unwrap_err();
+		assert!(err.kind() == std::io::ErrorKind::WouldBlock || err.kind() == std::io::ErrorKind::TimedOut);
+		assert!(stream.set_read_timeout(Some(Duration::from_secs(0))).is_err());
+	}
+
+	#[test]
+	fn stream_duplicate() {
+		use crate::sam::{Session, StreamConnect};
+		use std::io::Read;
+
+		let session = Session::transient(fake_bridge(b"")).unwrap();
+		let mut stream = StreamConnect::with_session(&session, "peer.i2p", 80).unwrap();
+		let mut reader = stream.duplicate().unwrap();
+		// the read is pending while the other handle writes
+		let read = thread::spawn(move || {
+			let mut buf = [0; 5];
+			reader.read_exact(&mut buf).unwrap();
+			buf
+		});
+		thread::sleep(Duration::from_millis(20));
+		stream.write_all(b"hello").unwrap();
+		assert_eq!(&read.join().unwrap(), b"hello");
+	}
+
+	#[test]
+	fn stream_half_close() {
+		use crate::sam::{Session, StreamConnect};
+		use std::io::Read;
+		use std::net::Shutdown;
+
+		let session = Session::transient(fake_bridge(b"")).unwrap();
+		let mut stream = StreamConnect::with_session(&session, "peer.i2p", 80).unwrap();
+		stream.write_all(b"request").unwrap();
+		stream.shutdown(Shutdown::Write).unwrap();
+		// the peer sees the end of the request and can still answer
+		let mut reply = vec![];
+		stream.read_to_end(&mut reply).unwrap();
+		assert_eq!(reply, b"request");
+	}
+
+	#[test]
+	fn stream_peek() {
+		use crate::sam::{Session, StreamConnect};
+		use std::io::Read;
+
+		let session = Session::transient(fake_bridge(b"\x16\x03\x01 tls")).unwrap();
+		let mut stream = StreamConnect::with_session(&session, "peer.i2p", 80).unwrap();
+		let mut first = [0; 1];
+		assert_eq!(stream.peek(&mut first).unwrap(), 1);
+		assert_eq!(first, [0x16]);
+		// peeked data is read again, by any handle
+		let mut buf = [0; 3];
+		stream.duplicate().unwrap().read_exact(&mut buf).unwrap();
+		assert_eq!(&buf, b"\x16\x03\x01");
+		let mut buf = [0; 4];
+		stream.read_exact(&mut buf).unwrap();
+		assert_eq!(&buf, b" tls");
+	}
+
+	#[test]
+	fn accept_pool() {
+		use crate::sam::{Session, StreamForward};
+
+		let (addr, played) = scripted_bridge(vec![
+			session_script("3.2"),
+			vec![reply("HELLO REPLY RESULT=OK VERSION=3.2\n"), reply("STREAM STATUS RESULT=OK\n")],
+		]);
+		let session = Session::transient(addr).unwrap();
+		let _control = played.recv().unwrap();
+		let pool = StreamForward::with_session(&session).unwrap().accept_pool(2).unwrap();
+		assert_eq!(pool.size(), 2);
+		// both accepts are pending before any peer connects
+		let mut pending: Vec<Played> = played.iter().take(2).collect();
+		assert!(pending.iter().all(|accept| accept.lines[1].starts_with("STREAM ACCEPT")));
+		pending[0].stream.write_all(b"AAAA FROM_PORT=1 TO_PORT=80\n").unwrap();
+		pending[1].stream.write_all(b"AAAA FROM_PORT=2 TO_PORT=80\n").unwrap();
+
+		let mut ports = vec![pool.accept().unwrap().1.port(), pool.accept().unwrap().1.port()];
+		ports.sort();
+		assert_eq!(ports, vec![1, 2]);
+	}
+
+	#[test]
+	fn accept_pool_failure() {
+		use crate::sam::{Session, StreamForward};
+
+		let (addr, _played) = scripted_bridge(vec![
+			session_script("3.2"),
+			vec![
+				reply("HELLO REPLY RESULT=OK VERSION=3.2\n"),
+				reply("STREAM STATUS RESULT=INVALID_ID\n"),
+			],
+		]);
+		let session = Session::transient(addr).unwrap();
+		let pool = StreamForward::with_session(&session).unwrap().accept_pool(1).unwrap();
+		let err = pool.accept().err().unwrap();
+		assert_eq!(err.kind(), ErrorKind::SAMInvalidId("".to_string()));
+		// the failed socket isn't reopened
+		let err = pool.accept().err().unwrap();
+		assert_eq!(err.kind(), ErrorKind::Io("Accept pool stopped".to_string()));
+	}
+
+	#[test]
+	fn port_listeners() {
+		use crate::sam::{Session, StreamForward};
+
+		let mut script = session_script("3.3");
+		script.push(reply("SESSION STATUS RESULT=OK\n"));
+		script.push(reply("SESSION STATUS RESULT=OK\n"));
+		let (addr, played) = scripted_bridge(vec![script]);
+
+		let mut primary = Session::primary(addr, "TRANSIENT").unwrap();
+		let http = StreamForward::with_primary_port(&mut primary, 80).unwrap();
+		let irc = StreamForward::with_primary_port(&mut primary, 6667).unwrap();
+		let control = played.recv().unwrap();
+		let (http_add, irc_add) = (&control.lines[3], &control.lines[4]);
+		assert!(http_add.starts_with("SESSION ADD STYLE=STREAM "));
+		assert!(http_add.ends_with(" LISTEN_PORT=80 \n"));
+		assert!(irc_add.ends_with(" LISTEN_PORT=6667 \n"));
+		assert_ne!(http.session.id(), irc.session.id());
+		assert_eq!(http.local_addr().unwrap(), ("dest".to_string(), 80));
+		assert_eq!(irc.duplicate().unwrap().local_addr().unwrap().1, 6667);
+	}
+
+	#[test]
+	fn nonblocking_accept() {
+		use crate::error::ErrorKind;
+		use crate::sam::{Session, StreamForward};
+
+		let (addr, played) = scripted_bridge(vec![
+			session_script("3.2"),
+			vec![reply("HELLO REPLY RESULT=OK VERSION=3.2\n"), reply("STREAM STATUS RESULT=OK\n")],
+			// the accept socket must be reused rather than a new one opened
+			vec![],
+		]);
+
+		let session = Session::transient(addr).unwrap();
+		let _control = played.recv().unwrap();
+		let forward = StreamForward::with_session(&session).unwrap();
+		forward.set_nonblocking(true).unwrap();
+		let would_block = |forward: &StreamForward| match forward.accept() {
+			Err(e) => assert_eq!(e.kind(), ErrorKind::WouldBlock),
+			Ok(_) => panic!("accepted before the peer connected"),
+		};
+		would_block(&forward);
+		let mut stream = played.recv().unwrap().stream;
+		stream.write_all(b"AAAA FROM_").unwrap();
+		thread::sleep(Duration::from_millis(50));
+		would_block(&forward);
+		stream.write_all(b"PORT=7 TO_PORT=80\n").unwrap();
+		let accepted = loop {
+			match forward.accept() {
+				Ok(accepted) => break accepted,
+				Err(ref e) if e.kind() == ErrorKind::WouldBlock => thread::yield_now(),
+				Err(e) => panic!("accept failed: {}", e),
+			}
+		};
+		assert_eq!(accepted.1.port(), 7);
+		assert_eq!(accepted.0.peer_addr().unwrap(), ("AAAA".to_string(), 7));
+	}
+
+	#[test]
+	fn accept_timeout() {
+		use crate::error::ErrorKind;
+		use crate::sam::{Session, StreamForward};
+
+		let (addr, played) = scripted_bridge(vec![
+			session_script("3.2"),
+			vec![reply("HELLO REPLY RESULT=OK VERSION=3.2\n"), reply("STREAM STATUS RESULT=OK\n")],
+			vec![],
+		]);
+
+		let session = Session::transient(addr).unwrap();
+		let _control = played.recv().unwrap();
+		let forward = StreamForward::with_session(&session).unwrap();
+		match forward.accept_timeout(Duration::from_millis(100)) {
+			Err(e) => assert_eq!(e.kind(), ErrorKind::SAMTimeout("No peer connected in time".to_string())),
+			Ok(_) => panic!("accepted before the peer connected"),
+		}
+		// the pending accept socket gets the peer
+		let mut stream = played.recv().unwrap().stream;
+		stream.write_all(b"AAAA FROM_PORT=7 TO_PORT=80\n").unwrap();
+		let (accepted, addr) = forward.accept().unwrap();
+		assert_eq!(addr.port(), 7);
+		assert_eq!(accepted.read_timeout().unwrap(), None);
+	}
+
+	#[test]
+	fn stream_connect_timeout() {
+		use crate::error::ErrorKind;
+		use crate::sam::{Session, StreamConnect};
+		use std::time::Instant;
+
+		// the peer is never reached
+		let (addr, _played) = scripted_bridge(vec![
+			session_script("3.2"),
+			vec![reply("HELLO REPLY RESULT=OK VERSION=3.2\n")],
+		]);
+
+		let session = Session::transient(addr).unwrap();
+		let start = Instant::now();
+		let res = StreamConnect::with_session_timeout(&session, &"A".repeat(516), 80, Duration::from_millis(200));
+		match res.err().map(|e| e.kind()) {
+			Some(ErrorKind::SAMTimeout(_)) => {}
+			kind => panic!("unexpected result: {:?}", kind),
+		}
+		assert!(start.elapsed() < Duration::from_secs(2));
+	}
+
+	#[test]
+	fn stream_connect_resolves_once() {
+		use crate::error::Error;
+		use crate::naming::Resolver;
+		use crate::sam::{Session, StreamConnect};
+		use std::sync::atomic::{AtomicUsize, Ordering};
+		use std::sync::Arc;
+
+		struct Counting(AtomicUsize);
+		impl Resolver for Counting {
+			fn resolve(&self, _name: &str) -> Result<String, Error> {
+				self.0.fetch_add(1, Ordering::SeqCst);
+				Ok("A".repeat(516))
+			}
+		}
+
+		let (addr, played) = scripted_bridge(vec![
+			session_script("3.2"),
+			vec![reply("HELLO REPLY RESULT=OK VERSION=3.2\n"), reply("STREAM STATUS RESULT=OK\n")],
+		]);
+
+		let mut session = Session::transient(addr).unwrap();
+		let resolver = Arc::new(Counting(AtomicUsize::new(0)));
+		session.sam.resolver = Some(resolver.clone());
+		StreamConnect::with_session(&session, "example.i2p", 80).unwrap();
+		assert_eq!(resolver.0.load(Ordering::SeqCst), 1);
+		let connect = &played.iter().nth(1).unwrap().lines[1];
+		assert!(connect.contains(&format!(" DESTINATION={} ", "A".repeat(516))));
+	}
+
+	#[test]
+	fn stream_connect_slow_resolver() {
+		use crate::error::{Error, ErrorKind};
+		use crate::naming::Resolver;
+		use crate::sam::{Session, StreamConnect};
+		use std::sync::Arc;
+
+		struct Slow;
+		impl Resolver for Slow {
+			fn resolve(&self, _name: &str) -> Result<String, Error> {
+				thread::sleep(Duration::from_millis(300));
+				Ok("A".repeat(516))
+			}
+		}
+
+		let (addr, _played) = scripted_bridge(vec![
+			session_script("3.2"),
+			vec![reply("HELLO REPLY RESULT=OK VERSION=3.2\n"), reply("STREAM STATUS RESULT=OK\n")],
+		]);
+
+		let mut session = Session::transient(addr).unwrap();
+		session.sam.resolver = Some(Arc::new(Slow));
+		let res = StreamConnect::with_session_timeout(&session, "example.i2p", 80, Duration::from_millis(100));
+		match res.err().map(|e| e.kind()) {
+			Some(ErrorKind::SAMTimeout(_)) => {}
+			kind => panic!("unexpected result: {:?}", kind),
+		}
+	}
+
+	#[test]
+	fn stream_options() {
+		use crate::sam::{Session, StreamConnect};
+		use crate::sam_options::{StreamingOptions, StreamingProfile};
+
+		let (addr, played) = scripted_bridge(vec![
+			session_script("3.2"),
+			vec![reply("HELLO REPLY RESULT=OK VERSION=3.2\n"), reply("STREAM STATUS RESULT=OK\n")],
+		]);
+
+		let session = Session::transient(addr).unwrap();
+		let options = StreamingOptions::new()
+			.connect_delay(Duration::from_millis(500))
+			.profile(StreamingProfile::Bulk);
+		StreamConnect::with_session_options(&session, &"A".repeat(516), 80, &options).unwrap();
+		let connect = &played.iter().nth(1).unwrap().lines[1];
+		assert!(connect.starts_with("STREAM CONNECT "));
+		assert!(connect.ends_with(
+			" TO_PORT=80 i2p.streaming.connectDelay=500 i2p.streaming.profile=1\n"
+		));
+	}
+
+	#[test]
+	fn datagram_styles() {
+		use crate::error::ErrorKind;
+		use crate::sam::{DatagramSession, SessionStyle};
+		use crate::sam_options::SessionOptions;
+
+		assert!(SessionStyle::Datagram3.is_datagram());
+		assert!(!SessionStyle::Raw.is_datagram());
+		// rejected before connecting to the bridge
+		let res = DatagramSession::with_options(
+			"127.0.0.1:1",
+			"TRANSIENT",
+			SessionStyle::Stream,
+			&SessionOptions::new(),
+		);
+		match res.err().map(|e| e.kind()) {
+			Some(ErrorKind::SAMInvalidMessage(_)) => {}
+			kind => panic!("unexpected result: {:?}", kind),
+		}
+	}
+
+	#[test]
+	fn oversized_payload() {
+		use crate::sam::DatagramSession;
+
+		let mut script = session_script("3.2");
+		script.push(Step::Write(b"DATAGRAM RECEIVED DESTINATION=peer SIZE=65537\n".to_vec()));
+		let (addr, _played) = scripted_bridge(vec![script]);
+
+		let mut session = DatagramSession::new(addr).unwrap();
+		let err = session.recv_from(&mut [0; 16]).err().unwrap();
+		assert_eq!(err.kind(), ErrorKind::SAMInvalidMessage("Bad payload size".to_string()));
+	}
+
+	#[test]
+	fn datagram_receiver() {
+		use crate::sam::DatagramReceiver;
+
+		let receiver = DatagramReceiver::bind("127.0.0.1:0").unwrap();
+		let bridge = UdpSocket::bind("127.0.0.1:0").unwrap();
+		bridge
+			.send_to(b"dest FROM_PORT=1234 TO_PORT=0\nhello", receiver.local_addr().unwrap())
+			.unwrap();
+
+		let mut buf = [0; 3];
+		let (n, (dest, port)) = receiver.recv_from(&mut buf).unwrap();
+		assert_eq!(&buf[..n], b"hel");
+		assert_eq!(dest, "dest");
+		assert_eq!(port, 1234);
+	}
 }
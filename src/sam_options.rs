@@ -0,0 +1,962 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// Signature types of I2P destinations.
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SignatureType {
+	DSA_SHA1,
+	ECDSA_SHA256_P256,
+	ECDSA_SHA384_P384,
+	ECDSA_SHA512_P521,
+	RSA_SHA256_2048,
+	RSA_SHA384_3072,
+	RSA_SHA512_4096,
+	EdDSA_SHA512_Ed25519,
+	EdDSA_SHA512_Ed25519ph,
+	RedDSA_SHA512_Ed25519,
+}
+
+impl SignatureType {
+	/// The numeric code of this signature type
+	pub fn code(self) -> u16 {
+		match self {
+			SignatureType::DSA_SHA1 => 0,
+			SignatureType::ECDSA_SHA256_P256 => 1,
+			SignatureType::ECDSA_SHA384_P384 => 2,
+			SignatureType::ECDSA_SHA512_P521 => 3,
+			SignatureType::RSA_SHA256_2048 => 4,
+			SignatureType::RSA_SHA384_3072 => 5,
+			SignatureType::RSA_SHA512_4096 => 6,
+			SignatureType::EdDSA_SHA512_Ed25519 => 7,
+			SignatureType::EdDSA_SHA512_Ed25519ph => 8,
+			SignatureType::RedDSA_SHA512_Ed25519 => 11,
+		}
+	}
+
+	/// The signature type with the provided numeric code, if known
+	pub fn from_code(code: u16) -> Option<SignatureType> {
+		match code {
+			0 => Some(SignatureType::DSA_SHA1),
+			1 => Some(SignatureType::ECDSA_SHA256_P256),
+			2 => Some(SignatureType::ECDSA_SHA384_P384),
+			3 => Some(SignatureType::ECDSA_SHA512_P521),
+			4 => Some(SignatureType::RSA_SHA256_2048),
+			5 => Some(SignatureType::RSA_SHA384_3072),
+			6 => Some(SignatureType::RSA_SHA512_4096),
+			7 => Some(SignatureType::EdDSA_SHA512_Ed25519),
+			8 => Some(SignatureType::EdDSA_SHA512_Ed25519ph),
+			11 => Some(SignatureType::RedDSA_SHA512_Ed25519),
+			_ => None,
+		}
+	}
+
+	/// Length in bytes of the public signing keys of this type
+	pub fn public_key_len(self) -> usize {
+		match self {
+			SignatureType::DSA_SHA1 => 128,
+			SignatureType::ECDSA_SHA256_P256 => 64,
+			SignatureType::ECDSA_SHA384_P384 => 96,
+			SignatureType::ECDSA_SHA512_P521 => 132,
+			SignatureType::RSA_SHA256_2048 => 256,
+			SignatureType::RSA_SHA384_3072 => 384,
+			SignatureType::RSA_SHA512_4096 => 512,
+			SignatureType::EdDSA_SHA512_Ed25519
+			| SignatureType::EdDSA_SHA512_Ed25519ph
+			| SignatureType::RedDSA_SHA512_Ed25519 => 32,
+		}
+	}
+
+	/// Length in bytes of the private signing keys of this type
+	pub fn private_key_len(self) -> usize {
+		match self {
+			SignatureType::DSA_SHA1 => 20,
+			SignatureType::ECDSA_SHA256_P256 => 32,
+			SignatureType::ECDSA_SHA384_P384 => 48,
+			SignatureType::ECDSA_SHA512_P521 => 66,
+			SignatureType::RSA_SHA256_2048 => 512,
+			SignatureType::RSA_SHA384_3072 => 768,
+			SignatureType::RSA_SHA512_4096 => 1024,
+			SignatureType::EdDSA_SHA512_Ed25519
+			| SignatureType::EdDSA_SHA512_Ed25519ph
+			| SignatureType::RedDSA_SHA512_Ed25519 => 32,
+		}
+	}
+
+	/// Length in bytes of the signatures of this type
+	pub fn signature_len(self) -> usize {
+		match self {
+			SignatureType::DSA_SHA1 => 40,
+			SignatureType::ECDSA_SHA256_P256 => 64,
+			SignatureType::ECDSA_SHA384_P384 => 96,
+			SignatureType::ECDSA_SHA512_P521 => 132,
+			SignatureType::RSA_SHA256_2048 => 256,
+			SignatureType::RSA_SHA384_3072 => 384,
+			SignatureType::RSA_SHA512_4096 => 512,
+			SignatureType::EdDSA_SHA512_Ed25519
+			| SignatureType::EdDSA_SHA512_Ed25519ph
+			| SignatureType::RedDSA_SHA512_Ed25519 => 64,
+		}
+	}
+
+	/// The name of this signature type, as expected by SAM
+	pub fn name(self) -> &'static str {
+		match self {
+			SignatureType::DSA_SHA1 => "DSA_SHA1",
+			SignatureType::ECDSA_SHA256_P256 => "ECDSA_SHA256_P256",
+			SignatureType::ECDSA_SHA384_P384 => "ECDSA_SHA384_P384",
+			SignatureType::ECDSA_SHA512_P521 => "ECDSA_SHA512_P521",
+			SignatureType::RSA_SHA256_2048 => "RSA_SHA256_2048",
+			SignatureType::RSA_SHA384_3072 => "RSA_SHA384_3072",
+			SignatureType::RSA_SHA512_4096 => "RSA_SHA512_4096",
+			SignatureType::EdDSA_SHA512_Ed25519 => "EdDSA_SHA512_Ed25519",
+			SignatureType::EdDSA_SHA512_Ed25519ph => "EdDSA_SHA512_Ed25519ph",
+			SignatureType::RedDSA_SHA512_Ed25519 => "RedDSA_SHA512_Ed25519",
+		}
+	}
+}
+
+/// Encryption types usable for the lease set of a session, in the order of
+/// preference given to `SessionOptions::lease_set_enc_types`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LeaseSetEncType {
+	/// ElGamal-2048, the legacy encryption type
+	ElGamal,
+	/// ECIES-X25519-AEAD-Ratchet
+	EciesX25519,
+	/// ML-KEM-512 hybrid with ECIES-X25519
+	MlKem512X25519,
+	/// ML-KEM-768 hybrid with ECIES-X25519
+	MlKem768X25519,
+	/// ML-KEM-1024 hybrid with ECIES-X25519
+	MlKem1024X25519,
+}
+
+impl LeaseSetEncType {
+	/// The numeric code of this encryption type
+	pub fn code(self) -> u16 {
+		match self {
+			LeaseSetEncType::ElGamal => 0,
+			LeaseSetEncType::EciesX25519 => 4,
+			LeaseSetEncType::MlKem512X25519 => 5,
+			LeaseSetEncType::MlKem768X25519 => 6,
+			LeaseSetEncType::MlKem1024X25519 => 7,
+		}
+	}
+}
+
+/// Reliability of the messages sent by a session.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MessageReliability {
+	BestEffort,
+	None,
+}
+
+impl MessageReliability {
+	fn string(self) -> &'static str {
+		match self {
+			MessageReliability::BestEffort => "BestEffort",
+			MessageReliability::None => "None",
+		}
+	}
+}
+
+/// Typed I2CP options for `SESSION CREATE`.
+///
+/// Options left unset aren't sent, leaving the router defaults in place.
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+/// use i2p::sam_options::{LeaseSetEncType, SessionOptions};
+///
+/// let opts = SessionOptions::new()
+///     .lease_set_enc_types(&[LeaseSetEncType::EciesX25519, LeaseSetEncType::ElGamal])
+///     .close_on_idle(Duration::from_secs(600))
+///     .dont_publish_lease_set(true);
+/// assert_eq!(opts.to_options()[0], ("i2cp.leaseSetEncType".to_string(), "4,0".to_string()));
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SessionOptions {
+	signature_type: Option<SignatureType>,
+	from_port: Option<u16>,
+	to_port: Option<u16>,
+	forward: Option<SocketAddr>,
+	listen_port: Option<u16>,
+	protocol: Option<u8>,
+	listen_protocol: Option<u8>,
+	header: Option<bool>,
+	tunnels: Option<TunnelConfig>,
+	lease_set_enc_types: Vec<LeaseSetEncType>,
+	reduce_on_idle: Option<(Duration, Option<u8>)>,
+	close_on_idle: Option<Duration>,
+	dont_publish_lease_set: Option<bool>,
+	fast_receive: Option<bool>,
+	message_reliability: Option<MessageReliability>,
+	gzip: Option<bool>,
+	streaming: Option<StreamingOptions>,
+	extra: Vec<(String, String)>,
+	pipelining: bool,
+}
+
+impl SessionOptions {
+	/// Creates an empty set of options.
+	pub fn new() -> SessionOptions {
+		SessionOptions::default()
+	}
+
+	/// Sets the signature type of the destination generated for `TRANSIENT`
+	/// sessions (`SIGNATURE_TYPE`).
+	pub fn signature_type(mut self, sig_type: SignatureType) -> SessionOptions {
+		self.signature_type = Some(sig_type);
+		self
+	}
+
+	/// Sets the default local port traffic of the session originates from
+	/// (`FROM_PORT`, SAM 3.2+).
+	pub fn from_port(mut self, port: u16) -> SessionOptions {
+		self.from_port = Some(port);
+		self
+	}
+
+	/// Sets the default remote port traffic of the session is sent to
+	/// (`TO_PORT`, SAM 3.2+).
+	pub fn to_port(mut self, port: u16) -> SessionOptions {
+		self.to_port = Some(port);
+		self
+	}
+
+	/// Have the bridge forward received datagrams over UDP to the provided
+	/// address instead of the control socket, for datagram and raw sessions
+	/// (`HOST`, `PORT`).
+	pub fn forward_to(mut self, addr: SocketAddr) -> SessionOptions {
+		self.forward = Some(addr);
+		self
+	}
+
+	/// Only delivers inbound traffic addressed to the provided local port to
+	/// a subsession, `0` accepting any port (`LISTEN_PORT`, SAM 3.3+).
+	pub fn listen_port(mut self, port: u16) -> SessionOptions {
+		self.listen_port = Some(port);
+		self
+	}
+
+	/// Sets the I2CP protocol number raw datagrams of the session are sent
+	/// with, 18 by default (`PROTOCOL`).
+	pub fn protocol(mut self, protocol: u8) -> SessionOptions {
+		self.protocol = Some(protocol);
+		self
+	}
+
+	/// Only delivers inbound raw datagrams of the provided I2CP protocol to
+	/// a raw session or subsession, `0` accepting any protocol
+	/// (`LISTEN_PROTOCOL`, SAM 3.2+).
+	pub fn listen_protocol(mut self, protocol: u8) -> SessionOptions {
+		self.listen_protocol = Some(protocol);
+		self
+	}
+
+	/// Whether raw datagrams forwarded over UDP are prefixed with a line
+	/// holding their ports and protocol, see `RawReceiver` (`HEADER`,
+	/// SAM 3.2+).
+	pub fn header(mut self, header: bool) -> SessionOptions {
+		self.header = Some(header);
+		self
+	}
+
+	/// Sets the length and number of the tunnels of the session, see
+	/// `TunnelConfig` for presets.
+	pub fn tunnels(mut self, tunnels: TunnelConfig) -> SessionOptions {
+		self.tunnels = Some(tunnels);
+		self
+	}
+
+	/// Sets the encryption types of the lease set, most preferred first
+	/// (`i2cp.leaseSetEncType`).
+	pub fn lease_set_enc_types(mut self, types: &[LeaseSetEncType]) -> SessionOptions {
+		self.lease_set_enc_types = types.to_vec();
+		self
+	}
+
+	/// Reduces the number of tunnels after the session has been idle for the
+	/// provided time, optionally down to the provided quantity
+	/// (`i2cp.reduceOnIdle`, `i2cp.reduceIdleTime`, `i2cp.reduceQuantity`).
+	pub fn reduce_on_idle(mut self, idle_time: Duration, quantity: Option<u8>) -> SessionOptions {
+		self.reduce_on_idle = Some((idle_time, quantity));
+		self
+	}
+
+	/// Closes the tunnels of the session after it has been idle for the
+	/// provided time (`i2cp.closeOnIdle`, `i2cp.closeIdleTime`).
+	pub fn close_on_idle(mut self, idle_time: Duration) -> SessionOptions {
+		self.close_on_idle = Some(idle_time);
+		self
+	}
+
+	/// Whether the lease set should be kept out of the network database, for
+	/// client-only sessions (`i2cp.dontPublishLeaseSet`).
+	pub fn dont_publish_lease_set(mut self, dont_publish: bool) -> SessionOptions {
+		self.dont_publish_lease_set = Some(dont_publish);
+		self
+	}
+
+	/// Whether received messages are delivered without acknowledgement
+	/// (`i2cp.fastReceive`).
+	pub fn fast_receive(mut self, fast_receive: bool) -> SessionOptions {
+		self.fast_receive = Some(fast_receive);
+		self
+	}
+
+	/// Sets the reliability of sent messages (`i2cp.messageReliability`).
+	pub fn message_reliability(mut self, reliability: MessageReliability) -> SessionOptions {
+		self.message_reliability = Some(reliability);
+		self
+	}
+
+	/// Whether payloads are gzipped (`i2cp.gzip`).
+	pub fn gzip(mut self, gzip: bool) -> SessionOptions {
+		self.gzip = Some(gzip);
+		self
+	}
+
+	/// Sets the options of the streaming library of the session, only used
+	/// by stream sessions (`i2p.streaming.*`).
+	pub fn streaming(mut self, streaming: StreamingOptions) -> SessionOptions {
+		self.streaming = Some(streaming);
+		self
+	}
+
+	/// Whether the commands creating the session, and connecting its streams
+	/// to peers already resolved, are sent without waiting for the reply to
+	/// the previous one, saving round trips. Off by default, as not every
+	/// bridge reads commands sent ahead of the reply to HELLO. Only changes
+	/// how the crate talks to the bridge, nothing is sent for it.
+	pub fn pipelining(mut self, pipelining: bool) -> SessionOptions {
+		self.pipelining = pipelining;
+		self
+	}
+
+	/// Sets an arbitrary option not covered by the typed setters. Options set
+	/// this way are sent after the typed ones and override them.
+	pub fn option(mut self, key: &str, value: &str) -> SessionOptions {
+		self.extra.push((key.to_string(), value.to_string()));
+		self
+	}
+
+	/// The key/value pairs these options translate to in `SESSION CREATE`.
+	pub fn to_options(&self) -> Vec<(String, String)> {
+		let mut opts = Vec::new();
+		let mut push = |k: &str, v: String| opts.push((k.to_string(), v));
+
+		if let Some(sig_type) = self.signature_type {
+			push("SIGNATURE_TYPE", sig_type.name().to_string());
+		}
+		if let Some(port) = self.from_port {
+			push("FROM_PORT", port.to_string());
+		}
+		if let Some(port) = self.to_port {
+			push("TO_PORT", port.to_string());
+		}
+		if let Some(addr) = self.forward {
+			push("HOST", addr.ip().to_string());
+			push("PORT", addr.port().to_string());
+		}
+		if let Some(port) = self.listen_port {
+			push("LISTEN_PORT", port.to_string());
+		}
+		if let Some(protocol) = self.protocol {
+			push("PROTOCOL", protocol.to_string());
+		}
+		if let Some(protocol) = self.listen_protocol {
+			push("LISTEN_PROTOCOL", protocol.to_string());
+		}
+		if let Some(header) = self.header {
+			push("HEADER", header.to_string());
+		}
+		if let Some(ref tunnels) = self.tunnels {
+			for (k, v) in tunnels.to_options() {
+				push(&k, v);
+			}
+		}
+		if !self.lease_set_enc_types.is_empty() {
+			let types: Vec<String> = self
+				.lease_set_enc_types
+				.iter()
+				.map(|t| t.code().to_string())
+				.collect();
+			push("i2cp.leaseSetEncType", types.join(","));
+		}
+		if let Some((idle_time, quantity)) = self.reduce_on_idle {
+			push("i2cp.reduceOnIdle", "true".to_string());
+			push("i2cp.reduceIdleTime", idle_time.as_millis().to_string());
+			if let Some(quantity) = quantity {
+				push("i2cp.reduceQuantity", quantity.to_string());
+			}
+		}
+		if let Some(idle_time) = self.close_on_idle {
+			push("i2cp.closeOnIdle", "true".to_string());
+			push("i2cp.closeIdleTime", idle_time.as_millis().to_string());
+		}
+		if let Some(dont_publish) = self.dont_publish_lease_set {
+			push("i2cp.dontPublishLeaseSet", dont_publish.to_string());
+		}
+		if let Some(fast_receive) = self.fast_receive {
+			push("i2cp.fastReceive", fast_receive.to_string());
+		}
+		if let Some(reliability) = self.message_reliability {
+			push("i2cp.messageReliability", reliability.string().to_string());
+		}
+		if let Some(gzip) = self.gzip {
+			push("i2cp.gzip", gzip.to_string());
+		}
+		if let Some(ref streaming) = self.streaming {
+			for (k, v) in streaming.to_options() {
+				push(&k, v);
+			}
+		}
+		for (k, v) in &self.extra {
+			push(k, v.clone());
+		}
+		opts
+	}
+
+	/// Whether the commands of the session are pipelined, see `pipelining`.
+	pub(crate) fn pipelines(&self) -> bool {
+		self.pipelining
+	}
+}
+
+/// Geometry of the tunnels in one direction.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct TunnelGeometry {
+	length: Option<u8>,
+	length_variance: Option<i8>,
+	quantity: Option<u8>,
+	backup_quantity: Option<u8>,
+}
+
+impl TunnelGeometry {
+	fn push_options(&self, direction: &str, opts: &mut Vec<(String, String)>) {
+		let mut push = |k: &str, v: String| opts.push((format!("{}.{}", direction, k), v));
+		if let Some(length) = self.length {
+			push("length", length.to_string());
+		}
+		if let Some(variance) = self.length_variance {
+			push("lengthVariance", variance.to_string());
+		}
+		if let Some(quantity) = self.quantity {
+			push("quantity", quantity.to_string());
+		}
+		if let Some(quantity) = self.backup_quantity {
+			push("backupQuantity", quantity.to_string());
+		}
+	}
+}
+
+/// Length and number of the inbound and outbound tunnels of a session,
+/// passed through `SessionOptions::tunnels`. Setters without a direction
+/// apply to both.
+///
+/// Longer tunnels give more anonymity at the expense of latency and
+/// reliability, more tunnels give more bandwidth and resilience.
+///
+/// # Examples
+///
+/// ```
+/// use i2p::sam_options::{SessionOptions, TunnelConfig};
+///
+/// let tunnels = TunnelConfig::balanced().inbound_quantity(4);
+/// let opts = SessionOptions::new().tunnels(tunnels);
+/// assert!(opts.to_options().contains(&("inbound.quantity".to_string(), "4".to_string())));
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TunnelConfig {
+	inbound: TunnelGeometry,
+	outbound: TunnelGeometry,
+}
+
+impl TunnelConfig {
+	/// Creates an empty configuration, leaving the router defaults in place.
+	pub fn new() -> TunnelConfig {
+		TunnelConfig::default()
+	}
+
+	/// Single hop tunnels, for services which care more about latency than
+	/// about the anonymity of their location.
+	pub fn low_latency() -> TunnelConfig {
+		TunnelConfig::new()
+			.length(1)
+			.length_variance(0)
+			.quantity(3)
+			.backup_quantity(1)
+	}
+
+	/// Three hop tunnels, the usual router defaults.
+	pub fn balanced() -> TunnelConfig {
+		TunnelConfig::new()
+			.length(3)
+			.length_variance(0)
+			.quantity(2)
+			.backup_quantity(0)
+	}
+
+	/// Three to four hop tunnels, making tunnel lengths harder to guess.
+	pub fn high_anonymity() -> TunnelConfig {
+		TunnelConfig::new()
+			.length(3)
+			.length_variance(1)
+			.quantity(2)
+			.backup_quantity(0)
+	}
+
+	/// Number of hops of the tunnels (`inbound.length`, `outbound.length`).
+	pub fn length(self, hops: u8) -> TunnelConfig {
+		self.inbound_length(hops).outbound_length(hops)
+	}
+
+	/// Random variance added to the number of hops: up to that many more
+	/// hops when positive, that many more or fewer when negative
+	/// (`inbound.lengthVariance`, `outbound.lengthVariance`).
+	pub fn length_variance(self, variance: i8) -> TunnelConfig {
+		self.inbound_length_variance(variance)
+			.outbound_length_variance(variance)
+	}
+
+	/// Number of tunnels (`inbound.quantity`, `outbound.quantity`).
+	pub fn quantity(self, tunnels: u8) -> TunnelConfig {
+		self.inbound_quantity(tunnels).outbound_quantity(tunnels)
+	}
+
+	/// Number of standby tunnels
+	/// (`inbound.backupQuantity`, `outbound.backupQuantity`).
+	pub fn backup_quantity(self, tunnels: u8) -> TunnelConfig {
+		self.inbound_backup_quantity(tunnels)
+			.outbound_backup_quantity(tunnels)
+	}
+
+	pub fn inbound_length(mut self, hops: u8) -> TunnelConfig {
+		self.inbound.length = Some(hops);
+		self
+	}
+
+	pub fn outbound_length(mut self, hops: u8) -> TunnelConfig {
+		self.outbound.length = Some(hops);
+		self
+	}
+
+	pub fn inbound_length_variance(mut self, variance: i8) -> TunnelConfig {
+		self.inbound.length_variance = Some(variance);
+		self
+	}
+
+	pub fn outbound_length_variance(mut self, variance: i8) -> TunnelConfig {
+		self.outbound.length_variance = Some(variance);
+		self
+	}
+
+	pub fn inbound_quantity(mut self, tunnels: u8) -> TunnelConfig {
+		self.inbound.quantity = Some(tunnels);
+		self
+	}
+
+	pub fn outbound_quantity(mut self, tunnels: u8) -> TunnelConfig {
+		self.outbound.quantity = Some(tunnels);
+		self
+	}
+
+	pub fn inbound_backup_quantity(mut self, tunnels: u8) -> TunnelConfig {
+		self.inbound.backup_quantity = Some(tunnels);
+		self
+	}
+
+	pub fn outbound_backup_quantity(mut self, tunnels: u8) -> TunnelConfig {
+		self.outbound.backup_quantity = Some(tunnels);
+		self
+	}
+
+	/// The options as key/value pairs, in the order they'd be sent.
+	pub fn to_options(&self) -> Vec<(String, String)> {
+		let mut opts = Vec::new();
+		self.inbound.push_options("inbound", &mut opts);
+		self.outbound.push_options("outbound", &mut opts);
+		opts
+	}
+}
+
+/// What the streaming library does with connections idle for longer than
+/// the inactivity timeout.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InactivityAction {
+	Nothing,
+	Disconnect,
+	SendKeepalive,
+}
+
+impl InactivityAction {
+	fn code(self) -> u8 {
+		match self {
+			InactivityAction::Nothing => 0,
+			InactivityAction::Disconnect => 1,
+			InactivityAction::SendKeepalive => 2,
+		}
+	}
+}
+
+/// Traffic profile the streaming library optimizes connections for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StreamingProfile {
+	Bulk,
+	Interactive,
+}
+
+impl StreamingProfile {
+	/// Streaming options tuned for the profile, roughly what `TCP_NODELAY`
+	/// is to TCP: interactive connections are opened right away and keep a
+	/// small window so little data waits behind what's being typed, while
+	/// bulk ones wait for initial data to send along with the connection
+	/// and grow a larger window.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use i2p::sam_options::StreamingProfile;
+	///
+	/// let opts = StreamingProfile::Interactive.options().to_options();
+	/// assert!(opts.contains(&("i2p.streaming.connectDelay".to_string(), "0".to_string())));
+	/// ```
+	pub fn options(self) -> StreamingOptions {
+		let opts = StreamingOptions::new().profile(self);
+		match self {
+			StreamingProfile::Interactive => opts
+				.connect_delay(Duration::from_millis(0))
+				.initial_window_size(6)
+				.max_window_size(32),
+			StreamingProfile::Bulk => opts
+				.connect_delay(Duration::from_secs(1))
+				.initial_window_size(12)
+				.max_window_size(128),
+		}
+	}
+
+	fn code(self) -> u8 {
+		match self {
+			StreamingProfile::Bulk => 1,
+			StreamingProfile::Interactive => 2,
+		}
+	}
+}
+
+/// Typed options of the I2P streaming library, passed to stream sessions
+/// through `SessionOptions::streaming`, or to single connections through
+/// `StreamConnect::with_session_options`.
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+/// use i2p::sam_options::{SessionOptions, StreamingOptions};
+///
+/// let streaming = StreamingOptions::new()
+///     .max_window_size(64)
+///     .max_conns_per_minute(10);
+/// let opts = SessionOptions::new().streaming(streaming);
+/// assert_eq!(opts.to_options()[0], ("i2p.streaming.maxWindowSize".to_string(), "64".to_string()));
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StreamingOptions {
+	connect_delay: Option<Duration>,
+	initial_window_size: Option<u32>,
+	max_window_size: Option<u32>,
+	initial_rto: Option<Duration>,
+	max_resends: Option<u32>,
+	max_message_size: Option<u32>,
+	inactivity: Option<(Duration, InactivityAction)>,
+	profile: Option<StreamingProfile>,
+	max_conns_per_minute: Option<u32>,
+	max_conns_per_hour: Option<u32>,
+	max_conns_per_day: Option<u32>,
+	max_total_conns_per_minute: Option<u32>,
+	answer_pings: Option<bool>,
+}
+
+impl StreamingOptions {
+	/// Creates an empty set of streaming options.
+	pub fn new() -> StreamingOptions {
+		StreamingOptions::default()
+	}
+
+	/// Delays sending the first packet of outgoing connections, so it can
+	/// carry initial data (`i2p.streaming.connectDelay`).
+	pub fn connect_delay(mut self, delay: Duration) -> StreamingOptions {
+		self.connect_delay = Some(delay);
+		self
+	}
+
+	/// Sets the initial window size, in messages
+	/// (`i2p.streaming.initialWindowSize`).
+	pub fn initial_window_size(mut self, size: u32) -> StreamingOptions {
+		self.initial_window_size = Some(size);
+		self
+	}
+
+	/// Sets the maximum window size, in messages
+	/// (`i2p.streaming.maxWindowSize`).
+	pub fn max_window_size(mut self, size: u32) -> StreamingOptions {
+		self.max_window_size = Some(size);
+		self
+	}
+
+	/// Sets the initial retransmission timeout (`i2p.streaming.initialRTO`).
+	pub fn initial_rto(mut self, rto: Duration) -> StreamingOptions {
+		self.initial_rto = Some(rto);
+		self
+	}
+
+	/// Sets how many times a message is resent before the connection is
+	/// dropped (`i2p.streaming.maxResends`).
+	pub fn max_resends(mut self, resends: u32) -> StreamingOptions {
+		self.max_resends = Some(resends);
+		self
+	}
+
+	/// Sets the maximum payload size of a message, in bytes
+	/// (`i2p.streaming.maxMessageSize`).
+	pub fn max_message_size(mut self, size: u32) -> StreamingOptions {
+		self.max_message_size = Some(size);
+		self
+	}
+
+	/// Applies `action` to connections idle for longer than `timeout`
+	/// (`i2p.streaming.inactivityTimeout`, `i2p.streaming.inactivityAction`).
+	pub fn inactivity(mut self, timeout: Duration, action: InactivityAction) -> StreamingOptions {
+		self.inactivity = Some((timeout, action));
+		self
+	}
+
+	/// Sets the traffic profile of connections (`i2p.streaming.profile`).
+	pub fn profile(mut self, profile: StreamingProfile) -> StreamingOptions {
+		self.profile = Some(profile);
+		self
+	}
+
+	/// Limits the incoming connections accepted from a single peer per
+	/// minute (`i2p.streaming.maxConnsPerMinute`).
+	pub fn max_conns_per_minute(mut self, conns: u32) -> StreamingOptions {
+		self.max_conns_per_minute = Some(conns);
+		self
+	}
+
+	/// Limits the incoming connections accepted from a single peer per hour
+	/// (`i2p.streaming.maxConnsPerHour`).
+	pub fn max_conns_per_hour(mut self, conns: u32) -> StreamingOptions {
+		self.max_conns_per_hour = Some(conns);
+		self
+	}
+
+	/// Limits the incoming connections accepted from a single peer per day
+	/// (`i2p.streaming.maxConnsPerDay`).
+	pub fn max_conns_per_day(mut self, conns: u32) -> StreamingOptions {
+		self.max_conns_per_day = Some(conns);
+		self
+	}
+
+	/// Limits the incoming connections accepted from all peers per minute
+	/// (`i2p.streaming.maxTotalConnsPerMinute`).
+	pub fn max_total_conns_per_minute(mut self, conns: u32) -> StreamingOptions {
+		self.max_total_conns_per_minute = Some(conns);
+		self
+	}
+
+	/// Whether pings from peers are answered (`i2p.streaming.answerPings`).
+	pub fn answer_pings(mut self, answer: bool) -> StreamingOptions {
+		self.answer_pings = Some(answer);
+		self
+	}
+
+	/// The options as key/value pairs, in the order they'd be sent.
+	pub fn to_options(&self) -> Vec<(String, String)> {
+		let mut opts = Vec::new();
+		let mut push = |k: &str, v: String| opts.push((format!("i2p.streaming.{}", k), v));
+
+		if let Some(delay) = self.connect_delay {
+			push("connectDelay", delay.as_millis().to_string());
+		}
+		if let Some(size) = self.initial_window_size {
+			push("initialWindowSize", size.to_string());
+		}
+		if let Some(size) = self.max_window_size {
+			push("maxWindowSize", size.to_string());
+		}
+		if let Some(rto) = self.initial_rto {
+			push("initialRTO", rto.as_millis().to_string());
+		}
+		if let Some(resends) = self.max_resends {
+			push("maxResends", resends.to_string());
+		}
+		if let Some(size) = self.max_message_size {
+			push("maxMessageSize", size.to_string());
+		}
+		if let Some((timeout, action)) = self.inactivity {
+			push("inactivityTimeout", timeout.as_millis().to_string());
+			push("inactivityAction", action.code().to_string());
+		}
+		if let Some(profile) = self.profile {
+			push("profile", profile.code().to_string());
+		}
+		if let Some(conns) = self.max_conns_per_minute {
+			push("maxConnsPerMinute", conns.to_string());
+		}
+		if let Some(conns) = self.max_conns_per_hour {
+			push("maxConnsPerHour", conns.to_string());
+		}
+		if let Some(conns) = self.max_conns_per_day {
+			push("maxConnsPerDay", conns.to_string());
+		}
+		if let Some(conns) = self.max_total_conns_per_minute {
+			push("maxTotalConnsPerMinute", conns.to_string());
+		}
+		if let Some(answer) = self.answer_pings {
+			push("answerPings", answer.to_string());
+		}
+		opts
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::time::Duration;
+
+	use crate::sam_options::*;
+
+	fn opts(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+		pairs
+			.iter()
+			.map(|(k, v)| (k.to_string(), v.to_string()))
+			.collect()
+	}
+
+	#[test]
+	fn empty() {
+		assert!(SessionOptions::new().to_options().is_empty());
+	}
+
+	#[test]
+	fn idle() {
+		let o = SessionOptions::new()
+			.reduce_on_idle(Duration::from_secs(300), Some(1))
+			.close_on_idle(Duration::from_secs(1800));
+		assert_eq!(
+			o.to_options(),
+			opts(&[
+				("i2cp.reduceOnIdle", "true"),
+				("i2cp.reduceIdleTime", "300000"),
+				("i2cp.reduceQuantity", "1"),
+				("i2cp.closeOnIdle", "true"),
+				("i2cp.closeIdleTime", "1800000"),
+			])
+		);
+	}
+
+	#[test]
+	fn signature_type() {
+		for code in 0..16 {
+			if let Some(sig_type) = SignatureType::from_code(code) {
+				assert_eq!(sig_type.code(), code);
+			}
+		}
+		let o = SessionOptions::new().signature_type(SignatureType::EdDSA_SHA512_Ed25519);
+		assert_eq!(
+			o.to_options(),
+			opts(&[("SIGNATURE_TYPE", "EdDSA_SHA512_Ed25519")])
+		);
+	}
+
+	#[test]
+	fn subsession() {
+		let o = SessionOptions::new()
+			.from_port(80)
+			.forward_to("127.0.0.1:9000".parse().unwrap())
+			.listen_port(80)
+			.listen_protocol(18);
+		assert_eq!(
+			o.to_options(),
+			opts(&[
+				("FROM_PORT", "80"),
+				("HOST", "127.0.0.1"),
+				("PORT", "9000"),
+				("LISTEN_PORT", "80"),
+				("LISTEN_PROTOCOL", "18"),
+			])
+		);
+	}
+
+	#[test]
+	fn tunnels() {
+		let o = SessionOptions::new().tunnels(TunnelConfig::low_latency().outbound_length(2));
+		assert_eq!(
+			o.to_options(),
+			opts(&[
+				("inbound.length", "1"),
+				("inbound.lengthVariance", "0"),
+				("inbound.quantity", "3"),
+				("inbound.backupQuantity", "1"),
+				("outbound.length", "2"),
+				("outbound.lengthVariance", "0"),
+				("outbound.quantity", "3"),
+				("outbound.backupQuantity", "1"),
+			])
+		);
+		assert!(TunnelConfig::new().to_options().is_empty());
+	}
+
+	#[test]
+	fn streaming() {
+		let streaming = StreamingOptions::new()
+			.initial_rto(Duration::from_secs(3))
+			.inactivity(Duration::from_secs(90), InactivityAction::SendKeepalive)
+			.profile(StreamingProfile::Interactive);
+		let o = SessionOptions::new().gzip(false).streaming(streaming);
+		assert_eq!(
+			o.to_options(),
+			opts(&[
+				("i2cp.gzip", "false"),
+				("i2p.streaming.initialRTO", "3000"),
+				("i2p.streaming.inactivityTimeout", "90000"),
+				("i2p.streaming.inactivityAction", "2"),
+				("i2p.streaming.profile", "2"),
+			])
+		);
+	}
+
+	#[test]
+	fn profile_options() {
+		assert_eq!(
+			StreamingProfile::Bulk.options().to_options(),
+			opts(&[
+				("i2p.streaming.connectDelay", "1000"),
+				("i2p.streaming.initialWindowSize", "12"),
+				("i2p.streaming.maxWindowSize", "128"),
+				("i2p.streaming.profile", "1"),
+			])
+		);
+	}
+
+	#[test]
+	fn misc() {
+		let o = SessionOptions::new()
+			.lease_set_enc_types(&[LeaseSetEncType::EciesX25519])
+			.dont_publish_lease_set(true)
+			.message_reliability(MessageReliability::None)
+			.option("i2cp.foo", "bar");
+		assert_eq!(
+			o.to_options(),
+			opts(&[
+				("i2cp.leaseSetEncType", "4"),
+				("i2cp.dontPublishLeaseSet", "true"),
+				("i2cp.messageReliability", "None"),
+				("i2cp.foo", "bar"),
+			])
+		);
+	}
+}
@@ -0,0 +1,586 @@
+use std::fmt;
+use std::io::{self, IoSlice, IoSliceMut, Read, Write};
+use std::str::FromStr;
+use std::net::{Shutdown, SocketAddr, TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, RawFd};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::time::Duration;
+#[cfg(feature = "tls")]
+pub use self::tls::TlsEndpoint;
+#[cfg(feature = "tls")]
+use std::sync::{Arc, Mutex};
+
+use crate::error::{Error, ErrorKind};
+
+/// Where to reach a SAM bridge.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum SamEndpoint {
+	/// A bridge listening on TCP, the default for both Java I2P and i2pd.
+	Tcp(SocketAddr),
+	/// A bridge listening on a Unix domain socket (i2pd).
+	#[cfg(unix)]
+	Unix(PathBuf),
+	/// A bridge wrapped in TLS, as supported by Java I2P.
+	#[cfg(feature = "tls")]
+	Tls(TlsEndpoint),
+}
+
+impl fmt::Display for SamEndpoint {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			SamEndpoint::Tcp(addr) => write!(f, "{}", addr),
+			#[cfg(unix)]
+			SamEndpoint::Unix(path) => write!(f, "unix:{}", path.display()),
+			#[cfg(feature = "tls")]
+			SamEndpoint::Tls(tls) => write!(f, "tls:{}", tls.addr()),
+		}
+	}
+}
+
+/// Parses `host:port` for TCP bridges, the host being resolved if needed,
+/// and `unix:/path` for Unix domain sockets, as found in configuration or
+/// environment variables such as `I2P_SAM_ADDRESS`.
+impl FromStr for SamEndpoint {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<SamEndpoint, Error> {
+		let s = s.trim();
+		#[cfg(unix)]
+		{
+			if let Some(path) = s.strip_prefix("unix:") {
+				return Ok(SamEndpoint::Unix(PathBuf::from(path)));
+			}
+		}
+		s.to_socket_addrs()
+			.ok()
+			.and_then(|mut addrs| addrs.next())
+			.map(SamEndpoint::Tcp)
+			.ok_or_else(|| ErrorKind::Io(format!("Invalid SAM bridge address: {}", s)).into())
+	}
+}
+
+/// The socket carrying a SAM connection, whatever the endpoint type.
+pub(crate) enum SamStream {
+	Tcp(TcpStream),
+	#[cfg(unix)]
+	Unix(UnixStream),
+	/// TLS streams can't be cloned at the socket level, all handles share the
+	/// same session instead.
+	#[cfg(feature = "tls")]
+	Tls(Arc<Mutex<tls::Stream>>, TlsEndpoint),
+}
+
+impl SamStream {
+	pub fn connect_tcp<A: ToSocketAddrs>(addr: A) -> io::Result<SamStream> {
+		TcpStream::connect(addr).map(SamStream::Tcp)
+	}
+
+	pub fn connect_tcp_timeout<A: ToSocketAddrs>(addr: A, timeout: Duration) -> io::Result<SamStream> {
+		let mut last_err = None;
+		for addr in addr.to_socket_addrs()? {
+			match TcpStream::connect_timeout(&addr, timeout) {
+				Ok(stream) => return Ok(SamStream::Tcp(stream)),
+				Err(e) => last_err = Some(e),
+			}
+		}
+		Err(last_err.unwrap_or_else(|| {
+			io::Error::new(io::ErrorKind::InvalidInput, "could not resolve to any addresses")
+		}))
+	}
+
+	/// Connects to the provided endpoint, within `timeout` for TCP bridges.
+	pub fn connect_timeout(endpoint: &SamEndpoint, timeout: Option<Duration>) -> io::Result<SamStream> {
+		match (endpoint, timeout) {
+			(SamEndpoint::Tcp(addr), Some(timeout)) => Self::connect_tcp_timeout(addr, timeout),
+			_ => Self::connect(endpoint),
+		}
+	}
+
+	pub fn connect(endpoint: &SamEndpoint) -> io::Result<SamStream> {
+		match endpoint {
+			SamEndpoint::Tcp(addr) => Self::connect_tcp(addr),
+			#[cfg(unix)]
+			SamEndpoint::Unix(path) => UnixStream::connect(path).map(SamStream::Unix),
+			#[cfg(feature = "tls")]
+			SamEndpoint::Tls(tls) => {
+				let stream = tls.connect()?;
+				Ok(SamStream::Tls(Arc::new(Mutex::new(stream)), tls.clone()))
+			}
+		}
+	}
+
+	/// The endpoint of the bridge this stream is connected to.
+	pub fn endpoint(&self) -> io::Result<SamEndpoint> {
+		match self {
+			SamStream::Tcp(s) => s.peer_addr().map(SamEndpoint::Tcp),
+			#[cfg(unix)]
+			SamStream::Unix(s) => {
+				let addr = s.peer_addr()?;
+				let path = addr
+					.as_pathname()
+					.ok_or_else(|| io::Error::other("unnamed SAM bridge socket"))?;
+				Ok(SamEndpoint::Unix(path.to_path_buf()))
+			}
+			#[cfg(feature = "tls")]
+			SamStream::Tls(_, tls) => Ok(SamEndpoint::Tls(tls.clone())),
+		}
+	}
+
+	pub fn try_clone(&self) -> io::Result<SamStream> {
+		match self {
+			SamStream::Tcp(s) => s.try_clone().map(SamStream::Tcp),
+			#[cfg(unix)]
+			SamStream::Unix(s) => s.try_clone().map(SamStream::Unix),
+			#[cfg(feature = "tls")]
+			SamStream::Tls(s, tls) => Ok(SamStream::Tls(s.clone(), tls.clone())),
+		}
+	}
+
+	pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+		match self {
+			SamStream::Tcp(s) => s.shutdown(how),
+			#[cfg(unix)]
+			SamStream::Unix(s) => s.shutdown(how),
+			#[cfg(feature = "tls")]
+			SamStream::Tls(s, _) => tls::lock(s)?.shutdown(how),
+		}
+	}
+
+	/// The local address of the socket, `None` for Unix domain sockets.
+	pub fn local_addr(&self) -> io::Result<Option<SocketAddr>> {
+		match self {
+			SamStream::Tcp(s) => s.local_addr().map(Some),
+			#[cfg(unix)]
+			SamStream::Unix(_) => Ok(None),
+			#[cfg(feature = "tls")]
+			SamStream::Tls(s, _) => tls::lock(s)?.sock.local_addr().map(Some),
+		}
+	}
+
+	pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+		match self {
+			SamStream::Tcp(s) => s.set_read_timeout(timeout),
+			#[cfg(unix)]
+			SamStream::Unix(s) => s.set_read_timeout(timeout),
+			#[cfg(feature = "tls")]
+			SamStream::Tls(s, _) => tls::lock(s)?.sock.set_read_timeout(timeout),
+		}
+	}
+
+	pub fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+		match self {
+			SamStream::Tcp(s) => s.set_write_timeout(timeout),
+			#[cfg(unix)]
+			SamStream::Unix(s) => s.set_write_timeout(timeout),
+			#[cfg(feature = "tls")]
+			SamStream::Tls(s, _) => tls::lock(s)?.sock.set_write_timeout(timeout),
+		}
+	}
+
+	pub fn read_timeout(&self) -> io::Result<Option<Duration>> {
+		match self {
+			SamStream::Tcp(s) => s.read_timeout(),
+			#[cfg(unix)]
+			SamStream::Unix(s) => s.read_timeout(),
+			#[cfg(feature = "tls")]
+			SamStream::Tls(s, _) => tls::lock(s)?.sock.read_timeout(),
+		}
+	}
+
+	pub fn write_timeout(&self) -> io::Result<Option<Duration>> {
+		match self {
+			SamStream::Tcp(s) => s.write_timeout(),
+			#[cfg(unix)]
+			SamStream::Unix(s) => s.write_timeout(),
+			#[cfg(feature = "tls")]
+			SamStream::Tls(s, _) => tls::lock(s)?.sock.write_timeout(),
+		}
+	}
+
+	pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+		match self {
+			SamStream::Tcp(s) => s.set_nonblocking(nonblocking),
+			#[cfg(unix)]
+			SamStream::Unix(s) => s.set_nonblocking(nonblocking),
+			#[cfg(feature = "tls")]
+			SamStream::Tls(s, _) => tls::lock(s)?.sock.set_nonblocking(nonblocking),
+		}
+	}
+}
+
+/// The socket polled for readiness, the TCP socket under the TLS session of
+/// TLS streams.
+#[cfg(unix)]
+impl AsRawFd for SamStream {
+	fn as_raw_fd(&self) -> RawFd {
+		match self {
+			SamStream::Tcp(s) => s.as_raw_fd(),
+			SamStream::Unix(s) => s.as_raw_fd(),
+			#[cfg(feature = "tls")]
+			SamStream::Tls(s, _) => s.lock().unwrap_or_else(|e| e.into_inner()).sock.as_raw_fd(),
+		}
+	}
+}
+
+impl Read for SamStream {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		(&*self).read(buf)
+	}
+
+	fn read_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> io::Result<usize> {
+		(&*self).read_vectored(bufs)
+	}
+}
+
+impl Write for SamStream {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		(&*self).write(buf)
+	}
+
+	fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+		(&*self).write_vectored(bufs)
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		(&*self).flush()
+	}
+}
+
+// like sockets, all variants can be read and written through shared references
+impl Read for &SamStream {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		match *self {
+			SamStream::Tcp(s) => (&*s).read(buf),
+			#[cfg(unix)]
+			SamStream::Unix(s) => (&*s).read(buf),
+			#[cfg(feature = "tls")]
+			SamStream::Tls(s, _) => tls::read(s, buf),
+		}
+	}
+
+	fn read_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> io::Result<usize> {
+		match *self {
+			SamStream::Tcp(s) => (&*s).read_vectored(bufs),
+			#[cfg(unix)]
+			SamStream::Unix(s) => (&*s).read_vectored(bufs),
+			// records are decrypted one at a time anyway
+			#[cfg(feature = "tls")]
+			SamStream::Tls(s, _) => match bufs.iter_mut().find(|b| !b.is_empty()) {
+				Some(buf) => tls::read(s, buf),
+				None => Ok(0),
+			},
+		}
+	}
+}
+
+impl Write for &SamStream {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		match *self {
+			SamStream::Tcp(s) => (&*s).write(buf),
+			#[cfg(unix)]
+			SamStream::Unix(s) => (&*s).write(buf),
+			#[cfg(feature = "tls")]
+			SamStream::Tls(s, _) => tls::lock(s)?.write(buf),
+		}
+	}
+
+	fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+		match *self {
+			SamStream::Tcp(s) => (&*s).write_vectored(bufs),
+			#[cfg(unix)]
+			SamStream::Unix(s) => (&*s).write_vectored(bufs),
+			#[cfg(feature = "tls")]
+			SamStream::Tls(s, _) => tls::lock(s)?.write_vectored(bufs),
+		}
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		match *self {
+			SamStream::Tcp(s) => (&*s).flush(),
+			#[cfg(unix)]
+			SamStream::Unix(s) => (&*s).flush(),
+			#[cfg(feature = "tls")]
+			SamStream::Tls(s, _) => tls::lock(s)?.flush(),
+		}
+	}
+}
+
+#[cfg(feature = "tls")]
+mod tls {
+	use std::convert::TryFrom;
+	use std::fmt;
+	use std::hash::{Hash, Hasher};
+	use std::io::{self, Read, Write};
+	use std::net::{Shutdown, SocketAddr, TcpStream};
+	use std::sync::{Arc, Mutex, MutexGuard};
+
+	use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+	use rustls::crypto::{self, CryptoProvider};
+	use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+	use rustls::{ClientConfig, ClientConnection, DigitallySignedStruct, RootCertStore, SignatureScheme};
+	use sha2::{Digest, Sha256};
+
+	use crate::error::{Error, ErrorKind};
+
+	/// A SAM bridge wrapped in TLS, along with the TLS configuration used to
+	/// reach it.
+	#[derive(Clone)]
+	pub struct TlsEndpoint {
+		addr: SocketAddr,
+		server_name: ServerName<'static>,
+		config: Arc<ClientConfig>,
+	}
+
+	impl TlsEndpoint {
+		/// A TLS endpoint using a custom rustls configuration.
+		pub fn new(addr: SocketAddr, server_name: &str, config: Arc<ClientConfig>) -> Result<TlsEndpoint, Error> {
+			let server_name = ServerName::try_from(server_name.to_string())
+				.map_err(|e| ErrorKind::Tls(e.to_string()).to_err())?;
+			Ok(TlsEndpoint {
+				addr,
+				server_name,
+				config,
+			})
+		}
+
+		/// A TLS endpoint whose certificate is verified against the provided
+		/// roots, and must be valid for `server_name`.
+		pub fn with_root_certificates(
+			addr: SocketAddr,
+			server_name: &str,
+			roots: RootCertStore,
+		) -> Result<TlsEndpoint, Error> {
+			let config = ClientConfig::builder_with_provider(provider())
+				.with_safe_default_protocol_versions()
+				.map_err(|e| ErrorKind::Tls(e.to_string()).to_err())?
+				.with_root_certificates(roots)
+				.with_no_client_auth();
+			Self::new(addr, server_name, Arc::new(config))
+		}
+
+		/// A TLS endpoint whose certificate must have one of the provided
+		/// SHA-256 fingerprints. No other verification of the certificate is
+		/// done, which makes this suitable for the self-signed certificates
+		/// SAM bridges usually generate.
+		pub fn with_pinned_certificates(addr: SocketAddr, fingerprints: &[[u8; 32]]) -> Result<TlsEndpoint, Error> {
+			let verifier = PinnedCertVerifier {
+				fingerprints: fingerprints.to_vec(),
+				provider: provider(),
+			};
+			let config = ClientConfig::builder_with_provider(provider())
+				.with_safe_default_protocol_versions()
+				.map_err(|e| ErrorKind::Tls(e.to_string()).to_err())?
+				.dangerous()
+				.with_custom_certificate_verifier(Arc::new(verifier))
+				.with_no_client_auth();
+			Ok(TlsEndpoint {
+				addr,
+				server_name: ServerName::from(addr.ip()),
+				config: Arc::new(config),
+			})
+		}
+
+		/// The TCP address of the bridge.
+		pub fn addr(&self) -> SocketAddr {
+			self.addr
+		}
+
+		pub(crate) fn connect(&self) -> io::Result<Stream> {
+			let conn = ClientConnection::new(self.config.clone(), self.server_name.clone())
+				.map_err(io::Error::other)?;
+			let sock = TcpStream::connect(self.addr)?;
+			Ok(Stream { conn, sock })
+		}
+	}
+
+	impl fmt::Debug for TlsEndpoint {
+		fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+			f.debug_struct("TlsEndpoint")
+				.field("addr", &self.addr)
+				.field("server_name", &self.server_name)
+				.finish()
+		}
+	}
+
+	impl PartialEq for TlsEndpoint {
+		fn eq(&self, other: &TlsEndpoint) -> bool {
+			self.addr == other.addr
+				&& self.server_name == other.server_name
+				&& Arc::ptr_eq(&self.config, &other.config)
+		}
+	}
+
+	impl Eq for TlsEndpoint {}
+
+	impl Hash for TlsEndpoint {
+		fn hash<H: Hasher>(&self, state: &mut H) {
+			self.addr.hash(state);
+			self.server_name.hash(state);
+		}
+	}
+
+	fn provider() -> Arc<CryptoProvider> {
+		Arc::new(crypto::ring::default_provider())
+	}
+
+	/// A TLS session over its TCP socket.
+	pub(crate) struct Stream {
+		conn: ClientConnection,
+		pub sock: TcpStream,
+	}
+
+	impl Stream {
+		pub fn shutdown(&mut self, how: Shutdown) -> io::Result<()> {
+			if how != Shutdown::Read {
+				self.conn.send_close_notify();
+				self.flush()?;
+			}
+			self.sock.shutdown(how)
+		}
+	}
+
+	impl Read for Stream {
+		fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+			rustls::Stream::new(&mut self.conn, &mut self.sock).read(buf)
+		}
+	}
+
+	impl Write for Stream {
+		fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+			rustls::Stream::new(&mut self.conn, &mut self.sock).write(buf)
+		}
+
+		fn flush(&mut self) -> io::Result<()> {
+			rustls::Stream::new(&mut self.conn, &mut self.sock).flush()
+		}
+	}
+
+	/// Reads from a shared TLS session, only holding the lock while records
+	/// get decrypted, not while waiting on the socket, so that other handles
+	/// of the session can write in the meantime.
+	pub(crate) fn read(stream: &Mutex<Stream>, buf: &mut [u8]) -> io::Result<usize> {
+		let sock = {
+			let mut s = lock(stream)?;
+			if s.conn.is_handshaking() {
+				return s.read(buf);
+			}
+			match s.conn.reader().read(buf) {
+				Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
+				res => return res,
+			}
+			s.sock.try_clone()?
+		};
+		let mut records = [0; 16 * 1024];
+		loop {
+			let n = (&sock).read(&mut records)?;
+			let mut s = lock(stream)?;
+			let Stream { ref mut conn, ref mut sock } = *s;
+			let mut data = &records[..n];
+			loop {
+				// an empty read tells rustls the peer closed the socket
+				conn.read_tls(&mut data)?;
+				conn.process_new_packets()
+					.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+				if data.is_empty() {
+					break;
+				}
+			}
+			while conn.wants_write() {
+				conn.write_tls(sock)?;
+			}
+			match conn.reader().read(buf) {
+				Err(ref e) if e.kind() == io::ErrorKind::WouldBlock && n > 0 => {}
+				res => return res,
+			}
+		}
+	}
+
+	pub(crate) fn lock(stream: &Mutex<Stream>) -> io::Result<MutexGuard<'_, Stream>> {
+		stream
+			.lock()
+			.map_err(|_| io::Error::other("TLS stream poisoned"))
+	}
+
+	#[derive(Debug)]
+	struct PinnedCertVerifier {
+		fingerprints: Vec<[u8; 32]>,
+		provider: Arc<CryptoProvider>,
+	}
+
+	impl ServerCertVerifier for PinnedCertVerifier {
+		fn verify_server_cert(
+			&self,
+			end_entity: &CertificateDer<'_>,
+			_intermediates: &[CertificateDer<'_>],
+			_server_name: &ServerName<'_>,
+			_ocsp_response: &[u8],
+			_now: UnixTime,
+		) -> Result<ServerCertVerified, rustls::Error> {
+			let fingerprint = Sha256::digest(end_entity.as_ref());
+			if self.fingerprints.iter().any(|f| f[..] == fingerprint[..]) {
+				Ok(ServerCertVerified::assertion())
+			} else {
+				Err(rustls::Error::General(
+					"SAM bridge certificate doesn't match the pinned fingerprints".to_string(),
+				))
+			}
+		}
+
+		fn verify_tls12_signature(
+			&self,
+			message: &[u8],
+			cert: &CertificateDer<'_>,
+			dss: &DigitallySignedStruct,
+		) -> Result<HandshakeSignatureValid, rustls::Error> {
+			crypto::verify_tls12_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+		}
+
+		fn verify_tls13_signature(
+			&self,
+			message: &[u8],
+			cert: &CertificateDer<'_>,
+			dss: &DigitallySignedStruct,
+		) -> Result<HandshakeSignatureValid, rustls::Error> {
+			crypto::verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+		}
+
+		fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+			self.provider
+				.signature_verification_algorithms
+				.supported_schemes()
+		}
+	}
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+	use std::io::{BufRead, BufReader, Write};
+	use std::os::unix::net::UnixListener;
+	use std::thread;
+
+	use crate::sam::SamConnection;
+
+	#[test]
+	fn connect_unix() {
+		let path = std::env::temp_dir().join(format!("i2p-rs-sam-{}.sock", std::process::id()));
+		let _ = std::fs::remove_file(&path);
+		let listener = UnixListener::bind(&path).unwrap();
+
+		let bridge = thread::spawn(move || {
+			let (mut stream, _) = listener.accept().unwrap();
+			let mut hello = String::new();
+			BufReader::new(&stream).read_line(&mut hello).unwrap();
+			assert!(hello.starts_with("HELLO VERSION MIN=3.0"));
+			stream.write_all(b"HELLO REPLY RESULT=OK VERSION=3.1\n").unwrap();
+		});
+
+		let sam = SamConnection::connect_unix(&path).unwrap();
+		assert_eq!(sam.version(), "3.1");
+		bridge.join().unwrap();
+		let _ = std::fs::remove_file(&path);
+	}
+}
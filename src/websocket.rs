@@ -0,0 +1,288 @@
+//! The opening handshake of WebSocket connections over I2P streams, for
+//! both client and server roles.
+//!
+//! Only the HTTP upgrade is done here, nothing frames or parses WebSocket
+//! messages. Once `client_handshake` or `server_handshake` returns, the
+//! stream carries WebSocket frames, which are left to the application.
+
+use std::io::{Read, Write};
+
+use data_encoding::BASE64;
+use rand::{self, RngCore};
+use ring::digest;
+
+use crate::error::{Error, ErrorKind};
+
+/// GUID the accept key is derived with, RFC 6455 section 1.3
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+/// Longest handshake accepted, request or response
+const MAX_HANDSHAKE_LEN: usize = 8192;
+
+/// Sends the opening handshake of a client to `host` for `path`, then
+/// reads and checks the reply of the server.
+pub fn client_handshake<S: Read + Write>(stream: &mut S, host: &str, path: &str) -> Result<(), Error> {
+	let key = new_key();
+	stream.write_all(client_request(host, path, &key).as_bytes())?;
+	stream.flush()?;
+	let mut reply = Vec::new();
+	let mut byte = [0; 1];
+	while !is_complete(&reply)? {
+		if stream.read(&mut byte)? == 0 {
+			return Err(failed("connection closed during the handshake"));
+		}
+		reply.push(byte[0]);
+	}
+	check_reply(&reply, &key)
+}
+
+/// Reads the opening handshake of a client and accepts it, returning the
+/// requested path. Invalid handshakes are answered with `400 Bad Request`.
+pub fn server_handshake<S: Read + Write>(stream: &mut S) -> Result<String, Error> {
+	let mut request = Vec::new();
+	let mut byte = [0; 1];
+	while !is_complete(&request)? {
+		if stream.read(&mut byte)? == 0 {
+			return Err(failed("connection closed during the handshake"));
+		}
+		request.push(byte[0]);
+	}
+	let (reply, res) = server_reply(&request);
+	stream.write_all(reply.as_bytes())?;
+	stream.flush()?;
+	res
+}
+
+/// Same as `client_handshake`, over an asynchronous stream.
+#[cfg(feature = "async")]
+pub async fn client_handshake_async(
+	stream: &mut crate::asynch::I2pStream,
+	host: &str,
+	path: &str,
+) -> Result<(), Error> {
+	let key = new_key();
+	stream.write_all(client_request(host, path, &key).as_bytes()).await?;
+	stream.flush().await?;
+	let mut reply = Vec::new();
+	let mut byte = [0; 1];
+	while !is_complete(&reply)? {
+		if stream.read(&mut byte).await? == 0 {
+			return Err(failed("connection closed during the handshake"));
+		}
+		reply.push(byte[0]);
+	}
+	check_reply(&reply, &key)
+}
+
+/// Same as `server_handshake`, over an asynchronous stream.
+#[cfg(feature = "async")]
+pub async fn server_handshake_async(stream: &mut crate::asynch::I2pStream) -> Result<String, Error> {
+	let mut request = Vec::new();
+	let mut byte = [0; 1];
+	while !is_complete(&request)? {
+		if stream.read(&mut byte).await? == 0 {
+			return Err(failed("connection closed during the handshake"));
+		}
+		request.push(byte[0]);
+	}
+	let (reply, res) = server_reply(&request);
+	stream.write_all(reply.as_bytes()).await?;
+	stream.flush().await?;
+	res
+}
+
+fn failed(msg: &str) -> Error {
+	ErrorKind::Io(format!("WebSocket handshake failed: {}", msg)).into()
+}
+
+fn new_key() -> String {
+	let mut nonce = [0; 16];
+	rand::thread_rng().fill_bytes(&mut nonce);
+	BASE64.encode(&nonce)
+}
+
+fn client_request(host: &str, path: &str, key: &str) -> String {
+	format!(
+		"GET {} HTTP/1.1\r\nHost: {}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+		 Sec-WebSocket-Key: {}\r\nSec-WebSocket-Version: 13\r\n\r\n",
+		if path.is_empty() { "/" } else { path },
+		host,
+		key
+	)
+}
+
+/// Whether the handshake read so far is complete. Handshakes are read a
+/// byte at a time, so the frames following them stay on the stream.
+fn is_complete(head: &[u8]) -> Result<bool, Error> {
+	if head.ends_with(b"\r\n\r\n") {
+		return Ok(true);
+	}
+	if head.len() >= MAX_HANDSHAKE_LEN {
+		return Err(failed("handshake too long"));
+	}
+	Ok(false)
+}
+
+/// The first line and the headers of an HTTP head.
+struct Head<'a> {
+	start: &'a str,
+	headers: Vec<(&'a str, &'a str)>,
+}
+
+impl<'a> Head<'a> {
+	fn parse(head: &'a [u8]) -> Result<Head<'a>, Error> {
+		let head = std::str::from_utf8(head).map_err(|_| failed("invalid HTTP head"))?;
+		let mut lines = head.split("\r\n");
+		let start = lines.next().unwrap_or("");
+		let headers = lines
+			.filter(|line| !line.is_empty())
+			.map(|line| {
+				line.split_once(':')
+					.map(|(name, value)| (name.trim(), value.trim()))
+					.ok_or_else(|| failed("invalid HTTP header"))
+			})
+			.collect::<Result<_, _>>()?;
+		Ok(Head { start, headers })
+	}
+
+	fn header(&self, name: &str) -> Option<&'a str> {
+		self.headers
+			.iter()
+			.find(|(n, _)| n.eq_ignore_ascii_case(name))
+			.map(|&(_, value)| value)
+	}
+
+	/// Whether the comma separated list of header `name` holds `token`.
+	fn has_token(&self, name: &str, token: &str) -> bool {
+		self.header(name)
+			.is_some_and(|value| value.split(',').any(|t| t.trim().eq_ignore_ascii_case(token)))
+	}
+}
+
+fn check_reply(reply: &[u8], key: &str) -> Result<(), Error> {
+	let head = Head::parse(reply)?;
+	if head.start.split(' ').nth(1) != Some("101") {
+		return Err(failed(&format!("unexpected reply {:?}", head.start)));
+	}
+	if !head.has_token("Upgrade", "websocket") || !head.has_token("Connection", "upgrade") {
+		return Err(failed("the server didn't upgrade the connection"));
+	}
+	if head.header("Sec-WebSocket-Accept") != Some(accept_key(key).as_str()) {
+		return Err(failed("invalid Sec-WebSocket-Accept"));
+	}
+	Ok(())
+}
+
+/// The reply to a client handshake, and the requested path if it's valid.
+fn server_reply(request: &[u8]) -> (String, Result<String, Error>) {
+	match check_request(request) {
+		Ok((path, key)) => (
+			format!(
+				"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+				 Sec-WebSocket-Accept: {}\r\n\r\n",
+				accept_key(&key)
+			),
+			Ok(path),
+		),
+		Err(e) => (
+			"HTTP/1.1 400 Bad Request\r\nSec-WebSocket-Version: 13\r\nContent-Length: 0\r\n\r\n".to_string(),
+			Err(e),
+		),
+	}
+}
+
+fn check_request(request: &[u8]) -> Result<(String, String), Error> {
+	let head = Head::parse(request)?;
+	let path = match head.start.split(' ').collect::<Vec<_>>()[..] {
+		["GET", path, version] if version.starts_with("HTTP/1.") => path,
+		_ => return Err(failed(&format!("unexpected request {:?}", head.start))),
+	};
+	if !head.has_token("Upgrade", "websocket") || !head.has_token("Connection", "upgrade") {
+		return Err(failed("the client didn't ask for an upgrade"));
+	}
+	if head.header("Sec-WebSocket-Version") != Some("13") {
+		return Err(failed("unsupported WebSocket version"));
+	}
+	let key = head.header("Sec-WebSocket-Key").ok_or_else(|| failed("no Sec-WebSocket-Key"))?;
+	Ok((path.to_string(), key.to_string()))
+}
+
+/// The `Sec-WebSocket-Accept` answering `key`. The handshake is defined
+/// with SHA-1, which only proves the server understood the request, so its
+/// weaknesses don't matter here.
+fn accept_key(key: &str) -> String {
+	let digest = digest::digest(&digest::SHA1_FOR_LEGACY_USE_ONLY, format!("{}{}", key, WEBSOCKET_GUID).as_bytes());
+	BASE64.encode(digest.as_ref())
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::{Read, Write};
+	use std::net::{TcpListener, TcpStream};
+	use std::thread;
+
+	use super::accept_key;
+
+	#[test]
+	fn keys() {
+		// RFC 6455 section 1.3
+		assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+	}
+
+	#[test]
+	fn handshake() {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+		let server = thread::spawn(move || {
+			let (mut stream, _) = listener.accept().unwrap();
+			let path = crate::websocket::server_handshake(&mut stream).unwrap();
+			stream.write_all(b"frame").unwrap();
+			path
+		});
+
+		let mut stream = TcpStream::connect(addr).unwrap();
+		crate::websocket::client_handshake(&mut stream, "chat.i2p", "/room").unwrap();
+		assert_eq!(server.join().unwrap(), "/room");
+		// what follows the handshake is left on the stream
+		let mut frame = [0; 5];
+		stream.read_exact(&mut frame).unwrap();
+		assert_eq!(&frame, b"frame");
+	}
+
+	#[test]
+	fn rejected() {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+		thread::spawn(move || {
+			let (mut stream, _) = listener.accept().unwrap();
+			assert!(crate::websocket::server_handshake(&mut stream).is_err());
+		});
+
+		let mut stream = TcpStream::connect(addr).unwrap();
+		stream.write_all(b"GET / HTTP/1.1\r\nHost: chat.i2p\r\n\r\n").unwrap();
+		let mut reply = String::new();
+		stream.read_to_string(&mut reply).unwrap();
+		assert!(reply.starts_with("HTTP/1.1 400 "));
+	}
+
+	#[cfg(feature = "async")]
+	#[test]
+	fn server_handshake_async() {
+		use crate::asynch::tests::block_on;
+		use crate::asynch::{I2pStream, Session};
+		use crate::sam::tests::fake_bridge;
+
+		let addr = fake_bridge(
+			b"GET /chat HTTP/1.1\r\nHost: chat.i2p\r\nUpgrade: websocket\r\nConnection: keep-alive, Upgrade\r\n\
+			  Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n",
+		);
+		block_on(async {
+			let session = Session::transient(addr).await.unwrap();
+			let mut stream = I2pStream::connect_with_session(&session, "chat.i2p:80").await.unwrap();
+			assert_eq!(crate::websocket::server_handshake_async(&mut stream).await.unwrap(), "/chat");
+			// the bridge echoes the reply
+			let mut reply = vec![0; 12];
+			stream.read_exact(&mut reply).await.unwrap();
+			assert_eq!(&reply, b"HTTP/1.1 101");
+		});
+	}
+}